@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+use uuid::Uuid;
+
+/// Text with metadata, passed between splitting, embedding and storage.
+///
+/// Splitter chunks convert into documents and documents convert into Qdrant
+/// points, so pipelines do not need to map fields by hand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub content: String,
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Where the content came from, e.g. a file path or URL
+    pub source: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Document {
+    /// A document with a random UUID, no metadata and no source
+    pub fn from_text(content: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            content: content.into(),
+            metadata: HashMap::new(),
+            source: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Read a UTF-8 file into a document whose source is the file path
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`](crate::Error::Io) if the file cannot be read or
+    /// is not valid UTF-8.
+    pub fn from_file(path: &Path) -> crate::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self::from_text(content).with_source(path.display().to_string()))
+    }
+
+    #[must_use]
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_metadata(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.metadata.insert(key.into(), value);
+        self
+    }
+}
@@ -0,0 +1,74 @@
+use tiktoken_rs::{
+    cl100k_base, cl100k_base_singleton, o200k_base, o200k_base_singleton, p50k_base,
+    p50k_base_singleton, CoreBPE,
+};
+
+use crate::error::Error;
+
+/// Tokenizer encodings of `OpenAI` models, which token counts and the text
+/// splitter's `SplitterConfig` are based on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// `cl100k_base`, used by `gpt-4`, `gpt-3.5` and `text-embedding-3-*`
+    #[default]
+    Cl100k,
+    /// `o200k_base`, used by `gpt-4o`, `gpt-4.1` and the `o` series
+    O200k,
+    /// `p50k_base`, used by `text-davinci-*`
+    P50k,
+}
+
+impl Encoding {
+    /// The encoding `model` uses: `O200k` for `gpt-4o*`, `gpt-4.1*` and the
+    /// `o1`/`o3`/`o4` families, `Cl100k` for `gpt-4*`, `gpt-3.5*` and
+    /// `text-embedding-3-*`, and `P50k` for `text-davinci-*`. `None` for
+    /// other models.
+    #[must_use]
+    pub fn for_model(model: &str) -> Option<Self> {
+        let is_family = |family: &str| {
+            model == family
+                || model
+                    .strip_prefix(family)
+                    .is_some_and(|rest| rest.starts_with('-'))
+        };
+
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || ["o1", "o3", "o4"].into_iter().any(is_family)
+        {
+            Some(Self::O200k)
+        } else if model.starts_with("gpt-4")
+            || model.starts_with("gpt-3.5")
+            || model.starts_with("text-embedding-3-")
+        {
+            Some(Self::Cl100k)
+        } else if model.starts_with("text-davinci-") {
+            Some(Self::P50k)
+        } else {
+            None
+        }
+    }
+
+    /// Load the tokenizer data of the encoding
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Other`] if the bundled tokenizer data fails to load.
+    pub fn tokenizer(self) -> crate::Result<CoreBPE> {
+        match self {
+            Self::Cl100k => cl100k_base(),
+            Self::O200k => o200k_base(),
+            Self::P50k => p50k_base(),
+        }
+        .map_err(|err| Error::Other(format!("Failed to load tokenizer: {err}")))
+    }
+
+    /// Tokenizer of the encoding, loaded once per process
+    pub(crate) fn shared(self) -> &'static CoreBPE {
+        match self {
+            Self::Cl100k => cl100k_base_singleton(),
+            Self::O200k => o200k_base_singleton(),
+            Self::P50k => p50k_base_singleton(),
+        }
+    }
+}
@@ -1,6 +1,32 @@
+pub mod document;
+#[cfg(any(feature = "openai", feature = "text-splitter"))]
+pub mod encoding;
 pub mod errors;
+#[cfg(test)]
+pub(crate) mod test_server;
 pub mod types;
 pub mod utils;
 
+pub use document::Document;
+#[cfg(any(feature = "openai", feature = "text-splitter"))]
+pub use encoding::Encoding;
 pub use errors::CommonError;
 pub use utils::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_url() {
+        assert_eq!(join_url("http://host", "models"), "http://host/models");
+        assert_eq!(join_url("http://host/", "/models"), "http://host/models");
+        assert_eq!(join_url("http://host//", "//models"), "http://host/models");
+        assert_eq!(
+            join_url("http://host/v1", "models/list"),
+            "http://host/v1/models/list"
+        );
+        assert_eq!(join_url("http://host/", ""), "http://host/");
+        assert_eq!(join_url("http://host", "/"), "http://host/");
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Canned reply of a [`serve`] server
+pub struct MockResponse {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    /// `200 OK` with `body` of `content_type`
+    pub fn new(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    /// `200 OK` with a JSON body
+    pub fn json(body: &str) -> Self {
+        Self::new("application/json", body)
+    }
+
+    /// Reply with `status`, e.g. `"503 Service Unavailable"`
+    pub fn status(mut self, status: &'static str) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Answer one connection per response, in order, on a free local port. The
+/// handle resolves to the raw requests received, each read up to its
+/// `content-length`.
+pub async fn serve(responses: Vec<MockResponse>) -> (SocketAddr, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut requests = Vec::with_capacity(responses.len());
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut socket).await);
+            let head = format!(
+                "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                response.status,
+                response.content_type,
+                response.body.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&response.body).await.unwrap();
+        }
+        requests
+    });
+    (addr, server)
+}
+
+/// [`serve`] for a single request
+pub async fn serve_once(response: MockResponse) -> (SocketAddr, JoinHandle<String>) {
+    let (addr, server) = serve(vec![response]).await;
+    let server = tokio::spawn(async move { server.await.unwrap().remove(0) });
+    (addr, server)
+}
+
+async fn read_request(socket: &mut TcpStream) -> String {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let read = socket.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if read == 0 || body.len() >= length {
+                return text.into_owned();
+            }
+        } else if read == 0 {
+            return text.into_owned();
+        }
+    }
+}
@@ -72,10 +72,7 @@ impl Base64Image {
 
     /// Get the MIME type for this image format
     pub fn mime_type(&self) -> &'static str {
-        match self.format {
-            ImageFormat::Png => "image/png",
-            ImageFormat::WebP => "image/webp",
-        }
+        self.format.mime_type()
     }
 
     /// Get dimensions as a tuple (width, height)
@@ -181,6 +178,8 @@ impl Base64ImageBuilder {
 pub enum ImageFormat {
     Png,
     WebP,
+    Jpeg,
+    Gif,
 }
 
 impl ImageFormat {
@@ -188,14 +187,35 @@ impl ImageFormat {
         match ext.to_lowercase().as_str() {
             "png" => Some(ImageFormat::Png),
             "webp" => Some(ImageFormat::WebP),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "gif" => Some(Self::Gif),
             _ => None,
         }
     }
 
+    /// The format of the file at `path`, from its extension
+    #[must_use]
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             ImageFormat::Png => "png",
             ImageFormat::WebP => "webp",
+            Self::Jpeg => "jpg",
+            Self::Gif => "gif",
+        }
+    }
+
+    /// MIME type used in data URIs, e.g. `"image/png"`
+    #[must_use]
+    pub const fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
         }
     }
 
@@ -203,6 +223,8 @@ impl ImageFormat {
         match self {
             ImageFormat::Png => image::ImageFormat::Png,
             ImageFormat::WebP => image::ImageFormat::WebP,
+            Self::Jpeg => image::ImageFormat::Jpeg,
+            Self::Gif => image::ImageFormat::Gif,
         }
     }
 }
@@ -8,6 +8,39 @@ use super::{
     types::{Base64Image, ImageFormat, ImageMetadata},
 };
 
+/// Join a base URL and a path with exactly one `/` between them, so a base
+/// ending in `/` or a path starting with one never produces `//`
+#[must_use]
+pub fn join_url(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Read the image file at `path` into a `data:` URI, with the MIME type of
+/// its [`ImageFormat`] extension. The bytes are embedded as they are, without
+/// decoding or re-encoding the image.
+///
+/// # Errors
+///
+/// Returns [`CommonError::InvalidPath`] if the extension is not a known image
+/// format, or [`CommonError::FileRead`] if the file cannot be read.
+pub fn read_image_to_data_uri(path: &std::path::Path) -> Result<String, CommonError> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        CommonError::InvalidPath(format!("{} is not a supported image", path.display()))
+    })?;
+    let bytes = std::fs::read(path).map_err(|e| {
+        CommonError::FileRead(format!("Failed to read image at {}: {e}", path.display()))
+    })?;
+    Ok(format!(
+        "data:{};base64,{}",
+        format.mime_type(),
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
 // --- ASYNC VERSIONS ---
 use tokio::{fs as async_fs, io::AsyncReadExt};
 
@@ -1,10 +1,41 @@
+use serde::Deserialize;
 use thiserror::Error;
 
+/// Error object in the body of a failed API call to `OpenAI`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct OpenAIErrorDetail {
+    pub message: String,
+    /// Error category, e.g. `"invalid_request_error"`
+    #[serde(rename = "type", default)]
+    pub error_type: String,
+    /// Machine-readable code, e.g. `"context_length_exceeded"`
+    pub code: Option<String>,
+    /// Request parameter the error refers to
+    pub param: Option<String>,
+}
+
+impl std::fmt::Display for OpenAIErrorDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.error_type, self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " (code: {code})")?;
+        }
+        if let Some(param) = &self.param {
+            write!(f, " (param: {param})")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("OpenAI error: {0}")]
     OpenAI(#[from] async_openai::error::OpenAIError),
 
+    /// API error whose body was parsed, see [`OpenAIErrorDetail`]
+    #[error("OpenAI API error: {0}")]
+    OpenAIStructured(OpenAIErrorDetail),
+
     #[error("OpenAI validation error: {0}")]
     OpenAIValidation(String),
 
@@ -13,15 +44,37 @@ pub enum Error {
         retry_after: Option<std::time::Duration>,
     },
 
+    /// Call refused without a request while a circuit breaker is open
+    #[error("Circuit breaker open: retry after {retry_after:?}")]
+    CircuitOpen { retry_after: std::time::Duration },
+
     #[error("OpenAI model not supported for operation: {model}")]
     OpenAIUnsupportedModel { model: String, operation: String },
 
     #[error("OpenAI missing parameter: {param}")]
     OpenAIMissingParameter { param: String },
 
+    #[cfg(feature = "qdrant")]
+    #[error("Qdrant error: {0}")]
+    Qdrant(#[from] qdrant_client::QdrantError),
+
+    #[cfg(feature = "qdrant")]
+    #[error("Failed to serialize payload for point {point_id}: {source}")]
+    PayloadSerialization {
+        point_id: String,
+        source: serde_json::Error,
+    },
+
+    #[cfg(feature = "realtime")]
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+
     #[error("Langfuse error: {0}")]
     Langfuse(String),
 
+    #[error("Image error: {0}")]
+    Common(#[from] crate::common::CommonError),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
@@ -0,0 +1,29 @@
+use std::future::Future;
+
+use tracing::{info_span, Instrument};
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// Run `future` with `trace_id` as the ambient Langfuse trace.
+///
+/// Inside it, [`current_trace_id`] returns the id and
+/// [`LangfuseService::create_generation_in_current_trace`](super::LangfuseService::create_generation_in_current_trace)
+/// attaches generations to the trace without the id being passed down. The
+/// future also runs in a `langfuse_trace` span with a `trace_id` field, so
+/// log lines emitted inside it can be matched to the trace.
+///
+/// The id is task-local: tasks spawned inside `future` do not inherit it and
+/// need their own `in_trace`.
+pub async fn in_trace<F: Future>(trace_id: impl Into<String>, future: F) -> F::Output {
+    let trace_id = trace_id.into();
+    let span = info_span!("langfuse_trace", trace_id = %trace_id);
+    TRACE_ID.scope(trace_id, future.instrument(span)).await
+}
+
+/// Id of the innermost [`in_trace`] scope of the current task, if any
+#[must_use]
+pub fn current_trace_id() -> Option<String> {
+    TRACE_ID.try_with(Clone::clone).ok()
+}
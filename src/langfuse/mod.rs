@@ -1,15 +1,490 @@
+mod context;
 mod service;
+mod tree;
 mod types;
+mod usage;
 
+pub use context::{current_trace_id, in_trace};
 pub use service::*;
+pub use tree::{SpanHandle, SpanTreeBuilder};
 pub use types::*;
+pub use usage::{ModelPrice, TraceUsage, UsageTotals};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::openai::OpenAIMessage;
+    use crate::common::test_server::{serve, serve_once, MockResponse};
+    use crate::openai::{ImageDetail, ImageUrl, Message};
+    use serde_json::json;
     use uuid::Uuid;
 
+    #[test]
+    fn test_serialize_vision_messages() {
+        let small_image = ImageUrl::from_base64("aGVsbG8=", Some(ImageDetail::Low));
+        let large_image = ImageUrl::from_base64(&"A".repeat(10_000), None);
+        let messages = vec![
+            Message::system("You describe images"),
+            Message::with_images(
+                "What is this?",
+                vec![
+                    ImageUrl::from_url("https://example.com/cat.png", None),
+                    small_image,
+                    large_image,
+                ],
+            )
+            .with_name("alice"),
+        ];
+
+        let value = LangfuseServiceImpl::serialize_messages(&messages);
+        assert_eq!(
+            value[0],
+            json!({"role": "system", "content": "You describe images"})
+        );
+        assert_eq!(
+            value[1],
+            json!({
+                "role": "user",
+                "name": "alice",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,aGVsbG8=", "detail": "low"}},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,<10000 base64 chars omitted>"}},
+                ]
+            })
+        );
+
+        // A malformed data URI without a comma is still shortened
+        let malformed = ImageUrl {
+            url: format!("data:{}", "A".repeat(10_000)),
+            detail: None,
+        };
+        let value = LangfuseServiceImpl::serialize_messages(&[Message::with_images(
+            "What is this?",
+            vec![malformed],
+        )]);
+        assert_eq!(
+            value[0]["content"][1]["image_url"]["url"],
+            "data:<10000 chars omitted>"
+        );
+    }
+
+    #[test]
+    fn test_truncate_strings() {
+        let mut value = json!([
+            {"role": "user", "content": "a".repeat(100)},
+            {"role": "assistant", "content": [{"type": "text", "text": "żółw".repeat(10)}]},
+            {"role": "user", "content": "short"},
+        ]);
+
+        LangfuseServiceImpl::truncate_strings(&mut value, 10);
+        assert_eq!(
+            value[0]["content"],
+            json!(format!("{}... [truncated 90 chars]", "a".repeat(10)))
+        );
+        assert_eq!(
+            value[1]["content"][0]["text"],
+            json!("żółwżółwżó... [truncated 30 chars]")
+        );
+        assert_eq!(value[2]["content"], json!("short"));
+        assert_eq!(value[0]["role"], json!("user"));
+    }
+
+    #[test]
+    fn test_merge_trace_metadata() {
+        assert_eq!(LangfuseServiceImpl::merge_trace_metadata(None, None), None);
+        assert_eq!(
+            LangfuseServiceImpl::merge_trace_metadata(None, Some(json!({"feature": "search"}))),
+            Some(json!({"feature": "search"}))
+        );
+        assert_eq!(
+            LangfuseServiceImpl::merge_trace_metadata(Some("c1"), None),
+            Some(json!({"conversation_id": "c1"}))
+        );
+        assert_eq!(
+            LangfuseServiceImpl::merge_trace_metadata(
+                Some("c1"),
+                Some(json!({"feature": "search"}))
+            ),
+            Some(json!({"feature": "search", "conversation_id": "c1"}))
+        );
+        assert_eq!(
+            LangfuseServiceImpl::merge_trace_metadata(Some("c1"), Some(json!("beta"))),
+            Some(json!({"metadata": "beta", "conversation_id": "c1"}))
+        );
+    }
+
+    #[test]
+    fn test_trace_update_event() {
+        let base = BaseEvent {
+            id: "event-1".to_string(),
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            metadata: None,
+        };
+        let body = TraceUpdateBody {
+            id: "trace-1".to_string(),
+            output: Some(json!("done")),
+            ..Default::default()
+        };
+
+        let value = serde_json::to_value(IngestionEvent::trace_update(base, body)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "type": "trace-create",
+                "id": "event-1",
+                "timestamp": "2024-01-01T00:00:00Z",
+                "body": {"id": "trace-1", "output": "done"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_audio_message() {
+        let message = Message::with_audio(vec![0; 300], crate::openai::AudioFormat::Wav);
+        let value = LangfuseServiceImpl::serialize_messages(&[message]);
+        assert_eq!(
+            value[0],
+            json!({
+                "role": "user",
+                "content": [{
+                    "type": "input_audio",
+                    "input_audio": {"format": "wav", "data": "<400 base64 chars omitted>"},
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ambient_trace_id() {
+        assert_eq!(current_trace_id(), None);
+
+        let (outer, inner) = in_trace("trace-1", async {
+            let inner = in_trace("trace-2", async { current_trace_id() }).await;
+            (current_trace_id(), inner)
+        })
+        .await;
+        assert_eq!(outer.as_deref(), Some("trace-1"));
+        assert_eq!(inner.as_deref(), Some("trace-2"));
+        assert_eq!(current_trace_id(), None);
+
+        let spawned = in_trace("trace-1", async {
+            tokio::spawn(async { current_trace_id() }).await.unwrap()
+        })
+        .await;
+        assert_eq!(spawned, None);
+    }
+
+    #[test]
+    fn test_ingestion_url() {
+        let mut config = LangfuseConfig {
+            public_key: "pk".to_string(),
+            secret_key: "sk".to_string(),
+            api_url: "https://langfuse.example.com/".to_string(),
+            max_field_length: None,
+            compress_requests: true,
+            ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+        };
+        assert_eq!(
+            config.ingestion_url(),
+            "https://langfuse.example.com/api/public/ingestion"
+        );
+
+        config.api_url = "https://example.com/langfuse".to_string();
+        let config = config.with_ingestion_path("api/v2/ingestion");
+        assert_eq!(
+            config.ingestion_url(),
+            "https://example.com/langfuse/api/v2/ingestion"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_retries() {
+        use std::time::Duration;
+
+        let body = r#"{"successes":[],"errors":[]}"#;
+        let (addr, server) = serve(
+            ["503 Service Unavailable", "429 Too Many Requests", "200 OK"]
+                .map(|status| MockResponse::json(body).status(status))
+                .into(),
+        )
+        .await;
+
+        let config = |api_url: String| LangfuseConfig {
+            public_key: "pk".to_string(),
+            secret_key: "sk".to_string(),
+            api_url,
+            max_field_length: None,
+            compress_requests: false,
+            ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+        };
+        let client_config = LangfuseClientConfig {
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            max_retries: 2,
+        };
+        let batch = || IngestionBatch {
+            batch: Vec::new(),
+            metadata: None,
+        };
+
+        let service = LangfuseServiceImpl::with_client_config(
+            config(format!("http://{addr}")),
+            client_config,
+        );
+        service.send_batch(batch()).await.unwrap();
+        assert_eq!(server.await.unwrap().len(), 3);
+
+        // Nothing listens on port 1, so every attempt is refused
+        let service = LangfuseServiceImpl::with_client_config(
+            config("http://127.0.0.1:1".to_string()),
+            client_config,
+        );
+        let err = service.send_batch(batch()).await.unwrap_err();
+        assert!(matches!(err, crate::error::Error::Request(e) if e.is_connect()));
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_gzip_fallback() {
+        let ok = || MockResponse::json(r#"{"successes":[],"errors":[]}"#);
+        let connect = |addr: std::net::SocketAddr| {
+            LangfuseServiceImpl::new(LangfuseConfig {
+                public_key: "pk".to_string(),
+                secret_key: "sk".to_string(),
+                api_url: format!("http://{addr}"),
+                max_field_length: None,
+                compress_requests: true,
+                ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+            })
+        };
+        let batch = |service: &LangfuseServiceImpl| {
+            let input = json!({"query": "hello world ".repeat(200)});
+            let (_, event) = service.event_create("trace-1", "event", Some(input), None, None);
+            IngestionBatch {
+                batch: vec![event],
+                metadata: None,
+            }
+        };
+        let gzipped = |request: &String| request.contains("content-encoding: gzip");
+
+        // A 400 about the batch is not resent
+        let (addr, server) = serve(vec![MockResponse::json(
+            r#"{"message":"Invalid request data","error":["batch.0.body.name: Required"]}"#,
+        )
+        .status("400 Bad Request")])
+        .await;
+        let service = connect(addr);
+        let err = service.send_batch(batch(&service)).await.unwrap_err();
+        assert!(err.to_string().contains("Invalid request data"), "{err}");
+        assert_eq!(server.await.unwrap().len(), 1);
+
+        // 415 or a 400 about an unparseable body is resent plain, and later
+        // batches are not compressed
+        for rejection in [
+            MockResponse::json(r#"{"message":"Unsupported Media Type"}"#)
+                .status("415 Unsupported Media Type"),
+            MockResponse::json(r#"{"message":"Unexpected token in JSON at position 0"}"#)
+                .status("400 Bad Request"),
+        ] {
+            let (addr, server) = serve(vec![rejection, ok(), ok()]).await;
+            let service = connect(addr);
+            service.send_batch(batch(&service)).await.unwrap();
+            service.send_batch(batch(&service)).await.unwrap();
+            let requests = server.await.unwrap();
+            assert!(gzipped(&requests[0]));
+            assert!(!gzipped(&requests[1]));
+            assert!(!gzipped(&requests[2]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_span_tree() {
+        let (addr, server) =
+            serve_once(MockResponse::json(r#"{"successes":[],"errors":[]}"#)).await;
+
+        let service = LangfuseServiceImpl::new(LangfuseConfig {
+            public_key: "pk".to_string(),
+            secret_key: "sk".to_string(),
+            api_url: format!("http://{addr}"),
+            max_field_length: None,
+            compress_requests: false,
+            ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+        });
+        let mut tree = service.create_span_tree("trace-1");
+        assert_eq!(tree.trace_id(), "trace-1");
+        let root = tree.root("pipeline", Some(&[Message::user("question")]));
+        let retrieval = tree.child(&root, "retrieval");
+        let rerank = tree.child(&retrieval, "rerank");
+        tree.complete(&rerank, None);
+        tree.complete(&retrieval, None);
+        tree.complete(&root, Some(&[Message::assistant("answer")]));
+        tree.submit().await.unwrap();
+
+        // One request carries the whole tree
+        let request = server.await.unwrap();
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        let batch = body["batch"].as_array().unwrap().clone();
+        let types: Vec<_> = batch.iter().map(|event| event["type"].clone()).collect();
+        assert_eq!(
+            types,
+            [
+                "span-create",
+                "span-create",
+                "span-create",
+                "span-update",
+                "span-update",
+                "span-update"
+            ]
+        );
+        assert!(batch[..3]
+            .iter()
+            .all(|event| event["body"]["traceId"] == json!("trace-1")));
+        assert_eq!(batch[0]["body"]["id"], json!(root.id()));
+        assert!(batch[0]["body"].get("parentObservationId").is_none());
+        assert_eq!(
+            batch[0]["body"]["input"],
+            json!([{"role": "user", "content": "question"}])
+        );
+        assert_eq!(batch[1]["body"]["parentObservationId"], json!(root.id()));
+        assert_eq!(
+            batch[2]["body"]["parentObservationId"],
+            json!(retrieval.id())
+        );
+        assert_eq!(batch[5]["body"]["id"], json!(root.id()));
+        assert!(batch[5]["body"]["endTime"].is_string());
+        assert_eq!(
+            batch[5]["body"]["output"],
+            json!([{"role": "assistant", "content": "answer"}])
+        );
+
+        // An empty tree sends nothing, so the unreachable server is not an error
+        let service = LangfuseServiceImpl::new(LangfuseConfig {
+            public_key: "pk".to_string(),
+            secret_key: "sk".to_string(),
+            api_url: "http://127.0.0.1:1".to_string(),
+            max_field_length: None,
+            compress_requests: false,
+            ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+        });
+        service.create_span_tree("trace-2").submit().await.unwrap();
+    }
+
+    #[test]
+    fn test_compress_body() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let config = LangfuseConfig {
+            public_key: "pk".to_string(),
+            secret_key: "sk".to_string(),
+            api_url: "http://127.0.0.1:1".to_string(),
+            max_field_length: None,
+            compress_requests: true,
+            ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+        };
+        let service = LangfuseServiceImpl::new(config.clone());
+
+        assert!(service.compress_body(b"{}").is_none());
+
+        let body = serde_json::to_vec(&json!({"input": "hello world ".repeat(200)})).unwrap();
+        let compressed = service.compress_body(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        let mut decoded = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let service = LangfuseServiceImpl::new(config.with_compression(false));
+        assert!(service.compress_body(&body).is_none());
+    }
+
+    #[test]
+    fn test_event_create() {
+        let service = LangfuseServiceImpl::new(
+            LangfuseConfig {
+                public_key: "pk".to_string(),
+                secret_key: "sk".to_string(),
+                api_url: "http://127.0.0.1:1".to_string(),
+                max_field_length: None,
+                compress_requests: true,
+                ingestion_path: DEFAULT_INGESTION_PATH.to_string(),
+            }
+            .with_max_field_length(5),
+        );
+
+        let (event_id, event) = service.event_create(
+            "trace-1",
+            "cache hit",
+            Some(json!({"query": "long query text"})),
+            None,
+            Some(json!({"cache": "embeddings"})),
+        );
+        let value = serde_json::to_value(event).unwrap();
+
+        assert_eq!(value["type"], json!("event-create"));
+        assert_eq!(value["body"]["id"], json!(event_id));
+        assert_eq!(value["body"]["traceId"], json!("trace-1"));
+        assert_eq!(value["body"]["name"], json!("cache hit"));
+        assert_eq!(
+            value["body"]["input"],
+            json!({"query": "long ... [truncated 10 chars]"})
+        );
+        assert_eq!(value["body"]["metadata"], json!({"cache": "embeddings"}));
+        assert!(value["body"].get("output").is_none());
+        assert!(value["body"]["startTime"].is_string());
+    }
+
+    #[test]
+    fn test_trace_usage() {
+        let completion =
+            |model: &str, prompt_tokens, completion_tokens| crate::openai::ChatCompletion {
+                id: None,
+                created: None,
+                choices: Vec::new(),
+                model: model.to_string(),
+                usage: Some(crate::openai::Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
+                }),
+            };
+
+        let mut usage = TraceUsage::new()
+            .with_price("gpt-4o", ModelPrice::new(2.5, 10.0))
+            .with_price("gpt-4o-mini", ModelPrice::new(0.15, 0.6));
+        usage.record(&completion("gpt-4o-2024-08-06", 1_000_000, 100_000));
+        usage.record(&completion("gpt-4o-mini", 2_000_000, 0));
+        usage.record(&completion("llama-3", 10, 5));
+        // Only dated snapshots share the price of their model
+        usage.record(&completion("gpt-4o-audio-preview", 10, 5));
+
+        let totals = usage.totals();
+        assert_eq!(totals.generations, 4);
+        assert_eq!(totals.prompt_tokens, 3_000_020);
+        assert_eq!(totals.total_tokens, 3_100_030);
+        assert_eq!(usage.by_model()["gpt-4o-mini"].prompt_tokens, 2_000_000);
+        // 2.5 + 1.0 for gpt-4o, 0.3 for gpt-4o-mini
+        assert!((usage.estimated_cost() - 3.8).abs() < 1e-9);
+        assert_eq!(usage.unpriced_models(), ["gpt-4o-audio-preview", "llama-3"]);
+
+        let metadata = usage.to_metadata();
+        assert_eq!(metadata["usage"]["total_tokens"], json!(3_100_030));
+        assert_eq!(
+            metadata["usage"]["by_model"]["llama-3"]["generations"],
+            json!(1)
+        );
+        assert!(metadata["usage"]["estimated_cost_usd"].is_number());
+        assert!(TraceUsage::new().to_metadata()["usage"]
+            .get("estimated_cost_usd")
+            .is_none());
+
+        let mut usage = TraceUsage::new().with_price("gpt-4o", ModelPrice::new(2.5, 10.0));
+        usage.record(&completion("gpt-4o-mini-2024-07-18", 10, 5));
+        assert_eq!(usage.unpriced_models(), ["gpt-4o-mini-2024-07-18"]);
+    }
+
     #[tokio::test]
     async fn test_create_trace() {
         dotenv::dotenv().ok();
@@ -31,17 +506,9 @@ mod tests {
         let conversation_id = "test_conversation_123";
 
         // Create test messages
-        let input_messages = vec![OpenAIMessage::new(
-            "user",
-            "Hello, how are you?".to_string(),
-            None,
-        )];
+        let input_messages = vec![Message::user("Hello, how are you?")];
 
-        let output_messages = vec![OpenAIMessage::new(
-            "assistant",
-            "I'm doing well, thank you!".to_string(),
-            None,
-        )];
+        let output_messages = vec![Message::assistant("I'm doing well, thank you!")];
 
         // Create the trace with input/output data
         let result = service
@@ -51,6 +518,8 @@ mod tests {
                 Some(&input_messages),
                 Some(&output_messages),
                 Some(conversation_id),
+                Some(&["test".to_string(), "chat".to_string()]),
+                Some(json!({"experiment": "baseline", "user_segment": "internal"})),
             )
             .await;
 
@@ -89,7 +558,15 @@ mod tests {
         let conversation_id = "test_conversation_456";
 
         let trace_result = service
-            .create_trace(trace_id, trace_name, None, None, Some(conversation_id))
+            .create_trace(
+                trace_id,
+                trace_name,
+                None,
+                None,
+                Some(conversation_id),
+                None,
+                None,
+            )
             .await;
 
         let trace_id_str = match trace_result {
@@ -101,18 +578,20 @@ mod tests {
         };
 
         // Create test input messages
-        let input_messages = vec![OpenAIMessage::new(
-            "user",
-            "What is the capital of France?".to_string(),
-            None,
-        )];
+        let input_messages = vec![Message::user("What is the capital of France?")];
 
         // Create a generation
         let generation_name = "test_generation";
         let model = "gpt-4o";
 
         let generation_result = service
-            .create_generation(&trace_id_str, generation_name, model, &input_messages)
+            .create_generation(
+                &trace_id_str,
+                generation_name,
+                model,
+                &input_messages,
+                Some(json!({"temperature": 0.0})),
+            )
             .await;
 
         match generation_result {
@@ -121,6 +600,8 @@ mod tests {
 
                 // Create a mock ChatCompletion for testing
                 let mock_output = crate::openai::ChatCompletion {
+                    id: None,
+                    created: None,
                     choices: vec![crate::openai::Choice {
                         message: crate::openai::Message::assistant(
                             "The capital of France is Paris.".to_string(),
@@ -182,7 +663,15 @@ mod tests {
         let conversation_id = "test_conversation_789";
 
         let trace_result = service
-            .create_trace(trace_id, trace_name, None, None, Some(conversation_id))
+            .create_trace(
+                trace_id,
+                trace_name,
+                None,
+                None,
+                Some(conversation_id),
+                None,
+                None,
+            )
             .await;
 
         let trace_id_str = match trace_result {
@@ -1,30 +1,83 @@
 use async_trait::async_trait;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use chrono;
-use reqwest::Client;
+use flate2::{write::GzEncoder, Compression};
+use reqwest::{header, Client, Response, StatusCode};
 use serde_json::json;
+use std::{
+    fmt::Write as _,
+    io::Write as _,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::{
     error::Error,
     langfuse::types::{
-        BaseEvent, GenerationCreateBody, GenerationUpdateBody, IngestionBatch, IngestionEvent,
-        IngestionResponse, IngestionUsage, LangfuseConfig, OpenAIUsage, SpanCreateBody,
-        SpanUpdateBody, TraceBody,
+        BaseEvent, EventCreateBody, GenerationCreateBody, GenerationUpdateBody, IngestionBatch,
+        IngestionEvent, IngestionResponse, IngestionUsage, LangfuseClientConfig, LangfuseConfig,
+        OpenAIUsage, OptionalObservationBody, SpanCreateBody, SpanUpdateBody, TraceBody,
+        TraceUpdateBody,
     },
-    openai::{ChatCompletion, OpenAIMessage},
+    langfuse::{context::current_trace_id, tree::SpanTreeBuilder, usage::TraceUsage},
+    openai::{ChatCompletion, ContentPart, ImageUrl, Message, MessageContent},
 };
 
+/// Data URIs longer than this are not sent to Langfuse verbatim
+const MAX_INLINE_DATA_URI_LEN: usize = 2048;
+
+/// Ingestion bodies smaller than this are sent uncompressed
+const MIN_COMPRESSED_BODY_LEN: usize = 1024;
+
+/// Delay before the first retry, doubled on each further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Lowercase parts of 400 bodies reporting a request body the server could
+/// not decompress or parse
+const BODY_DECODE_ERRORS: [&str; 7] = [
+    "gzip",
+    "compress",
+    "content-encoding",
+    "invalid json",
+    "malformed json",
+    "unexpected token",
+    "not valid json",
+];
+
 pub struct LangfuseServiceImpl {
     config: LangfuseConfig,
     client: Client,
+    compress: AtomicBool,
+    max_retries: u32,
 }
 
 impl LangfuseServiceImpl {
     pub fn new(config: LangfuseConfig) -> Self {
+        Self::with_client_config(config, LangfuseClientConfig::default())
+    }
+
+    /// Create a service whose HTTP client uses the given timeouts and retry
+    /// budget
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized, like
+    /// [`Client::new`].
+    #[must_use]
+    pub fn with_client_config(config: LangfuseConfig, client_config: LangfuseClientConfig) -> Self {
+        let client = Client::builder()
+            .timeout(client_config.request_timeout)
+            .connect_timeout(client_config.connect_timeout)
+            .build()
+            .expect("failed to build the Langfuse HTTP client");
+        let compress = AtomicBool::new(config.compress_requests);
         Self {
             config,
-            client: Client::new(),
+            client,
+            compress,
+            max_retries: client_config.max_retries,
         }
     }
 
@@ -33,11 +86,120 @@ impl LangfuseServiceImpl {
         format!("Basic {}", BASE64.encode(credentials))
     }
 
-    fn serialize_messages(messages: &[OpenAIMessage]) -> serde_json::Value {
-        serde_json::to_value(messages).unwrap_or_else(|_| json!(messages))
+    /// Serialize messages in the chat completions format so the Langfuse UI can render
+    /// image parts. Text-only messages keep the flat `{role, content}` shape.
+    pub(crate) fn serialize_messages(messages: &[Message]) -> serde_json::Value {
+        serde_json::Value::Array(messages.iter().map(Self::serialize_message).collect())
+    }
+
+    fn serialize_message(message: &Message) -> serde_json::Value {
+        let content = match &message.content {
+            MessageContent::Text(text) => json!(text),
+            MessageContent::Image(images) => {
+                json!(images.iter().map(Self::serialize_image).collect::<Vec<_>>())
+            }
+            MessageContent::Mixed(parts) => json!(parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text(text) => json!({ "type": "text", "text": text }),
+                    ContentPart::Image(image) => Self::serialize_image(image),
+                })
+                .collect::<Vec<_>>()),
+            MessageContent::Audio(audio) => json!([{
+                "type": "input_audio",
+                "input_audio": {
+                    "format": audio.format,
+                    "data": format!("<{} base64 chars omitted>", audio.data.len()),
+                },
+            }]),
+        };
+
+        let mut value = json!({
+            "role": message.role.as_str(),
+            "content": content,
+        });
+        if let Some(name) = &message.name {
+            value["name"] = json!(name);
+        }
+        if let Some(tool_call_id) = &message.tool_call_id {
+            value["tool_call_id"] = json!(tool_call_id);
+        }
+        value
+    }
+
+    /// Image part with large data URIs replaced by a short reference
+    fn serialize_image(image: &ImageUrl) -> serde_json::Value {
+        let url = if image.is_data_uri() && image.url.len() > MAX_INLINE_DATA_URI_LEN {
+            match image.url.split_once(',') {
+                Some((header, data)) => {
+                    format!("{header},<{} base64 chars omitted>", data.len())
+                }
+                None => format!("data:<{} chars omitted>", image.url.len() - "data:".len()),
+            }
+        } else {
+            image.url.clone()
+        };
+
+        let mut image_url = json!({ "url": url });
+        if let Some(detail) = &image.detail {
+            image_url["detail"] = json!(detail);
+        }
+        json!({ "type": "image_url", "image_url": image_url })
+    }
+
+    /// Trace metadata with `conversation_id` added. Object metadata gets the id
+    /// as an extra key; any other value is nested under `metadata`.
+    pub(crate) fn merge_trace_metadata(
+        conversation_id: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Option<serde_json::Value> {
+        let Some(conversation_id) = conversation_id else {
+            return metadata;
+        };
+
+        let mut merged = match metadata {
+            Some(serde_json::Value::Object(map)) => map,
+            Some(other) => serde_json::Map::from_iter([("metadata".to_string(), other)]),
+            None => serde_json::Map::new(),
+        };
+        merged.insert("conversation_id".to_string(), json!(conversation_id));
+        Some(serde_json::Value::Object(merged))
+    }
+
+    /// Apply the configured `max_field_length` to a trace input/output value
+    pub(crate) fn limit_field(&self, mut value: serde_json::Value) -> serde_json::Value {
+        if let Some(max_len) = self.config.max_field_length {
+            Self::truncate_strings(&mut value, max_len);
+        }
+        value
     }
 
-    fn create_base_event() -> BaseEvent {
+    /// Cut every string in `value` longer than `max_len` characters down to its first
+    /// `max_len` characters followed by a `[truncated N chars]` marker.
+    pub(crate) fn truncate_strings(value: &mut serde_json::Value, max_len: usize) {
+        match value {
+            serde_json::Value::String(text) => {
+                if let Some((byte_index, _)) = text.char_indices().nth(max_len) {
+                    let omitted = text[byte_index..].chars().count();
+                    text.truncate(byte_index);
+                    let _ = write!(text, "... [truncated {omitted} chars]");
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    Self::truncate_strings(item, max_len);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for item in map.values_mut() {
+                    Self::truncate_strings(item, max_len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn create_base_event() -> BaseEvent {
         BaseEvent {
             id: Uuid::new_v4().to_string(),
             timestamp: chrono::Utc::now().to_rfc3339(),
@@ -53,16 +215,144 @@ impl LangfuseServiceImpl {
         })
     }
 
-    pub async fn send_batch(&self, batch: IngestionBatch) -> Result<IngestionResponse, Error> {
-        let url = format!("{}/api/public/ingestion", self.config.api_url);
+    /// `event-create` for a point-in-time marker on `trace_id`, with its id
+    pub(crate) fn event_create(
+        &self,
+        trace_id: &str,
+        name: &str,
+        input: Option<serde_json::Value>,
+        output: Option<serde_json::Value>,
+        metadata: Option<serde_json::Value>,
+    ) -> (String, IngestionEvent) {
+        let event_id = Uuid::new_v4().to_string();
+        let body = EventCreateBody {
+            id: Some(event_id.clone()),
+            observation: OptionalObservationBody {
+                traceId: Some(trace_id.to_string()),
+                name: Some(name.to_string()),
+                startTime: Some(chrono::Utc::now().to_rfc3339()),
+                metadata,
+                input: input.map(|value| self.limit_field(value)),
+                output: output.map(|value| self.limit_field(value)),
+                level: None,
+                statusMessage: None,
+                parentObservationId: None,
+                version: None,
+                environment: None,
+            },
+        };
 
-        let response = self
+        (
+            event_id,
+            IngestionEvent::event_create(Self::create_base_event(), body),
+        )
+    }
+
+    /// Record a tree of spans on `trace_id` and send it in one request with
+    /// [`SpanTreeBuilder::submit`], instead of one request per span
+    #[must_use]
+    pub fn create_span_tree(&self, trace_id: impl Into<String>) -> SpanTreeBuilder<'_> {
+        SpanTreeBuilder::new(self, trace_id)
+    }
+
+    /// Gzip `body` if compression is still enabled and the body is large
+    /// enough to benefit. Returns `None` when the body should go as is.
+    pub(crate) fn compress_body(&self, body: &[u8]) -> Option<Vec<u8>> {
+        if !self.compress.load(Ordering::Relaxed) || body.len() < MIN_COMPRESSED_BODY_LEN {
+            return None;
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).ok()?;
+        encoder.finish().ok()
+    }
+
+    /// Post a batch, retrying timeouts, connection failures, 429 and 5xx
+    /// responses up to `max_retries` times with exponential backoff.
+    ///
+    /// Once retries run out a network failure is returned as
+    /// [`Error::Request`] and a failing response is handed back to the caller
+    /// to report as an API error.
+    async fn post_ingestion(
+        &self,
+        body: &[u8],
+        content_encoding: Option<&str>,
+    ) -> Result<Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let retry_reason = match self.try_post_ingestion(body, content_encoding).await {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    if attempt >= self.max_retries {
+                        return Ok(response);
+                    }
+                    format!("HTTP {}", response.status())
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if (e.is_timeout() || e.is_connect()) && attempt < self.max_retries => {
+                    e.to_string()
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let delay = RETRY_BASE_DELAY * 2_u32.saturating_pow(attempt);
+            attempt += 1;
+            warn!(
+                attempt,
+                max_retries = self.max_retries,
+                "Langfuse ingestion failed ({retry_reason}), retrying in {delay:?}"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn try_post_ingestion(
+        &self,
+        body: &[u8],
+        content_encoding: Option<&str>,
+    ) -> Result<Response, reqwest::Error> {
+        let mut request = self
             .client
-            .post(&url)
-            .header("Authorization", self.get_auth_header())
-            .json(&batch)
-            .send()
-            .await?;
+            .post(self.config.ingestion_url())
+            .header(header::AUTHORIZATION, self.get_auth_header())
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(encoding) = content_encoding {
+            request = request.header(header::CONTENT_ENCODING, encoding);
+        }
+        request.body(body.to_vec()).send().await
+    }
+
+    /// Resend a batch the server could not decode when gzipped, and stop
+    /// compressing if the plain request is accepted
+    async fn post_uncompressed(&self, body: &[u8], status: StatusCode) -> Result<Response, Error> {
+        let retry = self.post_ingestion(body, None).await?;
+        if retry.status().is_success() {
+            warn!(%status, "Langfuse rejected a gzip request, sending uncompressed from now on");
+            self.compress.store(false, Ordering::Relaxed);
+        }
+        Ok(retry)
+    }
+
+    pub async fn send_batch(&self, batch: IngestionBatch) -> Result<IngestionResponse, Error> {
+        let body = serde_json::to_vec(&batch)?;
+
+        let response = match self.compress_body(&body) {
+            Some(compressed) => {
+                let response = self.post_ingestion(&compressed, Some("gzip")).await?;
+                let status = response.status();
+                if status == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                    self.post_uncompressed(&body, status).await?
+                } else if status == StatusCode::BAD_REQUEST {
+                    // Other 400s reject the batch itself, which resending
+                    // would not change
+                    let error_text = response.text().await?;
+                    if !is_body_decode_error(&error_text) {
+                        return Err(Error::Langfuse(format!("HTTP {status}: {error_text}")));
+                    }
+                    self.post_uncompressed(&body, status).await?
+                } else {
+                    response
+                }
+            }
+            None => self.post_ingestion(&body, None).await?,
+        };
 
         let status = response.status();
 
@@ -102,15 +392,31 @@ impl LangfuseServiceImpl {
     }
 }
 
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Whether a 400 body says the server could not decode or parse the request
+/// body, as a server that ignores `Content-Encoding: gzip` reports
+fn is_body_decode_error(error_text: &str) -> bool {
+    let error_text = error_text.to_ascii_lowercase();
+    BODY_DECODE_ERRORS
+        .iter()
+        .any(|marker| error_text.contains(marker))
+}
+
 #[async_trait]
 pub trait LangfuseService: Send + Sync {
+    #[allow(clippy::too_many_arguments)]
     async fn create_trace(
         &self,
         trace_id: Uuid,
         name: &str,
-        input: Option<&[OpenAIMessage]>,
-        output: Option<&[OpenAIMessage]>,
+        input: Option<&[Message]>,
+        output: Option<&[Message]>,
         conversation_id: Option<&str>,
+        tags: Option<&[String]>,
+        trace_metadata: Option<serde_json::Value>,
     ) -> Result<String, Error>;
 
     async fn create_generation(
@@ -118,9 +424,30 @@ pub trait LangfuseService: Send + Sync {
         trace_id: &str,
         name: &str,
         model: &str,
-        input: &[OpenAIMessage],
+        input: &[Message],
+        metadata: Option<serde_json::Value>,
     ) -> Result<String, Error>;
 
+    /// [`LangfuseService::create_generation`] on the ambient trace set by
+    /// [`in_trace`](super::in_trace)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Langfuse`] if no trace is in scope, or the error of
+    /// `create_generation`.
+    async fn create_generation_in_current_trace(
+        &self,
+        name: &str,
+        model: &str,
+        input: &[Message],
+        metadata: Option<serde_json::Value>,
+    ) -> Result<String, Error> {
+        let trace_id = current_trace_id()
+            .ok_or_else(|| Error::Langfuse("No Langfuse trace in scope".to_string()))?;
+        self.create_generation(&trace_id, name, model, input, metadata)
+            .await
+    }
+
     async fn update_generation(
         &self,
         generation_id: &str,
@@ -131,43 +458,63 @@ pub trait LangfuseService: Send + Sync {
         &self,
         trace_id: &str,
         name: &str,
-        input: Option<&[OpenAIMessage]>,
+        input: Option<&[Message]>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<String, Error>;
+
+    async fn update_span(&self, span_id: &str, output: &[Message]) -> Result<(), Error>;
+
+    /// Record a zero-duration event such as a cache hit or tool call on a
+    /// trace and return its id
+    async fn create_event(
+        &self,
+        trace_id: &str,
+        name: &str,
+        input: Option<serde_json::Value>,
+        output: Option<serde_json::Value>,
+        metadata: Option<serde_json::Value>,
     ) -> Result<String, Error>;
 
-    async fn update_span(&self, span_id: &str, output: &[OpenAIMessage]) -> Result<(), Error>;
+    /// Set the output and/or metadata of an existing trace, e.g. once the
+    /// conversation turn it records has finished. Metadata replaces keys of
+    /// the same name.
+    async fn update_trace(
+        &self,
+        trace_id: &str,
+        output: Option<&[Message]>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Error>;
+
+    /// Attach the totals of `usage` to an existing trace's metadata under
+    /// `usage`, see [`TraceUsage::to_metadata`]
+    async fn update_trace_usage(&self, trace_id: &str, usage: &TraceUsage) -> Result<(), Error>;
 }
 
 #[async_trait]
 impl LangfuseService for LangfuseServiceImpl {
+    #[allow(clippy::too_many_arguments)]
     async fn create_trace(
         &self,
         trace_id: Uuid,
         name: &str,
-        input: Option<&[OpenAIMessage]>,
-        output: Option<&[OpenAIMessage]>,
+        input: Option<&[Message]>,
+        output: Option<&[Message]>,
         conversation_id: Option<&str>,
+        tags: Option<&[String]>,
+        trace_metadata: Option<serde_json::Value>,
     ) -> Result<String, Error> {
-        let mut metadata = serde_json::Map::new();
-        if let Some(conv_id) = conversation_id {
-            metadata.insert("conversation_id".to_string(), json!(conv_id));
-        }
-
         let body = TraceBody {
             id: Some(trace_id.to_string()),
             timestamp: Some(chrono::Utc::now().to_rfc3339()),
             name: Some(name.to_string()),
             userId: None,
-            input: input.map(Self::serialize_messages),
-            output: output.map(Self::serialize_messages),
+            input: input.map(|messages| self.limit_field(Self::serialize_messages(messages))),
+            output: output.map(|messages| self.limit_field(Self::serialize_messages(messages))),
             sessionId: None,
             release: None,
             version: None,
-            metadata: if metadata.is_empty() {
-                None
-            } else {
-                Some(json!(metadata))
-            },
-            tags: None,
+            metadata: Self::merge_trace_metadata(conversation_id, trace_metadata),
+            tags: tags.map(<[String]>::to_vec),
             environment: None,
             public: None,
         };
@@ -188,7 +535,8 @@ impl LangfuseService for LangfuseServiceImpl {
         trace_id: &str,
         name: &str,
         model: &str,
-        input: &[OpenAIMessage],
+        input: &[Message],
+        metadata: Option<serde_json::Value>,
     ) -> Result<String, Error> {
         let generation_id = Uuid::new_v4().to_string();
 
@@ -198,9 +546,9 @@ impl LangfuseService for LangfuseServiceImpl {
             name: Some(name.to_string()),
             startTime: Some(chrono::Utc::now().to_rfc3339()),
             endTime: None,
-            input: Some(Self::serialize_messages(input)),
+            input: Some(self.limit_field(Self::serialize_messages(input))),
             output: None, // Will be set on update
-            metadata: None,
+            metadata,
             level: None,
             statusMessage: None,
             parentObservationId: None,
@@ -238,7 +586,7 @@ impl LangfuseService for LangfuseServiceImpl {
             id: generation_id.to_string(),
             endTime: Some(chrono::Utc::now().to_rfc3339()),
             input: None,
-            output: Some(serde_json::to_value(output)?),
+            output: Some(self.limit_field(serde_json::to_value(output)?)),
             metadata: None,
             level: None,
             statusMessage: None,
@@ -269,7 +617,8 @@ impl LangfuseService for LangfuseServiceImpl {
         &self,
         trace_id: &str,
         name: &str,
-        input: Option<&[OpenAIMessage]>,
+        input: Option<&[Message]>,
+        metadata: Option<serde_json::Value>,
     ) -> Result<String, Error> {
         let span_id = Uuid::new_v4().to_string();
 
@@ -279,9 +628,9 @@ impl LangfuseService for LangfuseServiceImpl {
             name: Some(name.to_string()),
             startTime: Some(chrono::Utc::now().to_rfc3339()),
             endTime: None,
-            input: input.map(Self::serialize_messages),
+            input: input.map(|messages| self.limit_field(Self::serialize_messages(messages))),
             output: None, // Will be set on update
-            metadata: None,
+            metadata,
             level: None,
             statusMessage: None,
             parentObservationId: None,
@@ -300,12 +649,12 @@ impl LangfuseService for LangfuseServiceImpl {
         Ok(span_id)
     }
 
-    async fn update_span(&self, span_id: &str, output: &[OpenAIMessage]) -> Result<(), Error> {
+    async fn update_span(&self, span_id: &str, output: &[Message]) -> Result<(), Error> {
         let body = SpanUpdateBody {
             id: span_id.to_string(),
             endTime: Some(chrono::Utc::now().to_rfc3339()),
             input: None,
-            output: Some(Self::serialize_messages(output)),
+            output: Some(self.limit_field(Self::serialize_messages(output))),
             metadata: None,
             level: None,
             statusMessage: None,
@@ -321,4 +670,52 @@ impl LangfuseService for LangfuseServiceImpl {
         self.send_batch(batch).await?;
         Ok(())
     }
+
+    async fn create_event(
+        &self,
+        trace_id: &str,
+        name: &str,
+        input: Option<serde_json::Value>,
+        output: Option<serde_json::Value>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<String, Error> {
+        let (event_id, event) = self.event_create(trace_id, name, input, output, metadata);
+
+        let batch = IngestionBatch {
+            batch: vec![event],
+            metadata: None,
+        };
+
+        self.send_batch(batch).await?;
+        Ok(event_id)
+    }
+
+    async fn update_trace(
+        &self,
+        trace_id: &str,
+        output: Option<&[Message]>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        let body = TraceUpdateBody {
+            id: trace_id.to_string(),
+            output: output.map(|messages| self.limit_field(Self::serialize_messages(messages))),
+            metadata,
+            ..Default::default()
+        };
+
+        let event = IngestionEvent::trace_update(Self::create_base_event(), body);
+
+        let batch = IngestionBatch {
+            batch: vec![event],
+            metadata: None,
+        };
+
+        self.send_batch(batch).await?;
+        Ok(())
+    }
+
+    async fn update_trace_usage(&self, trace_id: &str, usage: &TraceUsage) -> Result<(), Error> {
+        self.update_trace(trace_id, None, Some(usage.to_metadata()))
+            .await
+    }
 }
@@ -0,0 +1,151 @@
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    langfuse::{
+        service::LangfuseServiceImpl,
+        types::{IngestionBatch, IngestionEvent, SpanCreateBody, SpanUpdateBody},
+    },
+    openai::Message,
+};
+
+/// A span added to a [`SpanTreeBuilder`], to attach children to or complete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanHandle {
+    id: String,
+}
+
+impl SpanHandle {
+    /// Langfuse observation id of the span
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Collects the spans of a trace and sends them in one ingestion request,
+/// created with [`LangfuseServiceImpl::create_span_tree`].
+///
+/// Start and end times are taken when [`SpanTreeBuilder::root`],
+/// [`SpanTreeBuilder::child`] and [`SpanTreeBuilder::complete`] are called,
+/// so the tree shows the real timing of each step even though nothing
+/// reaches Langfuse before [`SpanTreeBuilder::submit`].
+///
+/// ```no_run
+/// use ai_utils::langfuse::{LangfuseConfig, LangfuseServiceImpl};
+/// use ai_utils::openai::Message;
+///
+/// # async fn run() -> Result<(), ai_utils::Error> {
+/// let service = LangfuseServiceImpl::new(LangfuseConfig::new());
+/// let mut tree = service.create_span_tree("trace-id");
+/// let pipeline = tree.root("rag", Some(&[Message::user("What is Qdrant?")]));
+/// let retrieval = tree.child(&pipeline, "retrieval");
+/// tree.complete(&retrieval, None);
+/// let generation = tree.child(&pipeline, "generation");
+/// tree.complete(&generation, Some(&[Message::assistant("A vector database.")]));
+/// tree.complete(&pipeline, None);
+/// tree.submit().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SpanTreeBuilder<'a> {
+    service: &'a LangfuseServiceImpl,
+    trace_id: String,
+    events: Vec<IngestionEvent>,
+}
+
+impl<'a> SpanTreeBuilder<'a> {
+    pub(super) fn new(service: &'a LangfuseServiceImpl, trace_id: impl Into<String>) -> Self {
+        Self {
+            service,
+            trace_id: trace_id.into(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Trace the spans belong to
+    #[must_use]
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Start a top-level span of the trace
+    pub fn root(&mut self, name: &str, input: Option<&[Message]>) -> SpanHandle {
+        self.start(name, input, None)
+    }
+
+    /// Start a span nested under `parent`
+    pub fn child(&mut self, parent: &SpanHandle, name: &str) -> SpanHandle {
+        self.start(name, None, Some(parent))
+    }
+
+    /// End the span of `handle`, recording `output` if given
+    pub fn complete(&mut self, handle: &SpanHandle, output: Option<&[Message]>) {
+        let body = SpanUpdateBody {
+            id: handle.id.clone(),
+            endTime: Some(chrono::Utc::now().to_rfc3339()),
+            input: None,
+            output: output.map(|messages| {
+                self.service
+                    .limit_field(LangfuseServiceImpl::serialize_messages(messages))
+            }),
+            metadata: None,
+            level: None,
+            statusMessage: None,
+        };
+        self.events.push(IngestionEvent::span_update(
+            LangfuseServiceImpl::create_base_event(),
+            body,
+        ));
+    }
+
+    fn start(
+        &mut self,
+        name: &str,
+        input: Option<&[Message]>,
+        parent: Option<&SpanHandle>,
+    ) -> SpanHandle {
+        let id = Uuid::new_v4().to_string();
+        let body = SpanCreateBody {
+            id: Some(id.clone()),
+            traceId: self.trace_id.clone(),
+            name: Some(name.to_string()),
+            startTime: Some(chrono::Utc::now().to_rfc3339()),
+            endTime: None,
+            input: input.map(|messages| {
+                self.service
+                    .limit_field(LangfuseServiceImpl::serialize_messages(messages))
+            }),
+            output: None,
+            metadata: None,
+            level: None,
+            statusMessage: None,
+            parentObservationId: parent.map(|parent| parent.id.clone()),
+            version: None,
+            environment: None,
+        };
+        self.events.push(IngestionEvent::span_create(
+            LangfuseServiceImpl::create_base_event(),
+            body,
+        ));
+        SpanHandle { id }
+    }
+
+    /// Send every recorded span in a single batch. Sends nothing if no span
+    /// was added.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`LangfuseServiceImpl::send_batch`].
+    pub async fn submit(self) -> Result<(), Error> {
+        if self.events.is_empty() {
+            return Ok(());
+        }
+        let batch = IngestionBatch {
+            batch: self.events,
+            metadata: None,
+        };
+        self.service.send_batch(batch).await?;
+        Ok(())
+    }
+}
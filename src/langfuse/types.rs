@@ -1,5 +1,6 @@
-use crate::openai::OpenAIMessage;
+use crate::{common::join_url, openai::OpenAIMessage};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Serialize)]
@@ -11,10 +12,21 @@ pub struct LangfuseTrace {
     pub conversation_id: String,
 }
 
+pub const DEFAULT_INGESTION_PATH: &str = "/api/public/ingestion";
+
+#[derive(Clone)]
 pub struct LangfuseConfig {
     pub public_key: String,
     pub secret_key: String,
     pub api_url: String,
+    /// Strings in trace inputs/outputs longer than this many characters are
+    /// cut down before sending. `None` sends everything verbatim.
+    pub max_field_length: Option<usize>,
+    /// Gzip ingestion request bodies. On by default; turned off for the rest
+    /// of the service's life if the server rejects a compressed request.
+    pub compress_requests: bool,
+    /// Path of the ingestion endpoint relative to `api_url`
+    pub ingestion_path: String,
 }
 
 impl LangfuseConfig {
@@ -26,6 +38,61 @@ impl LangfuseConfig {
                 .expect("LANGFUSE_SECRET_KEY must be set"),
             api_url: std::env::var("LANGFUSE_HOST")
                 .unwrap_or_else(|_| "https://cloud.langfuse.com".to_string()),
+            max_field_length: std::env::var("LANGFUSE_MAX_FIELD_LENGTH")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            compress_requests: true,
+            ingestion_path: std::env::var("LANGFUSE_INGESTION_PATH")
+                .unwrap_or_else(|_| DEFAULT_INGESTION_PATH.to_string()),
+        }
+    }
+
+    /// Truncate input/output strings longer than `max_len` characters
+    #[must_use]
+    pub const fn with_max_field_length(mut self, max_len: usize) -> Self {
+        self.max_field_length = Some(max_len);
+        self
+    }
+
+    #[must_use]
+    pub const fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress_requests = enabled;
+        self
+    }
+
+    /// Send batches to `path` instead of `/api/public/ingestion`, e.g. when
+    /// Langfuse is served behind a path prefix
+    #[must_use]
+    pub fn with_ingestion_path(mut self, path: impl Into<String>) -> Self {
+        self.ingestion_path = path.into();
+        self
+    }
+
+    /// Full ingestion endpoint URL, with exactly one `/` between `api_url`
+    /// and `ingestion_path`
+    #[must_use]
+    pub fn ingestion_url(&self) -> String {
+        join_url(&self.api_url, &self.ingestion_path)
+    }
+}
+
+/// HTTP client settings for [`LangfuseServiceImpl`](super::LangfuseServiceImpl)
+#[derive(Debug, Clone, Copy)]
+pub struct LangfuseClientConfig {
+    /// Time allowed to establish a connection
+    pub connect_timeout: Duration,
+    /// Time allowed for a whole request, including reading the response
+    pub request_timeout: Duration,
+    /// Extra attempts after a timeout, connection failure, 429 or 5xx
+    pub max_retries: u32,
+}
+
+impl Default for LangfuseClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            max_retries: 3,
         }
     }
 }
@@ -59,6 +126,13 @@ pub enum IngestionEvent {
         base: BaseEvent,
         body: TraceBody,
     },
+    TraceUpdate {
+        #[serde(rename = "type")]
+        event_type: String,
+        #[serde(flatten)]
+        base: BaseEvent,
+        body: TraceUpdateBody,
+    },
     ScoreCreate {
         #[serde(rename = "type")]
         event_type: String,
@@ -134,6 +208,18 @@ impl IngestionEvent {
         }
     }
 
+    /// Update fields of an existing trace. Langfuse has no separate
+    /// trace-update event: a `trace-create` for an existing id overwrites only
+    /// the fields it sets, so this is sent as one with the id required.
+    #[must_use]
+    pub fn trace_update(base: BaseEvent, body: TraceUpdateBody) -> Self {
+        Self::TraceUpdate {
+            event_type: "trace-create".to_string(),
+            base,
+            body,
+        }
+    }
+
     pub fn score_create(base: BaseEvent, body: ScoreBody) -> Self {
         Self::ScoreCreate {
             event_type: "score-create".to_string(),
@@ -238,6 +324,29 @@ pub struct TraceBody {
     pub public: Option<bool>,
 }
 
+/// Fields to change on an existing trace; unset fields are left as they are
+#[derive(Debug, Serialize, Default)]
+#[allow(non_snake_case)]
+pub struct TraceUpdateBody {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userId: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessionId: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public: Option<bool>,
+}
+
 #[derive(Debug, Serialize)]
 #[allow(non_snake_case)]
 pub struct ScoreBody {
@@ -0,0 +1,164 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::openai::{ChatCompletion, Usage};
+
+/// USD price of a model per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPrice {
+    #[must_use]
+    pub const fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self {
+            input_per_million,
+            output_per_million,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn cost(&self, totals: &UsageTotals) -> f64 {
+        (totals.prompt_tokens as f64).mul_add(
+            self.input_per_million,
+            totals.completion_tokens as f64 * self.output_per_million,
+        ) / 1_000_000.0
+    }
+}
+
+/// Token counts summed over generations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UsageTotals {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub generations: u64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, usage: &Usage) {
+        self.prompt_tokens += u64::from(usage.prompt_tokens);
+        self.completion_tokens += u64::from(usage.completion_tokens);
+        self.total_tokens += u64::from(usage.total_tokens);
+        self.generations += 1;
+    }
+}
+
+/// Client-side accumulator of token usage and estimated cost across the
+/// generations of a trace.
+///
+/// Record each completion as it is passed to
+/// [`LangfuseService::update_generation`](super::LangfuseService::update_generation),
+/// then attach the totals with
+/// [`LangfuseService::update_trace_usage`](super::LangfuseService::update_trace_usage).
+/// Prices are not built in; set them with [`TraceUsage::with_price`].
+#[derive(Debug, Clone, Default)]
+pub struct TraceUsage {
+    totals: UsageTotals,
+    by_model: BTreeMap<String, UsageTotals>,
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl TraceUsage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Price for `model` and its dated snapshots, e.g. `"gpt-4o"` also
+    /// prices `"gpt-4o-2024-08-06"` but not `"gpt-4o-mini"`.
+    #[must_use]
+    pub fn with_price(mut self, model: impl Into<String>, price: ModelPrice) -> Self {
+        self.prices.insert(model.into(), price);
+        self
+    }
+
+    /// Add the usage of `completion`; completions without usage still count
+    /// as a generation
+    pub fn record(&mut self, completion: &ChatCompletion) {
+        if let Some(usage) = &completion.usage {
+            self.record_usage(&completion.model, usage);
+        } else {
+            self.totals.generations += 1;
+            self.by_model
+                .entry(completion.model.clone())
+                .or_default()
+                .generations += 1;
+        }
+    }
+
+    pub fn record_usage(&mut self, model: &str, usage: &Usage) {
+        self.totals.add(usage);
+        self.by_model
+            .entry(model.to_string())
+            .or_default()
+            .add(usage);
+    }
+
+    #[must_use]
+    pub const fn totals(&self) -> UsageTotals {
+        self.totals
+    }
+
+    #[must_use]
+    pub const fn by_model(&self) -> &BTreeMap<String, UsageTotals> {
+        &self.by_model
+    }
+
+    fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices
+            .get(model)
+            .or_else(|| self.prices.get(undated(model)?))
+    }
+
+    /// Estimated USD cost of the models that have a price
+    #[must_use]
+    pub fn estimated_cost(&self) -> f64 {
+        self.by_model
+            .iter()
+            .filter_map(|(model, totals)| Some(self.price(model)?.cost(totals)))
+            .sum()
+    }
+
+    /// Models used in the trace that have no price, so are missing from
+    /// [`TraceUsage::estimated_cost`]
+    #[must_use]
+    pub fn unpriced_models(&self) -> Vec<&str> {
+        self.by_model
+            .keys()
+            .filter(|model| self.price(model).is_none())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Trace metadata of the form
+    /// `{"usage": {"prompt_tokens", .., "estimated_cost_usd", "by_model": {..}}}`.
+    /// The cost is left out when no model has a price.
+    #[must_use]
+    pub fn to_metadata(&self) -> serde_json::Value {
+        let mut usage = serde_json::json!(self.totals);
+        usage["by_model"] = serde_json::json!(self.by_model);
+        if self
+            .by_model
+            .keys()
+            .any(|model| self.price(model).is_some())
+        {
+            usage["estimated_cost_usd"] = serde_json::json!(self.estimated_cost());
+        }
+        serde_json::json!({ "usage": usage })
+    }
+}
+
+/// `model` without a snapshot date suffix, e.g. `"gpt-4o"` for
+/// `"gpt-4o-2024-08-06"`
+fn undated(model: &str) -> Option<&str> {
+    let (name, date) = model.split_at_checked(model.len().checked_sub(11)?)?;
+    let date = date.as_bytes();
+    let is_date = date.iter().enumerate().all(|(i, byte)| match i {
+        0 | 5 | 8 => *byte == b'-',
+        _ => byte.is_ascii_digit(),
+    });
+    is_date.then_some(name)
+}
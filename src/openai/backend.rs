@@ -0,0 +1,160 @@
+use std::time::Instant;
+
+use async_openai::{config::OpenAIConfig, types::embeddings::CreateEmbeddingRequestArgs, Client};
+use async_trait::async_trait;
+
+use super::{
+    cache::CachedOpenAIService,
+    service::{api_error, log_embedding_usage, OpenAIService},
+    types::{ChatCompletion, ChatOptions, Message},
+};
+use crate::error::Error;
+
+/// Chat half of a provider, for code that only sends chat requests.
+///
+/// Embeddings go through [`EmbeddingService`](crate::qdrant::EmbeddingService)
+/// instead, so chat and embeddings can come from different providers: chat
+/// through one backend and embed with an [`OpenAICompatibleEmbedder`] pointed
+/// at a local model, handing that embedder to
+/// [`QdrantService::with_embedder`](crate::qdrant::QdrantService::with_embedder).
+#[async_trait]
+pub trait ChatBackend: Send + Sync {
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatCompletion, Error>;
+}
+
+#[async_trait]
+impl ChatBackend for OpenAIService {
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatCompletion, Error> {
+        Self::chat(self, messages, options).await
+    }
+}
+
+#[async_trait]
+impl ChatBackend for CachedOpenAIService {
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+    ) -> Result<ChatCompletion, Error> {
+        self.inner().chat(messages, options).await
+    }
+}
+
+/// Embedding client for any endpoint serving the `OpenAI` embeddings API,
+/// such as Ollama, vLLM or LM Studio, configured independently of the chat
+/// provider.
+///
+/// ```no_run
+/// use ai_utils::openai::OpenAICompatibleEmbedder;
+///
+/// # async fn run() -> Result<(), ai_utils::Error> {
+/// let embedder = OpenAICompatibleEmbedder::new("http://localhost:11434/v1", "nomic-embed-text");
+/// let vector = embedder.embed("hello".to_string()).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct OpenAICompatibleEmbedder {
+    client: Client<OpenAIConfig>,
+    model: String,
+    dimensions: Option<u32>,
+}
+
+impl OpenAICompatibleEmbedder {
+    /// Embed with `model` at `api_base`, the URL the `/embeddings` path is
+    /// appended to. Requests carry no API key, which suits local servers;
+    /// hosted ones need [`OpenAICompatibleEmbedder::with_api_key`].
+    /// `OPENAI_API_KEY` is never read, so it is not sent to other endpoints.
+    #[must_use]
+    pub fn new(api_base: impl Into<String>, model: impl Into<String>) -> Self {
+        let config = OpenAIConfig::new().with_api_key("").with_api_base(api_base);
+        Self {
+            client: Client::with_config(config),
+            model: model.into(),
+            dimensions: None,
+        }
+    }
+
+    /// Send `api_key` as a bearer token, as hosted endpoints require
+    #[must_use]
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        let config = self.client.config().clone().with_api_key(api_key);
+        self.client = Client::with_config(config);
+        self
+    }
+
+    /// Ask for vectors of `dimensions` entries, for models that can shorten
+    /// their output
+    #[must_use]
+    pub const fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Name of the embedding model
+    #[must_use]
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if `text` is blank or the request fails.
+    pub async fn embed(&self, text: String) -> Result<Vec<f32>, Error> {
+        if text.trim().is_empty() {
+            return Err(Error::OpenAIValidation(
+                "Text for embedding cannot be empty".to_string(),
+            ));
+        }
+
+        let mut embeddings = self.request(vec![text]).await?;
+        embeddings
+            .pop()
+            .ok_or_else(|| Error::Other("Embedding response has no data".to_string()))
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if `texts` is empty or the request fails.
+    pub async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        if texts.is_empty() {
+            return Err(Error::OpenAIValidation(
+                "Texts for batch embedding cannot be empty".to_string(),
+            ));
+        }
+
+        self.request(texts).await
+    }
+
+    async fn request(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        let mut args = CreateEmbeddingRequestArgs::default();
+        args.model(self.model.clone()).input(texts);
+        if let Some(dimensions) = self.dimensions {
+            args.dimensions(dimensions);
+        }
+
+        let started = Instant::now();
+        let response = self
+            .client
+            .embeddings()
+            .create(args.build()?)
+            .await
+            .map_err(api_error)?;
+        log_embedding_usage(&response, started);
+
+        let mut data = response.data;
+        data.sort_by_key(|embedding| embedding.index);
+        Ok(data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+}
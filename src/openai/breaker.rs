@@ -0,0 +1,163 @@
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use async_openai::error::OpenAIError;
+use tracing::warn;
+
+use crate::error::Error;
+
+/// State of a [`CircuitBreaker`], e.g. for a health endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed { consecutive_failures: u32 },
+    /// Calls fail fast with [`Error::CircuitOpen`] until the cooldown ends
+    Open { retry_after: Duration },
+    /// The cooldown ended; one probe call goes through and its result
+    /// closes or reopens the circuit
+    HalfOpen,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// When the half-open probe was let through, if it has not resolved
+    probe_started: Option<Instant>,
+}
+
+/// Stops calls to a provider during a sustained outage, attached with
+/// [`OpenAIService::with_circuit_breaker`](super::OpenAIService::with_circuit_breaker).
+///
+/// After `failure_threshold` consecutive failed calls the circuit opens and
+/// every call fails with [`Error::CircuitOpen`] without a request for
+/// `cooldown`. Then a single probe call goes through while the others keep
+/// failing fast: a success closes the circuit, a failure opens it for another
+/// cooldown. A probe that does not resolve within `cooldown`, e.g. because
+/// it was cancelled, is replaced by the next call. Only provider failures
+/// count: network errors, rate limits and server errors, not rejected
+/// requests, unparseable responses or local validation.
+///
+/// Share one breaker across services, or clones of one service, to trip
+/// them together.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    /// A closed breaker that opens after `failure_threshold` consecutive
+    /// failures, at least 1, for `cooldown`
+    #[must_use]
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[must_use]
+    pub fn state(&self) -> CircuitState {
+        self.state_of(&self.lock())
+    }
+
+    fn state_of(&self, state: &BreakerState) -> CircuitState {
+        let BreakerState {
+            consecutive_failures,
+            opened_at,
+            ..
+        } = *state;
+        let Some(opened_at) = opened_at else {
+            return CircuitState::Closed {
+                consecutive_failures,
+            };
+        };
+        match self.cooldown.checked_sub(opened_at.elapsed()) {
+            Some(retry_after) if !retry_after.is_zero() => CircuitState::Open { retry_after },
+            _ => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Fail fast while the circuit is open, letting one probe call through
+    /// once the cooldown ends
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CircuitOpen`] until the cooldown ends, and afterwards
+    /// while a probe call is in flight, with a zero `retry_after`.
+    pub fn check(&self) -> Result<(), Error> {
+        let mut state = self.lock();
+        let result = match self.state_of(&state) {
+            CircuitState::Open { retry_after } => Err(Error::CircuitOpen { retry_after }),
+            CircuitState::HalfOpen
+                if state
+                    .probe_started
+                    .is_some_and(|started| started.elapsed() < self.cooldown) =>
+            {
+                Err(Error::CircuitOpen {
+                    retry_after: Duration::ZERO,
+                })
+            }
+            CircuitState::HalfOpen => {
+                state.probe_started = Some(Instant::now());
+                Ok(())
+            }
+            CircuitState::Closed { .. } => Ok(()),
+        };
+        drop(state);
+        result
+    }
+
+    /// Count the outcome of a call made after [`CircuitBreaker::check`]
+    pub fn record<T>(&self, result: &Result<T, Error>) {
+        match result {
+            Ok(_) => self.record_success(),
+            Err(err) if is_provider_failure(err) => self.record_failure(),
+            // The next call probes again
+            Err(_) => self.lock().probe_started = None,
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.lock() = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.lock();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.opened_at.is_some() || state.consecutive_failures >= self.failure_threshold {
+            warn!(
+                failures = state.consecutive_failures,
+                cooldown = ?self.cooldown,
+                "Opening circuit breaker"
+            );
+            state.opened_at = Some(Instant::now());
+        }
+        state.probe_started = None;
+    }
+}
+
+/// Whether `err` says the provider is unavailable rather than the request
+/// being wrong. API errors must have gone through
+/// [`api_error`](super::service::api_error), which leaves only transport
+/// failures and unparseable responses as [`Error::OpenAI`].
+fn is_provider_failure(err: &Error) -> bool {
+    match err {
+        Error::OpenAIRateLimited { .. }
+        | Error::OpenAI(OpenAIError::Reqwest(_) | OpenAIError::StreamError(_)) => true,
+        // 5xx bodies are not parsed, so their errors have no type
+        Error::OpenAIStructured(detail) => {
+            matches!(detail.error_type.as_str(), "server_error" | "")
+        }
+        _ => false,
+    }
+}
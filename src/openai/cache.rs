@@ -0,0 +1,173 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use lru::LruCache;
+
+use crate::{
+    error::Error,
+    openai::{
+        service::{AIService, OpenAIService},
+        types::{ChatCompletion, Message, OpenAIModel, TtsVoice},
+    },
+};
+
+/// Hit, miss and eviction counts of an embedding cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Embeddings keyed by (model name, text) with least-recently-used eviction
+pub struct EmbeddingCache {
+    entries: LruCache<(String, String), Vec<f32>>,
+    stats: CacheStats,
+}
+
+impl EmbeddingCache {
+    /// A capacity of 0 is treated as 1
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn get(&mut self, model: &str, text: &str) -> Option<Vec<f32>> {
+        let key = (model.to_string(), text.to_string());
+        let cached = self.entries.get(&key).cloned();
+        if cached.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        cached
+    }
+
+    pub fn insert(&mut self, model: &str, text: String, embedding: Vec<f32>) {
+        let key = (model.to_string(), text);
+        if let Some((evicted, _)) = self.entries.push(key.clone(), embedding) {
+            // `push` also returns the old value when replacing the same key
+            if evicted != key {
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    pub const fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+/// [`OpenAIService`] that remembers embeddings, created with
+/// [`OpenAIService::with_embedding_cache`].
+///
+/// Only `embed` and `embed_batch` are cached; every other call goes straight
+/// to the wrapped service. Clones share the cache.
+#[derive(Clone)]
+pub struct CachedOpenAIService {
+    inner: OpenAIService,
+    cache: Arc<Mutex<EmbeddingCache>>,
+}
+
+impl CachedOpenAIService {
+    fn cache(&self) -> MutexGuard<'_, EmbeddingCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts since the cache was created
+    #[must_use]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache().stats()
+    }
+
+    /// The wrapped, uncached service
+    #[must_use]
+    pub const fn inner(&self) -> &OpenAIService {
+        &self.inner
+    }
+}
+
+impl OpenAIService {
+    /// Wrap this service with an LRU cache holding up to `capacity` embeddings
+    #[must_use]
+    pub fn with_embedding_cache(self, capacity: usize) -> CachedOpenAIService {
+        CachedOpenAIService {
+            inner: self,
+            cache: Arc::new(Mutex::new(EmbeddingCache::new(capacity))),
+        }
+    }
+}
+
+#[async_trait]
+impl AIService for CachedOpenAIService {
+    async fn completion(
+        &self,
+        messages: Vec<Message>,
+        model: OpenAIModel,
+    ) -> Result<ChatCompletion, Error> {
+        AIService::completion(&self.inner, messages, model).await
+    }
+
+    async fn generate_image_url(&self, prompt: String) -> Result<String, Error> {
+        AIService::generate_image_url(&self.inner, prompt).await
+    }
+
+    async fn transcribe(&self, audio: Vec<u8>) -> Result<String, Error> {
+        AIService::transcribe(&self.inner, audio).await
+    }
+
+    async fn synthesize(&self, text: String, voice: TtsVoice) -> Result<Vec<u8>, Error> {
+        AIService::synthesize(&self.inner, text, voice).await
+    }
+
+    async fn embed(&self, text: String) -> Result<Vec<f32>, Error> {
+        let model = self.inner.embedding_model().to_string();
+        let cached = self.cache().get(&model, &text);
+        if let Some(embedding) = cached {
+            return Ok(embedding);
+        }
+
+        let embedding = AIService::embed(&self.inner, text.clone()).await?;
+        self.cache().insert(&model, text, embedding.clone());
+        Ok(embedding)
+    }
+
+    async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        let model = self.inner.embedding_model().to_string();
+        let mut embeddings: Vec<Option<Vec<f32>>> = {
+            let mut cache = self.cache();
+            texts.iter().map(|text| cache.get(&model, text)).collect()
+        };
+
+        let missing: Vec<usize> = (0..texts.len())
+            .filter(|&index| embeddings[index].is_none())
+            .collect();
+        if !missing.is_empty() {
+            let fetched = AIService::embed_batch(
+                &self.inner,
+                missing.iter().map(|&index| texts[index].clone()).collect(),
+            )
+            .await?;
+
+            for (index, embedding) in missing.into_iter().zip(fetched) {
+                self.cache()
+                    .insert(&model, texts[index].clone(), embedding.clone());
+                embeddings[index] = Some(embedding);
+            }
+        }
+
+        embeddings
+            .into_iter()
+            .map(|embedding| {
+                embedding.ok_or_else(|| {
+                    Error::Other("Embedding response is missing an input".to_string())
+                })
+            })
+            .collect()
+    }
+}
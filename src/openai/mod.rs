@@ -1,8 +1,997 @@
+mod backend;
+mod breaker;
+mod cache;
+#[cfg(feature = "realtime")]
+mod realtime;
 mod service;
 mod types;
 
+pub use backend::{ChatBackend, OpenAICompatibleEmbedder};
+pub use breaker::{CircuitBreaker, CircuitState};
+pub use cache::{CacheStats, CachedOpenAIService};
+#[cfg(feature = "realtime")]
+pub use realtime::{RealtimeEvent, RealtimeSession, REALTIME_URL};
 pub use service::*;
 pub use types::*;
 
 // Re-export the new unified types for convenience
 pub use types::{ContentPart, ImageUrl, Message, MessageContent, MessageRole};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_server::{serve_once, MockResponse};
+
+    #[test]
+    fn test_message_to_openai_message() {
+        let message = Message::user("Describe this").with_name("alice");
+        let legacy = OpenAIMessage::from(&message);
+        assert_eq!(legacy.role, "user");
+        assert_eq!(legacy.content, "Describe this");
+        assert_eq!(legacy.name.as_deref(), Some("alice"));
+
+        let message = Message::with_images(
+            "What is in these images?",
+            vec![
+                ImageUrl::from_url("https://example.com/cat.png", None),
+                ImageUrl::from_base64("aGVsbG8=", Some(ImageDetail::High)),
+            ],
+        );
+        let legacy = OpenAIMessage::from(&message);
+        assert_eq!(
+            legacy.content,
+            "What is in these images?\n[image: https://example.com/cat.png]\n[image: image/png data URI]"
+        );
+        assert!(!legacy.content.contains("aGVsbG8="));
+    }
+
+    /// Capability matrix for every built-in model. The `supports_*` methods use
+    /// exhaustive matches, so a new variant fails to compile until it is
+    /// classified there; add it to this table as well.
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn test_all_model_capabilities() {
+        // (model, chat, vision, audio, transcription, embeddings, speech, max_tokens)
+        let matrix = [
+            (
+                OpenAIModel::Gpt4o,
+                true,
+                true,
+                true,
+                false,
+                false,
+                false,
+                Some(128_000),
+            ),
+            (
+                OpenAIModel::Gpt4oMini,
+                true,
+                false,
+                true,
+                false,
+                false,
+                false,
+                Some(128_000),
+            ),
+            (
+                OpenAIModel::Gpt41,
+                true,
+                false,
+                false,
+                false,
+                false,
+                false,
+                Some(128_000),
+            ),
+            (
+                OpenAIModel::Gpt4oTranscribe,
+                false,
+                false,
+                false,
+                true,
+                false,
+                false,
+                None,
+            ),
+            (
+                OpenAIModel::TextEmbedding3Large,
+                false,
+                false,
+                false,
+                false,
+                true,
+                false,
+                None,
+            ),
+            (
+                OpenAIModel::Tts1,
+                false,
+                false,
+                false,
+                false,
+                false,
+                true,
+                None,
+            ),
+            (
+                OpenAIModel::Tts1Hd,
+                false,
+                false,
+                false,
+                false,
+                false,
+                true,
+                None,
+            ),
+        ];
+
+        for (model, chat, vision, audio, transcription, embeddings, speech, max_tokens) in matrix {
+            assert_eq!(model.supports_chat(), chat, "{model} chat");
+            assert_eq!(model.supports_vision(), vision, "{model} vision");
+            assert_eq!(model.supports_audio_input(), audio, "{model} audio");
+            assert_eq!(
+                model.supports_transcription(),
+                transcription,
+                "{model} transcription"
+            );
+            assert_eq!(
+                model.supports_embeddings(),
+                embeddings,
+                "{model} embeddings"
+            );
+            assert_eq!(model.supports_speech(), speech, "{model} speech");
+            assert_eq!(model.max_tokens(), max_tokens, "{model} max_tokens");
+
+            for (operation, supported) in [
+                ("chat", chat),
+                ("vision", vision),
+                ("audio", audio),
+                ("transcription", transcription),
+                ("embeddings", embeddings),
+                ("speech", speech),
+            ] {
+                assert_eq!(
+                    model.validate_operation(operation).is_ok(),
+                    supported,
+                    "{model} {operation}"
+                );
+            }
+            assert!(model.validate_operation("unknown").is_err());
+        }
+    }
+
+    #[test]
+    fn test_model_deprecation() {
+        for model in [
+            OpenAIModel::Gpt4o,
+            OpenAIModel::Gpt4oMini,
+            OpenAIModel::Gpt41,
+            OpenAIModel::Gpt4oTranscribe,
+            OpenAIModel::TextEmbedding3Large,
+            OpenAIModel::Tts1,
+            OpenAIModel::Tts1Hd,
+            OpenAIModel::Custom("my-fine-tune".to_string()),
+        ] {
+            assert!(!model.is_deprecated(), "{model}");
+            assert!(model.recommended_replacement().is_none(), "{model}");
+        }
+    }
+
+    #[test]
+    fn test_supports_multiple_choices() {
+        assert!(OpenAIModel::Gpt4o.supports_multiple_choices());
+        assert!(OpenAIModel::Gpt41.supports_multiple_choices());
+        assert!(!OpenAIModel::Custom("o1-preview".to_string()).supports_multiple_choices());
+
+        let (_, options) = ChatRequestBuilder::new(OpenAIModel::Gpt4oMini).n(3).build();
+        assert_eq!(options.n, Some(3));
+    }
+
+    #[test]
+    fn test_embedding_cache() {
+        let mut cache = cache::EmbeddingCache::new(2);
+        let large = OpenAIModel::TextEmbedding3Large.to_string();
+        let small = "text-embedding-3-small".to_string();
+
+        assert_eq!(cache.get(&large, "a"), None);
+        cache.insert(&large, "a".to_string(), vec![1.0]);
+        assert_eq!(cache.get(&large, "a"), Some(vec![1.0]));
+        // Same text under another model is a separate entry
+        assert_eq!(cache.get(&small, "a"), None);
+
+        // Replacing an entry is not an eviction
+        cache.insert(&large, "a".to_string(), vec![1.5]);
+        cache.insert(&large, "b".to_string(), vec![2.0]);
+        assert_eq!(cache.stats().evictions, 0);
+
+        // "b" was used longer ago than "a", so it goes first
+        assert_eq!(cache.get(&large, "a"), Some(vec![1.5]));
+        cache.insert(&small, "a".to_string(), vec![3.0]);
+        assert_eq!(cache.get(&large, "b"), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 3,
+                evictions: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_embeddings() {
+        dotenv::dotenv().ok();
+        if std::env::var("OPENAI_API_KEY").is_err() {
+            eprintln!("Skipping test_cached_embeddings: OPENAI_API_KEY not set");
+            return;
+        }
+        let service = OpenAIService::new().unwrap().with_embedding_cache(16);
+
+        let first = service.embed("cached text".to_string()).await.unwrap();
+        let batch = service
+            .embed_batch(vec!["cached text".to_string(), "fresh text".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(batch[0], first);
+        assert_eq!(
+            service.cache_stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_n() {
+        dotenv::dotenv().ok();
+        if std::env::var("OPENAI_API_KEY").is_err() {
+            eprintln!("Skipping test_chat_n: OPENAI_API_KEY not set");
+            return;
+        }
+        let service = OpenAIService::new().unwrap();
+        let options = ChatOptions {
+            model: OpenAIModel::Gpt4oMini,
+            temperature: Some(1.0),
+            max_tokens: Some(20),
+            ..Default::default()
+        };
+
+        let answers = service
+            .chat_n(vec![Message::user("Name a random fruit.")], options, 3)
+            .await
+            .unwrap();
+        assert_eq!(answers.len(), 3);
+    }
+
+    #[test]
+    fn test_with_image_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = dir.path().join("red.png");
+        let webp = dir.path().join("blue.webp");
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(&png)
+            .unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([0, 0, 255]))
+            .save(&webp)
+            .unwrap();
+        let (png, webp) = (png.to_str().unwrap(), webp.to_str().unwrap());
+
+        let message = Message::with_image_paths("Compare these", &[png, webp]).unwrap();
+        let MessageContent::Mixed(parts) = &message.content else {
+            panic!("expected mixed content");
+        };
+        assert_eq!(parts.len(), 3);
+        assert!(
+            matches!(&parts[1], ContentPart::Image(image) if image.url.starts_with("data:image/png;base64,"))
+        );
+        assert!(
+            matches!(&parts[2], ContentPart::Image(image) if image.url.starts_with("data:image/webp;base64,"))
+        );
+        assert!(message.validate().is_ok());
+
+        let missing = dir.path().join("missing.png");
+        let err = Message::with_image_paths("x", &[png, missing.to_str().unwrap()]);
+        assert!(matches!(err, Err(crate::Error::Common(_))));
+        assert!(Message::with_image_paths("x", &["notes.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_image_detail_serde() {
+        let image = ImageUrl::from_url("https://example.com/cat.png", Some(ImageDetail::Low));
+        let value = serde_json::to_value(&image).unwrap();
+        assert_eq!(value["detail"], "low");
+        let parsed: ImageUrl = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.detail, Some(ImageDetail::Low));
+
+        let typo = serde_json::json!({"url": "https://example.com/cat.png", "detail": "hight"});
+        assert!(serde_json::from_value::<ImageUrl>(typo).is_err());
+    }
+
+    #[test]
+    fn test_image_url_from_file() {
+        use crate::common::CommonError;
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = dir.path().join("photo.JPG");
+        std::fs::write(&jpeg, b"not decoded").unwrap();
+
+        let image = ImageUrl::from_file(&jpeg).unwrap();
+        assert_eq!(
+            image.url,
+            format!("data:image/jpeg;base64,{}", BASE64.encode(b"not decoded"))
+        );
+        assert!(image.validate().is_ok());
+
+        assert!(matches!(
+            ImageUrl::from_file(&dir.path().join("missing.png")),
+            Err(crate::Error::Common(CommonError::FileRead(_)))
+        ));
+        assert!(matches!(
+            ImageUrl::from_file(std::path::Path::new("notes.txt")),
+            Err(crate::Error::Common(CommonError::InvalidPath(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_audio_message() {
+        let message = Message::with_audio(b"hello".to_vec(), AudioFormat::Mp3);
+        let MessageContent::Audio(audio) = &message.content else {
+            panic!("expected audio content");
+        };
+        assert_eq!(audio.data, "aGVsbG8=");
+        assert_eq!(message.role, MessageRole::User);
+        assert!(message.has_audio());
+        assert!(message.validate().is_ok());
+        assert!(Message::with_audio(Vec::new(), AudioFormat::Wav)
+            .validate()
+            .is_err());
+        assert_eq!(OpenAIMessage::from(&message).content, "[audio: mp3]");
+
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let options = |model| ChatOptions {
+            model,
+            ..Default::default()
+        };
+        let Err(err) = service
+            .chat(vec![message.clone()], options(OpenAIModel::Gpt41))
+            .await
+        else {
+            panic!("gpt-4.1 should reject audio input");
+        };
+        assert!(matches!(
+            err,
+            crate::Error::OpenAIUnsupportedModel { operation, .. } if operation == "audio"
+        ));
+
+        let ogg = Message::with_audio(b"hello".to_vec(), AudioFormat::Ogg);
+        let Err(err) = service.chat(vec![ogg], options(OpenAIModel::Gpt4o)).await else {
+            panic!("ogg should be rejected before sending");
+        };
+        assert!(matches!(
+            err,
+            crate::Error::OpenAIValidation(message) if message.contains("ogg")
+        ));
+    }
+
+    #[test]
+    fn test_tool_result_message() {
+        let message = Message::tool_result("call_1", "{\"temperature\": 21}");
+        assert_eq!(message.role, MessageRole::Tool);
+        assert_eq!(message.tool_call_id.as_deref(), Some("call_1"));
+        assert!(message.validate().is_ok());
+
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let converted =
+            serde_json::to_value(service.convert_message_to_openai(&message).unwrap()).unwrap();
+        assert_eq!(
+            converted,
+            serde_json::json!({
+                "role": "tool",
+                "content": "{\"temperature\": 21}",
+                "tool_call_id": "call_1",
+            })
+        );
+
+        let mut orphan = message;
+        orphan.tool_call_id = None;
+        assert!(orphan.validate().is_err());
+        assert!(service.convert_message_to_openai(&orphan).is_err());
+    }
+
+    #[test]
+    fn test_assistant_prefill() {
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let prefill = Message::assistant("{\"answer\":").with_name("bot");
+        let converted =
+            serde_json::to_value(service.convert_message_to_openai(&prefill).unwrap()).unwrap();
+        assert_eq!(
+            converted,
+            serde_json::json!({"role": "assistant", "content": "{\"answer\":", "name": "bot"})
+        );
+
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1_700_000_000,
+            "model": "gpt-4o-mini",
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": " 42}"}, "finish_reason": "stop"},
+                {"index": 1, "message": {"role": "assistant", "content": "{\"answer\": 7}"}, "finish_reason": "stop"},
+            ],
+        }))
+        .unwrap();
+        let mut completion = service.convert_response_to_chat_completion(response);
+        assert_eq!(completion.choices[0].message.role, MessageRole::Assistant);
+
+        let messages = [Message::user("What is the answer?"), prefill];
+        OpenAIService::apply_prefill(&mut completion, &messages);
+        let texts: Vec<_> = completion
+            .choices
+            .iter()
+            .map(|choice| choice.message.text_content().unwrap())
+            .collect();
+        assert_eq!(texts, ["{\"answer\": 42}", "{\"answer\": 7}"]);
+
+        // Without a trailing assistant message nothing changes
+        OpenAIService::apply_prefill(&mut completion, &messages[..1]);
+        assert_eq!(
+            completion.choices[0].message.text_content(),
+            Some("{\"answer\": 42}")
+        );
+
+        // Prefill is opt-in
+        assert!(!ChatOptions::default().prefill);
+        let (_, options) = ChatRequestBuilder::new(OpenAIModel::Gpt4oMini)
+            .prefill(true)
+            .build();
+        assert!(options.prefill);
+    }
+
+    #[tokio::test]
+    async fn test_stop_sequences() {
+        let (_, options) = ChatRequestBuilder::new(OpenAIModel::Gpt4oMini)
+            .stop_str("\n\n")
+            .build();
+        assert_eq!(options.stop, Some(vec!["\n\n".to_string()]));
+        assert!(options.validate().is_ok());
+        assert!(ChatOptions::default().validate().is_ok());
+
+        let (_, empty) = ChatRequestBuilder::new(OpenAIModel::Gpt4oMini)
+            .stop(vec!["END".to_string(), String::new()])
+            .build();
+        assert!(matches!(
+            empty.validate(),
+            Err(crate::Error::OpenAIValidation(message)) if message.contains("Stop sequence 1")
+        ));
+
+        let (_, too_many) = ChatRequestBuilder::new(OpenAIModel::Gpt4oMini)
+            .stop((0..=MAX_STOP_SEQUENCES).map(|i| i.to_string()).collect())
+            .build();
+        assert!(too_many.validate().is_err());
+
+        // Rejected before anything is sent
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let result = service.chat(vec![Message::user("Hi")], empty).await;
+        assert!(matches!(result, Err(crate::Error::OpenAIValidation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_embedder() {
+        // Out of order, as some servers return them
+        let (addr, server) = serve_once(MockResponse::json(
+            r#"{"object":"list","model":"local-embed","data":[
+                {"object":"embedding","index":1,"embedding":[0.0,1.0]},
+                {"object":"embedding","index":0,"embedding":[1.0,0.0]}],
+                "usage":{"prompt_tokens":2,"total_tokens":2}}"#,
+        ))
+        .await;
+
+        let embedder = OpenAICompatibleEmbedder::new(format!("http://{addr}/v1"), "local-embed")
+            .with_api_key("local")
+            .with_dimensions(2);
+        assert_eq!(embedder.model(), "local-embed");
+        let embeddings = embedder
+            .embed_batch(vec!["first".to_string(), "second".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(embeddings, [[1.0, 0.0], [0.0, 1.0]]);
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/embeddings"));
+        assert!(request.contains(r#""model":"local-embed""#));
+        assert!(request.contains(r#""dimensions":2"#));
+        assert!(request
+            .to_ascii_lowercase()
+            .contains("authorization: bearer local"));
+
+        assert!(embedder.embed("  ".to_string()).await.is_err());
+
+        // Chat and embeddings are configured separately
+        let chat: Box<dyn ChatBackend> = Box::new(OpenAIService::with_config(
+            async_openai::config::OpenAIConfig::new(),
+        ));
+        assert!(chat.chat(Vec::new(), ChatOptions::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_organization_and_project_headers() {
+        let (addr, server) = serve_once(MockResponse::new("audio/mpeg", "ID3audio")).await;
+
+        let service = OpenAIService::with_config(
+            async_openai::config::OpenAIConfig::new().with_api_base(format!("http://{addr}/v1")),
+        )
+        .with_organization("org-billing")
+        .unwrap()
+        .with_project(" proj_search ")
+        .unwrap();
+        service
+            .synthesize("Hello there".to_string(), TtsVoice::Nova)
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.contains("\r\nopenai-organization: org-billing\r\n"));
+        assert!(request.contains("\r\nopenai-project: proj_search\r\n"));
+
+        for id in ["", "proj\nsearch"] {
+            let result = OpenAIService::with_config(async_openai::config::OpenAIConfig::new())
+                .with_project(id);
+            assert!(matches!(result, Err(crate::Error::Config(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_speech() {
+        let (addr, server) = serve_once(MockResponse::new("audio/mpeg", "ID3audio")).await;
+
+        let service = OpenAIService::with_config(
+            async_openai::config::OpenAIConfig::new().with_api_base(format!("http://{addr}/v1")),
+        )
+        .with_speech_model(OpenAIModel::Tts1Hd)
+        .unwrap();
+        assert!(matches!(service.speech_model(), OpenAIModel::Tts1Hd));
+        let audio = service
+            .synthesize_speech("Hello there", TtsVoice::Nova, SpeechFormat::Opus)
+            .await
+            .unwrap();
+        assert_eq!(audio, b"ID3audio");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/audio/speech"));
+        assert!(request.contains(r#""model":"tts-1-hd""#));
+        assert!(request.contains(r#""voice":"nova""#));
+        assert!(request.contains(r#""response_format":"opus""#));
+
+        assert!(matches!(
+            service.clone().with_speech_model(OpenAIModel::Gpt4o),
+            Err(crate::Error::OpenAIUnsupportedModel { .. })
+        ));
+
+        // Invalid input is rejected before any request
+        for text in [String::new(), "x".repeat(MAX_SPEECH_INPUT_CHARS + 1)] {
+            let result = service.synthesize(text, TtsVoice::Alloy).await;
+            assert!(matches!(result, Err(crate::Error::OpenAIValidation(_))));
+        }
+        assert_eq!(
+            serde_json::to_string(&SpeechFormat::Pcm).unwrap(),
+            r#""pcm""#
+        );
+    }
+
+    /// Layer keeping the fields and level of each event
+    type RecordedEvent = (tracing::Level, std::collections::HashMap<String, String>);
+
+    #[derive(Clone, Default)]
+    struct RecordedEvents(std::sync::Arc<std::sync::Mutex<Vec<RecordedEvent>>>);
+
+    struct EventFields(std::collections::HashMap<String, String>);
+
+    impl tracing::field::Visit for EventFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RecordedEvents {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = EventFields(std::collections::HashMap::new());
+            event.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_events() {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let (addr, server) = serve_once(MockResponse::json(
+            r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,
+                "model":"gpt-4o-2024-08-06","choices":[{"index":0,"finish_reason":"stop",
+                "message":{"role":"assistant","content":"Hi"}}],
+                "usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#,
+        ))
+        .await;
+
+        let recorded = RecordedEvents::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorded.clone()));
+        let service = OpenAIService::with_config(
+            async_openai::config::OpenAIConfig::new().with_api_base(format!("http://{addr}/v1")),
+        );
+        let options = ChatOptions {
+            model: OpenAIModel::Gpt4o,
+            ..Default::default()
+        };
+        service
+            .chat(vec![Message::user("Hello")], options)
+            .await
+            .unwrap();
+
+        assert!(server
+            .await
+            .unwrap()
+            .starts_with("POST /v1/chat/completions"));
+
+        let events = recorded.0.lock().unwrap().clone();
+        let (level, fields) = events
+            .iter()
+            .find(|(_, fields)| fields.contains_key("prompt_tokens"))
+            .unwrap();
+        assert_eq!(*level, tracing::Level::DEBUG);
+        assert_eq!(fields["model"], "gpt-4o-2024-08-06");
+        assert_eq!(fields["prompt_tokens"], "12");
+        assert_eq!(fields["completion_tokens"], "3");
+        assert_eq!(fields["total_tokens"], "15");
+        assert!(fields.contains_key("latency_ms"));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        use std::time::Duration;
+
+        let server_error = || {
+            Err::<(), _>(crate::Error::OpenAIStructured(
+                crate::error::OpenAIErrorDetail {
+                    message: "The server had an error".to_string(),
+                    error_type: "server_error".to_string(),
+                    code: None,
+                    param: None,
+                },
+            ))
+        };
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(50));
+
+        // Rejected requests and unparseable responses do not count,
+        // successes reset the count
+        breaker.record(&Err::<(), _>(crate::Error::OpenAIValidation(
+            "bad".to_string(),
+        )));
+        breaker.record(&Err::<(), _>(crate::Error::OpenAIStructured(
+            crate::error::OpenAIErrorDetail {
+                message: "Invalid file format".to_string(),
+                error_type: "invalid_request_error".to_string(),
+                code: None,
+                param: Some("file".to_string()),
+            },
+        )));
+        let garbled = serde_json::from_str::<serde_json::Value>("<html>").unwrap_err();
+        breaker.record(&Err::<(), _>(crate::Error::OpenAI(
+            async_openai::error::OpenAIError::JSONDeserialize(garbled, "<html>".to_string()),
+        )));
+        breaker.record(&server_error());
+        breaker.record(&server_error());
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed {
+                consecutive_failures: 2
+            }
+        );
+        breaker.record(&Ok(()));
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed {
+                consecutive_failures: 0
+            }
+        );
+
+        for _ in 0..3 {
+            breaker.record(&server_error());
+        }
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert!(matches!(
+            breaker.check(),
+            Err(crate::Error::CircuitOpen { retry_after }) if retry_after <= Duration::from_millis(50)
+        ));
+
+        // After the cooldown a single probe goes through; its failure
+        // reopens the circuit and its success closes it
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.check().is_ok());
+        assert!(matches!(
+            breaker.check(),
+            Err(crate::Error::CircuitOpen { retry_after }) if retry_after.is_zero()
+        ));
+        breaker.record(&server_error());
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        breaker.record(&Ok(()));
+        assert!(breaker.check().is_ok());
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed {
+                consecutive_failures: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_fails_fast() {
+        use std::{sync::Arc, time::Duration};
+
+        // Nothing listens on port 1, so every request fails to connect
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(30)));
+        let service = OpenAIService::with_config(
+            async_openai::config::OpenAIConfig::new().with_api_base("http://127.0.0.1:1"),
+        )
+        .with_circuit_breaker(Arc::clone(&breaker));
+        let clone = service.clone();
+        assert_eq!(
+            service.circuit_state(),
+            Some(CircuitState::Closed {
+                consecutive_failures: 0
+            })
+        );
+
+        for _ in 0..2 {
+            let result = service.embed("hello".to_string()).await;
+            assert!(matches!(result, Err(crate::Error::OpenAI(_))));
+        }
+        // Clones share the breaker
+        let result = clone.synthesize("hello".to_string(), TtsVoice::Echo).await;
+        assert!(matches!(result, Err(crate::Error::CircuitOpen { .. })));
+        assert!(matches!(
+            service.circuit_state(),
+            Some(CircuitState::Open { .. })
+        ));
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert_eq!(
+            OpenAIService::with_config(async_openai::config::OpenAIConfig::new()).circuit_state(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_structured_api_error() {
+        use async_openai::error::{ApiError, OpenAIError};
+
+        let api = |code: &str| {
+            OpenAIError::ApiError(ApiError {
+                message: "Something went wrong".to_string(),
+                r#type: Some("invalid_request_error".to_string()),
+                param: Some("messages".to_string()),
+                code: Some(code.to_string()),
+            })
+        };
+        let crate::Error::OpenAIStructured(detail) = api_error(api("context_length_exceeded"))
+        else {
+            panic!("expected a structured error");
+        };
+        assert_eq!(detail.error_type, "invalid_request_error");
+        assert_eq!(detail.code.as_deref(), Some("context_length_exceeded"));
+        assert_eq!(detail.param.as_deref(), Some("messages"));
+
+        assert!(matches!(
+            api_error(api("rate_limit_exceeded")),
+            crate::Error::OpenAIRateLimited { retry_after: None }
+        ));
+
+        // Error bodies the client could not deserialize are parsed here
+        let body = r#"{"error": {"message": "Bad key", "type": "auth_error", "code": null}}"#;
+        let parse_error = serde_json::from_str::<u8>("x").unwrap_err();
+        let error = api_error(OpenAIError::JSONDeserialize(parse_error, body.to_string()));
+        assert!(matches!(
+            error,
+            crate::Error::OpenAIStructured(detail) if detail.message == "Bad key" && detail.code.is_none()
+        ));
+
+        let parse_error = serde_json::from_str::<u8>("x").unwrap_err();
+        let error = api_error(OpenAIError::JSONDeserialize(
+            parse_error,
+            "<html>".to_string(),
+        ));
+        assert!(matches!(error, crate::Error::OpenAI(_)));
+    }
+
+    #[test]
+    fn test_completion_id() {
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1_700_000_000,
+            "model": "gpt-4o-mini",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hi"},
+                "finish_reason": "stop",
+            }],
+        }))
+        .unwrap();
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let completion = service.convert_response_to_chat_completion(response);
+        assert_eq!(completion.id.as_deref(), Some("chatcmpl-123"));
+        assert_eq!(completion.created, Some(1_700_000_000));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_tokens() {
+        let service = OpenAIService::with_config(async_openai::config::OpenAIConfig::new());
+        let estimate =
+            |messages: &[Message]| OpenAIService::estimate_tokens(messages, &OpenAIModel::Gpt4o);
+        assert_eq!(estimate(&[]), 0);
+
+        let hello = [Message::user("Hello world")];
+        assert_eq!(estimate(&hello), 4 + 2);
+
+        let named = [Message::user("Hello world").with_name("alice")];
+        assert!(estimate(&named) > estimate(&hello));
+
+        // Images cost by detail
+        let image = |detail| {
+            [Message::with_images(
+                "Hello world",
+                vec![ImageUrl::from_url("https://example.com/cat.png", detail)],
+            )]
+        };
+        assert_eq!(estimate(&image(Some(ImageDetail::Low))), 4 + 2 + 85);
+        assert_eq!(estimate(&image(Some(ImageDetail::High))), 4 + 2 + 765);
+        assert_eq!(estimate(&image(None)), 4 + 2 + 765);
+
+        // The encoding follows the model
+        let text = [Message::user("東京の天気はどうですか？ 今日は晴れです。")];
+        let custom = |model: &str| {
+            OpenAIService::estimate_tokens(&text, &OpenAIModel::Custom(model.to_string()))
+        };
+        assert_eq!(custom("gpt-4o-2024-08-06"), estimate(&text));
+        assert_ne!(custom("gpt-4-turbo"), estimate(&text));
+        assert_eq!(custom("llama-3"), estimate(&text));
+
+        let messages = vec![
+            Message::system("You are terse."),
+            Message::user("word ".repeat(130_000)),
+        ];
+        assert!(estimate(&messages) > 128_000);
+        let options = ChatOptions {
+            model: OpenAIModel::Gpt4oMini,
+            enforce_context_limit: true,
+            ..Default::default()
+        };
+        let Err(err) = service.chat(messages, options).await else {
+            panic!("chat should fail before sending");
+        };
+        assert!(matches!(
+            &err,
+            crate::Error::OpenAIValidation(message)
+                if message.starts_with("Token count ") && message.ends_with("exceeds model limit 128000")
+        ));
+    }
+
+    #[cfg(feature = "realtime")]
+    #[tokio::test]
+    // The handshake callback's error type is set by tungstenite
+    #[allow(clippy::result_large_err)]
+    async fn test_realtime_session() {
+        use futures::{SinkExt, StreamExt};
+        use tokio::net::TcpListener;
+        use tokio_tungstenite::tungstenite::{
+            handshake::server::{Request, Response},
+            Message as WsMessage,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut handshake = None;
+            let mut ws = tokio_tungstenite::accept_hdr_async(
+                socket,
+                |request: &Request, response: Response| {
+                    handshake = Some((
+                        request.uri().to_string(),
+                        request.headers()["authorization"]
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                    ));
+                    Ok(response)
+                },
+            )
+            .await
+            .unwrap();
+            let Some(Ok(WsMessage::Text(append))) = ws.next().await else {
+                panic!("expected an audio message");
+            };
+            for event in [
+                r#"{"type":"session.created","session":{"id":"sess_1"}}"#,
+                r#"{"type":"conversation.item.input_audio_transcription.completed","item_id":"item_1","content_index":0,"transcript":"Hello"}"#,
+                r#"{"type":"response.output_audio_transcript.delta","response_id":"resp_1","item_id":"item_2","output_index":0,"content_index":0,"delta":"Hi"}"#,
+                r#"{"type":"response.audio.delta","response_id":"resp_1","item_id":"item_2","output_index":0,"content_index":0,"delta":"AAEC"}"#,
+                r#"{"type":"response.function_call_arguments.done","response_id":"resp_1","item_id":"item_3","output_index":1,"call_id":"call_1","name":"get_weather","arguments":"{\"city\":\"Paris\"}"}"#,
+                r#"{"type":"error","event_id":"evt_1","error":{"type":"invalid_request_error","code":"invalid_value","message":"Bad audio","param":null}}"#,
+            ] {
+                ws.send(WsMessage::text(event)).await.unwrap();
+            }
+            ws.close(None).await.unwrap();
+            (handshake.unwrap(), append.as_str().to_string())
+        });
+
+        let mut session = RealtimeSession::connect_to(
+            &format!("ws://{addr}/v1/realtime"),
+            "sk-test",
+            "gpt-realtime",
+        )
+        .await
+        .unwrap();
+        session.send_audio_chunk(&[0, 1, 2]).await.unwrap();
+
+        let RealtimeEvent::Other { event_type, event } = session.recv_event().await.unwrap() else {
+            panic!("session.created should be passed through");
+        };
+        assert_eq!(event_type, "session.created");
+        assert_eq!(event["session"]["id"], "sess_1");
+        assert_eq!(
+            session.recv_event().await.unwrap(),
+            RealtimeEvent::InputTranscription {
+                item_id: "item_1".to_string(),
+                transcript: "Hello".to_string(),
+            }
+        );
+        assert!(matches!(
+            session.recv_event().await.unwrap(),
+            RealtimeEvent::TranscriptDelta { delta, .. } if delta == "Hi"
+        ));
+        assert!(matches!(
+            session.recv_event().await.unwrap(),
+            RealtimeEvent::AudioDelta { audio, .. } if audio == [0, 1, 2]
+        ));
+        assert_eq!(
+            session.recv_event().await.unwrap(),
+            RealtimeEvent::FunctionCall {
+                call_id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                arguments: r#"{"city":"Paris"}"#.to_string(),
+            }
+        );
+        let RealtimeEvent::Error(detail) = session.recv_event().await.unwrap() else {
+            panic!("error events should be parsed");
+        };
+        assert_eq!(detail.code.as_deref(), Some("invalid_value"));
+        assert_eq!(detail.message, "Bad audio");
+        assert!(matches!(
+            session.recv_event().await,
+            Err(crate::Error::WebSocket(_))
+        ));
+
+        let ((uri, authorization), append) = server.await.unwrap();
+        assert_eq!(uri, "/v1/realtime?model=gpt-realtime");
+        assert_eq!(authorization, "Bearer sk-test");
+        let append: serde_json::Value = serde_json::from_str(&append).unwrap();
+        assert_eq!(
+            append,
+            serde_json::json!({"type": "input_audio_buffer.append", "audio": "AAEC"})
+        );
+    }
+}
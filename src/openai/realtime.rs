@@ -0,0 +1,296 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{header::AUTHORIZATION, HeaderValue},
+        Message as WsMessage,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info};
+
+use crate::error::{Error, OpenAIErrorDetail};
+
+/// Endpoint of the Realtime API, used by [`RealtimeSession::connect`]
+pub const REALTIME_URL: &str = "wss://api.openai.com/v1/realtime";
+
+/// Server event of a [`RealtimeSession`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RealtimeEvent {
+    /// Transcript of audio sent with [`RealtimeSession::send_audio_chunk`],
+    /// when input transcription is enabled for the session
+    InputTranscription { item_id: String, transcript: String },
+    /// Part of the transcript of the audio the model is speaking
+    TranscriptDelta {
+        response_id: String,
+        item_id: String,
+        delta: String,
+    },
+    /// Part of the audio the model is speaking, decoded from base64 and in
+    /// the session's output format, 16-bit PCM at 24 kHz by default
+    AudioDelta {
+        response_id: String,
+        item_id: String,
+        audio: Vec<u8>,
+    },
+    /// The model finished the arguments of a function call; answer it with a
+    /// `function_call_output` item through [`RealtimeSession::send_event`]
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    /// Error reported by the server; the session stays open
+    Error(OpenAIErrorDetail),
+    /// Any other event, e.g. `session.created` or `response.done`
+    Other { event_type: String, event: Value },
+}
+
+/// Server events with a dedicated [`RealtimeEvent`] variant. Both the beta
+/// and the current names of the response events are accepted.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum ServerEvent {
+    #[serde(rename = "conversation.item.input_audio_transcription.completed")]
+    InputTranscription { item_id: String, transcript: String },
+    #[serde(
+        rename = "response.output_audio_transcript.delta",
+        alias = "response.audio_transcript.delta"
+    )]
+    TranscriptDelta {
+        response_id: String,
+        item_id: String,
+        delta: String,
+    },
+    #[serde(rename = "response.output_audio.delta", alias = "response.audio.delta")]
+    AudioDelta {
+        response_id: String,
+        item_id: String,
+        delta: String,
+    },
+    #[serde(rename = "response.function_call_arguments.done")]
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    #[serde(rename = "error")]
+    Error { error: OpenAIErrorDetail },
+    #[serde(other)]
+    Other,
+}
+
+impl RealtimeEvent {
+    /// Parse a JSON server event
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Serialization`] if `text` is not a JSON object with a
+    /// `type`, or an event with a variant lacks its fields, and
+    /// [`Error::OpenAIValidation`] if audio is not valid base64.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let event: Value = serde_json::from_str(text)?;
+        let event = match ServerEvent::deserialize(&event)? {
+            ServerEvent::InputTranscription {
+                item_id,
+                transcript,
+            } => Self::InputTranscription {
+                item_id,
+                transcript,
+            },
+            ServerEvent::TranscriptDelta {
+                response_id,
+                item_id,
+                delta,
+            } => Self::TranscriptDelta {
+                response_id,
+                item_id,
+                delta,
+            },
+            ServerEvent::AudioDelta {
+                response_id,
+                item_id,
+                delta,
+            } => Self::AudioDelta {
+                response_id,
+                item_id,
+                audio: BASE64.decode(delta).map_err(|err| {
+                    Error::OpenAIValidation(format!("Invalid audio in realtime event: {err}"))
+                })?,
+            },
+            ServerEvent::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            } => Self::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            },
+            ServerEvent::Error { error } => Self::Error(error),
+            ServerEvent::Other => Self::Other {
+                event_type: event["type"].as_str().unwrap_or_default().to_string(),
+                event,
+            },
+        };
+        Ok(event)
+    }
+}
+
+/// A WebSocket connection to the Realtime API, for streaming audio to a model
+/// and receiving its transcripts, audio and function calls as they are
+/// produced.
+///
+/// Client and server events are JSON messages with a `type`, as described in
+/// the Realtime API reference. [`RealtimeSession::send_event`] sends any
+/// client event, such as `session.update` to configure voice, input
+/// transcription and tools.
+///
+/// ```no_run
+/// use ai_utils::openai::{RealtimeEvent, RealtimeSession};
+///
+/// # async fn run(audio: Vec<u8>) -> Result<(), ai_utils::Error> {
+/// let mut session = RealtimeSession::connect("sk-...", "gpt-realtime").await?;
+/// for chunk in audio.chunks(4800) {
+///     session.send_audio_chunk(chunk).await?;
+/// }
+/// session.commit_audio().await?;
+/// session.create_response().await?;
+///
+/// loop {
+///     match session.recv_event().await? {
+///         RealtimeEvent::TranscriptDelta { delta, .. } => print!("{delta}"),
+///         RealtimeEvent::AudioDelta { audio, .. } => { /* play `audio` */ }
+///         RealtimeEvent::Other { event_type, .. } if event_type == "response.done" => break,
+///         _ => {}
+///     }
+/// }
+/// session.close().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RealtimeSession {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl RealtimeSession {
+    /// Open a session with `model`, e.g. `gpt-realtime`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the connection or handshake fails,
+    /// including when the API key is rejected.
+    pub async fn connect(api_key: &str, model: &str) -> Result<Self, Error> {
+        Self::connect_to(REALTIME_URL, api_key, model).await
+    }
+
+    /// Open a session on another endpoint, such as a proxy or an Azure
+    /// deployment; `url` is a `ws://` or `wss://` URL without query
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if the URL or API key cannot be used in the
+    /// handshake request and [`Error::WebSocket`] if the connection fails.
+    pub async fn connect_to(url: &str, api_key: &str, model: &str) -> Result<Self, Error> {
+        let mut request = format!("{url}?model={model}")
+            .into_client_request()
+            .map_err(|err| Error::Config(format!("Invalid realtime URL: {err}")))?;
+        let authorization = HeaderValue::from_str(&format!("Bearer {api_key}"))
+            .map_err(|err| Error::Config(format!("Invalid API key: {err}")))?;
+        request.headers_mut().insert(AUTHORIZATION, authorization);
+
+        let (ws, _) = connect_async(request).await?;
+        info!("Connected realtime session for model {}", model);
+        Ok(Self { ws })
+    }
+
+    /// Send a client event, e.g. `session.update`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the message cannot be sent.
+    pub async fn send_event(&mut self, event: &Value) -> Result<(), Error> {
+        self.ws.send(WsMessage::text(event.to_string())).await?;
+        Ok(())
+    }
+
+    /// Append audio to the input buffer, in the session's input format,
+    /// 16-bit PCM at 24 kHz mono by default
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the message cannot be sent.
+    pub async fn send_audio_chunk(&mut self, audio: &[u8]) -> Result<(), Error> {
+        self.send_event(&json!({
+            "type": "input_audio_buffer.append",
+            "audio": BASE64.encode(audio),
+        }))
+        .await
+    }
+
+    /// End the user's turn, for sessions without server voice activity
+    /// detection
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the message cannot be sent.
+    pub async fn commit_audio(&mut self) -> Result<(), Error> {
+        self.send_event(&json!({ "type": "input_audio_buffer.commit" }))
+            .await
+    }
+
+    /// Ask the model to respond to the conversation so far
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the message cannot be sent.
+    pub async fn create_response(&mut self) -> Result<(), Error> {
+        self.send_event(&json!({ "type": "response.create" })).await
+    }
+
+    /// Wait for the next server event
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the connection fails or was closed,
+    /// and the errors of [`RealtimeEvent::parse`] for malformed events.
+    pub async fn recv_event(&mut self) -> Result<RealtimeEvent, Error> {
+        while let Some(message) = self.ws.next().await {
+            match message? {
+                WsMessage::Text(text) => {
+                    let event = RealtimeEvent::parse(text.as_str())?;
+                    if let RealtimeEvent::Other { event_type, .. } = &event {
+                        debug!("Realtime event {}", event_type);
+                    }
+                    return Ok(event);
+                }
+                WsMessage::Close(frame) => {
+                    info!("Realtime session closed by server: {:?}", frame);
+                    break;
+                }
+                // Pings are answered by the WebSocket stream itself
+                WsMessage::Binary(_)
+                | WsMessage::Ping(_)
+                | WsMessage::Pong(_)
+                | WsMessage::Frame(_) => {}
+            }
+        }
+        Err(tungstenite::Error::ConnectionClosed.into())
+    }
+
+    /// Close the connection
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WebSocket`] if the close handshake fails.
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.ws.close(None).await?;
+        Ok(())
+    }
+}
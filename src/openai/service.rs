@@ -1,26 +1,44 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use async_openai::error::OpenAIError;
 use async_openai::{
     config::OpenAIConfig,
     types::{
-        audio::{AudioInput, CreateTranscriptionRequest, CreateTranscriptionRequestArgs},
+        audio::{
+            AudioInput, CreateSpeechRequestArgs, CreateTranscriptionRequest,
+            CreateTranscriptionRequestArgs, SpeechModel, SpeechResponseFormat, Voice,
+        },
         chat::{
-            ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartImage,
+            ChatCompletionRequestAssistantMessage, ChatCompletionRequestAssistantMessageContent,
+            ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartAudio,
+            ChatCompletionRequestMessageContentPartImage,
             ChatCompletionRequestMessageContentPartText, ChatCompletionRequestSystemMessage,
-            ChatCompletionRequestSystemMessageContent, ChatCompletionRequestUserMessage,
+            ChatCompletionRequestSystemMessageContent, ChatCompletionRequestToolMessage,
+            ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessage,
             ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
-            CreateChatCompletionRequest, CreateChatCompletionResponse, ImageDetail,
-            ImageUrl as OpenAIImageUrl, Role, StopConfiguration,
+            CreateChatCompletionRequest, CreateChatCompletionResponse,
+            ImageDetail as OpenAIImageDetail, ImageUrl as OpenAIImageUrl, InputAudio,
+            InputAudioFormat, Role, StopConfiguration,
         },
-        embeddings::CreateEmbeddingRequestArgs,
+        embeddings::{CreateEmbeddingRequest, CreateEmbeddingRequestArgs, CreateEmbeddingResponse},
         images::{CreateImageRequestArgs, Image, ImageResponseFormat, ImageSize},
     },
     Client,
 };
 use async_trait::async_trait;
+use futures::future::try_join_all;
+use serde::Deserialize;
+use tracing::{debug, instrument, warn};
 
 use crate::{
-    error::Error,
-    openai::types::{
-        ChatCompletion, ChatOptions, Message, MessageContent, MessageRole, OpenAIModel,
+    common::Encoding,
+    error::{Error, OpenAIErrorDetail},
+    openai::{
+        breaker::{CircuitBreaker, CircuitState},
+        types::{
+            AudioFormat, ChatCompletion, ChatOptions, ContentPart, ImageDetail, ImageUrl, Message,
+            MessageContent, MessageRole, OpenAIModel, SpeechFormat, TtsVoice,
+        },
     },
 };
 
@@ -36,15 +54,36 @@ pub trait AIService: Send + Sync {
 
     async fn transcribe(&self, audio: Vec<u8>) -> Result<String, Error>;
 
+    /// Read `text` aloud with `voice`, returning MP3 audio
+    async fn synthesize(&self, text: String, voice: TtsVoice) -> Result<Vec<u8>, Error>;
+
     async fn embed(&self, text: String) -> Result<Vec<f32>, Error>;
 
     async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error>;
 }
 
+#[derive(Clone)]
 pub struct OpenAIService {
     client: Client<OpenAIConfig>,
+    /// Shared by clones, see [`OpenAIService::with_circuit_breaker`]
+    breaker: Option<Arc<CircuitBreaker>>,
+    /// See [`OpenAIService::with_speech_model`]
+    speech_model: OpenAIModel,
 }
 
+/// Tokens the API adds around every chat message for its role and delimiters
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// What a low-detail image costs, whatever its size
+const LOW_DETAIL_IMAGE_TOKENS: usize = 85;
+
+/// What a 1024x1024 image costs at high detail: 85 tokens plus 170 for each
+/// of its four 512-pixel tiles
+const HIGH_DETAIL_IMAGE_TOKENS: usize = 765;
+
+/// Longest input, in characters, the speech API accepts
+pub const MAX_SPEECH_INPUT_CHARS: usize = 4096;
+
 impl OpenAIService {
     pub fn new() -> Result<Self, Error> {
         let api_key = std::env::var("OPENAI_API_KEY")
@@ -61,10 +100,183 @@ impl OpenAIService {
             ));
         }
 
-        let config = OpenAIConfig::new().with_api_key(api_key);
-        Ok(Self {
+        let mut config = OpenAIConfig::new().with_api_key(api_key);
+        if let Some(org_id) = optional_env("OPENAI_ORG_ID")? {
+            config = config.with_org_id(org_id);
+        }
+        if let Some(project_id) = optional_env("OPENAI_PROJECT_ID")? {
+            config = config.with_project_id(project_id);
+        }
+        Ok(Self::with_config(config))
+    }
+
+    pub(crate) fn with_config(config: OpenAIConfig) -> Self {
+        Self {
             client: Client::with_config(config),
-        })
+            breaker: None,
+            speech_model: OpenAIModel::Tts1,
+        }
+    }
+
+    /// Send requests with the `OpenAI-Organization` header, overriding
+    /// `OPENAI_ORG_ID`, so usage is billed to `org_id`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if `org_id` cannot be sent as a header value.
+    pub fn with_organization(self, org_id: impl Into<String>) -> Result<Self, Error> {
+        let org_id = header_id("Organization id", &org_id.into())?;
+        let config = self.client.config().clone().with_org_id(org_id);
+        Ok(self.reconfigured(config))
+    }
+
+    /// Send requests with the `OpenAI-Project` header, overriding
+    /// `OPENAI_PROJECT_ID`, so usage is attributed to `project_id`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if `project_id` cannot be sent as a header
+    /// value.
+    pub fn with_project(self, project_id: impl Into<String>) -> Result<Self, Error> {
+        let project_id = header_id("Project id", &project_id.into())?;
+        let config = self.client.config().clone().with_project_id(project_id);
+        Ok(self.reconfigured(config))
+    }
+
+    fn reconfigured(self, config: OpenAIConfig) -> Self {
+        Self {
+            client: Client::with_config(config),
+            ..self
+        }
+    }
+
+    /// Stop sending requests while `breaker` is open, see [`CircuitBreaker`].
+    /// Pass the same breaker to several services to trip them together.
+    #[must_use]
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Synthesize speech with `model` instead of [`OpenAIModel::Tts1`], e.g.
+    /// [`OpenAIModel::Tts1Hd`] for higher quality audio
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OpenAIUnsupportedModel`] if `model` cannot synthesize
+    /// speech.
+    pub fn with_speech_model(mut self, model: OpenAIModel) -> Result<Self, Error> {
+        model.validate_operation("speech")?;
+        self.speech_model = model;
+        Ok(self)
+    }
+
+    /// State of the circuit breaker, if one is attached
+    #[must_use]
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_deref().map(CircuitBreaker::state)
+    }
+
+    /// Run an API call through the circuit breaker, if one is attached
+    async fn guarded<T>(
+        &self,
+        call: impl Future<Output = Result<T, Error>> + Send,
+    ) -> Result<T, Error> {
+        let Some(breaker) = &self.breaker else {
+            return call.await;
+        };
+        breaker.check()?;
+        let result = call.await;
+        breaker.record(&result);
+        result
+    }
+
+    /// Send a chat request, logging its token usage and latency
+    async fn create_chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, Error> {
+        let started = Instant::now();
+        let response = self
+            .guarded(async { self.client.chat().create(request).await.map_err(api_error) })
+            .await?;
+        let usage = response.usage.as_ref();
+        debug!(
+            model = %response.model,
+            prompt_tokens = usage.map(|usage| usage.prompt_tokens),
+            completion_tokens = usage.map(|usage| usage.completion_tokens),
+            total_tokens = usage.map(|usage| usage.total_tokens),
+            latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+            "Chat completion succeeded"
+        );
+        Ok(response)
+    }
+
+    /// Send an embeddings request, logging its token usage and latency
+    async fn create_embeddings(
+        &self,
+        request: CreateEmbeddingRequest,
+    ) -> Result<CreateEmbeddingResponse, Error> {
+        let started = Instant::now();
+        let response = self
+            .guarded(async {
+                self.client
+                    .embeddings()
+                    .create(request)
+                    .await
+                    .map_err(api_error)
+            })
+            .await?;
+        log_embedding_usage(&response, started);
+        Ok(response)
+    }
+
+    /// Estimate the prompt tokens of `messages` for `model`: the content and
+    /// name of each message, counted with the encoding of
+    /// [`Encoding::for_model`] (`o200k_base` for unknown models), plus 4
+    /// tokens of role overhead.
+    ///
+    /// Images are estimated from their detail without fetching them:
+    /// low-detail images cost 85 tokens and others the 765 of a 1024x1024
+    /// image, so larger high-detail images are underestimated. Audio is not
+    /// counted.
+    #[must_use]
+    pub fn estimate_tokens(messages: &[Message], model: &OpenAIModel) -> usize {
+        let tokenizer = Encoding::for_model(&model.to_string())
+            .unwrap_or(Encoding::O200k)
+            .shared();
+        let count = |text: &str| tokenizer.encode_with_special_tokens(text).len();
+        messages
+            .iter()
+            .map(|message| {
+                let content = match &message.content {
+                    MessageContent::Text(text) => count(text),
+                    MessageContent::Image(images) => images.iter().map(image_tokens).sum(),
+                    MessageContent::Audio(_) => 0,
+                    MessageContent::Mixed(parts) => parts
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text(text) => count(text),
+                            ContentPart::Image(image) => image_tokens(image),
+                        })
+                        .sum(),
+                };
+                let name = message.name.as_deref().map_or(0, count);
+                TOKENS_PER_MESSAGE + content + name
+            })
+            .sum()
+    }
+
+    /// Model used by `embed` and `embed_batch`
+    #[must_use]
+    pub const fn embedding_model(&self) -> OpenAIModel {
+        OpenAIModel::TextEmbedding3Large
+    }
+
+    /// Model used by `synthesize_speech` and `synthesize`
+    #[must_use]
+    pub fn speech_model(&self) -> OpenAIModel {
+        self.speech_model.clone()
     }
 
     /// Validate the service configuration
@@ -85,7 +297,7 @@ impl OpenAIService {
         Ok(())
     }
 
-    fn convert_message_to_openai(
+    pub(crate) fn convert_message_to_openai(
         &self,
         message: &Message,
     ) -> Result<ChatCompletionRequestMessage, Error> {
@@ -105,20 +317,7 @@ impl OpenAIService {
             (MessageRole::User, MessageContent::Image(images)) => {
                 let image_parts: Vec<ChatCompletionRequestUserMessageContentPart> = images
                     .iter()
-                    .map(|img| {
-                        ChatCompletionRequestUserMessageContentPart::ImageUrl(
-                            ChatCompletionRequestMessageContentPartImage {
-                                image_url: OpenAIImageUrl {
-                                    url: img.url.clone(),
-                                        detail: img.detail.as_ref().map(|d| match d.as_str() {
-                                        "high" => ImageDetail::High,
-                                        "low" => ImageDetail::Low,
-                                        _ => ImageDetail::Auto,
-                                    }),
-                                },
-                            },
-                        )
-                    })
+                    .map(Self::convert_image_part)
                     .collect();
 
                 Ok(ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
@@ -138,18 +337,7 @@ impl OpenAIService {
                             )
                         }
                         crate::openai::types::ContentPart::Image(img) => {
-                            ChatCompletionRequestUserMessageContentPart::ImageUrl(
-                                ChatCompletionRequestMessageContentPartImage {
-                                    image_url: OpenAIImageUrl {
-                                        url: img.url.clone(),
-                                        detail: img.detail.as_ref().map(|d| match d.as_str() {
-                                            "high" => ImageDetail::High,
-                                            "low" => ImageDetail::Low,
-                                            _ => ImageDetail::Auto,
-                                        }),
-                                    },
-                                },
-                            )
+                            Self::convert_image_part(img)
                         }
                     })
                     .collect();
@@ -159,20 +347,81 @@ impl OpenAIService {
                     name: message.name.clone(),
                 }))
             }
+            (MessageRole::User, MessageContent::Audio(audio)) => {
+                let format = match audio.format {
+                    AudioFormat::Mp3 => InputAudioFormat::Mp3,
+                    AudioFormat::Wav => InputAudioFormat::Wav,
+                    other => {
+                        return Err(Error::OpenAIValidation(format!(
+                            "Audio format {other} is not supported for chat input, use wav or mp3"
+                        )))
+                    }
+                };
+                let part = ChatCompletionRequestUserMessageContentPart::InputAudio(
+                    ChatCompletionRequestMessageContentPartAudio {
+                        input_audio: InputAudio {
+                            data: audio.data.clone(),
+                            format,
+                        },
+                    },
+                );
+
+                Ok(ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Array(vec![part]),
+                    name: message.name.clone(),
+                }))
+            }
+            (MessageRole::Assistant, MessageContent::Text(text)) => Ok(
+                ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+                    content: Some(ChatCompletionRequestAssistantMessageContent::Text(
+                        text.clone(),
+                    )),
+                    name: message.name.clone(),
+                    ..Default::default()
+                }),
+            ),
+            (MessageRole::Tool, MessageContent::Text(result)) => {
+                let tool_call_id = message.tool_call_id.clone().ok_or_else(|| {
+                    Error::OpenAIValidation("Tool message must have a tool_call_id".to_string())
+                })?;
+                Ok(ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+                    content: ChatCompletionRequestToolMessageContent::Text(result.clone()),
+                    tool_call_id,
+                }))
+            }
             (role, content) => {
                 Err(Error::OpenAIValidation(format!(
-                    "Unsupported message role/content combination: {:?} with {:?}. Only User and System roles are supported.",
+                    "Unsupported message role/content combination: {:?} with {:?}. Only User, System and text Assistant and Tool messages are supported.",
                     role, content
                 )))
             }
         }
     }
 
-    fn convert_response_to_chat_completion(
+    fn convert_image_part(
+        img: &crate::openai::types::ImageUrl,
+    ) -> ChatCompletionRequestUserMessageContentPart {
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(
+            ChatCompletionRequestMessageContentPartImage {
+                image_url: OpenAIImageUrl {
+                    url: img.url.clone(),
+                    detail: img.detail.map(|detail| match detail {
+                        ImageDetail::High => OpenAIImageDetail::High,
+                        ImageDetail::Low => OpenAIImageDetail::Low,
+                        ImageDetail::Auto => OpenAIImageDetail::Auto,
+                    }),
+                },
+            },
+        )
+    }
+
+    pub(crate) fn convert_response_to_chat_completion(
         &self,
         response: CreateChatCompletionResponse,
     ) -> ChatCompletion {
         ChatCompletion {
+            id: Some(response.id).filter(|id| !id.is_empty()),
+            created: Some(u64::from(response.created)),
             choices: response
                 .choices
                 .into_iter()
@@ -180,13 +429,13 @@ impl OpenAIService {
                     message: Message {
                         role: match choice.message.role {
                             Role::System => MessageRole::System,
-                            Role::User => MessageRole::User,
-                            Role::Tool => MessageRole::User, // fallback
-                            Role::Function => MessageRole::User, // fallback
-                            _ => MessageRole::User,          // fallback for any other roles
+                            Role::Assistant => MessageRole::Assistant,
+                            Role::Tool => MessageRole::Tool,
+                            _ => MessageRole::User, // fallback for any other roles
                         },
                         content: MessageContent::Text(choice.message.content.unwrap_or_default()),
                         name: None,
+                        tool_call_id: None,
                     },
                 })
                 .collect(),
@@ -199,7 +448,35 @@ impl OpenAIService {
         }
     }
 
-    /// Unified chat completion API using builder/options pattern
+    /// Complete the choices of a request ending with an assistant message,
+    /// the prefill, so each holds the whole assistant reply. Providers that
+    /// already echo the prefill are left as they are.
+    pub(crate) fn apply_prefill(completion: &mut ChatCompletion, messages: &[Message]) {
+        let Some(Message {
+            role: MessageRole::Assistant,
+            content: MessageContent::Text(prefill),
+            ..
+        }) = messages.last()
+        else {
+            return;
+        };
+        for choice in &mut completion.choices {
+            if let MessageContent::Text(text) = &mut choice.message.content {
+                if !text.starts_with(prefill.as_str()) {
+                    text.insert_str(0, prefill);
+                }
+            }
+        }
+    }
+
+    /// Unified chat completion API using builder/options pattern.
+    ///
+    /// With [`ChatOptions::prefill`], a trailing assistant message is sent as
+    /// a prefill: providers that support it, such as Anthropic models behind
+    /// a compatible endpoint, continue the reply from there, and the returned
+    /// choices start with the prefill. Otherwise the choices are returned as
+    /// the provider sent them.
+    #[instrument(skip_all, fields(model = %options.model, message_count = messages.len()))]
     pub async fn chat(
         &self,
         messages: Vec<Message>,
@@ -207,6 +484,18 @@ impl OpenAIService {
     ) -> Result<ChatCompletion, Error> {
         // Validate model supports chat
         options.model.validate_operation("chat")?;
+        options.validate()?;
+        if options.model.is_deprecated() {
+            if let Some(replacement) = options.model.recommended_replacement() {
+                warn!(
+                    model = %options.model,
+                    %replacement,
+                    "Model is deprecated, switch to the recommended replacement"
+                );
+            } else {
+                warn!(model = %options.model, "Model is deprecated");
+            }
+        }
 
         // Validate messages
         if messages.is_empty() {
@@ -225,6 +514,20 @@ impl OpenAIService {
         if has_images {
             options.model.validate_operation("vision")?;
         }
+        if messages.iter().any(Message::has_audio) {
+            options.model.validate_operation("audio")?;
+        }
+
+        if options.enforce_context_limit {
+            if let Some(limit) = options.model.max_tokens() {
+                let tokens = Self::estimate_tokens(&messages, &options.model);
+                if tokens > limit as usize {
+                    return Err(Error::OpenAIValidation(format!(
+                        "Token count {tokens} exceeds model limit {limit}"
+                    )));
+                }
+            }
+        }
 
         let request_messages: Vec<ChatCompletionRequestMessage> = messages
             .iter()
@@ -252,15 +555,120 @@ impl OpenAIService {
         if let Some(user) = options.user {
             request.safety_identifier = Some(user);
         }
+        if let Some(n) = options.n {
+            request.n = Some(n);
+        }
 
-        let response = self
-            .client
-            .chat()
-            .create(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+        let response = self.create_chat(request).await?;
 
-        Ok(self.convert_response_to_chat_completion(response))
+        let mut completion = self.convert_response_to_chat_completion(response);
+        if options.prefill {
+            Self::apply_prefill(&mut completion, &messages);
+        }
+        Ok(completion)
+    }
+
+    /// Generate `n` independent completions for the same prompt and return the
+    /// text of each. Models that accept `n` are asked once; others get `n`
+    /// parallel requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `n` is zero or any request fails.
+    pub async fn chat_n(
+        &self,
+        messages: Vec<Message>,
+        options: ChatOptions,
+        n: u8,
+    ) -> Result<Vec<String>, Error> {
+        if n == 0 {
+            return Err(Error::OpenAIValidation("n must be at least 1".to_string()));
+        }
+
+        let completions = if options.model.supports_multiple_choices() {
+            let options = ChatOptions {
+                n: Some(n),
+                ..options
+            };
+            vec![self.chat(messages, options).await?]
+        } else {
+            let options = ChatOptions { n: None, ..options };
+            try_join_all((0..n).map(|_| self.chat(messages.clone(), options.clone()))).await?
+        };
+
+        Ok(completions
+            .into_iter()
+            .flat_map(|completion| completion.choices)
+            .map(|choice| {
+                choice
+                    .message
+                    .text_content()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect())
+    }
+
+    /// Read `text` aloud with `voice` and return the audio, encoded as
+    /// `format`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OpenAIValidation`] if `text` is blank or longer than
+    /// [`MAX_SPEECH_INPUT_CHARS`], and an error if the request fails.
+    #[instrument(skip_all, fields(voice = ?voice, format = ?format))]
+    pub async fn synthesize_speech(
+        &self,
+        text: impl Into<String>,
+        voice: TtsVoice,
+        format: SpeechFormat,
+    ) -> Result<Vec<u8>, Error> {
+        let text = text.into();
+        if text.trim().is_empty() {
+            return Err(Error::OpenAIValidation(
+                "Text for speech synthesis cannot be empty".to_string(),
+            ));
+        }
+        let chars = text.chars().count();
+        if chars > MAX_SPEECH_INPUT_CHARS {
+            return Err(Error::OpenAIValidation(format!(
+                "Text for speech synthesis has {chars} characters, at most {MAX_SPEECH_INPUT_CHARS} are allowed"
+            )));
+        }
+
+        let request = CreateSpeechRequestArgs::default()
+            .input(text)
+            .model(SpeechModel::Other(self.speech_model().to_string()))
+            .voice(match voice {
+                TtsVoice::Alloy => Voice::Alloy,
+                TtsVoice::Echo => Voice::Echo,
+                TtsVoice::Fable => Voice::Fable,
+                TtsVoice::Onyx => Voice::Onyx,
+                TtsVoice::Nova => Voice::Nova,
+                TtsVoice::Shimmer => Voice::Shimmer,
+            })
+            .response_format(match format {
+                SpeechFormat::Mp3 => SpeechResponseFormat::Mp3,
+                SpeechFormat::Opus => SpeechResponseFormat::Opus,
+                SpeechFormat::Aac => SpeechResponseFormat::Aac,
+                SpeechFormat::Flac => SpeechResponseFormat::Flac,
+                SpeechFormat::Wav => SpeechResponseFormat::Wav,
+                SpeechFormat::Pcm => SpeechResponseFormat::Pcm,
+            })
+            .build()?;
+
+        let response = self
+            .guarded(async {
+                self.client
+                    .audio()
+                    .speech()
+                    .create(request)
+                    .await
+                    .map_err(api_error)
+            })
+            .await?;
+
+        Ok(response.bytes.to_vec())
     }
 
     /// Deprecated: use chat() with builder/options instead
@@ -281,6 +689,75 @@ impl OpenAIService {
     }
 }
 
+/// `value` as an id for the organization or project header, which must be
+/// visible ASCII
+fn header_id(setting: &str, value: &str) -> Result<String, Error> {
+    let value = value.trim().to_string();
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Err(Error::Config(format!(
+            "{setting} must be non-empty visible ASCII, got {value:?}"
+        )));
+    }
+    Ok(value)
+}
+
+/// Estimated prompt tokens of `image`, see [`OpenAIService::estimate_tokens`]
+const fn image_tokens(image: &ImageUrl) -> usize {
+    match image.detail {
+        Some(ImageDetail::Low) => LOW_DETAIL_IMAGE_TOKENS,
+        Some(ImageDetail::High | ImageDetail::Auto) | None => HIGH_DETAIL_IMAGE_TOKENS,
+    }
+}
+
+/// Id set in the environment variable `name`, ignoring it when empty
+fn optional_env(name: &str) -> Result<Option<String>, Error> {
+    match std::env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => header_id(name, &value).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Log the token usage of a successful embeddings call at debug level, so
+/// subscribers only see it when they opt in
+pub(super) fn log_embedding_usage(response: &CreateEmbeddingResponse, started: Instant) {
+    debug!(
+        model = %response.model,
+        prompt_tokens = response.usage.prompt_tokens,
+        total_tokens = response.usage.total_tokens,
+        latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+        "Embedding succeeded"
+    );
+}
+
+/// Structured form of an API error: rate limits become
+/// [`Error::OpenAIRateLimited`] and error bodies that parse become
+/// [`Error::OpenAIStructured`]. Anything else stays [`Error::OpenAI`].
+pub(super) fn api_error(error: OpenAIError) -> Error {
+    #[derive(Deserialize)]
+    struct Body {
+        error: OpenAIErrorDetail,
+    }
+
+    let detail = match &error {
+        OpenAIError::ApiError(api) => OpenAIErrorDetail {
+            message: api.message.clone(),
+            error_type: api.r#type.clone().unwrap_or_default(),
+            code: api.code.clone(),
+            param: api.param.clone(),
+        },
+        OpenAIError::JSONDeserialize(_, content) => match serde_json::from_str::<Body>(content) {
+            Ok(body) => body.error,
+            Err(_) => return Error::OpenAI(error),
+        },
+        _ => return Error::OpenAI(error),
+    };
+    if detail.code.as_deref() == Some("rate_limit_exceeded") {
+        return Error::OpenAIRateLimited { retry_after: None };
+    }
+    Error::OpenAIStructured(detail)
+}
+
 #[async_trait]
 impl AIService for OpenAIService {
     async fn completion(
@@ -322,12 +799,7 @@ impl AIService for OpenAIService {
             ..Default::default()
         };
 
-        let response = self
-            .client
-            .chat()
-            .create(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+        let response = self.create_chat(request).await?;
 
         Ok(self.convert_response_to_chat_completion(response))
     }
@@ -349,11 +821,14 @@ impl AIService for OpenAIService {
             .build()?;
 
         let response = self
-            .client
-            .images()
-            .generate(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+            .guarded(async {
+                self.client
+                    .images()
+                    .generate(request)
+                    .await
+                    .map_err(api_error)
+            })
+            .await?;
 
         let image = &response.data[0];
         match &**image {
@@ -378,16 +853,23 @@ impl AIService for OpenAIService {
             .build()?;
 
         let response = self
-            .client
-            .audio()
-            .transcription()
-            .create(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+            .guarded(async {
+                self.client
+                    .audio()
+                    .transcription()
+                    .create(request)
+                    .await
+                    .map_err(api_error)
+            })
+            .await?;
 
         Ok(response.text)
     }
 
+    async fn synthesize(&self, text: String, voice: TtsVoice) -> Result<Vec<u8>, Error> {
+        self.synthesize_speech(text, voice, SpeechFormat::Mp3).await
+    }
+
     async fn embed(&self, text: String) -> Result<Vec<f32>, Error> {
         // Validate text
         if text.trim().is_empty() {
@@ -397,16 +879,11 @@ impl AIService for OpenAIService {
         }
 
         let request = CreateEmbeddingRequestArgs::default()
-            .model(OpenAIModel::TextEmbedding3Large.to_string())
+            .model(self.embedding_model().to_string())
             .input(text)
             .build()?;
 
-        let response = self
-            .client
-            .embeddings()
-            .create(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+        let response = self.create_embeddings(request).await?;
 
         Ok(response.data[0].embedding.clone())
     }
@@ -420,16 +897,11 @@ impl AIService for OpenAIService {
         }
 
         let request = CreateEmbeddingRequestArgs::default()
-            .model(OpenAIModel::TextEmbedding3Large.to_string())
+            .model(self.embedding_model().to_string())
             .input(texts)
             .build()?;
 
-        let response = self
-            .client
-            .embeddings()
-            .create(request)
-            .await
-            .map_err(|e| Error::OpenAI(e))?;
+        let response = self.create_embeddings(request).await?;
 
         Ok(response
             .data
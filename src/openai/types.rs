@@ -1,3 +1,4 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -5,6 +6,21 @@ pub enum MessageRole {
     System,
     User,
     Assistant,
+    /// Result of a tool call, see [`Message::tool_result`]
+    Tool,
+}
+
+impl MessageRole {
+    /// Get the role name as sent to the chat completions API
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::User => "user",
+            Self::Assistant => "assistant",
+            Self::Tool => "tool",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,6 +28,74 @@ pub enum MessageContent {
     Text(String),
     Image(Vec<ImageUrl>),
     Mixed(Vec<ContentPart>),
+    Audio(AudioContent),
+}
+
+/// Base64-encoded audio sent as chat input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioContent {
+    pub data: String,
+    pub format: AudioFormat,
+}
+
+/// Encoding of [`AudioContent`]. The chat API currently only accepts `Wav`
+/// and `Mp3`; other formats are rejected before the request is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    Wav,
+    Ogg,
+    Flac,
+    M4a,
+    Webm,
+}
+
+impl AudioFormat {
+    /// Format name as used by the API, e.g. `"mp3"`
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Wav => "wav",
+            Self::Ogg => "ogg",
+            Self::Flac => "flac",
+            Self::M4a => "m4a",
+            Self::Webm => "webm",
+        }
+    }
+}
+
+impl std::fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Built-in voices of the speech API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TtsVoice {
+    Alloy,
+    Echo,
+    Fable,
+    Onyx,
+    Nova,
+    Shimmer,
+}
+
+/// Encoding of the audio [`OpenAIService::synthesize_speech`](super::OpenAIService::synthesize_speech)
+/// returns. `Pcm` is raw 24 kHz 16-bit little-endian samples without a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    #[default]
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    Pcm,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +109,9 @@ pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
     pub name: Option<String>,
+    /// Id of the tool call a [`MessageRole::Tool`] message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl Message {
@@ -33,6 +120,7 @@ impl Message {
             role: MessageRole::System,
             content: MessageContent::Text(content.into()),
             name: None,
+            tool_call_id: None,
         }
     }
 
@@ -41,6 +129,7 @@ impl Message {
             role: MessageRole::User,
             content: MessageContent::Text(content.into()),
             name: None,
+            tool_call_id: None,
         }
     }
 
@@ -49,6 +138,7 @@ impl Message {
             role: MessageRole::Assistant,
             content: MessageContent::Text(content.into()),
             name: None,
+            tool_call_id: None,
         }
     }
 
@@ -60,6 +150,50 @@ impl Message {
             role: MessageRole::User,
             content: MessageContent::Mixed(parts),
             name: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// User message with `content` followed by the images at `paths`, each
+    /// embedded as a data URI by [`ImageUrl::from_file`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Common`](crate::error::Error::Common) if a path has
+    /// an unsupported extension or an image cannot be read.
+    pub fn with_image_paths(
+        content: impl Into<String>,
+        paths: &[&str],
+    ) -> Result<Self, crate::error::Error> {
+        let images = paths
+            .iter()
+            .map(|path| ImageUrl::from_file(std::path::Path::new(path)))
+            .collect::<crate::Result<_>>()?;
+        Ok(Self::with_images(content, images))
+    }
+
+    /// User message carrying `data` as audio input, base64-encoded
+    #[must_use]
+    pub fn with_audio(data: Vec<u8>, format: AudioFormat) -> Self {
+        Self {
+            role: MessageRole::User,
+            content: MessageContent::Audio(AudioContent {
+                data: BASE64.encode(data),
+                format,
+            }),
+            name: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// Report the output of the tool call `tool_call_id` back to the model
+    #[must_use]
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: MessageRole::Tool,
+            content: MessageContent::Text(content.into()),
+            name: None,
+            tool_call_id: Some(tool_call_id.into()),
         }
     }
 
@@ -120,6 +254,24 @@ impl Message {
                     }
                 }
             }
+            MessageContent::Audio(audio) => {
+                if audio.data.is_empty() {
+                    return Err(crate::error::Error::OpenAIValidation(
+                        "Audio message must contain audio data".to_string(),
+                    ));
+                }
+            }
+        }
+
+        if self.role == MessageRole::Tool
+            && self
+                .tool_call_id
+                .as_deref()
+                .is_none_or(|id| id.trim().is_empty())
+        {
+            return Err(crate::error::Error::OpenAIValidation(
+                "Tool message must have a tool_call_id".to_string(),
+            ));
         }
 
         // Validate name if present
@@ -134,6 +286,12 @@ impl Message {
         Ok(())
     }
 
+    /// Check if the message contains audio input
+    #[must_use]
+    pub const fn has_audio(&self) -> bool {
+        matches!(self.content, MessageContent::Audio(_))
+    }
+
     /// Check if the message contains images
     pub fn has_images(&self) -> bool {
         matches!(
@@ -150,7 +308,7 @@ impl Message {
                 ContentPart::Text(text) => Some(text.as_str()),
                 ContentPart::Image(_) => None,
             }),
-            MessageContent::Image(_) => None,
+            MessageContent::Image(_) | MessageContent::Audio(_) => None,
         }
     }
 }
@@ -174,8 +332,51 @@ impl OpenAIMessage {
     }
 }
 
+impl From<&Message> for OpenAIMessage {
+    /// Flatten a `Message` into the legacy string form. Image parts and audio
+    /// become `[image: ...]`/`[audio: ...]` placeholders so base64 payloads
+    /// are not inlined.
+    fn from(message: &Message) -> Self {
+        let content = match &message.content {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Image(images) => images
+                .iter()
+                .map(ImageUrl::placeholder)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            MessageContent::Mixed(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text(text) => text.clone(),
+                    ContentPart::Image(image) => image.placeholder(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            MessageContent::Audio(audio) => format!("[audio: {}]", audio.format),
+        };
+
+        Self {
+            role: message.role.as_str().to_string(),
+            content,
+            name: message.name.clone(),
+        }
+    }
+}
+
+impl From<Message> for OpenAIMessage {
+    fn from(message: Message) -> Self {
+        Self::from(&message)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ChatCompletion {
+    /// Response id assigned by the API, e.g. `"chatcmpl-..."`
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Unix timestamp of when the completion was created
+    #[serde(default)]
+    pub created: Option<u64>,
     pub choices: Vec<Choice>,
     pub model: String,
     pub usage: Option<Usage>,
@@ -201,14 +402,24 @@ pub enum OpenAiError {
     ResponseError(String),
 }
 
+/// Resolution at which a vision model looks at an image. `Low` costs a
+/// flat token amount; `Auto` lets the model decide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    High,
+    Low,
+    Auto,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ImageUrl {
     pub url: String,
-    pub detail: Option<String>, // e.g., "high"
+    pub detail: Option<ImageDetail>,
 }
 
 impl ImageUrl {
-    pub fn new(url: &str, detail: Option<String>) -> Self {
+    pub fn new(url: &str, detail: Option<ImageDetail>) -> Self {
         Self {
             url: format!("data:image/png;base64,{}", url),
             detail,
@@ -216,7 +427,7 @@ impl ImageUrl {
     }
 
     /// Create an ImageUrl from a regular URL
-    pub fn from_url(url: &str, detail: Option<String>) -> Self {
+    pub fn from_url(url: &str, detail: Option<ImageDetail>) -> Self {
         Self {
             url: url.to_string(),
             detail,
@@ -224,13 +435,30 @@ impl ImageUrl {
     }
 
     /// Create an ImageUrl from base64 data
-    pub fn from_base64(base64_data: &str, detail: Option<String>) -> Self {
+    pub fn from_base64(base64_data: &str, detail: Option<ImageDetail>) -> Self {
         Self {
             url: format!("data:image/png;base64,{}", base64_data),
             detail,
         }
     }
 
+    /// Create an `ImageUrl` embedding the image file at `path` as a data URI
+    /// with [`read_image_to_data_uri`](crate::common::read_image_to_data_uri):
+    /// the MIME type comes from the [`ImageFormat`](crate::common::types::ImageFormat)
+    /// of the extension (`png`, `jpg`/`jpeg`, `webp` or `gif`) and the file
+    /// bytes are sent as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Common`](crate::error::Error::Common) if the
+    /// extension is not a supported image type or the file cannot be read.
+    pub fn from_file(path: &std::path::Path) -> crate::Result<Self> {
+        Ok(Self::from_url(
+            &crate::common::read_image_to_data_uri(path)?,
+            None,
+        ))
+    }
+
     /// Validate the URL format
     pub fn validate(&self) -> Result<(), crate::error::Error> {
         if self.url.trim().is_empty() {
@@ -258,6 +486,21 @@ impl ImageUrl {
     pub fn is_http_url(&self) -> bool {
         self.url.starts_with("http")
     }
+
+    /// Short textual stand-in for the image, used when flattening messages to text
+    fn placeholder(&self) -> String {
+        if self.is_data_uri() {
+            let media_type = self
+                .url
+                .trim_start_matches("data:")
+                .split([';', ','])
+                .next()
+                .unwrap_or_default();
+            format!("[image: {media_type} data URI]")
+        } else {
+            format!("[image: {}]", self.url)
+        }
+    }
 }
 
 // Legacy type for backward compatibility
@@ -296,6 +539,10 @@ pub enum OpenAIModel {
     Gpt41,
     #[serde(rename = "text-embedding-3-large")]
     TextEmbedding3Large,
+    #[serde(rename = "tts-1")]
+    Tts1,
+    #[serde(rename = "tts-1-hd")]
+    Tts1Hd,
     Custom(String),
 }
 
@@ -307,6 +554,8 @@ impl std::fmt::Display for OpenAIModel {
             OpenAIModel::Gpt4oTranscribe => write!(f, "gpt-4o-transcribe"),
             OpenAIModel::Gpt41 => write!(f, "gpt-4.1"),
             OpenAIModel::TextEmbedding3Large => write!(f, "text-embedding-3-large"),
+            OpenAIModel::Tts1 => write!(f, "tts-1"),
+            OpenAIModel::Tts1Hd => write!(f, "tts-1-hd"),
             OpenAIModel::Custom(model) => write!(f, "{}", model),
         }
     }
@@ -315,31 +564,121 @@ impl std::fmt::Display for OpenAIModel {
 impl OpenAIModel {
     /// Check if the model supports chat completions
     pub fn supports_chat(&self) -> bool {
-        matches!(
-            self,
-            OpenAIModel::Gpt4o
-                | OpenAIModel::Gpt4oMini
-                | OpenAIModel::Gpt41
-                | OpenAIModel::Custom(_)
-        )
+        match self {
+            Self::Gpt4o | Self::Gpt4oMini | Self::Gpt41 | Self::Custom(_) => true,
+            Self::Gpt4oTranscribe | Self::TextEmbedding3Large | Self::Tts1 | Self::Tts1Hd => false,
+        }
     }
 
     /// Check if the model supports vision (image analysis)
     pub fn supports_vision(&self) -> bool {
-        matches!(self, OpenAIModel::Gpt4o | OpenAIModel::Custom(_))
+        match self {
+            Self::Gpt4o | Self::Custom(_) => true,
+            Self::Gpt4oMini
+            | Self::Gpt41
+            | Self::Gpt4oTranscribe
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd => false,
+        }
+    }
+
+    /// Check if the model accepts audio content in chat messages
+    #[must_use]
+    pub const fn supports_audio_input(&self) -> bool {
+        match self {
+            Self::Gpt4o | Self::Gpt4oMini | Self::Custom(_) => true,
+            Self::Gpt41
+            | Self::Gpt4oTranscribe
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd => false,
+        }
     }
 
     /// Check if the model supports audio transcription
     pub fn supports_transcription(&self) -> bool {
-        matches!(self, OpenAIModel::Gpt4oTranscribe)
+        match self {
+            Self::Gpt4oTranscribe => true,
+            Self::Gpt4o
+            | Self::Gpt4oMini
+            | Self::Gpt41
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd
+            | Self::Custom(_) => false,
+        }
+    }
+
+    /// Check if the model supports speech synthesis
+    #[must_use]
+    pub const fn supports_speech(&self) -> bool {
+        match self {
+            Self::Tts1 | Self::Tts1Hd | Self::Custom(_) => true,
+            Self::Gpt4o
+            | Self::Gpt4oMini
+            | Self::Gpt41
+            | Self::Gpt4oTranscribe
+            | Self::TextEmbedding3Large => false,
+        }
     }
 
     /// Check if the model supports embeddings
     pub fn supports_embeddings(&self) -> bool {
-        matches!(
-            self,
-            OpenAIModel::TextEmbedding3Large | OpenAIModel::Custom(_)
-        )
+        match self {
+            Self::TextEmbedding3Large | Self::Custom(_) => true,
+            Self::Gpt4o
+            | Self::Gpt4oMini
+            | Self::Gpt41
+            | Self::Gpt4oTranscribe
+            | Self::Tts1
+            | Self::Tts1Hd => false,
+        }
+    }
+
+    /// Check if the model returns several choices for one request via `n`.
+    /// Custom models are assumed not to, so `chat_n` fans out instead.
+    #[must_use]
+    pub const fn supports_multiple_choices(&self) -> bool {
+        match self {
+            Self::Gpt4o | Self::Gpt4oMini | Self::Gpt41 => true,
+            Self::Gpt4oTranscribe
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd
+            | Self::Custom(_) => false,
+        }
+    }
+
+    /// Check if the provider has deprecated the model. No built-in model is
+    /// deprecated yet; add a variant here when one is.
+    #[must_use]
+    pub const fn is_deprecated(&self) -> bool {
+        match self {
+            Self::Gpt4o
+            | Self::Gpt4oMini
+            | Self::Gpt4oTranscribe
+            | Self::Gpt41
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd
+            | Self::Custom(_) => false,
+        }
+    }
+
+    /// Suggested model to migrate to from a deprecated one
+    #[must_use]
+    pub const fn recommended_replacement(&self) -> Option<Self> {
+        match self {
+            Self::Gpt4o
+            | Self::Gpt4oMini
+            | Self::Gpt4oTranscribe
+            | Self::Gpt41
+            | Self::TextEmbedding3Large
+            | Self::Tts1
+            | Self::Tts1Hd
+            | Self::Custom(_) => None,
+        }
     }
 
     /// Get the maximum tokens for the model
@@ -350,6 +689,7 @@ impl OpenAIModel {
             OpenAIModel::Gpt41 => Some(128000),
             OpenAIModel::Gpt4oTranscribe => None,
             OpenAIModel::TextEmbedding3Large => None,
+            OpenAIModel::Tts1 | OpenAIModel::Tts1Hd => None,
             OpenAIModel::Custom(_) => None, // Unknown for custom models
         }
     }
@@ -359,8 +699,10 @@ impl OpenAIModel {
         let supported = match operation {
             "chat" => self.supports_chat(),
             "vision" => self.supports_vision(),
+            "audio" => self.supports_audio_input(),
             "transcription" => self.supports_transcription(),
             "embeddings" => self.supports_embeddings(),
+            "speech" => self.supports_speech(),
             _ => false,
         };
 
@@ -375,6 +717,9 @@ impl OpenAIModel {
     }
 }
 
+/// Most stop sequences the chat API accepts in one request
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
 #[derive(Debug, Clone)]
 pub struct ChatOptions {
     pub model: OpenAIModel,
@@ -383,6 +728,17 @@ pub struct ChatOptions {
     pub top_p: Option<f32>,
     pub stop: Option<Vec<String>>,
     pub user: Option<String>,
+    /// Number of independent choices to generate for the same prompt
+    pub n: Option<u8>,
+    /// Fail before sending when the estimated prompt tokens exceed the
+    /// model's context window; see [`OpenAIService::estimate_tokens`](super::OpenAIService::estimate_tokens).
+    /// Images are estimated from their detail, so a prompt with large
+    /// high-detail images can pass and still exceed the window.
+    pub enforce_context_limit: bool,
+    /// Treat a trailing assistant message as a prefill and start each
+    /// returned choice with it, for providers that continue the reply from
+    /// there instead of repeating it
+    pub prefill: bool,
 }
 
 impl Default for ChatOptions {
@@ -394,10 +750,40 @@ impl Default for ChatOptions {
             top_p: None,
             stop: None,
             user: None,
+            n: None,
+            enforce_context_limit: false,
+            prefill: false,
         }
     }
 }
 
+impl ChatOptions {
+    /// Check the options the API would otherwise reject with an opaque error
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OpenAIValidation`](crate::error::Error::OpenAIValidation)
+    /// if a stop sequence is empty or there are more than
+    /// [`MAX_STOP_SEQUENCES`].
+    pub fn validate(&self) -> Result<(), crate::error::Error> {
+        let Some(stop) = &self.stop else {
+            return Ok(());
+        };
+        if stop.len() > MAX_STOP_SEQUENCES {
+            return Err(crate::error::Error::OpenAIValidation(format!(
+                "At most {MAX_STOP_SEQUENCES} stop sequences are allowed, got {}",
+                stop.len()
+            )));
+        }
+        if let Some(index) = stop.iter().position(String::is_empty) {
+            return Err(crate::error::Error::OpenAIValidation(format!(
+                "Stop sequence {index} cannot be empty"
+            )));
+        }
+        Ok(())
+    }
+}
+
 pub struct ChatRequestBuilder {
     messages: Vec<Message>,
     options: ChatOptions,
@@ -444,11 +830,35 @@ impl ChatRequestBuilder {
         self
     }
 
+    /// Stop at the single sequence `stop`
+    #[must_use]
+    pub fn stop_str(self, stop: &str) -> Self {
+        self.stop(vec![stop.to_string()])
+    }
+
     pub fn user(mut self, user: String) -> Self {
         self.options.user = Some(user);
         self
     }
 
+    #[must_use]
+    pub const fn n(mut self, n: u8) -> Self {
+        self.options.n = Some(n);
+        self
+    }
+
+    #[must_use]
+    pub const fn enforce_context_limit(mut self, enforce: bool) -> Self {
+        self.options.enforce_context_limit = enforce;
+        self
+    }
+
+    #[must_use]
+    pub const fn prefill(mut self, prefill: bool) -> Self {
+        self.options.prefill = prefill;
+        self
+    }
+
     pub fn build(self) -> (Vec<Message>, ChatOptions) {
         (self.messages, self.options)
     }
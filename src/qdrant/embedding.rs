@@ -0,0 +1,161 @@
+use std::future::Future;
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::{
+    error::Error,
+    openai::{AIService, CachedOpenAIService, OpenAICompatibleEmbedder, OpenAIService},
+};
+
+/// Texts per request made by [`EmbeddingService::embed_batch_within`]
+pub const DEADLINE_BATCH_SIZE: usize = 16;
+
+/// Outcome of [`EmbeddingService::embed_batch_within`]
+#[derive(Debug, Default)]
+pub struct PartialEmbeddings {
+    /// Vectors of the leading inputs, in input order
+    pub embeddings: Vec<Vec<f32>>,
+    /// Inputs left when the deadline passed or a request failed, in input
+    /// order
+    pub unprocessed: Vec<String>,
+    /// Error of the request that stopped embedding early, if any
+    pub error: Option<Error>,
+}
+
+/// Turns text into vectors for [`QdrantService`](super::QdrantService).
+///
+/// Methods return `impl Future` rather than going through `async_trait`, so a
+/// concrete embedder is monomorphized into the service with no boxed future
+/// per call. Use [`DynEmbeddingService`] when the embedder is chosen at runtime.
+pub trait EmbeddingService: Send + Sync {
+    fn embed(&self, text: String) -> impl Future<Output = Result<Vec<f32>, Error>> + Send;
+
+    fn embed_batch(
+        &self,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<Vec<f32>>, Error>> + Send;
+
+    /// Embed `texts` in requests of [`DEADLINE_BATCH_SIZE`] until `deadline`,
+    /// returning the vectors done by then and the texts left over. A request
+    /// still running at the deadline is dropped and its texts count as
+    /// unprocessed.
+    ///
+    /// A failed request stops embedding as well: the vectors of the earlier
+    /// requests are kept, its texts and the rest are unprocessed and the
+    /// error is returned in [`PartialEmbeddings::error`]. So does a request
+    /// that returns a different number of vectors than it was sent texts.
+    fn embed_batch_within(
+        &self,
+        mut texts: Vec<String>,
+        deadline: Instant,
+    ) -> impl Future<Output = PartialEmbeddings> + Send {
+        async move {
+            let mut embeddings = Vec::with_capacity(texts.len());
+            let mut error = None;
+            while embeddings.len() < texts.len() && Instant::now() < deadline {
+                let start = embeddings.len();
+                let end = texts.len().min(start + DEADLINE_BATCH_SIZE);
+                let batch = self.embed_batch(texts[start..end].to_vec());
+                match tokio::time::timeout_at(deadline, batch).await {
+                    Ok(Ok(vectors)) if vectors.len() == end - start => embeddings.extend(vectors),
+                    Ok(Ok(vectors)) => {
+                        error = Some(Error::Other(format!(
+                            "Embedder returned {} embeddings for {} texts",
+                            vectors.len(),
+                            end - start
+                        )));
+                        break;
+                    }
+                    Ok(Err(err)) => {
+                        error = Some(err);
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            let unprocessed = texts.split_off(embeddings.len());
+            PartialEmbeddings {
+                embeddings,
+                unprocessed,
+                error,
+            }
+        }
+    }
+}
+
+impl EmbeddingService for OpenAIService {
+    fn embed(&self, text: String) -> impl Future<Output = Result<Vec<f32>, Error>> + Send {
+        AIService::embed(self, text)
+    }
+
+    fn embed_batch(
+        &self,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<Vec<f32>>, Error>> + Send {
+        AIService::embed_batch(self, texts)
+    }
+}
+
+impl EmbeddingService for CachedOpenAIService {
+    fn embed(&self, text: String) -> impl Future<Output = Result<Vec<f32>, Error>> + Send {
+        AIService::embed(self, text)
+    }
+
+    fn embed_batch(
+        &self,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<Vec<f32>>, Error>> + Send {
+        AIService::embed_batch(self, texts)
+    }
+}
+
+impl EmbeddingService for OpenAICompatibleEmbedder {
+    fn embed(&self, text: String) -> impl Future<Output = Result<Vec<f32>, Error>> + Send {
+        Self::embed(self, text)
+    }
+
+    fn embed_batch(
+        &self,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<Vec<f32>>, Error>> + Send {
+        Self::embed_batch(self, texts)
+    }
+}
+
+/// Object-safe counterpart of [`EmbeddingService`], implemented for every
+/// embedding service
+#[async_trait]
+pub trait DynEmbeddingService: Send + Sync {
+    async fn embed_dyn(&self, text: String) -> Result<Vec<f32>, Error>;
+
+    async fn embed_batch_dyn(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error>;
+}
+
+#[async_trait]
+impl<T: EmbeddingService> DynEmbeddingService for T {
+    async fn embed_dyn(&self, text: String) -> Result<Vec<f32>, Error> {
+        self.embed(text).await
+    }
+
+    async fn embed_batch_dyn(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        self.embed_batch(texts).await
+    }
+}
+
+/// Embedder selected at runtime
+pub type BoxedEmbedder = Box<dyn DynEmbeddingService>;
+
+impl EmbeddingService for BoxedEmbedder {
+    fn embed(&self, text: String) -> impl Future<Output = Result<Vec<f32>, Error>> + Send {
+        self.as_ref().embed_dyn(text)
+    }
+
+    fn embed_batch(
+        &self,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<Vec<f32>>, Error>> + Send {
+        self.as_ref().embed_batch_dyn(texts)
+    }
+}
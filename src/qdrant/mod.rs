@@ -1,11 +1,115 @@
+pub mod embedding;
 pub mod qdrant_service;
+pub mod query;
+pub mod rerank;
+pub mod snippet;
+
+pub use embedding::{
+    BoxedEmbedder, DynEmbeddingService, EmbeddingService, PartialEmbeddings, DEADLINE_BATCH_SIZE,
+};
+pub use qdrant_service::{
+    BoxedQdrantService, CollectionMigrationBuilder, MergeStrategy, MigrationProgress,
+    MultiSearchHit, MultiSearchResult, PointInput, QdrantConfig, QdrantConfigBuilder,
+    QdrantService,
+};
+pub use query::QueryRequest;
+pub use rerank::{mmr_rerank, reciprocal_rank_fusion, RRF_K};
+pub use snippet::{QueryTerms, SnippetOptions};
 
 #[cfg(test)]
 mod tests {
-    use std::{env, time::Duration};
+    use std::{
+        collections::HashMap,
+        env,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
+    use futures::TryStreamExt as _;
     use qdrant_client::Qdrant;
 
+    use qdrant_client::qdrant::{
+        vector_output::Vector, CollectionStatus, Condition, Distance, FieldType, Filter, Fusion,
+    };
+    use serde_json::json;
+
+    use crate::error::Error;
+
+    use super::{
+        embedding::{BoxedEmbedder, EmbeddingService, DEADLINE_BATCH_SIZE},
+        mmr_rerank,
+        qdrant_service::{
+            normalize_score, payload_value, point_id_to_string, point_payload,
+            raw_scores_lower_is_better, BatchUpsertResult, BoxedQdrantService, ExportOptions,
+            FacetCounter, FacetOptions, FacetValue, HnswParams, ImportOptions, MergeStrategy,
+            MigrateOptions, MigrationProgress, OptimizerParams, PointInput, PurgeOptions,
+            QdrantConfig, QdrantService, QuantizationConfig, ReplaceReport, SearchResult,
+            DEFAULT_SOURCE_FIELD, DEFAULT_TIMEOUT, QUANTIZED_HNSW_EF,
+        },
+        query::QueryRequest,
+        reciprocal_rank_fusion,
+        snippet::{extract_snippet, query_terms},
+        RRF_K,
+    };
+
+    /// Service backed by a real Qdrant instance, or `None` when credentials are missing
+    fn live_service(test_name: &str) -> Option<QdrantService> {
+        dotenv::dotenv().ok();
+        if env::var("QDRANT_URL").is_err()
+            || env::var("QDRANT_API_KEY").is_err()
+            || env::var("OPENAI_API_KEY").is_err()
+        {
+            eprintln!("Skipping {test_name}: QDRANT_URL, QDRANT_API_KEY or OPENAI_API_KEY not set");
+            return None;
+        }
+        QdrantService::new().ok()
+    }
+
+    /// Deterministic embedder that counts how many texts it embedded
+    #[derive(Clone, Default)]
+    struct FakeEmbedder {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl EmbeddingService for FakeEmbedder {
+        async fn embed(&self, text: String) -> Result<Vec<f32>, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            #[allow(clippy::cast_precision_loss)]
+            Ok(vec![text.len() as f32, 1.0, 0.0])
+        }
+
+        async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+            let mut vectors = Vec::with_capacity(texts.len());
+            for text in texts {
+                vectors.push(self.embed(text).await?);
+            }
+            Ok(vectors)
+        }
+    }
+
+    /// Live Qdrant with [`FakeEmbedder`], so no `OPENAI_API_KEY` is needed
+    fn live_fake_service(test_name: &str) -> Option<QdrantService<FakeEmbedder>> {
+        dotenv::dotenv().ok();
+        let Ok(config) = QdrantConfig::from_env() else {
+            eprintln!("Skipping {test_name}: QDRANT_URL or QDRANT_API_KEY not set");
+            return None;
+        };
+        QdrantService::with_embedder(config, FakeEmbedder::default()).ok()
+    }
+
+    fn result(id: &str, score: f32, vector: Vec<f32>) -> SearchResult {
+        SearchResult {
+            id: id.to_string(),
+            score,
+            payload: HashMap::new(),
+            vector: Some(vector),
+            snippet: None,
+        }
+    }
+
     #[tokio::test]
     async fn test() {
         dotenv::dotenv().ok();
@@ -31,4 +135,1699 @@ mod tests {
         let collections_list = client.list_collections().await;
         let _ = dbg!(collections_list);
     }
+
+    #[test]
+    fn test_mmr_rerank_prefers_diverse_results() {
+        let query = [1.0, 0.0];
+        // "b" is a near duplicate of "a", "c" is less relevant but points elsewhere
+        let results = vec![
+            result("a", 0.80, vec![0.8, 0.6]),
+            result("b", 0.79, vec![0.79, 0.61]),
+            result("c", 0.70, vec![0.7, -0.71]),
+        ];
+
+        let by_score: Vec<String> = results.iter().map(|r| r.id.clone()).collect();
+        assert_eq!(by_score, ["a", "b", "c"]);
+
+        let reranked: Vec<String> = mmr_rerank(results.clone(), &query, 0.5, 2)
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(reranked, ["a", "c"]);
+
+        // With lambda = 1.0 MMR degenerates to pure relevance
+        let relevance_only: Vec<String> = mmr_rerank(results, &query, 1.0, 3)
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(relevance_only, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let ids = |results: &[SearchResult]| -> Vec<String> {
+            results.iter().map(|r| r.id.clone()).collect()
+        };
+        let vector_hits = vec![
+            result("a", 0.9, vec![]),
+            result("b", 0.8, vec![]),
+            result("c", 0.7, vec![]),
+        ];
+        // Keyword scores are on another scale; only the ranks count
+        let keyword_hits = vec![result("c", 1.0, vec![]), result("d", 1.0, vec![])];
+
+        let balanced = reciprocal_rank_fusion(vector_hits.clone(), keyword_hits.clone(), 0.5, 3);
+        assert_eq!(ids(&balanced), ["c", "a", "b"]);
+        let expected = 0.5 / (RRF_K + 3.0) + 0.5 / (RRF_K + 1.0);
+        assert!((balanced[0].score - expected).abs() < f32::EPSILON);
+
+        let vector_only = reciprocal_rank_fusion(vector_hits.clone(), keyword_hits.clone(), 1.0, 4);
+        assert_eq!(ids(&vector_only)[..3], ["a", "b", "c"]);
+        let keyword_only = reciprocal_rank_fusion(vector_hits, keyword_hits, 0.0, 2);
+        assert_eq!(ids(&keyword_only), ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_keyword_and_hybrid_search() {
+        let Some(service) = live_fake_service("test_keyword_and_hybrid_search") else {
+            return;
+        };
+        let collection = format!("test_hybrid_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+        service
+            .create_payload_index(&collection, "text", FieldType::Text)
+            .await
+            .unwrap();
+        let points = [
+            "Tokio runtime internals",
+            "Rust async runtime overview",
+            "Gardening in spring",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, text)| PointInput::new(&(i + 1).to_string(), text, &HashMap::new()))
+        .collect();
+        service
+            .upsert_points_batch(&collection, points)
+            .await
+            .unwrap();
+
+        let keyword = service
+            .keyword_search(&collection, "text", "runtime", 10)
+            .await;
+        // FakeEmbedder vectors follow text length, so this is nearest to point 3
+        let hybrid = service
+            .hybrid_search(&collection, vec![19.0, 1.0, 0.0], "text", "runtime", 3, 0.3)
+            .await;
+        let invalid = service
+            .hybrid_search(&collection, vec![19.0, 1.0, 0.0], "text", "runtime", 3, 1.5)
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        let mut keyword: Vec<String> = keyword.unwrap().into_iter().map(|r| r.id).collect();
+        keyword.sort();
+        assert_eq!(keyword, ["1", "2"]);
+
+        let hybrid = hybrid.unwrap();
+        assert_eq!(hybrid.len(), 3);
+        assert_ne!(
+            hybrid[0].id, "3",
+            "keyword matches outweigh the vector ranking"
+        );
+        assert!(hybrid.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_with_vectors() {
+        let Some(service) = live_service("test_search_with_vectors") else {
+            return;
+        };
+        let collection = format!("test_vectors_{}", uuid::Uuid::new_v4().simple());
+
+        service.create_collection(&collection, 3072).await.unwrap();
+        service
+            .upsert_points(
+                &collection,
+                vec![
+                    PointInput::new("1", "Rust is a systems language", &HashMap::new()),
+                    PointInput::new("2", "Bananas are yellow", &HashMap::new()),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let with_vectors = service
+            .search(&collection, "programming languages")
+            .limit(2)
+            .with_vectors(true)
+            .search()
+            .await;
+        let without_vectors = service
+            .search(&collection, "programming languages")
+            .limit(2)
+            .search()
+            .await;
+        let summary = service.collection_summary(&collection).await;
+        let everything = service
+            .search(&collection, "programming languages")
+            .relevance_cutoff(0.0)
+            .search()
+            .await;
+        let nothing = service
+            .search(&collection, "programming languages")
+            .relevance_cutoff(1.01)
+            .search()
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        let summary = summary.unwrap();
+        assert_eq!(summary.vector_size, Some(3072));
+        assert_eq!(summary.distance, Some(Distance::Cosine));
+        assert_eq!(everything.unwrap().len(), 2);
+        assert!(nothing.unwrap().is_empty());
+
+        let with_vectors = with_vectors.unwrap();
+        assert_eq!(with_vectors.len(), 2);
+        assert!(with_vectors
+            .iter()
+            .all(|r| r.vector.as_ref().is_some_and(|v| v.len() == 3072)));
+        assert!(without_vectors.unwrap().iter().all(|r| r.vector.is_none()));
+    }
+
+    #[test]
+    fn test_facet_counter() {
+        let payloads = [
+            json!({"metadata": {"category": "books", "tags": ["a", "b"]}}),
+            json!({"metadata": {"category": "books", "tags": ["a"]}}),
+            json!({"metadata": {"category": "games"}}),
+            json!({"metadata": {}}),
+        ];
+
+        let mut categories = FacetCounter::default();
+        let mut tags = FacetCounter::default();
+        for payload in &payloads {
+            let payload: HashMap<String, serde_json::Value> =
+                serde_json::from_value(payload.clone()).unwrap();
+            categories.add(payload_value(&payload, "metadata.category"));
+            tags.add(payload_value(&payload, "metadata.tags"));
+        }
+
+        assert_eq!(
+            categories.into_values(None),
+            vec![
+                FacetValue {
+                    value: json!("books"),
+                    count: 2
+                },
+                FacetValue {
+                    value: json!("games"),
+                    count: 1
+                },
+            ]
+        );
+        let tags = tags.into_values(Some(1));
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].value, json!("a"));
+        assert_eq!(tags[0].count, 2);
+    }
+
+    #[test]
+    // Newer clients also describe always_ram through the memory mode
+    #[allow(deprecated)]
+    fn test_quantization_config() {
+        use qdrant_client::qdrant::{quantization_config::Quantization, CompressionRatio};
+
+        let scalar = Quantization::from(QuantizationConfig::Scalar {
+            quantile: 0.99,
+            always_ram: true,
+        });
+        assert!(matches!(
+            scalar,
+            Quantization::Scalar(q) if q.quantile == Some(0.99) && q.always_ram == Some(true)
+        ));
+
+        let product = Quantization::from(QuantizationConfig::Product {
+            compression: CompressionRatio::X16,
+        });
+        assert!(matches!(
+            product,
+            Quantization::Product(q) if q.compression == CompressionRatio::X16 as i32
+        ));
+
+        let binary = Quantization::from(QuantizationConfig::Binary { always_ram: false });
+        assert!(matches!(binary, Quantization::Binary(q) if q.always_ram == Some(false)));
+    }
+
+    #[tokio::test]
+    async fn test_create_quantized_collection() {
+        let Some(service) = live_fake_service("test_create_quantized_collection") else {
+            return;
+        };
+        let collection = format!("test_quantized_{}", uuid::Uuid::new_v4().simple());
+        let created = service
+            .create_collection_with_quantization(
+                &collection,
+                3,
+                QuantizationConfig::Scalar {
+                    quantile: 0.99,
+                    always_ram: true,
+                },
+            )
+            .await;
+        let points = vec![PointInput::new("1", "Some text", &HashMap::new())];
+        let upserted = service.upsert_points(&collection, points).await;
+        let hits = service
+            .search(&collection, "Some text")
+            .hnsw_ef(QUANTIZED_HNSW_EF)
+            .search()
+            .await;
+        service.delete_collection(&collection).await.ok();
+
+        created.unwrap();
+        upserted.unwrap();
+        assert_eq!(hits.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_by_metadata() {
+        let Some(service) = live_fake_service("test_count_by_metadata") else {
+            return;
+        };
+        let collection = format!("test_count_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+
+        let points = [("1", "wiki"), ("2", "wiki"), ("3", "blog")]
+            .iter()
+            .map(|(id, source)| {
+                let metadata = HashMap::from([("source".to_string(), json!(source))]);
+                PointInput::new(id, "Some text", &metadata)
+            })
+            .collect();
+        service.upsert_points(&collection, points).await.unwrap();
+
+        let wiki = service
+            .count_by_metadata(&collection, "source", "wiki")
+            .await;
+        let missing = service
+            .count_by_metadata(&collection, "source", "forum")
+            .await;
+        let all = service
+            .count_with_filter(&collection, Filter::default())
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        assert_eq!(wiki.unwrap(), 2);
+        assert_eq!(missing.unwrap(), 0);
+        assert_eq!(all.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_facet() {
+        let Some(service) = live_service("test_facet") else {
+            return;
+        };
+        let collection = format!("test_facet_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3072).await.unwrap();
+
+        let points = [
+            ("1", "red"),
+            ("2", "red"),
+            ("3", "red"),
+            ("4", "green"),
+            ("5", "green"),
+            ("6", "blue"),
+        ]
+        .iter()
+        .map(|(id, category)| {
+            let metadata = HashMap::from([("category".to_string(), json!(category))]);
+            PointInput::new(id, &format!("A {category} thing"), &metadata)
+        })
+        .collect();
+        service.upsert_points(&collection, points).await.unwrap();
+
+        let expected = vec![
+            FacetValue {
+                value: json!("red"),
+                count: 3,
+            },
+            FacetValue {
+                value: json!("green"),
+                count: 2,
+            },
+            FacetValue {
+                value: json!("blue"),
+                count: 1,
+            },
+        ];
+
+        // Without an index the scroll fallback is used
+        let scrolled = service
+            .facet(&collection, "metadata.category", FacetOptions::default())
+            .await;
+
+        service
+            .create_payload_index(&collection, "metadata.category", FieldType::Keyword)
+            .await
+            .unwrap();
+        let indexed = service
+            .facet(
+                &collection,
+                "metadata.category",
+                FacetOptions {
+                    exact: true,
+                    ..Default::default()
+                },
+            )
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        assert_eq!(scrolled.unwrap(), expected);
+        assert_eq!(indexed.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_normalize_score() {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+        // Cosine: (s + 1) / 2
+        assert!(close(normalize_score(1.0, Distance::Cosine), 1.0));
+        assert!(close(normalize_score(0.5, Distance::Cosine), 0.75));
+        assert!(close(normalize_score(0.0, Distance::Cosine), 0.5));
+        assert!(close(normalize_score(-1.0, Distance::Cosine), 0.0));
+
+        // Dot: logistic, 1 / (1 + e^-ln 3) = 0.75
+        assert!(close(normalize_score(0.0, Distance::Dot), 0.5));
+        assert!(close(normalize_score(3f32.ln(), Distance::Dot), 0.75));
+        assert!(close(normalize_score(-3f32.ln(), Distance::Dot), 0.25));
+
+        // Euclid / Manhattan distances: 1 / (1 + d)
+        assert!(close(normalize_score(0.0, Distance::Euclid), 1.0));
+        assert!(close(normalize_score(1.0, Distance::Euclid), 0.5));
+        assert!(close(normalize_score(3.0, Distance::Manhattan), 0.25));
+        assert!(close(normalize_score(4.0, Distance::Manhattan), 0.2));
+
+        // Unknown metric is only clamped
+        assert!(close(normalize_score(1.7, Distance::UnknownDistance), 1.0));
+        assert!(close(normalize_score(0.3, Distance::UnknownDistance), 0.3));
+
+        let hit = result("a", 0.6, vec![]);
+        assert!(close(hit.normalized_score(Distance::Cosine), 0.8));
+    }
+
+    #[tokio::test]
+    async fn test_payload_serialization_errors() {
+        let metadata = HashMap::from([
+            ("author".to_string(), json!("Ann")),
+            ("pages".to_string(), json!(12)),
+            ("draft".to_string(), json!(false)),
+            (
+                "source".to_string(),
+                json!({"kind": "pdf", "tags": ["a", "b"]}),
+            ),
+        ]);
+        let payload = point_payload(&PointInput::new("7", "text", &metadata)).unwrap();
+        assert_eq!(
+            serde_json::Value::from(payload),
+            json!({
+                "id": "7",
+                "text": "text",
+                "metadata": {
+                    "author": "Ann",
+                    "pages": 12,
+                    "draft": false,
+                    "source": {"kind": "pdf", "tags": ["a", "b"]},
+                },
+            })
+        );
+
+        let source = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let error = Error::PayloadSerialization {
+            point_id: "42".to_string(),
+            source,
+        };
+        let message = error.to_string();
+        assert!(message.contains("point 42"), "{message}");
+        assert!(message.contains("EOF"), "{message}");
+
+        // Invalid points are reported by index and id without embedding or
+        // contacting Qdrant
+        let embedder = FakeEmbedder::default();
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, embedder.clone()).unwrap();
+        let points = vec![
+            PointInput::new("doc-1", "first", &metadata),
+            PointInput::new("-3", "second", &metadata),
+        ];
+        let result = service.upsert_points_batch("test", points).await.unwrap();
+        assert_eq!(result.upserted, 0);
+        let errors: Vec<_> = result
+            .errors
+            .iter()
+            .map(|(index, error)| (*index, error.to_string()))
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 0);
+        assert!(errors[0].1.contains("'doc-1'"), "{}", errors[0].1);
+        assert_eq!(errors[1].0, 1);
+        assert!(errors[1].1.contains("'-3'"), "{}", errors[1].1);
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_health_monitor_marks_unreachable_service_unhealthy() {
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, FakeEmbedder::default()).unwrap();
+        assert!(service.is_healthy());
+        let points = || vec![PointInput::new("1", "text", &HashMap::new())];
+
+        // A failed manual check does not block writes without a monitor
+        assert!(service.health_check().await.is_err());
+        assert!(!service.is_healthy());
+        let batch = service.upsert_points_batch("test", points()).await;
+        assert!(matches!(batch, Err(Error::Qdrant(_))));
+
+        let failures = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&failures);
+        let monitor = service.start_health_monitor(Duration::from_millis(20), move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        let failed = tokio::time::timeout(Duration::from_secs(5), async {
+            while failures.load(Ordering::Relaxed) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(failed.is_ok());
+        assert!(!service.is_healthy());
+        assert!(service
+            .wait_healthy(Duration::from_millis(200))
+            .await
+            .is_err());
+
+        // The monitor gates batch upserts while it runs
+        let batch = service.upsert_points_batch("test", points()).await;
+        assert!(matches!(batch, Err(Error::Other(_))));
+
+        monitor.abort();
+        assert!(monitor.await.unwrap_err().is_cancelled());
+        let batch = service.upsert_points_batch("test", points()).await;
+        assert!(matches!(batch, Err(Error::Qdrant(_))));
+
+        // Dropping the last clone stops the monitor
+        let monitor = service.start_health_monitor(Duration::from_millis(20), |_| {});
+        let clone = service.clone();
+        drop(service);
+        assert!(!monitor.is_finished());
+        drop(clone);
+        let stopped = tokio::time::timeout(Duration::from_secs(5), monitor).await;
+        assert!(stopped.unwrap().unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn test_point_input_from_document() {
+        let document = crate::common::Document::from_text("Body text")
+            .with_source("docs/guide.md")
+            .with_metadata("lang", json!("en"));
+        let id = document.id.clone();
+        let created_at = document.created_at.to_rfc3339();
+
+        let point = PointInput::from(document);
+        assert_eq!(point.id, id);
+        assert!(uuid::Uuid::parse_str(&point.id).is_ok());
+        assert_eq!(point.text, "Body text");
+        assert_eq!(point.metadata["source"], json!("docs/guide.md"));
+        assert_eq!(point.metadata["lang"], json!("en"));
+        assert_eq!(point.metadata["created_at"], json!(created_at));
+    }
+
+    #[test]
+    fn test_batch_upsert_result() {
+        let clean = BatchUpsertResult {
+            upserted: 3,
+            errors: Vec::new(),
+        };
+        assert!(!clean.has_errors());
+        assert!((clean.success_rate() - 1.0).abs() < f64::EPSILON);
+        assert_eq!(clean.errors_as_result().unwrap(), 3);
+
+        let partial = || BatchUpsertResult {
+            upserted: 2,
+            errors: vec![
+                (1, Error::Other("bad id".to_string())),
+                (4, Error::Other("bad payload".to_string())),
+            ],
+        };
+        assert!(partial().has_errors());
+        assert!((partial().success_rate() - 0.5).abs() < f64::EPSILON);
+        assert!(matches!(
+            partial().errors_as_result(),
+            Err(Error::Other(message)) if message == "bad id"
+        ));
+        let Err(Error::Other(message)) = partial().into_result_strict() else {
+            panic!("expected a strict failure");
+        };
+        assert!(message.contains("2 of 4"));
+        assert!(message.contains("#4: Other error: bad payload"));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_collection() {
+        let Some(service) = live_service("test_migrate_collection") else {
+            return;
+        };
+        let id = uuid::Uuid::new_v4().simple();
+        let source = format!("test_migrate_src_{id}");
+        let re_embedded = format!("test_migrate_embed_{id}");
+        let copied = format!("test_migrate_copy_{id}");
+        service.create_collection(&source, 3072).await.unwrap();
+
+        let points = vec![
+            PointInput::new("1", "Rust is a systems language", &HashMap::new()),
+            PointInput::new("2", "Bananas are yellow", &HashMap::new()),
+        ];
+        service.upsert_points(&source, points).await.unwrap();
+        // No `text` payload, so it cannot be re-embedded
+        service
+            .upsert_point_with_vector(&source, 3, vec![0.1; 3072], HashMap::new())
+            .await
+            .unwrap();
+
+        let options = MigrateOptions {
+            re_embed: true,
+            batch_size: 2,
+            ..Default::default()
+        };
+        let re_embed_report = service
+            .migrate_collection(&source, &re_embedded, options)
+            .await;
+        let copy_report = service
+            .migrate_collection(&source, &copied, MigrateOptions::default())
+            .await;
+        let resize_without_re_embed = service
+            .migrate_collection(
+                &source,
+                &format!("test_migrate_resize_{id}"),
+                MigrateOptions {
+                    new_vector_size: Some(8),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        for collection in [&source, &re_embedded, &copied] {
+            service.delete_collection(collection).await.unwrap();
+        }
+
+        let re_embed_report = re_embed_report.unwrap();
+        assert_eq!(re_embed_report.migrated, 2);
+        assert_eq!(re_embed_report.skipped, 1);
+        assert!(re_embed_report.failed.is_empty());
+
+        let copy_report = copy_report.unwrap();
+        assert_eq!(copy_report.migrated, 3);
+        assert!(copy_report.failed.is_empty());
+
+        assert!(matches!(resize_without_re_embed, Err(Error::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_rejects_missing_embeddings() {
+        /// Drops the last embedding of every batch
+        struct ShortEmbedder;
+
+        impl EmbeddingService for ShortEmbedder {
+            async fn embed(&self, _: String) -> Result<Vec<f32>, Error> {
+                Ok(vec![1.0, 0.0, 0.0])
+            }
+
+            async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+                Ok(vec![vec![1.0, 0.0, 0.0]; texts.len() - 1])
+            }
+        }
+
+        // Nothing listens on port 1, so reaching Qdrant would fail differently
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, ShortEmbedder).unwrap();
+        let points = || {
+            (1..=4)
+                .map(|id| PointInput::new(&id.to_string(), "text", &HashMap::new()))
+                .collect::<Vec<_>>()
+        };
+        let missing = |result: Result<BatchUpsertResult, Error>| match result {
+            Err(Error::Other(message)) => message.contains("3 embeddings for 4 texts"),
+            _ => false,
+        };
+        assert!(missing(service.upsert_points_batch("test", points()).await));
+        assert!(missing(
+            service
+                .upsert_points_batch_chunked("test", points(), 4, 2)
+                .await
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_points_batch_chunked() {
+        let Some(service) = live_fake_service("test_upsert_points_batch_chunked") else {
+            return;
+        };
+        let collection = format!("test_chunked_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+
+        let mut points: Vec<PointInput> = (1..=25)
+            .map(|id| PointInput::new(&id.to_string(), &format!("text {id}"), &HashMap::new()))
+            .collect();
+        points.insert(3, PointInput::new("not-an-id", "text", &HashMap::new()));
+
+        let result = service
+            .upsert_points_batch_chunked(&collection, points, 10, 7)
+            .await;
+        let zero = service
+            .upsert_points_batch_chunked(&collection, Vec::new(), 0, 7)
+            .await;
+        let count = service
+            .count_with_filter(&collection, Filter::default())
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        let result = result.unwrap();
+        assert_eq!(result.upserted, 25);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].0, 3);
+        assert!(zero.is_err());
+        assert_eq!(count.unwrap(), 25);
+    }
+
+    #[tokio::test]
+    async fn test_collection_migration() {
+        let Some(service) = live_fake_service("test_collection_migration") else {
+            return;
+        };
+        let id = uuid::Uuid::new_v4().simple();
+        let source = format!("test_migration_src_{id}");
+        let target = format!("test_migration_dst_{id}");
+        service.create_collection(&source, 3).await.unwrap();
+
+        let points = ["one", "two", "three"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| PointInput::new(&(i + 1).to_string(), text, &HashMap::new()))
+            .collect();
+        service
+            .upsert_points_batch(&source, points)
+            .await
+            .unwrap()
+            .into_result_strict()
+            .unwrap();
+        // No `text` payload, so it cannot be re-embedded
+        service
+            .upsert_point_with_vector(&source, 4, vec![0.1; 3], HashMap::new())
+            .await
+            .unwrap();
+
+        let progress: Result<Vec<MigrationProgress>, Error> = service
+            .collection_migration(&source, &target, 3)
+            .batch_size(2)
+            .hnsw(HnswParams {
+                m: Some(8),
+                ..Default::default()
+            })
+            .stream()
+            .try_collect()
+            .await;
+        let migrated = service.collection_summary(&target).await;
+
+        for collection in [&source, &target] {
+            service.delete_collection(collection).await.unwrap();
+        }
+
+        let progress = progress.unwrap();
+        assert_eq!(progress.len(), 2);
+        let last = progress.last().unwrap();
+        assert_eq!(last.total, 4);
+        assert_eq!(last.processed, 4);
+        assert_eq!(last.failed, 1);
+        assert_eq!(migrated.unwrap().points_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_replace_document_validates_first() {
+        let embedder = FakeEmbedder::default();
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, embedder.clone()).unwrap();
+        let points = vec![
+            PointInput::new("1", "valid", &HashMap::new()),
+            PointInput::new("chunk-2", "invalid id", &HashMap::new()),
+        ];
+
+        // Rejected before Qdrant is asked for the old points or anything is embedded
+        let Err(Error::Other(message)) = service.replace_document("test", "doc.md", points).await
+        else {
+            panic!("expected the invalid point to be reported");
+        };
+        assert!(message.contains("'chunk-2'"), "{message}");
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replace_document() {
+        let Some(service) = live_service("test_replace_document") else {
+            return;
+        };
+        let collection = format!("test_replace_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3072).await.unwrap();
+
+        let chunks = |ids: std::ops::RangeInclusive<u64>, source: &str| -> Vec<PointInput> {
+            let metadata = HashMap::from([("source".to_string(), json!(source))]);
+            ids.map(|id| {
+                PointInput::new(&id.to_string(), &format!("{source} chunk {id}"), &metadata)
+            })
+            .collect()
+        };
+
+        service
+            .upsert_points_batch(&collection, chunks(1..=5, "doc-a"))
+            .await
+            .unwrap()
+            .into_result_strict()
+            .unwrap();
+        service
+            .upsert_points_batch(&collection, chunks(100..=101, "doc-b"))
+            .await
+            .unwrap()
+            .into_result_strict()
+            .unwrap();
+
+        // doc-a shrinks from 5 chunks to 3, reusing ids 1 and 2
+        let report = service
+            .replace_document(
+                &collection,
+                "doc-a",
+                chunks(1..=2, "doc-a")
+                    .into_iter()
+                    .chain(chunks(10..=10, "doc-a"))
+                    .collect(),
+            )
+            .await;
+
+        let source_filter = |source: &str| {
+            Some(Filter::must([Condition::matches(
+                "metadata.source",
+                source.to_string(),
+            )]))
+        };
+        let remaining = service
+            .scroll_page(&collection, source_filter("doc-a"), None, false, 100)
+            .await;
+        let untouched = service
+            .scroll_page(&collection, source_filter("doc-b"), None, false, 100)
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        assert_eq!(
+            report.unwrap(),
+            ReplaceReport {
+                deleted: 3,
+                inserted: 3
+            }
+        );
+        let mut ids: Vec<String> = remaining
+            .unwrap()
+            .0
+            .into_iter()
+            .filter_map(|point| point.id.map(point_id_to_string))
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["1", "10", "2"]);
+        assert_eq!(untouched.unwrap().0.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_across_tasks() {
+        fn assert_shareable<T: Send + Sync + Clone + 'static>() {}
+        assert_shareable::<QdrantService>();
+
+        let service = Arc::new(
+            QdrantService::with_config(QdrantConfig::builder("http://127.0.0.1:1").build())
+                .unwrap(),
+        );
+        let clone = (*service).clone();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let service = Arc::clone(&service);
+                tokio::spawn(async move { service.health_check().await.is_err() })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.await.unwrap());
+        }
+
+        // Clones share state with the original rather than rebuilding it
+        assert!(!service.is_healthy());
+        assert!(!clone.is_healthy());
+    }
+
+    #[test]
+    fn test_query_request_nesting() {
+        let request = QueryRequest::fusion(Fusion::Rrf)
+            .prefetch(
+                QueryRequest::nearest(vec![1.0, 0.0]).limit(200).prefetch(
+                    QueryRequest::nearest(vec![0.5, 0.5])
+                        .using("dense")
+                        .limit(1000),
+                ),
+            )
+            .prefetch(QueryRequest::nearest(vec![0.0, 1.0]).limit(200))
+            .limit(10)
+            .with_vectors(true)
+            .into_query_points("docs");
+
+        assert_eq!(request.collection_name, "docs");
+        assert_eq!(request.limit, Some(10));
+        assert!(request.query.is_some());
+        assert!(request.with_vectors.is_some());
+        assert_eq!(request.prefetch.len(), 2);
+        assert_eq!(request.prefetch[0].limit, Some(200));
+        assert_eq!(request.prefetch[0].prefetch.len(), 1);
+        assert_eq!(
+            request.prefetch[0].prefetch[0].using.as_deref(),
+            Some("dense")
+        );
+        assert_eq!(request.prefetch[0].prefetch[0].limit, Some(1000));
+        assert!(request.prefetch[1].prefetch.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_points_prefetch_fusion() {
+        let Some(service) = live_service("test_query_points_prefetch_fusion") else {
+            return;
+        };
+        let collection = format!("test_query_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+
+        let vectors = [
+            (1, vec![1.0, 0.0, 0.0]),
+            (2, vec![0.9, 0.1, 0.0]),
+            (3, vec![0.0, 1.0, 0.0]),
+            (4, vec![0.0, 0.9, 0.1]),
+            (5, vec![0.0, 0.0, 1.0]),
+        ];
+        for (id, vector) in vectors {
+            service
+                .upsert_point_with_vector(&collection, id, vector, HashMap::new())
+                .await
+                .unwrap();
+        }
+
+        // Both stages return 2, 1, 3 (in that order) and never 4 or 5
+        let fused = service
+            .query_points(
+                &collection,
+                QueryRequest::fusion(Fusion::Rrf)
+                    .prefetch(QueryRequest::nearest(vec![1.0, 0.2, 0.0]).limit(3))
+                    .prefetch(QueryRequest::nearest(vec![0.75, 0.66, 0.0]).limit(3))
+                    .limit(4),
+            )
+            .await;
+        // Re-rank a dense prefetch by a second query vector
+        let reranked = service
+            .query_points(
+                &collection,
+                QueryRequest::nearest(vec![0.0, 1.0, 0.0])
+                    .prefetch(QueryRequest::nearest(vec![0.7, 0.7, 0.0]).limit(4))
+                    .limit(2),
+            )
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        let fused = fused.unwrap();
+        assert_eq!(fused.len(), 3);
+        assert_eq!(fused[0].id, "2");
+        assert!(fused.iter().all(|hit| hit.id != "4" && hit.id != "5"));
+        assert!(fused.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        let reranked: Vec<&str> = reranked
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|hit| hit.id.as_str())
+            .collect();
+        assert_eq!(reranked, ["3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_custom_embedder() {
+        let config = || QdrantConfig::builder("http://127.0.0.1:1").build();
+
+        // Statically dispatched embedder
+        let embedder = FakeEmbedder::default();
+        let service = QdrantService::with_embedder(config(), embedder.clone()).unwrap();
+        let search = service
+            .search_points("test".to_string(), "query".to_string(), 1)
+            .await;
+        assert!(matches!(search, Err(Error::Qdrant(_))));
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 1);
+
+        // Boxed embedder chosen at runtime
+        let embedder = FakeEmbedder::default();
+        let boxed: BoxedEmbedder = Box::new(embedder.clone());
+        assert_eq!(
+            boxed.embed("abcd".to_string()).await.unwrap(),
+            [4.0, 1.0, 0.0]
+        );
+        let service: BoxedQdrantService = QdrantService::with_embedder(config(), boxed).unwrap();
+        let search = service.search("test", "query").search().await;
+        assert!(matches!(search, Err(Error::Qdrant(_))));
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_embed_batch_within() {
+        use tokio::time::Instant;
+
+        /// Takes 100ms per request and fails once `fail_after` texts are embedded
+        struct SlowEmbedder {
+            inner: FakeEmbedder,
+            fail_after: Option<usize>,
+        }
+
+        impl EmbeddingService for SlowEmbedder {
+            async fn embed(&self, text: String) -> Result<Vec<f32>, Error> {
+                self.inner.embed(text).await
+            }
+
+            async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if self.fail_after == Some(self.inner.calls.load(Ordering::Relaxed)) {
+                    return Err(Error::Other("embedder down".to_string()));
+                }
+                self.inner.embed_batch(texts).await
+            }
+        }
+
+        /// Returns one vector too few per request
+        struct ShortEmbedder;
+
+        impl EmbeddingService for ShortEmbedder {
+            async fn embed(&self, _text: String) -> Result<Vec<f32>, Error> {
+                Ok(vec![0.0])
+            }
+
+            async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+                Ok(vec![vec![0.0]; texts.len() - 1])
+            }
+        }
+
+        let embedder = SlowEmbedder {
+            inner: FakeEmbedder::default(),
+            fail_after: None,
+        };
+        let texts: Vec<String> = (0..40).map(|i| "x".repeat(i + 1)).collect();
+
+        // Two requests finish in time, the third is cut off
+        let deadline = Instant::now() + Duration::from_millis(250);
+        let partial = embedder.embed_batch_within(texts.clone(), deadline).await;
+        assert_eq!(partial.embeddings.len(), 2 * DEADLINE_BATCH_SIZE);
+        assert_eq!(partial.embeddings[5], [6.0, 1.0, 0.0]);
+        assert_eq!(partial.unprocessed, texts[2 * DEADLINE_BATCH_SIZE..]);
+        assert!(partial.error.is_none());
+
+        let done = embedder
+            .embed_batch_within(texts.clone(), Instant::now() + Duration::from_secs(5))
+            .await;
+        assert_eq!(done.embeddings.len(), texts.len());
+        assert!(done.unprocessed.is_empty());
+        assert!(done.error.is_none());
+
+        // A passed deadline makes no request
+        let calls = embedder.inner.calls.load(Ordering::Relaxed);
+        let late = embedder
+            .embed_batch_within(texts.clone(), Instant::now())
+            .await;
+        assert!(late.embeddings.is_empty());
+        assert_eq!(late.unprocessed, texts);
+        assert_eq!(embedder.inner.calls.load(Ordering::Relaxed), calls);
+
+        // A failed request keeps the vectors of the earlier ones
+        let failing = SlowEmbedder {
+            inner: FakeEmbedder::default(),
+            fail_after: Some(DEADLINE_BATCH_SIZE),
+        };
+        let partial = failing
+            .embed_batch_within(texts.clone(), Instant::now() + Duration::from_secs(5))
+            .await;
+        assert_eq!(partial.embeddings.len(), DEADLINE_BATCH_SIZE);
+        assert_eq!(partial.unprocessed, texts[DEADLINE_BATCH_SIZE..]);
+        assert!(matches!(partial.error, Some(Error::Other(_))));
+
+        // A request returning too few vectors stops embedding
+        let partial = ShortEmbedder
+            .embed_batch_within(texts.clone(), Instant::now() + Duration::from_secs(5))
+            .await;
+        assert!(partial.embeddings.is_empty());
+        assert_eq!(partial.unprocessed, texts);
+        assert!(matches!(
+            partial.error,
+            Some(Error::Other(message)) if message.contains("15 embeddings for 16 texts")
+        ));
+    }
+
+    #[test]
+    fn test_raw_score_merge_order() {
+        assert!(!raw_scores_lower_is_better(&[Distance::Cosine, Distance::Dot]).unwrap());
+        assert!(raw_scores_lower_is_better(&[Distance::Euclid, Distance::Manhattan]).unwrap());
+        assert!(!raw_scores_lower_is_better(&[]).unwrap());
+        assert!(matches!(
+            raw_scores_lower_is_better(&[Distance::Cosine, Distance::Euclid]),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_batch_report() {
+        use qdrant_client::qdrant::{PointId, RetrievedPoint};
+
+        let embedder = FakeEmbedder::default();
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, embedder.clone()).unwrap();
+
+        let point = |id: Option<u64>, text: Option<&str>| RetrievedPoint {
+            id: id.map(PointId::from),
+            payload: text
+                .map(|text| HashMap::from([("text".to_string(), text.into())]))
+                .unwrap_or_default(),
+            ..RetrievedPoint::default()
+        };
+        let points = vec![
+            point(None, Some("no id")),
+            point(Some(1), None),
+            point(Some(2), Some("two")),
+            point(Some(3), Some("three")),
+        ];
+
+        // Points without an id or text are skipped, the rest are embedded in
+        // one batch and reported failed as nothing listens on port 1
+        let report = service.migrate_batch("dst", points.clone(), true).await;
+        assert_eq!((report.migrated, report.skipped), (0, 2));
+        let failed: Vec<&str> = report.failed.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(failed, ["2", "3"]);
+        assert!(report.failed.iter().all(
+            |(_, e)| matches!(e, Error::Other(message) if message.starts_with("Upsert failed"))
+        ));
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 2);
+
+        // Copying needs stored vectors, which none of the points have
+        let report = service.migrate_batch("dst", points, false).await;
+        assert_eq!((report.migrated, report.skipped), (0, 4));
+        assert!(report.failed.is_empty());
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 2);
+
+        // An empty batch size is rejected before any request
+        let options = MigrateOptions {
+            batch_size: 0,
+            ..MigrateOptions::default()
+        };
+        let result = service.migrate_collection("src", "dst", options).await;
+        assert!(matches!(result, Err(Error::Config(_))));
+        let result = service
+            .migrate_collection("src", "dst", MigrateOptions::default())
+            .await;
+        assert!(matches!(result, Err(Error::Qdrant(_))));
+    }
+
+    #[tokio::test]
+    async fn test_search_multi_reports_errors_per_collection() {
+        let embedder = FakeEmbedder::default();
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, embedder.clone()).unwrap();
+
+        let collections = vec!["tenant_a".to_string(), "tenant_b".to_string()];
+        let merged = service
+            .search_multi(collections, "query", 5, MergeStrategy::RawScore)
+            .await
+            .unwrap();
+
+        assert!(merged.hits.is_empty());
+        let mut failed: Vec<&str> = merged
+            .errors
+            .iter()
+            .map(|(collection, _)| collection.as_str())
+            .collect();
+        failed.sort_unstable();
+        assert_eq!(failed, ["tenant_a", "tenant_b"]);
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 1);
+    }
+
+    /// Layer keeping the last value set on any span for each field, at
+    /// creation or later
+    #[derive(Clone, Default)]
+    struct RecordedFields(Arc<std::sync::Mutex<HashMap<String, String>>>);
+
+    impl tracing::field::Visit for RecordedFields {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RecordedFields {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(
+            &self,
+            _: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    impl RecordedFields {
+        fn get(&self, field: &str) -> Option<String> {
+            self.0.lock().unwrap().get(field).cloned()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_operation_span_fields() {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let recorded = RecordedFields::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorded.clone()));
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, FakeEmbedder::default()).unwrap();
+
+        assert!(service.create_collection("spans", 3).await.is_err());
+        assert_eq!(recorded.get("vector_size").as_deref(), Some("3"));
+        let quantized = service
+            .create_collection_with_quantization(
+                "spans",
+                5,
+                QuantizationConfig::Binary { always_ram: true },
+            )
+            .await;
+        assert!(quantized.is_err());
+        assert_eq!(recorded.get("vector_size").as_deref(), Some("5"));
+        let search = service
+            .search_points("spans".to_string(), "query".to_string(), 7)
+            .await;
+        assert!(search.is_err());
+        assert_eq!(recorded.get("limit").as_deref(), Some("7"));
+        assert_eq!(recorded.get("collection").as_deref(), Some("spans"));
+    }
+
+    #[tokio::test]
+    async fn test_search_span_attributes() {
+        use tracing::field::Empty;
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let recorded = RecordedFields::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorded.clone()));
+        let span = || {
+            tracing::info_span!(
+                "search",
+                vector.collection = Empty,
+                vector.results_count = Empty,
+                vector.query_duration_ms = Empty,
+                vector.hnsw_ef = Empty,
+                vector.score_threshold = Empty,
+            )
+        };
+
+        // Query settings are recorded even when the request fails
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_embedder(config, FakeEmbedder::default()).unwrap();
+        let search = service
+            .search("unreachable", "query")
+            .hnsw_ef(64)
+            .score_threshold(0.5)
+            .with_span(span())
+            .search()
+            .await;
+        assert!(search.is_err());
+        assert_eq!(
+            recorded.get("vector.collection").as_deref(),
+            Some("unreachable")
+        );
+        assert_eq!(recorded.get("vector.hnsw_ef").as_deref(), Some("64"));
+        assert_eq!(
+            recorded.get("vector.score_threshold").as_deref(),
+            Some("0.5")
+        );
+        assert!(recorded.get("vector.query_duration_ms").is_some());
+        assert_eq!(recorded.get("vector.results_count"), None);
+
+        let Some(service) = live_fake_service("test_search_span_attributes") else {
+            return;
+        };
+        let collection = format!("test_span_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+        service
+            .upsert_points(
+                &collection,
+                vec![
+                    PointInput::new("1", "one", &HashMap::new()),
+                    PointInput::new("2", "three", &HashMap::new()),
+                ],
+            )
+            .await
+            .unwrap();
+        let results = service
+            .search(&collection, "two")
+            .with_span(span())
+            .search()
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        assert_eq!(results.unwrap().len(), 2);
+        assert_eq!(recorded.get("vector.results_count").as_deref(), Some("2"));
+        assert!(recorded.get("vector.query_duration_ms").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_search_multi() {
+        dotenv::dotenv().ok();
+        let Some(service) = live_fake_service("test_search_multi") else {
+            return;
+        };
+        let id = uuid::Uuid::new_v4().simple();
+        let tenant_a = format!("test_multi_a_{id}");
+        let tenant_b = format!("test_multi_b_{id}");
+        service.create_collection(&tenant_a, 3).await.unwrap();
+        service.create_collection(&tenant_b, 3).await.unwrap();
+
+        // FakeEmbedder embeds "query" as [5, 1, 0]
+        let text = |text: &str| HashMap::from([("text".to_string(), json!(text))]);
+        for (collection, point, vector, content) in [
+            (&tenant_a, 1, vec![5.0, 1.0, 0.0], "apples"),
+            (&tenant_a, 2, vec![0.0, 0.0, 1.0], "pears"),
+            (&tenant_b, 1, vec![1.0, 1.0, 0.0], "engines"),
+        ] {
+            service
+                .upsert_point_with_vector(collection, point, vector, text(content))
+                .await
+                .unwrap();
+        }
+
+        let merged = service
+            .search_multi(
+                vec![
+                    tenant_a.clone(),
+                    tenant_b.clone(),
+                    format!("{tenant_a}_missing"),
+                ],
+                "query",
+                5,
+                MergeStrategy::Normalized,
+            )
+            .await;
+        service.delete_collection(&tenant_a).await.unwrap();
+        service.delete_collection(&tenant_b).await.unwrap();
+
+        let merged = merged.unwrap();
+        let hits: Vec<(&str, &serde_json::Value)> = merged
+            .hits
+            .iter()
+            .map(|hit| (hit.collection.as_str(), &hit.result.payload["text"]))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (tenant_a.as_str(), &json!("apples")),
+                (tenant_b.as_str(), &json!("engines")),
+                (tenant_a.as_str(), &json!("pears")),
+            ]
+        );
+        assert!(merged
+            .hits
+            .windows(2)
+            .all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(merged.errors.len(), 1);
+        assert_eq!(merged.errors[0].0, format!("{tenant_a}_missing"));
+    }
+
+    #[tokio::test]
+    async fn test_auto_create_collection() {
+        dotenv::dotenv().ok();
+        let Some(service) = live_fake_service("test_auto_create_collection") else {
+            return;
+        };
+        let id = uuid::Uuid::new_v4().simple();
+        let explicit = format!("test_upsert_or_create_{id}");
+        let automatic = format!("test_auto_create_{id}");
+
+        let points = vec![
+            PointInput::new("1", "first", &HashMap::new()),
+            PointInput::new("2", "second", &HashMap::new()),
+        ];
+        let existed_before = service.collection_exists(&explicit).await;
+        let created = service.upsert_or_create(&explicit, points.clone(), 3).await;
+        // The collection exists now, so the second call only upserts
+        let repeated = service.upsert_or_create(&explicit, points, 3).await;
+
+        let without_auto_create = service
+            .upsert_point_with_vector(&automatic, 1, vec![1.0, 0.0, 0.0], HashMap::new())
+            .await;
+        let auto_created = service
+            .clone()
+            .with_auto_create(3)
+            .upsert_point_with_vector(&automatic, 1, vec![1.0, 0.0, 0.0], HashMap::new())
+            .await;
+        let automatic_exists = service.collection_exists(&automatic).await;
+
+        service.delete_collection(&explicit).await.unwrap();
+        service.delete_collection(&automatic).await.ok();
+
+        assert!(!existed_before.unwrap());
+        assert_eq!(created.unwrap().into_result_strict().unwrap(), 2);
+        assert_eq!(repeated.unwrap().into_result_strict().unwrap(), 2);
+        assert!(without_auto_create.is_err());
+        auto_created.unwrap();
+        assert!(automatic_exists.unwrap());
+    }
+
+    #[test]
+    fn test_extract_snippet() {
+        let text = "Intro about cats. Rust ownership rules keep memory safe without \
+                    a garbage collector. Unrelated closing words here.";
+        let terms = query_terms("How does Rust ownership keep memory safe?");
+
+        assert_eq!(
+            extract_snippet(text, &terms, 40, false),
+            "Rust ownership rules keep memory safe"
+        );
+        assert_eq!(
+            extract_snippet(text, &terms, 40, true),
+            "**Rust** **ownership** rules **keep** **memory** **safe**"
+        );
+        // No matching word falls back to the start of the text
+        assert_eq!(
+            extract_snippet(text, &query_terms("zebra"), 20, false),
+            "Intro about cats"
+        );
+        // Short texts are returned whole, minus surrounding punctuation
+        assert_eq!(extract_snippet("Rust.", &terms, 100, false), "Rust");
+
+        // Char-based limits never split a multi-byte character
+        let text = "żółć gęślą jaźń zażółć gęślą jaźń";
+        let terms = query_terms("JAŹŃ zażółć");
+        assert_eq!(extract_snippet(text, &terms, 12, false), "jaźń zażółć");
+        assert_eq!(extract_snippet("żółwżółwżółw", &terms, 5, false), "żółwż");
+    }
+
+    #[tokio::test]
+    async fn test_purge_stale() {
+        let Some(service) = live_fake_service("test_purge_stale") else {
+            return;
+        };
+        let collection = format!("test_purge_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        let day = 24 * 60 * 60;
+        for (id, updated_at) in [
+            (1, now - 30 * day),
+            (2, now - 8 * day),
+            (3, now - 60),
+            (4, now),
+        ] {
+            service
+                .upsert_point_with_vector(
+                    &collection,
+                    id,
+                    vec![1.0, 0.0, 0.0],
+                    HashMap::from([("updated_at".to_string(), json!(updated_at))]),
+                )
+                .await
+                .unwrap();
+        }
+
+        let week = Duration::from_hours(7 * 24);
+        let unindexed = service
+            .purge_stale(&collection, PurgeOptions::new("updated_at", week))
+            .await;
+        service
+            .create_payload_index(&collection, "updated_at", FieldType::Integer)
+            .await
+            .unwrap();
+        let dry_run = service
+            .purge_stale(
+                &collection,
+                PurgeOptions {
+                    dry_run: true,
+                    ..PurgeOptions::new("updated_at", week)
+                },
+            )
+            .await;
+        let purged = service
+            .purge_stale(
+                &collection,
+                PurgeOptions {
+                    batch_size: 1,
+                    ..PurgeOptions::new("updated_at", week)
+                },
+            )
+            .await;
+        let remaining = service
+            .scroll_page(&collection, None, None, false, 100)
+            .await;
+        service.delete_collection(&collection).await.unwrap();
+
+        let Err(Error::Config(message)) = unindexed else {
+            panic!("expected a missing index error");
+        };
+        assert!(message.contains("create_payload_index"));
+        let dry_run = dry_run.unwrap();
+        assert_eq!((dry_run.stale, dry_run.deleted), (2, 0));
+        let purged = purged.unwrap();
+        assert_eq!((purged.stale, purged.deleted), (2, 2));
+        let mut remaining: Vec<String> = remaining
+            .unwrap()
+            .0
+            .into_iter()
+            .filter_map(|point| point.id.map(point_id_to_string))
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, ["3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_invalid_lines() {
+        let config = QdrantConfig::builder("http://127.0.0.1:1").build();
+        let service = QdrantService::with_config(config).unwrap();
+        let file = "{not json\n\n{\"id\": \"7\", \"payload\": {}}\n";
+
+        let strict = service
+            .import_collection("test", file.as_bytes(), ImportOptions::default())
+            .await;
+        let Err(Error::Other(message)) = strict else {
+            panic!("expected the first invalid line to fail the import");
+        };
+        assert!(message.starts_with("Line 1:"));
+
+        // Only invalid lines, so Qdrant is never contacted
+        let lenient = service
+            .import_collection(
+                "test",
+                file.as_bytes(),
+                ImportOptions {
+                    lenient: true,
+                    ..ImportOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(lenient.imported, 0);
+        let lines: Vec<usize> = lenient.skipped.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 3]);
+        assert!(lenient.skipped[1].1.to_string().contains("no vector"));
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let Some(service) = live_fake_service("test_export_import_round_trip") else {
+            return;
+        };
+        let id = uuid::Uuid::new_v4().simple();
+        let source = format!("test_export_{id}");
+        let restored = format!("test_import_{id}");
+        service.create_collection(&source, 3).await.unwrap();
+        for point in 1..=5_u8 {
+            service
+                .upsert_point_with_vector(
+                    &source,
+                    u64::from(point),
+                    vec![1.0, f32::from(point), 0.5],
+                    HashMap::from([
+                        ("text".to_string(), json!(format!("chunk {point}"))),
+                        (
+                            "meta".to_string(),
+                            json!({"rank": point, "tags": ["a", "b"]}),
+                        ),
+                    ]),
+                )
+                .await
+                .unwrap();
+        }
+
+        let mut file = Vec::new();
+        let exported = service
+            .export_collection(
+                &source,
+                &mut file,
+                ExportOptions {
+                    with_vectors: true,
+                    filter: None,
+                },
+            )
+            .await;
+        file.extend_from_slice(b"corrupt line\n");
+        let imported = service
+            .import_collection(
+                &restored,
+                file.as_slice(),
+                ImportOptions {
+                    batch_size: 2,
+                    recreate: true,
+                    lenient: true,
+                },
+            )
+            .await;
+        let restored_points = service.scroll_page(&restored, None, None, true, 100).await;
+
+        let copy = format!("test_copy_{id}");
+        service.create_collection(&copy, 3).await.unwrap();
+        let mut points = service
+            .export_points(
+                &source,
+                ExportOptions {
+                    with_vectors: true,
+                    filter: None,
+                },
+            )
+            .await
+            .unwrap();
+        points[0].vector = None;
+        let copied = service.import_points(&copy, points).await;
+        service.delete_collection(&source).await.unwrap();
+        service.delete_collection(&restored).await.ok();
+        service.delete_collection(&copy).await.ok();
+
+        let copied = copied.unwrap();
+        assert_eq!(copied.upserted, 4);
+        assert_eq!(copied.errors.len(), 1);
+        assert_eq!(copied.errors[0].0, 0);
+
+        assert_eq!(exported.unwrap(), 5);
+        let imported = imported.unwrap();
+        assert_eq!(imported.imported, 5);
+        assert_eq!(imported.skipped.len(), 1);
+        assert_eq!(imported.skipped[0].0, 6);
+
+        let restored_points = restored_points.unwrap().0;
+        assert_eq!(restored_points.len(), 5);
+        let sample = restored_points
+            .into_iter()
+            .find(|point| point.id.clone().map(point_id_to_string).as_deref() == Some("3"))
+            .unwrap();
+        assert_eq!(sample.payload["text"].clone().into_json(), json!("chunk 3"));
+        assert_eq!(
+            sample.payload["meta"].clone().into_json(),
+            json!({"rank": 3, "tags": ["a", "b"]})
+        );
+        let vector = sample.vectors.and_then(|vectors| vectors.get_vector());
+        assert!(matches!(vector, Some(Vector::Dense(dense)) if dense.data == [1.0, 3.0, 0.5]));
+    }
+
+    #[tokio::test]
+    async fn test_update_collection_config() {
+        let Some(service) = live_service("test_update_collection_config") else {
+            return;
+        };
+        let collection = format!("test_config_{}", uuid::Uuid::new_v4().simple());
+        service.create_collection(&collection, 3).await.unwrap();
+
+        let optimizer = service
+            .set_optimizer_config(
+                &collection,
+                OptimizerParams {
+                    indexing_threshold: Some(12_345),
+                    ..Default::default()
+                },
+            )
+            .await;
+        let hnsw = service
+            .set_hnsw_config(
+                &collection,
+                HnswParams {
+                    m: Some(24),
+                    ..Default::default()
+                },
+            )
+            .await;
+        let on_disk = service.set_on_disk_payload(&collection, true).await;
+        let summary = service.collection_summary(&collection).await;
+        service.delete_collection(&collection).await.unwrap();
+
+        optimizer.unwrap();
+        hnsw.unwrap();
+        on_disk.unwrap();
+        let summary = summary.unwrap();
+        assert_eq!(summary.status, CollectionStatus::Green);
+        assert_eq!(summary.optimizer.indexing_threshold, Some(12_345));
+        assert_eq!(summary.hnsw.m, Some(24));
+        assert!(summary.on_disk_payload);
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = QdrantConfig::builder("http://localhost:6334").build();
+        assert_eq!(config.url, "http://localhost:6334");
+        assert_eq!(config.api_key, None);
+        assert_eq!(config.timeout, DEFAULT_TIMEOUT);
+        assert_eq!(config.source_field, DEFAULT_SOURCE_FIELD);
+
+        let config = QdrantConfig::builder("https://qdrant.example.com")
+            .api_key("secret")
+            .timeout(Duration::from_secs(30))
+            .source_field("document_id")
+            .build();
+        assert_eq!(config.api_key.as_deref(), Some("secret"));
+        assert_eq!(config.source_field, "document_id");
+        assert_eq!(config.timeout, Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_vector_only_without_openai_key() {
+        // The embedder factory fails like OpenAIService::new without a key
+        let service: QdrantService = QdrantService::build(
+            QdrantConfig::builder("http://127.0.0.1:1").build(),
+            tokio::sync::OnceCell::new(),
+            || Err(Error::Config("OPENAI_API_KEY must be set".to_string())),
+        )
+        .expect("construction must not need OPENAI_API_KEY");
+
+        // Vector paths reach Qdrant (and fail to connect) rather than asking for a key
+        let upsert = service
+            .upsert_point_with_vector("test", 1, vec![0.1, 0.2, 0.3], HashMap::new())
+            .await;
+        assert!(matches!(upsert, Err(Error::Qdrant(_))), "{upsert:?}");
+        let search = service
+            .search_points_with_vector("test".to_string(), vec![0.1, 0.2, 0.3], 1)
+            .await;
+        assert!(matches!(search, Err(Error::Qdrant(_))));
+
+        // Text paths report the missing key only when they need embeddings
+        let search = service
+            .search_points("test".to_string(), "query".to_string(), 1)
+            .await;
+        assert!(
+            matches!(&search, Err(Error::Config(message)) if message.contains("OPENAI_API_KEY"))
+        );
+        let upsert = service
+            .upsert_point("test", PointInput::new("1", "text", &HashMap::new()))
+            .await;
+        assert!(matches!(upsert, Err(Error::Config(_))));
+    }
 }
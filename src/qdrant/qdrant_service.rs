@@ -1,43 +1,340 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{BufRead, Write},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use futures::{stream, Stream, StreamExt as _, TryStreamExt as _};
 use qdrant_client::{
     qdrant::{
-        CreateCollectionBuilder, Distance, PointStruct, SearchParamsBuilder, SearchPointsBuilder,
-        UpsertPointsBuilder, VectorParamsBuilder,
+        facet_value::Variant as FacetVariant, point_id::PointIdOptions, quantization_config,
+        vector_output::Vector, vectors_config::Config as VectorsConfig, BinaryQuantizationBuilder,
+        CollectionParamsDiffBuilder, CollectionStatus, CompressionRatio, Condition,
+        CountPointsBuilder, CreateCollectionBuilder, CreateFieldIndexCollectionBuilder,
+        DatetimeRange, DeletePointsBuilder, Distance, FacetCountsBuilder, FieldType, Filter,
+        HnswConfigDiff, HnswConfigDiffBuilder, OptimizersConfigDiff, OptimizersConfigDiffBuilder,
+        PayloadSchemaInfo, PayloadSchemaType, PointId, PointStruct, ProductQuantizationBuilder,
+        Range, RetrievedPoint, ScalarQuantizationBuilder, ScoredPoint, ScrollPointsBuilder,
+        SearchParamsBuilder, SearchPointsBuilder, Timestamp, UpdateCollectionBuilder,
+        UpsertPointsBuilder, VectorParamsBuilder, VectorsOutput,
     },
     Payload, Qdrant, QdrantError,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use tokio::{
+    sync::OnceCell,
+    task::{AbortHandle, JoinHandle},
+};
+use tracing::{debug, info, instrument, Span};
 
-use crate::{
-    error::Error,
-    openai::{AIService, OpenAIService},
+use super::{
+    embedding::{BoxedEmbedder, EmbeddingService},
+    query::QueryRequest,
+    snippet::{extract_snippet, query_terms, QueryTerms, SnippetOptions},
 };
+use crate::{common::Document, error::Error, openai::OpenAIService};
+
+/// Default `hnsw_ef` used for approximate vector searches
+pub const DEFAULT_HNSW_EF: u64 = 128;
+
+/// Suggested `hnsw_ef` for collections created with quantization, whose
+/// compressed vectors need a wider candidate list to keep recall
+pub const QUANTIZED_HNSW_EF: u64 = 256;
+
+/// How long the `set_*_config` helpers wait for a collection to turn green
+pub const DEFAULT_GREEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often [`QdrantService::wait_healthy`] and [`QdrantService::wait_green`]
+/// poll for a state change
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Page size used when scrolling through a whole collection
+const SCROLL_PAGE_SIZE: u32 = 256;
 
-pub struct QdrantService {
-    client: Qdrant,
-    openai_service: OpenAIService,
+/// Maximum number of collections searched at once by `search_multi`
+const MULTI_SEARCH_CONCURRENCY: usize = 8;
+
+/// Maximum number of embedding requests in flight in
+/// `upsert_points_batch_chunked`, to stay clear of API rate limits
+const EMBEDDING_CONCURRENCY: usize = 4;
+
+/// Request timeout used unless [`QdrantConfigBuilder::timeout`] overrides it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Payload field that links chunks to their source document by default
+pub const DEFAULT_SOURCE_FIELD: &str = "metadata.source";
+
+/// Connection settings for [`QdrantService`], created with [`QdrantConfig::builder`]
+/// or [`QdrantConfig::from_env`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct QdrantConfig {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub timeout: Duration,
+    /// Payload field used by [`QdrantService::replace_document`]
+    pub source_field: String,
 }
 
-impl QdrantService {
-    pub fn new() -> Result<Self, Error> {
+impl QdrantConfig {
+    #[must_use]
+    pub fn builder(url: impl Into<String>) -> QdrantConfigBuilder {
+        QdrantConfigBuilder::new(url)
+    }
+
+    /// Read `QDRANT_URL` and `QDRANT_API_KEY`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if either variable is unset.
+    pub fn from_env() -> Result<Self, Error> {
         let url = env::var("QDRANT_URL")
             .map_err(|_| Error::Config("QDRANT_URL must be set".to_string()))?;
         let api_key = env::var("QDRANT_API_KEY")
             .map_err(|_| Error::Config("QDRANT_API_KEY must be set".to_string()))?;
 
-        let client = Qdrant::from_url(&url)
-            .api_key(api_key)
+        Ok(Self::builder(url).api_key(api_key).build())
+    }
+}
+
+/// Fluent builder for [`QdrantConfig`]
+#[derive(Debug, Clone)]
+pub struct QdrantConfigBuilder {
+    config: QdrantConfig,
+}
+
+impl QdrantConfigBuilder {
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            config: QdrantConfig {
+                url: url.into(),
+                api_key: None,
+                timeout: DEFAULT_TIMEOUT,
+                source_field: DEFAULT_SOURCE_FIELD.to_string(),
+            },
+        }
+    }
+
+    #[must_use]
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.config.api_key = Some(api_key.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Payload field identifying a chunk's source document, as a dotted path
+    #[must_use]
+    pub fn source_field(mut self, field: impl Into<String>) -> Self {
+        self.config.source_field = field.into();
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> QdrantConfig {
+        self.config
+    }
+}
+
+/// Qdrant client with an embedding client `E` for text operations.
+///
+/// All state lives behind `Arc`s, so cloning is cheap and clones share the
+/// connection, embedder, distance cache and health flag. Share one instance
+/// across request handlers by cloning it or wrapping it in an `Arc`.
+pub struct QdrantService<E = OpenAIService> {
+    client: Arc<Qdrant>,
+    /// Created on the first text operation so vector-only use needs no `OPENAI_API_KEY`
+    embedder: Arc<OnceCell<E>>,
+    /// Creates the embedder when it was not passed in
+    make_embedder: fn() -> Result<E, Error>,
+    /// Distance metric per collection, filled by [`QdrantService::collection_distance`]
+    distances: Arc<Mutex<HashMap<String, Distance>>>,
+    /// Last known health, updated by [`QdrantService::health_check`] and the monitor
+    healthy: Arc<AtomicBool>,
+    /// Task of [`QdrantService::start_health_monitor`], stopped when the last
+    /// clone is dropped
+    monitor: Arc<HealthMonitor>,
+    source_field: Arc<str>,
+    /// Vector size used to create missing collections on upsert, see
+    /// [`QdrantService::with_auto_create`]
+    auto_create: Option<u64>,
+    /// Collections known to exist, so auto-create checks each one only once
+    known_collections: Arc<Mutex<HashSet<String>>>,
+}
+
+/// [`QdrantService`] with an embedder chosen at runtime
+pub type BoxedQdrantService = QdrantService<BoxedEmbedder>;
+
+impl<E> Clone for QdrantService<E> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Arc::clone(&self.client),
+            embedder: Arc::clone(&self.embedder),
+            make_embedder: self.make_embedder,
+            distances: Arc::clone(&self.distances),
+            healthy: Arc::clone(&self.healthy),
+            monitor: Arc::clone(&self.monitor),
+            source_field: Arc::clone(&self.source_field),
+            auto_create: self.auto_create,
+            known_collections: Arc::clone(&self.known_collections),
+        }
+    }
+}
+
+impl QdrantService {
+    pub fn new() -> Result<Self, Error> {
+        Self::with_config(QdrantConfig::from_env()?)
+    }
+
+    /// Connect with explicit settings. No embedding client is created until a
+    /// text-based method needs one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant client cannot be built.
+    pub fn with_config(config: QdrantConfig) -> Result<Self, Error> {
+        Self::build(config, OnceCell::new(), OpenAIService::new)
+    }
+}
+
+impl<E: EmbeddingService> QdrantService<E> {
+    /// Connect with explicit settings and a custom embedder
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant client cannot be built.
+    pub fn with_embedder(config: QdrantConfig, embedder: E) -> Result<Self, Error> {
+        Self::build(config, OnceCell::from(embedder), || {
+            Err(Error::Config("Embedder was not provided".to_string()))
+        })
+    }
+
+    /// Connect, creating the embedder with `make_embedder` on first use when
+    /// `embedder` is empty
+    pub(super) fn build(
+        config: QdrantConfig,
+        embedder: OnceCell<E>,
+        make_embedder: fn() -> Result<E, Error>,
+    ) -> Result<Self, Error> {
+        let client = Qdrant::from_url(&config.url)
+            .api_key(config.api_key)
+            .timeout(config.timeout)
             .build()
-            .map_err(|e| Error::Other(format!("Failed to create Qdrant client: {}", e)))?;
+            .map_err(|e| Error::Other(format!("Failed to create Qdrant client: {e}")))?;
 
         Ok(Self {
-            client,
-            openai_service: OpenAIService::new()?,
+            client: Arc::new(client),
+            embedder: Arc::new(embedder),
+            make_embedder,
+            distances: Arc::new(Mutex::new(HashMap::new())),
+            healthy: Arc::new(AtomicBool::new(true)),
+            monitor: Arc::default(),
+            source_field: config.source_field.into(),
+            auto_create: None,
+            known_collections: Arc::new(Mutex::new(HashSet::new())),
         })
     }
 
+    /// Create missing collections with `default_vector_size` and cosine
+    /// distance before upserting into them, e.g. on an application's first run
+    #[must_use]
+    pub const fn with_auto_create(mut self, default_vector_size: u64) -> Self {
+        self.auto_create = Some(default_vector_size);
+        self
+    }
+
+    /// Ping Qdrant and record the outcome for [`QdrantService::is_healthy`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Qdrant does not answer the health check.
+    pub async fn health_check(&self) -> Result<(), Error> {
+        ping(&self.client, &self.healthy).await
+    }
+
+    /// Spawn a task that runs a health check every `interval`, calling
+    /// `on_unhealthy` with the error whenever one fails. While it runs, batch
+    /// upserts fail fast when the last check failed.
+    ///
+    /// Abort the returned handle to stop monitoring. Starting another monitor
+    /// stops this one, and dropping the last clone of the service stops it
+    /// too.
+    pub fn start_health_monitor(
+        &self,
+        interval: Duration,
+        on_unhealthy: impl Fn(Error) + Send + Sync + 'static,
+    ) -> JoinHandle<()> {
+        let client = Arc::clone(&self.client);
+        let healthy = Arc::clone(&self.healthy);
+
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = ping(&client, &healthy).await {
+                    on_unhealthy(e);
+                }
+            }
+        });
+        if let Some(previous) = self.monitor.replace(handle.abort_handle()) {
+            previous.abort();
+        }
+        handle
+    }
+
+    /// Result of the most recent health check; `true` before any check ran
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Fail fast while a running health monitor reports Qdrant as down; a
+    /// failed [`QdrantService::health_check`] alone does not block writes
+    fn check_health(&self) -> Result<(), Error> {
+        if self.monitor.is_running() && !self.is_healthy() {
+            return Err(Error::Other(
+                "Qdrant is marked unhealthy, skipping batch upsert".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wait until [`QdrantService::is_healthy`] reports `true`. Relies on a
+    /// running health monitor to flip the flag back.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Qdrant is still unhealthy after `timeout`.
+    pub async fn wait_healthy(&self, timeout: Duration) -> Result<(), Error> {
+        let poll = async {
+            while !self.is_healthy() {
+                tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, poll)
+            .await
+            .map_err(|_| Error::Other(format!("Qdrant still unhealthy after {timeout:?}")))
+    }
+
+    /// Embedding client, initialized on first use
+    async fn embedder(&self) -> Result<&E, Error> {
+        let make_embedder = self.make_embedder;
+        self.embedder
+            .get_or_try_init(|| async move { make_embedder() })
+            .await
+    }
+
     pub async fn list_collections(&self) -> Result<Vec<String>, QdrantError> {
         let collections = self.client.list_collections().await?;
         Ok(collections
@@ -47,6 +344,57 @@ impl QdrantService {
             .collect())
     }
 
+    /// Whether a collection with this name exists
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn collection_exists(&self, collection_name: &str) -> Result<bool, Error> {
+        Ok(self.client.collection_exists(collection_name).await?)
+    }
+
+    /// Create the collection with `vector_size` unless it already exists
+    async fn ensure_collection(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+    ) -> Result<(), Error> {
+        let known = |collections: &Mutex<HashSet<String>>| {
+            collections
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .contains(collection_name)
+        };
+        if known(&self.known_collections) {
+            return Ok(());
+        }
+
+        if !self.collection_exists(collection_name).await? {
+            info!(collection_name, vector_size, "Creating missing collection");
+            if let Err(e) = self.create_collection(collection_name, vector_size).await {
+                // Another writer may have created it in the meantime
+                if !self.collection_exists(collection_name).await? {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        self.known_collections
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(collection_name.to_string());
+        Ok(())
+    }
+
+    /// Apply [`QdrantService::with_auto_create`] if it is enabled
+    async fn auto_create(&self, collection_name: &str) -> Result<(), Error> {
+        match self.auto_create {
+            Some(vector_size) => self.ensure_collection(collection_name, vector_size).await,
+            None => Ok(()),
+        }
+    }
+
+    #[instrument(skip_all, fields(collection = collection_name, vector_size = vector_size))]
     pub async fn create_collection(
         &self,
         collection_name: &str,
@@ -62,20 +410,247 @@ impl QdrantService {
         Ok(())
     }
 
+    /// Create a cosine collection whose vectors are also stored compressed,
+    /// trading some precision for a much smaller in-memory index.
+    ///
+    /// Searches on it should usually pass [`QUANTIZED_HNSW_EF`] to
+    /// [`QdrantSearchBuilder::hnsw_ef`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection already exists or the request fails.
+    #[instrument(skip_all, fields(collection = collection_name, vector_size = vector_size))]
+    pub async fn create_collection_with_quantization(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        quantization: QuantizationConfig,
+    ) -> Result<(), Error> {
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(collection_name)
+                    .vectors_config(VectorParamsBuilder::new(vector_size, Distance::Cosine))
+                    .quantization_config(quantization),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Point count and vector parameters of a collection
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection does not exist or the request fails.
+    pub async fn collection_summary(
+        &self,
+        collection_name: &str,
+    ) -> Result<CollectionSummary, Error> {
+        let info = self
+            .client
+            .collection_info(collection_name)
+            .await?
+            .result
+            .ok_or_else(|| {
+                Error::Other(format!("No info returned for collection {collection_name}"))
+            })?;
+
+        let status = info.status();
+        let config = info.config.unwrap_or_default();
+        // Newer servers also describe this through payload storage params
+        #[allow(deprecated)]
+        let on_disk_payload = config
+            .params
+            .as_ref()
+            .is_some_and(|params| params.on_disk_payload);
+        let vector_params = config
+            .params
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                VectorsConfig::Params(params) => Some(params),
+                VectorsConfig::ParamsMap(_) => None,
+            });
+
+        Ok(CollectionSummary {
+            name: collection_name.to_string(),
+            points_count: info.points_count.unwrap_or_default(),
+            vector_size: vector_params.as_ref().map(|params| params.size),
+            distance: vector_params.map(|params| params.distance()),
+            status,
+            hnsw: config.hnsw_config.map(HnswParams::from).unwrap_or_default(),
+            optimizer: config
+                .optimizer_config
+                .map(OptimizerParams::from)
+                .unwrap_or_default(),
+            on_disk_payload,
+        })
+    }
+
+    /// Update the HNSW index parameters and wait for the collection to turn green
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update fails or the collection is not green
+    /// within [`DEFAULT_GREEN_TIMEOUT`].
+    pub async fn set_hnsw_config(
+        &self,
+        collection_name: &str,
+        params: HnswParams,
+    ) -> Result<(), Error> {
+        self.update_collection(
+            UpdateCollectionBuilder::new(collection_name)
+                .hnsw_config(HnswConfigDiffBuilder::from(params)),
+        )
+        .await
+    }
+
+    /// Update the optimizer parameters and wait for the collection to turn green
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update fails or the collection is not green
+    /// within [`DEFAULT_GREEN_TIMEOUT`].
+    pub async fn set_optimizer_config(
+        &self,
+        collection_name: &str,
+        params: OptimizerParams,
+    ) -> Result<(), Error> {
+        let mut optimizers = OptimizersConfigDiffBuilder::default();
+        if let Some(indexing_threshold) = params.indexing_threshold {
+            optimizers = optimizers.indexing_threshold(indexing_threshold);
+        }
+        if let Some(default_segment_number) = params.default_segment_number {
+            optimizers = optimizers.default_segment_number(default_segment_number);
+        }
+        if let Some(max_segment_size) = params.max_segment_size {
+            optimizers = optimizers.max_segment_size(max_segment_size);
+        }
+        if let Some(memmap_threshold) = params.memmap_threshold {
+            optimizers = optimizers.memmap_threshold(memmap_threshold);
+        }
+
+        self.update_collection(
+            UpdateCollectionBuilder::new(collection_name).optimizers_config(optimizers),
+        )
+        .await
+    }
+
+    /// Move payloads to or from disk and wait for the collection to turn green
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update fails or the collection is not green
+    /// within [`DEFAULT_GREEN_TIMEOUT`].
+    pub async fn set_on_disk_payload(
+        &self,
+        collection_name: &str,
+        on_disk_payload: bool,
+    ) -> Result<(), Error> {
+        self.update_collection(
+            UpdateCollectionBuilder::new(collection_name)
+                .params(CollectionParamsDiffBuilder::default().on_disk_payload(on_disk_payload)),
+        )
+        .await
+    }
+
+    async fn update_collection(&self, request: UpdateCollectionBuilder) -> Result<(), Error> {
+        let request = request.build();
+        let collection_name = request.collection_name.clone();
+        self.client.update_collection(request).await?;
+        self.wait_green(&collection_name, DEFAULT_GREEN_TIMEOUT)
+            .await
+    }
+
+    /// Wait until the collection reports green status, i.e. no optimization
+    /// is pending or running
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Qdrant request fails or the collection is not
+    /// green after `timeout`.
+    pub async fn wait_green(&self, collection_name: &str, timeout: Duration) -> Result<(), Error> {
+        let poll = async {
+            loop {
+                if self.collection_summary(collection_name).await?.status == CollectionStatus::Green
+                {
+                    return Ok(());
+                }
+                tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, poll).await.map_err(|_| {
+            Error::Other(format!(
+                "Collection {collection_name} not green after {timeout:?}"
+            ))
+        })?
+    }
+
+    /// Distance metric of a collection, fetched once and then served from cache
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the summary request fails or the collection uses
+    /// named vectors, which have no single metric.
+    pub async fn collection_distance(&self, collection_name: &str) -> Result<Distance, Error> {
+        if let Some(distance) = self.cached_distance(collection_name) {
+            return Ok(distance);
+        }
+
+        let distance = self
+            .collection_summary(collection_name)
+            .await?
+            .distance
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "Collection {collection_name} has no single vector distance metric"
+                ))
+            })?;
+
+        self.distances
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(collection_name.to_string(), distance);
+        Ok(distance)
+    }
+
+    fn cached_distance(&self, collection_name: &str) -> Option<Distance> {
+        self.distances
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(collection_name)
+            .copied()
+    }
+
+    /// Drop a collection and all of its points
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection does not exist or the request fails.
+    #[instrument(skip_all, fields(collection = collection_name))]
+    pub async fn delete_collection(&self, collection_name: &str) -> Result<(), QdrantError> {
+        self.client.delete_collection(collection_name).await?;
+        self.distances
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(collection_name);
+        self.known_collections
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(collection_name);
+        Ok(())
+    }
+
     pub async fn upsert_point(
         &self,
         collection_name: &str,
         point: PointInput,
-    ) -> Result<(), QdrantError> {
-        let vector = self.openai_service.embed(point.text.clone()).await.unwrap();
-
-        let payload: Payload = json!(point).as_object().unwrap().clone().into();
+    ) -> Result<(), Error> {
+        let id = parse_point_id(&point.id)?;
+        let payload = point_payload(&point)?;
+        let vector = self.embedder().await?.embed(point.text).await?;
+        self.auto_create(collection_name).await?;
 
-        let points = vec![PointStruct::new(
-            point.id.parse::<u64>().unwrap(),
-            vector,
-            payload,
-        )];
+        let points = vec![PointStruct::new(id, vector, payload)];
 
         self.client
             .upsert_points(UpsertPointsBuilder::new(collection_name, points))
@@ -87,7 +662,7 @@ impl QdrantService {
         &self,
         collection_name: &str,
         points: Vec<PointInput>,
-    ) -> Result<(), QdrantError> {
+    ) -> Result<(), Error> {
         for point in points {
             self.upsert_point(collection_name, point).await?;
         }
@@ -95,24 +670,156 @@ impl QdrantService {
         Ok(())
     }
 
+    /// Embed all points with one request and upsert them with one call.
+    ///
+    /// Points with an invalid id or a payload that fails to serialize are
+    /// skipped and reported in [`BatchUpsertResult::errors`] instead of
+    /// failing the whole batch.
+    ///
+    /// Fails fast without contacting Qdrant while a running health monitor
+    /// reports it as unhealthy.
+    ///
+    /// Creates the collection first when [`QdrantService::with_auto_create`]
+    /// is enabled and it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a health monitor reports Qdrant as unhealthy, or
+    /// embedding or the Qdrant request fails.
+    #[instrument(skip_all, fields(collection = collection_name, point_count = points.len()))]
+    pub async fn upsert_points_batch(
+        &self,
+        collection_name: &str,
+        points: Vec<PointInput>,
+    ) -> Result<BatchUpsertResult, Error> {
+        self.check_health()?;
+
+        let (prepared, mut result) = prepare_points(points);
+        result.upserted = self.upsert_prepared(collection_name, prepared).await?;
+        Ok(result)
+    }
+
+    /// Embed and upsert points checked by [`prepare_points`] with one call
+    /// each, returning how many were written
+    async fn upsert_prepared(
+        &self,
+        collection_name: &str,
+        prepared: Vec<(PointId, Payload, String)>,
+    ) -> Result<usize, Error> {
+        if prepared.is_empty() {
+            return Ok(0);
+        }
+
+        let texts = prepared.iter().map(|(_, _, text)| text.clone()).collect();
+        let vectors = self.embedder().await?.embed_batch(texts).await?;
+
+        let points = embedded_points(prepared, vectors)?;
+        let upserted = points.len();
+
+        self.auto_create(collection_name).await?;
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, points))
+            .await?;
+
+        Ok(upserted)
+    }
+
+    /// [`QdrantService::upsert_points_batch`] for batches too large for one
+    /// embedding request.
+    ///
+    /// Texts are embedded in groups of `embedding_batch_size`, one API call
+    /// each with a few in flight at once. Once every group is embedded, the
+    /// points are upserted in groups of `upload_batch_size`, which can be
+    /// larger since Qdrant accepts bigger requests than the embedding API.
+    /// Invalid points are skipped and reported as in `upsert_points_batch`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a health monitor reports Qdrant as unhealthy, a
+    /// batch size is zero, or embedding or a Qdrant request fails. Nothing is
+    /// written when embedding fails; groups upserted before a failed upload
+    /// stay written.
+    #[instrument(skip_all, fields(collection = collection_name, point_count = points.len()))]
+    pub async fn upsert_points_batch_chunked(
+        &self,
+        collection_name: &str,
+        points: Vec<PointInput>,
+        embedding_batch_size: usize,
+        upload_batch_size: usize,
+    ) -> Result<BatchUpsertResult, Error> {
+        if embedding_batch_size == 0 || upload_batch_size == 0 {
+            return Err(Error::Other("Batch sizes must be at least 1".to_string()));
+        }
+        self.check_health()?;
+
+        let (prepared, mut result) = prepare_points(points);
+        if prepared.is_empty() {
+            return Ok(result);
+        }
+
+        let embedder = self.embedder().await?;
+        let vectors: Vec<Vec<Vec<f32>>> = stream::iter(prepared.chunks(embedding_batch_size))
+            .map(|group| {
+                let texts = group.iter().map(|(_, _, text)| text.clone()).collect();
+                embedder.embed_batch(texts)
+            })
+            .buffered(EMBEDDING_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let vectors = vectors.into_iter().flatten().collect();
+        let mut points = embedded_points(prepared, vectors)?;
+
+        self.auto_create(collection_name).await?;
+        while !points.is_empty() {
+            let rest = points.split_off(points.len().min(upload_batch_size));
+            let count = points.len();
+            self.client
+                .upsert_points(UpsertPointsBuilder::new(collection_name, points))
+                .await?;
+            result.upserted += count;
+            points = rest;
+        }
+
+        Ok(result)
+    }
+
+    /// [`QdrantService::upsert_points_batch`] into a collection that is first
+    /// created with `vector_size` if it does not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the collection cannot be checked or created, or
+    /// the batch upsert fails.
+    pub async fn upsert_or_create(
+        &self,
+        collection_name: &str,
+        points: Vec<PointInput>,
+        vector_size: u64,
+    ) -> Result<BatchUpsertResult, Error> {
+        self.ensure_collection(collection_name, vector_size).await?;
+        self.upsert_points_batch(collection_name, points).await
+    }
+
+    /// Search for `query`, embedded with the service's embedder. Payload
+    /// values come back as their JSON text, so strings keep their quotes;
+    /// [`QdrantService::search_points_with_vector`] returns typed payloads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if embedding the query or the Qdrant request fails.
+    #[instrument(skip_all, fields(collection = collection_name, limit = limit))]
     pub async fn search_points(
         &self,
         collection_name: String,
         query: String,
         limit: u64,
-    ) -> Result<Vec<QueryOutput>, QdrantError> {
-        let vector = self.openai_service.embed(query.clone()).await.unwrap();
+    ) -> Result<Vec<QueryOutput>, Error> {
+        let vector = self.embedder().await?.embed(query).await?;
 
-        let points = self
-            .client
-            .search_points(
-                SearchPointsBuilder::new(collection_name, vector, limit)
-                    .with_payload(true)
-                    .params(SearchParamsBuilder::default().hnsw_ef(128).exact(false)),
-            )
-            .await
-            .unwrap()
-            .result
+        Ok(self
+            .search_by_vector(collection_name, vector, limit)
+            .await?
             .into_iter()
             .map(|p| {
                 QueryOutput(
@@ -122,27 +829,2051 @@ impl QdrantService {
                         .collect(),
                 )
             })
-            .collect();
+            .collect())
+    }
 
-        Ok(points)
+    /// Upsert a point with a precomputed vector. Does not need `OPENAI_API_KEY`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn upsert_point_with_vector(
+        &self,
+        collection_name: &str,
+        id: u64,
+        vector: Vec<f32>,
+        payload: HashMap<String, serde_json::Value>,
+    ) -> Result<(), Error> {
+        let payload: Payload = serde_json::Map::from_iter(payload).into();
+        let points = vec![PointStruct::new(id, vector, payload)];
+        self.auto_create(collection_name).await?;
+
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, points))
+            .await?;
+
+        Ok(())
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PointInput {
-    pub id: String,
-    pub text: String,
-    pub metadata: HashMap<String, String>,
-}
+    /// Search with a precomputed query vector. Does not need `OPENAI_API_KEY`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn search_points_with_vector(
+        &self,
+        collection_name: String,
+        vector: Vec<f32>,
+        limit: u64,
+    ) -> Result<Vec<SearchResult>, Error> {
+        Ok(self
+            .search_by_vector(collection_name, vector, limit)
+            .await?
+            .into_iter()
+            .map(SearchResult::from)
+            .collect())
+    }
 
-impl PointInput {
-    pub fn new(id: &str, text: &str, metadata: &HashMap<String, String>) -> Self {
-        Self {
-            id: id.to_string(),
-            text: text.to_string(),
-            metadata: metadata.clone(),
+    async fn search_by_vector(
+        &self,
+        collection_name: String,
+        vector: Vec<f32>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>, Error> {
+        Ok(self
+            .client
+            .search_points(
+                SearchPointsBuilder::new(collection_name, vector, limit)
+                    .with_payload(true)
+                    .params(
+                        SearchParamsBuilder::default()
+                            .hnsw_ef(DEFAULT_HNSW_EF)
+                            .exact(false),
+                    ),
+            )
+            .await?
+            .result)
+    }
+
+    /// Points whose `field` matches every word of `query`, using Qdrant's
+    /// full-text filter.
+    ///
+    /// `field` needs a text payload index, see
+    /// [`QdrantService::create_payload_index`] with `FieldType::Text`;
+    /// without one Qdrant falls back to substring matching. Filter matches
+    /// are not ranked, so every hit scores 1.0 and they come in id order;
+    /// use [`QdrantService::hybrid_search`] to rank them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn keyword_search(
+        &self,
+        collection_name: &str,
+        field: &str,
+        query: &str,
+        limit: u64,
+    ) -> Result<Vec<SearchResult>, Error> {
+        let filter = Filter::must([Condition::matches_text(field, query)]);
+        let limit = u32::try_from(limit).unwrap_or(u32::MAX);
+        let (points, _) = self
+            .scroll_page(collection_name, Some(filter), None, false, limit)
+            .await?;
+
+        Ok(points
+            .into_iter()
+            .map(|point| SearchResult {
+                id: point.id.map(point_id_to_string).unwrap_or_default(),
+                score: 1.0,
+                payload: point
+                    .payload
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_json()))
+                    .collect(),
+                vector: None,
+                snippet: None,
+            })
+            .collect())
+    }
+
+    /// Combine vector search for `vector` with a keyword match on
+    /// `keyword_field`, as in [`QdrantService::keyword_search`].
+    ///
+    /// Qdrant's full-text filter does not score matches, so the keyword
+    /// ranking is a vector search for `vector` restricted to the points whose
+    /// field matches every word of `keyword_query`. Up to `limit` hits are
+    /// fetched from each ranking and merged with
+    /// [`reciprocal_rank_fusion`](super::reciprocal_rank_fusion), where
+    /// `vector_weight` in 0..=1 is the share of the unrestricted ranking: 1.0
+    /// is pure vector search, 0.0 only returns keyword matches. The `score`
+    /// of each hit is its fused score.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `vector_weight` is outside 0..=1 or a Qdrant
+    /// request fails.
+    pub async fn hybrid_search(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+        keyword_field: &str,
+        keyword_query: &str,
+        limit: u64,
+        vector_weight: f32,
+    ) -> Result<Vec<SearchResult>, Error> {
+        if !(0.0..=1.0).contains(&vector_weight) {
+            return Err(Error::Other(format!(
+                "vector_weight must be between 0 and 1, got {vector_weight}"
+            )));
         }
+
+        let keyword_request = SearchPointsBuilder::new(collection_name, vector.clone(), limit)
+            .with_payload(true)
+            .filter(Filter::must([Condition::matches_text(
+                keyword_field,
+                keyword_query,
+            )]));
+        let (vector_hits, keyword_hits) = futures::try_join!(
+            self.search_with_vector(collection_name, vector)
+                .limit(limit)
+                .search(),
+            async {
+                let response = self.client.search_points(keyword_request).await?;
+                Ok(response
+                    .result
+                    .into_iter()
+                    .map(SearchResult::from)
+                    .collect())
+            },
+        )?;
+
+        Ok(super::reciprocal_rank_fusion(
+            vector_hits,
+            keyword_hits,
+            vector_weight,
+            usize::try_from(limit).unwrap_or(usize::MAX),
+        ))
     }
-}
 
-pub struct QueryOutput(pub HashMap<String, String>);
+    /// Create a payload index so filters and facets on `field` are efficient.
+    ///
+    /// Fields of a [`PointInput`] live under `metadata`, e.g. `metadata.category`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn create_payload_index(
+        &self,
+        collection_name: &str,
+        field: &str,
+        field_type: FieldType,
+    ) -> Result<(), Error> {
+        self.client
+            .create_field_index(
+                CreateFieldIndexCollectionBuilder::new(collection_name, field, field_type)
+                    .wait(true),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Count how many points share each value of the payload `field`.
+    ///
+    /// Uses Qdrant's facet API when `field` has a payload index. Without an
+    /// index it falls back to scrolling the whole (filtered) collection and
+    /// counting client side, which is O(n) in the number of points.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Qdrant request fails.
+    pub async fn facet(
+        &self,
+        collection_name: &str,
+        field: &str,
+        options: FacetOptions,
+    ) -> Result<Vec<FacetValue>, Error> {
+        let info = self.client.collection_info(collection_name).await?;
+        let is_indexed = info
+            .result
+            .is_some_and(|info| info.payload_schema.contains_key(field));
+
+        if !is_indexed {
+            debug!(
+                collection = collection_name,
+                field, "No payload index for facet field, scrolling collection"
+            );
+            return self.facet_by_scroll(collection_name, field, options).await;
+        }
+
+        let mut request = FacetCountsBuilder::new(collection_name, field).exact(options.exact);
+        if let Some(filter) = options.filter {
+            request = request.filter(filter);
+        }
+        if let Some(limit) = options.limit {
+            request = request.limit(limit);
+        }
+
+        let response = self.client.facet(request).await?;
+
+        Ok(response
+            .hits
+            .into_iter()
+            .filter_map(|hit| {
+                let value = match hit.value?.variant? {
+                    FacetVariant::StringValue(value) => serde_json::Value::from(value),
+                    FacetVariant::IntegerValue(value) => serde_json::Value::from(value),
+                    FacetVariant::BoolValue(value) => serde_json::Value::from(value),
+                };
+                Some(FacetValue {
+                    value,
+                    count: hit.count,
+                })
+            })
+            .collect())
+    }
+
+    async fn facet_by_scroll(
+        &self,
+        collection_name: &str,
+        field: &str,
+        options: FacetOptions,
+    ) -> Result<Vec<FacetValue>, Error> {
+        let mut counts = FacetCounter::default();
+        let mut offset = None;
+
+        loop {
+            let (points, next_offset) = self
+                .scroll_page(
+                    collection_name,
+                    options.filter.clone(),
+                    offset,
+                    false,
+                    SCROLL_PAGE_SIZE,
+                )
+                .await?;
+            for point in points {
+                let payload: HashMap<String, serde_json::Value> = point
+                    .payload
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_json()))
+                    .collect();
+                counts.add(payload_value(&payload, field));
+            }
+
+            match next_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(counts.into_values(options.limit))
+    }
+
+    /// Exact number of points whose `metadata.{key}` payload field equals
+    /// `value`, e.g. the chunks ingested from one source.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn count_by_metadata(
+        &self,
+        collection_name: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<u64, Error> {
+        let filter = Filter::must([Condition::matches(
+            format!("metadata.{key}"),
+            value.to_string(),
+        )]);
+        self.count_with_filter(collection_name, filter).await
+    }
+
+    /// Exact number of points matching `filter`, counted by Qdrant without
+    /// fetching the points
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn count_with_filter(
+        &self,
+        collection_name: &str,
+        filter: Filter,
+    ) -> Result<u64, Error> {
+        let response = self
+            .client
+            .count(
+                CountPointsBuilder::new(collection_name)
+                    .filter(filter)
+                    .exact(true),
+            )
+            .await?;
+        Ok(response.result.map_or(0, |result| result.count))
+    }
+
+    /// Fetch one page of points, returning the offset of the next page if any
+    pub(crate) async fn scroll_page(
+        &self,
+        collection_name: &str,
+        filter: Option<Filter>,
+        offset: Option<PointId>,
+        with_vectors: bool,
+        limit: u32,
+    ) -> Result<(Vec<RetrievedPoint>, Option<PointId>), Error> {
+        let mut request = ScrollPointsBuilder::new(collection_name)
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(with_vectors);
+        if let Some(filter) = filter {
+            request = request.filter(filter);
+        }
+        if let Some(offset) = offset {
+            request = request.offset(offset);
+        }
+
+        let response = self.client.scroll(request).await?;
+        Ok((response.result, response.next_page_offset))
+    }
+
+    /// Replace all chunks of a source document with `new_points`.
+    ///
+    /// Existing points are found by the configured source field (see
+    /// [`QdrantConfigBuilder::source_field`]), so `new_points` must carry the
+    /// same `source_id` there. Every new point is validated before anything
+    /// is written. The new points are then upserted, and only old points
+    /// whose ids are not reused are deleted afterwards, so a failed upsert
+    /// never leaves the document missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any new point is invalid, or embedding or a Qdrant
+    /// request fails. Old points are kept in that case, and nothing is
+    /// written for invalid points.
+    pub async fn replace_document(
+        &self,
+        collection_name: &str,
+        source_id: &str,
+        new_points: Vec<PointInput>,
+    ) -> Result<ReplaceReport, Error> {
+        let (prepared, result) = prepare_points(new_points);
+        if let Some((_, e)) = result.errors.into_iter().next() {
+            return Err(e);
+        }
+        self.check_health()?;
+
+        let filter = Filter::must([Condition::matches(
+            self.source_field.as_ref(),
+            source_id.to_string(),
+        )]);
+        let existing = self.point_ids(collection_name, filter).await?;
+
+        let new_ids: HashSet<String> = prepared
+            .iter()
+            .map(|(id, _, _)| point_id_to_string(id.clone()))
+            .collect();
+        let inserted = self.upsert_prepared(collection_name, prepared).await?;
+
+        let stale: Vec<PointId> = existing
+            .into_iter()
+            .filter(|id| !new_ids.contains(&point_id_to_string(id.clone())))
+            .collect();
+        let deleted = stale.len();
+        if !stale.is_empty() {
+            self.client
+                .delete_points(
+                    DeletePointsBuilder::new(collection_name)
+                        .points(stale)
+                        .wait(true),
+                )
+                .await?;
+        }
+
+        Ok(ReplaceReport { deleted, inserted })
+    }
+
+    /// Delete points whose `timestamp_field` is older than `older_than`.
+    ///
+    /// The field needs a payload index: a `Datetime` index for RFC 3339
+    /// strings or an `Integer` index for Unix timestamps in seconds. Stale
+    /// points are scrolled and deleted `batch_size` at a time; with
+    /// `dry_run` they are only counted.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if the field has no datetime or integer
+    /// index, or an error if a Qdrant request fails. Batches deleted before
+    /// a failure stay deleted.
+    pub async fn purge_stale(
+        &self,
+        collection_name: &str,
+        options: PurgeOptions,
+    ) -> Result<PurgeReport, Error> {
+        let field = options.timestamp_field.as_str();
+        let info = self.client.collection_info(collection_name).await?;
+        let index_type = info.result.and_then(|info| {
+            info.payload_schema
+                .get(field)
+                .map(PayloadSchemaInfo::data_type)
+        });
+
+        let older_than = chrono::Duration::from_std(options.older_than)
+            .map_err(|e| Error::Config(format!("Invalid purge age: {e}")))?;
+        let cutoff = chrono::Utc::now() - older_than;
+        let condition = match index_type {
+            Some(PayloadSchemaType::Datetime) => Condition::datetime_range(
+                field,
+                DatetimeRange {
+                    lt: Some(Timestamp {
+                        seconds: cutoff.timestamp(),
+                        nanos: 0,
+                    }),
+                    ..Default::default()
+                },
+            ),
+            #[allow(clippy::cast_precision_loss)]
+            Some(PayloadSchemaType::Integer) => Condition::range(
+                field,
+                Range {
+                    lt: Some(cutoff.timestamp() as f64),
+                    ..Default::default()
+                },
+            ),
+            _ => {
+                return Err(Error::Config(format!(
+                    "purge_stale needs a datetime or integer payload index on {field} in \
+                     {collection_name}, create one with create_payload_index(\"{collection_name}\", \
+                     \"{field}\", FieldType::Datetime)"
+                )))
+            }
+        };
+        let filter = Filter::must([condition]);
+
+        let mut report = PurgeReport {
+            dry_run: options.dry_run,
+            ..PurgeReport::default()
+        };
+        let mut offset = None;
+        loop {
+            let (points, next_offset) = self
+                .scroll_page(
+                    collection_name,
+                    Some(filter.clone()),
+                    offset,
+                    false,
+                    options.batch_size,
+                )
+                .await?;
+            let ids: Vec<PointId> = points.into_iter().filter_map(|point| point.id).collect();
+            report.stale += ids.len();
+
+            if !options.dry_run && !ids.is_empty() {
+                let count = ids.len();
+                self.client
+                    .delete_points(
+                        DeletePointsBuilder::new(collection_name)
+                            .points(ids)
+                            .wait(true),
+                    )
+                    .await?;
+                report.deleted += count;
+                debug!(
+                    collection = collection_name,
+                    deleted = report.deleted,
+                    "Purged stale batch"
+                );
+            }
+
+            match next_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        info!(
+            collection = collection_name,
+            stale = report.stale,
+            deleted = report.deleted,
+            dry_run = report.dry_run,
+            "Purged stale points"
+        );
+        Ok(report)
+    }
+
+    /// Ids of every point matching `filter`
+    async fn point_ids(
+        &self,
+        collection_name: &str,
+        filter: Filter,
+    ) -> Result<Vec<PointId>, Error> {
+        let mut ids = Vec::new();
+        let mut offset = None;
+
+        loop {
+            let (points, next_offset) = self
+                .scroll_page(
+                    collection_name,
+                    Some(filter.clone()),
+                    offset,
+                    false,
+                    SCROLL_PAGE_SIZE,
+                )
+                .await?;
+            ids.extend(points.into_iter().filter_map(|point| point.id));
+
+            match next_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Copy every point of `source` into a new `destination` collection,
+    /// optionally re-embedding the stored `text` payload with the current
+    /// embedder, e.g. after switching embedding models.
+    ///
+    /// Pages of `batch_size` points are upserted with up to `concurrency`
+    /// batches in flight. Per-point failures are collected in the report
+    /// instead of aborting the run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source cannot be inspected or scrolled, the
+    /// destination cannot be created, or vectors would be copied into a
+    /// collection of a different size without `re_embed`. A `batch_size` of
+    /// 0 is an [`Error::Config`].
+    pub async fn migrate_collection(
+        &self,
+        source: &str,
+        destination: &str,
+        options: MigrateOptions,
+    ) -> Result<MigrationReport, Error> {
+        let (_, batches) = self
+            .start_migration(source.to_string(), destination.to_string(), options)
+            .await?;
+        let mut batches = pin!(batches);
+
+        let mut report = MigrationReport::default();
+        while let Some(batch) = batches.try_next().await? {
+            report.merge(batch);
+            info!(
+                source,
+                destination,
+                migrated = report.migrated,
+                failed = report.failed.len(),
+                skipped = report.skipped,
+                "Migrated batch"
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Start a re-embedding migration from `source` into a new `target`
+    /// collection of `target_vector_size`, reporting progress as a stream.
+    ///
+    /// Unlike [`QdrantService::migrate_collection`], batches run one at a
+    /// time and always re-embed the `text` payload, which suits switching
+    /// embedding models.
+    #[must_use]
+    pub fn collection_migration(
+        &self,
+        source: &str,
+        target: &str,
+        target_vector_size: u64,
+    ) -> CollectionMigrationBuilder<'_, E> {
+        CollectionMigrationBuilder {
+            service: self,
+            source: source.to_string(),
+            target: target.to_string(),
+            target_vector_size,
+            batch_size: SCROLL_PAGE_SIZE,
+            hnsw: None,
+        }
+    }
+
+    /// Check `source`, create `destination` and return the number of source
+    /// points with a stream of per-batch reports; the common part of
+    /// [`QdrantService::migrate_collection`] and
+    /// [`CollectionMigrationBuilder::stream`]
+    async fn start_migration(
+        &self,
+        source: String,
+        destination: String,
+        options: MigrateOptions,
+    ) -> Result<(u64, impl Stream<Item = Result<MigrationReport, Error>> + '_), Error> {
+        if options.batch_size == 0 {
+            return Err(Error::Config(
+                "MigrateOptions::batch_size must be greater than 0".to_string(),
+            ));
+        }
+        let summary = self.collection_summary(&source).await?;
+        let source_size = summary.vector_size;
+        let vector_size = options.new_vector_size.or(source_size).ok_or_else(|| {
+            Error::Config(format!("Cannot determine the vector size of {source}"))
+        })?;
+        if !options.re_embed && source_size != Some(vector_size) {
+            return Err(Error::Config(format!(
+                "Copying vectors of size {source_size:?} into size {vector_size} requires re_embed"
+            )));
+        }
+
+        let mut request = CreateCollectionBuilder::new(&destination)
+            .vectors_config(VectorParamsBuilder::new(vector_size, Distance::Cosine));
+        if let Some(hnsw) = options.hnsw {
+            request = request.hnsw_config(HnswConfigDiffBuilder::from(hnsw));
+        }
+        self.client.create_collection(request).await?;
+
+        let MigrateOptions {
+            re_embed,
+            batch_size,
+            concurrency,
+            ..
+        } = options;
+        let pages = stream::try_unfold(Some(None), move |offset| {
+            let source = source.clone();
+            async move {
+                let Some(offset) = offset else {
+                    return Ok(None);
+                };
+                let (points, next_offset) = self
+                    .scroll_page(&source, None, offset, !re_embed, batch_size)
+                    .await?;
+                if points.is_empty() {
+                    return Ok(None);
+                }
+                Ok::<_, Error>(Some((points, next_offset.map(Some))))
+            }
+        });
+        let batches = pages
+            .map_ok(move |points| {
+                let destination = destination.clone();
+                async move { Ok(self.migrate_batch(&destination, points, re_embed).await) }
+            })
+            .try_buffer_unordered(concurrency.max(1));
+
+        Ok((summary.points_count, batches))
+    }
+
+    pub(crate) async fn migrate_batch(
+        &self,
+        destination: &str,
+        points: Vec<RetrievedPoint>,
+        re_embed: bool,
+    ) -> MigrationReport {
+        let mut report = MigrationReport::default();
+        let mut ready = Vec::with_capacity(points.len());
+        let mut to_embed = Vec::new();
+
+        for point in points {
+            let Some(id) = point.id else {
+                report.skipped += 1;
+                continue;
+            };
+            if re_embed {
+                match point.payload.get("text").and_then(|text| text.as_str()) {
+                    Some(text) => to_embed.push((id, text.clone(), point.payload)),
+                    None => report.skipped += 1,
+                }
+            } else {
+                match dense_vector(point.vectors) {
+                    Some(vector) => ready.push((id, vector, point.payload)),
+                    None => report.skipped += 1,
+                }
+            }
+        }
+
+        if !to_embed.is_empty() {
+            let texts = to_embed.iter().map(|(_, text, _)| text.clone()).collect();
+            match self.embed_texts(texts).await {
+                Ok(vectors) => ready.extend(
+                    to_embed
+                        .into_iter()
+                        .zip(vectors)
+                        .map(|((id, _, payload), vector)| (id, vector, payload)),
+                ),
+                Err(e) => {
+                    let message = e.to_string();
+                    report.failed.extend(to_embed.into_iter().map(|(id, _, _)| {
+                        (
+                            point_id_to_string(id),
+                            Error::Other(format!("Embedding failed: {message}")),
+                        )
+                    }));
+                }
+            }
+        }
+
+        if ready.is_empty() {
+            return report;
+        }
+
+        let ids: Vec<String> = ready
+            .iter()
+            .map(|(id, _, _)| point_id_to_string(id.clone()))
+            .collect();
+        let points: Vec<PointStruct> = ready
+            .into_iter()
+            .map(|(id, vector, payload)| PointStruct::new(id, vector, Payload::from(payload)))
+            .collect();
+
+        match self
+            .client
+            .upsert_points(UpsertPointsBuilder::new(destination, points).wait(true))
+            .await
+        {
+            Ok(_) => report.migrated += ids.len(),
+            Err(e) => {
+                let message = e.to_string();
+                report.failed.extend(
+                    ids.into_iter()
+                        .map(|id| (id, Error::Other(format!("Upsert failed: {message}")))),
+                );
+            }
+        }
+
+        report
+    }
+
+    /// Write every point of a collection to `writer` as JSON Lines, one
+    /// [`ExportedPoint`] per line. Returns the number of points written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if scrolling the collection or writing fails.
+    pub async fn export_collection(
+        &self,
+        collection_name: &str,
+        mut writer: impl Write,
+        options: ExportOptions,
+    ) -> Result<usize, Error> {
+        let mut exported = 0;
+        self.for_each_exported(collection_name, options, |point| {
+            serde_json::to_writer(&mut writer, &point)?;
+            writer.write_all(b"\n")?;
+            exported += 1;
+            Ok(())
+        })
+        .await?;
+
+        writer.flush()?;
+        info!(
+            collection = collection_name,
+            exported, "Exported collection"
+        );
+        Ok(exported)
+    }
+
+    /// Collect every point of a collection in memory.
+    ///
+    /// Prefer [`QdrantService::export_collection`] for large collections, it
+    /// streams pages to a writer instead of buffering them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if scrolling the collection fails.
+    pub async fn export_points(
+        &self,
+        collection_name: &str,
+        options: ExportOptions,
+    ) -> Result<Vec<ExportedPoint>, Error> {
+        let mut exported = Vec::new();
+        self.for_each_exported(collection_name, options, |point| {
+            exported.push(point);
+            Ok(())
+        })
+        .await?;
+        Ok(exported)
+    }
+
+    async fn for_each_exported(
+        &self,
+        collection_name: &str,
+        options: ExportOptions,
+        mut f: impl FnMut(ExportedPoint) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut offset = None;
+        loop {
+            let (points, next_offset) = self
+                .scroll_page(
+                    collection_name,
+                    options.filter.clone(),
+                    offset,
+                    options.with_vectors,
+                    SCROLL_PAGE_SIZE,
+                )
+                .await?;
+            for point in points {
+                let Some(id) = point.id else {
+                    continue;
+                };
+                f(ExportedPoint {
+                    id: point_id_to_string(id),
+                    vector: dense_vector(point.vectors),
+                    payload: point
+                        .payload
+                        .into_iter()
+                        .map(|(key, value)| (key, value.into_json()))
+                        .collect(),
+                })?;
+            }
+
+            match next_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Upsert points returned by [`QdrantService::export_points`] with their
+    /// stored vectors, without re-embedding.
+    ///
+    /// Points without a vector are skipped and reported by their index in
+    /// `points`. The collection is created from the first point's vector size
+    /// if missing and auto-create is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Qdrant request fails.
+    pub async fn import_points(
+        &self,
+        collection_name: &str,
+        points: Vec<ExportedPoint>,
+    ) -> Result<BatchUpsertResult, Error> {
+        let mut result = BatchUpsertResult::default();
+        let batch_size = SCROLL_PAGE_SIZE as usize;
+        let mut batch = Vec::with_capacity(points.len().min(batch_size));
+        for (index, point) in points.into_iter().enumerate() {
+            match point.into_parts() {
+                Ok((id, vector, payload)) => batch.push(PointStruct::new(id, vector, payload)),
+                Err(e) => result.errors.push((index, e)),
+            }
+            if batch.len() >= batch_size {
+                result.upserted += self
+                    .import_batch(collection_name, std::mem::take(&mut batch))
+                    .await?;
+            }
+        }
+        if !batch.is_empty() {
+            result.upserted += self.import_batch(collection_name, batch).await?;
+        }
+        Ok(result)
+    }
+
+    /// Load points written by [`QdrantService::export_collection`], upserting
+    /// their stored vectors in batches without re-embedding.
+    ///
+    /// With `recreate`, the collection is dropped and created again with the
+    /// vector size of the first point. Lines that are not valid points, or
+    /// were exported without vectors, fail the import unless `lenient` is
+    /// set, in which case they are skipped and reported by line number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, a line is invalid and `lenient` is
+    /// off, or a Qdrant request fails. Batches upserted before the error are
+    /// kept.
+    pub async fn import_collection(
+        &self,
+        collection_name: &str,
+        reader: impl BufRead,
+        options: ImportOptions,
+    ) -> Result<ImportReport, Error> {
+        let batch_size = options.batch_size.max(1);
+        let mut report = ImportReport::default();
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut needs_recreate = options.recreate;
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (id, vector, payload) = match serde_json::from_str::<ExportedPoint>(&line)
+                .map_err(Error::from)
+                .and_then(ExportedPoint::into_parts)
+            {
+                Ok(parts) => parts,
+                Err(e) if options.lenient => {
+                    debug!(line_number, "Skipping invalid import line: {e}");
+                    report.skipped.push((line_number, e));
+                    continue;
+                }
+                Err(e) => return Err(Error::Other(format!("Line {line_number}: {e}"))),
+            };
+
+            if needs_recreate {
+                if self.collection_exists(collection_name).await? {
+                    self.delete_collection(collection_name).await?;
+                }
+                self.create_collection(collection_name, vector.len() as u64)
+                    .await?;
+                needs_recreate = false;
+            }
+
+            batch.push(PointStruct::new(id, vector, payload));
+            if batch.len() >= batch_size {
+                report.imported += self
+                    .import_batch(collection_name, std::mem::take(&mut batch))
+                    .await?;
+            }
+        }
+        if !batch.is_empty() {
+            report.imported += self.import_batch(collection_name, batch).await?;
+        }
+
+        info!(
+            collection = collection_name,
+            imported = report.imported,
+            skipped = report.skipped.len(),
+            "Imported collection"
+        );
+        Ok(report)
+    }
+
+    async fn import_batch(
+        &self,
+        collection_name: &str,
+        points: Vec<PointStruct>,
+    ) -> Result<usize, Error> {
+        let count = points.len();
+        self.auto_create(collection_name).await?;
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, points).wait(true))
+            .await?;
+        Ok(count)
+    }
+
+    async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        self.embedder().await?.embed_batch(texts).await
+    }
+
+    /// Run a Query API request, e.g. multi-stage retrieval with prefetch and fusion
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Qdrant request fails.
+    pub async fn query_points(
+        &self,
+        collection_name: &str,
+        request: QueryRequest,
+    ) -> Result<Vec<SearchResult>, Error> {
+        let response = self
+            .client
+            .query(request.into_query_points(collection_name))
+            .await?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .map(SearchResult::from)
+            .collect())
+    }
+
+    /// Start a search that embeds `query` before querying the collection
+    #[must_use]
+    pub fn search(&self, collection_name: &str, query: &str) -> QdrantSearchBuilder<'_, E> {
+        QdrantSearchBuilder::new(self, collection_name, SearchQuery::Text(query.to_string()))
+    }
+
+    /// Start a search with a precomputed query vector
+    #[must_use]
+    pub fn search_with_vector(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+    ) -> QdrantSearchBuilder<'_, E> {
+        QdrantSearchBuilder::new(self, collection_name, SearchQuery::Vector(vector))
+    }
+
+    /// Search several collections with one query and merge the hits.
+    ///
+    /// The query is embedded once, then up to `limit_per_collection` hits are
+    /// fetched from every collection concurrently. Each hit is tagged with
+    /// its source collection and the merged list is sorted by `merge`. A
+    /// failing collection is reported in [`MultiSearchResult::errors`] and
+    /// does not affect the others.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if embedding the query fails, or with
+    /// [`Error::Config`] if `merge` is [`MergeStrategy::RawScore`] and the
+    /// collections mix similarity and distance metrics.
+    pub async fn search_multi(
+        &self,
+        collections: Vec<String>,
+        query: &str,
+        limit_per_collection: u64,
+        merge: MergeStrategy,
+    ) -> Result<MultiSearchResult, Error> {
+        let vector = self.embedder().await?.embed(query.to_string()).await?;
+
+        let outcomes: Vec<_> = stream::iter(collections)
+            .map(|collection| {
+                let vector = vector.clone();
+                async move {
+                    let outcome = self
+                        .search_collection_scored(&collection, vector, limit_per_collection, merge)
+                        .await;
+                    (collection, outcome)
+                }
+            })
+            .buffer_unordered(MULTI_SEARCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut merged = MultiSearchResult::default();
+        let mut distances = Vec::new();
+        for (collection, outcome) in outcomes {
+            match outcome {
+                Ok((distance, hits)) => {
+                    distances.push(distance);
+                    merged
+                        .hits
+                        .extend(hits.into_iter().map(|(score, result)| MultiSearchHit {
+                            collection: collection.clone(),
+                            score,
+                            result,
+                        }));
+                }
+                Err(e) => merged.errors.push((collection, e)),
+            }
+        }
+        let lower_is_better = match merge {
+            MergeStrategy::RawScore => raw_scores_lower_is_better(&distances)?,
+            MergeStrategy::Normalized => false,
+        };
+        if lower_is_better {
+            merged.hits.sort_by(|a, b| a.score.total_cmp(&b.score));
+        } else {
+            merged.hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+
+        Ok(merged)
+    }
+
+    /// Hits of one collection paired with the score used for merging, and
+    /// the collection's metric
+    async fn search_collection_scored(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+        limit: u64,
+        merge: MergeStrategy,
+    ) -> Result<(Distance, Vec<(f32, SearchResult)>), Error> {
+        let distance = self.collection_distance(collection_name).await?;
+        let results = self
+            .search_with_vector(collection_name, vector)
+            .limit(limit)
+            .search()
+            .await?;
+
+        let hits = results
+            .into_iter()
+            .map(|result| {
+                let score = match merge {
+                    MergeStrategy::RawScore => result.score,
+                    MergeStrategy::Normalized => result.normalized_score(distance),
+                };
+                (score, result)
+            })
+            .collect();
+        Ok((distance, hits))
+    }
+}
+
+enum SearchQuery {
+    Text(String),
+    Vector(Vec<f32>),
+}
+
+/// Builder for vector searches created by [`QdrantService::search`]
+pub struct QdrantSearchBuilder<'a, E = OpenAIService> {
+    service: &'a QdrantService<E>,
+    collection_name: String,
+    query: SearchQuery,
+    limit: u64,
+    hnsw_ef: u64,
+    exact: bool,
+    score_threshold: Option<f32>,
+    relevance_cutoff: Option<f32>,
+    with_vectors: bool,
+    snippets: Option<SnippetOptions>,
+    span: Option<Span>,
+}
+
+impl<'a, E: EmbeddingService> QdrantSearchBuilder<'a, E> {
+    fn new(service: &'a QdrantService<E>, collection_name: &str, query: SearchQuery) -> Self {
+        Self {
+            service,
+            collection_name: collection_name.to_string(),
+            query,
+            limit: 10,
+            hnsw_ef: DEFAULT_HNSW_EF,
+            exact: false,
+            score_threshold: None,
+            relevance_cutoff: None,
+            with_vectors: false,
+            snippets: None,
+            span: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    #[must_use]
+    pub const fn hnsw_ef(mut self, hnsw_ef: u64) -> Self {
+        self.hnsw_ef = hnsw_ef;
+        self
+    }
+
+    #[must_use]
+    pub const fn exact(mut self, exact: bool) -> Self {
+        self.exact = exact;
+        self
+    }
+
+    #[must_use]
+    pub const fn score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = Some(score_threshold);
+        self
+    }
+
+    /// Drop hits whose [`SearchResult::normalized_score`] is below `cutoff`
+    /// (0..1), independent of the collection's distance metric
+    #[must_use]
+    pub const fn relevance_cutoff(mut self, cutoff: f32) -> Self {
+        self.relevance_cutoff = Some(cutoff);
+        self
+    }
+
+    /// Return the stored vector of every hit, e.g. for client-side re-ranking
+    #[must_use]
+    pub const fn with_vectors(mut self, with_vectors: bool) -> Self {
+        self.with_vectors = with_vectors;
+        self
+    }
+
+    /// Fill [`SearchResult::snippet`] with the part of each hit's `text`
+    /// payload that best matches the query terms. Runs locally on the
+    /// returned payloads.
+    #[must_use]
+    pub fn with_snippets(mut self, options: SnippetOptions) -> Self {
+        self.snippets = Some(options);
+        self
+    }
+
+    /// Record the search on `span`.
+    ///
+    /// `vector.collection`, `vector.hnsw_ef` and `vector.score_threshold` are
+    /// recorded before the request, `vector.query_duration_ms` after it, even
+    /// if it fails, and `vector.results_count` on success. Only fields the
+    /// span declares are recorded, so create it with them, e.g.
+    /// `info_span!("search", vector.collection = field::Empty)`.
+    #[must_use]
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Run the search
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if embedding the query or the Qdrant request fails.
+    pub async fn search(self) -> Result<Vec<SearchResult>, Error> {
+        if let Some(span) = &self.span {
+            span.record("vector.collection", self.collection_name.as_str());
+            span.record("vector.hnsw_ef", self.hnsw_ef);
+            if let Some(score_threshold) = self.score_threshold {
+                span.record("vector.score_threshold", score_threshold);
+            }
+        }
+        let snippet_terms =
+            self.snippets
+                .as_ref()
+                .map(|options| match (&options.query_terms_from, &self.query) {
+                    (QueryTerms::Custom(text), _)
+                    | (QueryTerms::Query, SearchQuery::Text(text)) => query_terms(text),
+                    (QueryTerms::Query, SearchQuery::Vector(_)) => HashSet::new(),
+                });
+
+        let vector = match self.query {
+            SearchQuery::Text(text) => self.service.embedder().await?.embed(text).await?,
+            SearchQuery::Vector(vector) => vector,
+        };
+
+        let distance = match self.relevance_cutoff {
+            Some(_) => Some(
+                self.service
+                    .collection_distance(&self.collection_name)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let mut request = SearchPointsBuilder::new(&self.collection_name, vector, self.limit)
+            .with_payload(true)
+            .with_vectors(self.with_vectors)
+            .params(
+                SearchParamsBuilder::default()
+                    .hnsw_ef(self.hnsw_ef)
+                    .exact(self.exact),
+            );
+        if let Some(score_threshold) = self.score_threshold {
+            request = request.score_threshold(score_threshold);
+        }
+
+        let started = Instant::now();
+        let response = self.service.client.search_points(request).await;
+        if let Some(span) = &self.span {
+            span.record(
+                "vector.query_duration_ms",
+                started.elapsed().as_secs_f64() * 1000.0,
+            );
+        }
+        let response = response?;
+
+        let results: Vec<SearchResult> = response
+            .result
+            .into_iter()
+            .map(SearchResult::from)
+            .filter(|result| match (self.relevance_cutoff, distance) {
+                (Some(cutoff), Some(distance)) => result.normalized_score(distance) >= cutoff,
+                _ => true,
+            })
+            .map(|mut result| {
+                if let (Some(options), Some(terms)) = (&self.snippets, &snippet_terms) {
+                    result.snippet = result
+                        .payload
+                        .get("text")
+                        .and_then(serde_json::Value::as_str)
+                        .map(|text| {
+                            extract_snippet(text, terms, options.max_chars, options.highlight)
+                        });
+                }
+                result
+            })
+            .collect();
+        if let Some(span) = &self.span {
+            span.record("vector.results_count", results.len());
+        }
+        Ok(results)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PointInput {
+    pub id: String,
+    pub text: String,
+    /// Stored as-is in the payload, so numbers, booleans and nested objects
+    /// keep their types for filtering and retrieval
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+impl PointInput {
+    pub fn new(id: &str, text: &str, metadata: &HashMap<String, serde_json::Value>) -> Self {
+        Self {
+            id: id.to_string(),
+            text: text.to_string(),
+            metadata: metadata.clone(),
+        }
+    }
+}
+
+/// The document's source and creation time are added to the metadata as
+/// `source` (matching [`DEFAULT_SOURCE_FIELD`]) and `created_at` (RFC 3339)
+impl From<Document> for PointInput {
+    fn from(document: Document) -> Self {
+        let mut metadata = document.metadata;
+        if let Some(source) = document.source {
+            metadata.insert("source".to_string(), serde_json::Value::String(source));
+        }
+        metadata.insert(
+            "created_at".to_string(),
+            serde_json::Value::String(document.created_at.to_rfc3339()),
+        );
+
+        Self {
+            id: document.id,
+            text: document.content,
+            metadata,
+        }
+    }
+}
+
+/// Slot for the health monitor task of a [`QdrantService`] and its clones
+#[derive(Default)]
+struct HealthMonitor(Mutex<Option<AbortHandle>>);
+
+impl HealthMonitor {
+    /// Keep `handle`, returning the previous monitor's
+    fn replace(&self, handle: AbortHandle) -> Option<AbortHandle> {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .replace(handle)
+    }
+
+    fn is_running(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        let handle = self
+            .0
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some(handle) = handle {
+            handle.abort();
+        }
+    }
+}
+
+async fn ping(client: &Qdrant, healthy: &AtomicBool) -> Result<(), Error> {
+    let result = client.health_check().await;
+    healthy.store(result.is_ok(), Ordering::Relaxed);
+    result?;
+    Ok(())
+}
+
+/// Qdrant point ids built from [`PointInput::id`] must be unsigned integers
+/// or UUIDs; UUIDs are normalized to the hyphenated lowercase form Qdrant
+/// returns
+fn parse_point_id(id: &str) -> Result<PointId, Error> {
+    if let Ok(num) = id.parse::<u64>() {
+        return Ok(num.into());
+    }
+    uuid::Uuid::parse_str(id)
+        .map(|uuid| uuid.to_string().into())
+        .map_err(|_| {
+            Error::Other(format!(
+                "Point id '{id}' must be an unsigned integer or a UUID"
+            ))
+        })
+}
+
+/// Ids and payloads of the valid `points` with their texts, and a result
+/// listing the invalid ones by index
+fn prepare_points(points: Vec<PointInput>) -> (Vec<(PointId, Payload, String)>, BatchUpsertResult) {
+    let mut result = BatchUpsertResult::default();
+    let mut prepared = Vec::with_capacity(points.len());
+    for (index, point) in points.into_iter().enumerate() {
+        match parse_point_id(&point.id).and_then(|id| Ok((id, point_payload(&point)?))) {
+            Ok((id, payload)) => prepared.push((id, payload, point.text)),
+            Err(e) => result.errors.push((index, e)),
+        }
+    }
+    (prepared, result)
+}
+
+/// Points from `prepared` and one embedding per point, in order
+fn embedded_points(
+    prepared: Vec<(PointId, Payload, String)>,
+    vectors: Vec<Vec<f32>>,
+) -> Result<Vec<PointStruct>, Error> {
+    if vectors.len() != prepared.len() {
+        return Err(Error::Other(format!(
+            "Embedder returned {} embeddings for {} texts",
+            vectors.len(),
+            prepared.len()
+        )));
+    }
+    Ok(prepared
+        .into_iter()
+        .zip(vectors)
+        .map(|((id, payload, _), vector)| PointStruct::new(id, vector, payload))
+        .collect())
+}
+
+/// Serialize a point into its Qdrant payload, naming the point on failure
+pub(crate) fn point_payload(point: &PointInput) -> Result<Payload, Error> {
+    match serde_json::to_value(point) {
+        Ok(serde_json::Value::Object(map)) => Ok(map.into()),
+        Ok(other) => Err(Error::Other(format!(
+            "Point {} serialized to {other} instead of a JSON object",
+            point.id
+        ))),
+        Err(source) => Err(Error::PayloadSerialization {
+            point_id: point.id.clone(),
+            source,
+        }),
+    }
+}
+
+/// Outcome of [`QdrantService::upsert_points_batch`]
+#[derive(Debug, Default)]
+pub struct BatchUpsertResult {
+    /// Number of points written
+    pub upserted: usize,
+    /// Skipped points as (index in the input, reason)
+    pub errors: Vec<(usize, Error)>,
+}
+
+impl BatchUpsertResult {
+    /// Whether any input point was skipped
+    #[must_use]
+    pub const fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// Fraction of input points that were written, 1.0 for an empty batch
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn success_rate(&self) -> f64 {
+        let total = self.upserted + self.errors.len();
+        if total == 0 {
+            return 1.0;
+        }
+        self.upserted as f64 / total as f64
+    }
+
+    /// Number of points written, or the first skipped point's error.
+    ///
+    /// The remaining errors are logged with `tracing::warn!` so they are not
+    /// lost when only the first one is surfaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first skipped point if any were skipped
+    pub fn errors_as_result(self) -> Result<usize, Error> {
+        let mut errors = self.errors.into_iter();
+        let Some((_, first)) = errors.next() else {
+            return Ok(self.upserted);
+        };
+        for (index, error) in errors {
+            tracing::warn!("Batch upsert skipped point at index {index}: {error}");
+        }
+        Err(first)
+    }
+
+    /// Number of points written, failing with a summary of every skipped
+    /// point on any partial failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Other`] listing the index and reason of each skipped
+    /// point if any were skipped
+    pub fn into_result_strict(self) -> Result<usize, Error> {
+        if self.errors.is_empty() {
+            return Ok(self.upserted);
+        }
+        let details = self
+            .errors
+            .iter()
+            .map(|(index, error)| format!("#{index}: {error}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(Error::Other(format!(
+            "Batch upsert partially failed, {} of {} points skipped: {details}",
+            self.errors.len(),
+            self.upserted + self.errors.len()
+        )))
+    }
+}
+
+pub struct QueryOutput(pub HashMap<String, String>);
+
+/// A single search hit with its typed payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: String,
+    pub score: f32,
+    pub payload: HashMap<String, serde_json::Value>,
+    /// Stored vector, only present when requested with `with_vectors(true)`
+    pub vector: Option<Vec<f32>>,
+    /// Best matching part of the `text` payload, only present when requested
+    /// with `with_snippets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+impl SearchResult {
+    /// Raw score mapped into 0..1 (higher is more relevant) for `distance`
+    #[must_use]
+    pub fn normalized_score(&self, distance: Distance) -> f32 {
+        normalize_score(self.score, distance)
+    }
+}
+
+/// How [`QdrantService::search_multi`] ranks hits from different collections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Sort by raw score, only meaningful when every collection uses the same
+    /// metric: descending for Cosine and Dot, ascending for the Euclid and
+    /// Manhattan distances. Collections mixing the two are rejected.
+    RawScore,
+    /// Sort by [`SearchResult::normalized_score`] for each collection's metric
+    #[default]
+    Normalized,
+}
+
+/// A search hit tagged with the collection it came from
+#[derive(Debug, Clone)]
+pub struct MultiSearchHit {
+    pub collection: String,
+    /// Score the hits were merged by, raw or normalized depending on the
+    /// [`MergeStrategy`]
+    pub score: f32,
+    pub result: SearchResult,
+}
+
+/// Outcome of [`QdrantService::search_multi`]
+#[derive(Debug, Default)]
+pub struct MultiSearchResult {
+    /// Hits from all collections, best first
+    pub hits: Vec<MultiSearchHit>,
+    /// Collections whose search failed, with the reason
+    pub errors: Vec<(String, Error)>,
+}
+
+/// Whether raw scores of collections with `distances` rank lower first, as
+/// distances do, or higher first, as similarities do
+pub(crate) fn raw_scores_lower_is_better(distances: &[Distance]) -> Result<bool, Error> {
+    let is_distance =
+        |distance: &Distance| matches!(distance, Distance::Euclid | Distance::Manhattan);
+    let lower_is_better = distances.first().is_some_and(is_distance);
+    if distances
+        .iter()
+        .any(|distance| is_distance(distance) != lower_is_better)
+    {
+        return Err(Error::Config(format!(
+            "Raw scores of collections with metrics {distances:?} cannot be merged, \
+             use MergeStrategy::Normalized"
+        )));
+    }
+    Ok(lower_is_better)
+}
+
+/// Map a raw Qdrant score into 0..1, higher meaning more relevant.
+///
+/// - Cosine similarity in -1..1 is shifted linearly: `(s + 1) / 2`.
+/// - Dot product is unbounded, so it goes through a logistic: `1 / (1 + e^-s)`.
+/// - Euclid and Manhattan scores are distances (lower is better): `1 / (1 + d)`.
+#[must_use]
+pub fn normalize_score(score: f32, distance: Distance) -> f32 {
+    let normalized = match distance {
+        Distance::Cosine => f32::midpoint(score, 1.0),
+        Distance::Dot => 1.0 / (1.0 + (-score).exp()),
+        Distance::Euclid | Distance::Manhattan => 1.0 / (1.0 + score.max(0.0)),
+        Distance::UnknownDistance => score,
+    };
+    normalized.clamp(0.0, 1.0)
+}
+
+/// HNSW index parameters; `None` fields are left unchanged on update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HnswParams {
+    /// Edges per node in the index graph
+    pub m: Option<u64>,
+    /// Neighbours considered while building the index
+    pub ef_construct: Option<u64>,
+    /// Segments smaller than this (in KB) are searched without the index
+    pub full_scan_threshold: Option<u64>,
+}
+
+impl From<HnswConfigDiff> for HnswParams {
+    fn from(config: HnswConfigDiff) -> Self {
+        Self {
+            m: config.m,
+            ef_construct: config.ef_construct,
+            full_scan_threshold: config.full_scan_threshold,
+        }
+    }
+}
+
+impl From<HnswParams> for HnswConfigDiffBuilder {
+    fn from(params: HnswParams) -> Self {
+        let mut hnsw = Self::default();
+        if let Some(m) = params.m {
+            hnsw = hnsw.m(m);
+        }
+        if let Some(ef_construct) = params.ef_construct {
+            hnsw = hnsw.ef_construct(ef_construct);
+        }
+        if let Some(full_scan_threshold) = params.full_scan_threshold {
+            hnsw = hnsw.full_scan_threshold(full_scan_threshold);
+        }
+        hnsw
+    }
+}
+
+/// Vector compression for [`QdrantService::create_collection_with_quantization`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuantizationConfig {
+    /// One byte per dimension, 4x smaller. `quantile` (e.g. 0.99) sets the
+    /// share of values the int8 range is fitted to, clipping outliers.
+    Scalar { quantile: f32, always_ram: bool },
+    /// Groups of dimensions encoded as centroids, 4x to 64x smaller at a
+    /// larger precision cost
+    Product { compression: CompressionRatio },
+    /// One bit per dimension, 32x smaller; best with high-dimensional
+    /// embeddings such as 1536 or 3072 dimensions
+    Binary { always_ram: bool },
+}
+
+impl From<QuantizationConfig> for quantization_config::Quantization {
+    fn from(config: QuantizationConfig) -> Self {
+        match config {
+            QuantizationConfig::Scalar {
+                quantile,
+                always_ram,
+            } => ScalarQuantizationBuilder::default()
+                .quantile(quantile)
+                .always_ram(always_ram)
+                .into(),
+            QuantizationConfig::Product { compression } => {
+                ProductQuantizationBuilder::new(compression.into()).into()
+            }
+            QuantizationConfig::Binary { always_ram } => {
+                BinaryQuantizationBuilder::new(always_ram).into()
+            }
+        }
+    }
+}
+
+/// Optimizer parameters; `None` fields are left unchanged on update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimizerParams {
+    /// Segments larger than this (in KB) get a vector index
+    pub indexing_threshold: Option<u64>,
+    /// Target number of segments
+    pub default_segment_number: Option<u64>,
+    /// Maximum segment size in KB
+    pub max_segment_size: Option<u64>,
+    /// Segments larger than this (in KB) are memory-mapped
+    pub memmap_threshold: Option<u64>,
+}
+
+impl From<OptimizersConfigDiff> for OptimizerParams {
+    fn from(config: OptimizersConfigDiff) -> Self {
+        Self {
+            indexing_threshold: config.indexing_threshold,
+            default_segment_number: config.default_segment_number,
+            max_segment_size: config.max_segment_size,
+            memmap_threshold: config.memmap_threshold,
+        }
+    }
+}
+
+/// Overview returned by [`QdrantService::collection_summary`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionSummary {
+    pub name: String,
+    pub points_count: u64,
+    /// `None` for collections with named vectors
+    pub vector_size: Option<u64>,
+    /// `None` for collections with named vectors
+    pub distance: Option<Distance>,
+    /// Green once no optimization is pending or running
+    pub status: CollectionStatus,
+    pub hnsw: HnswParams,
+    pub optimizer: OptimizerParams,
+    pub on_disk_payload: bool,
+}
+
+impl From<ScoredPoint> for SearchResult {
+    fn from(point: ScoredPoint) -> Self {
+        Self {
+            id: point.id.map(point_id_to_string).unwrap_or_default(),
+            score: point.score,
+            payload: point
+                .payload
+                .into_iter()
+                .map(|(key, value)| (key, value.into_json()))
+                .collect(),
+            vector: dense_vector(point.vectors),
+            snippet: None,
+        }
+    }
+}
+
+/// The default dense vector of a point, if it was returned
+fn dense_vector(vectors: Option<VectorsOutput>) -> Option<Vec<f32>> {
+    match vectors?.get_vector()? {
+        Vector::Dense(dense) => Some(dense.data),
+        _ => None,
+    }
+}
+
+pub(crate) fn point_id_to_string(id: PointId) -> String {
+    match id.point_id_options {
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        Some(PointIdOptions::Uuid(uuid)) => uuid,
+        None => String::new(),
+    }
+}
+
+/// Outcome of [`QdrantService::replace_document`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaceReport {
+    /// Old points removed because the new version no longer has their ids
+    pub deleted: usize,
+    /// New points upserted
+    pub inserted: usize,
+}
+
+/// Options for [`QdrantService::migrate_collection`]
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// Vector size of the destination, defaults to the source's
+    pub new_vector_size: Option<u64>,
+    /// Embed the `text` payload again instead of copying stored vectors
+    pub re_embed: bool,
+    /// Points scrolled and upserted per batch, at least 1
+    pub batch_size: u32,
+    /// Maximum number of batches processed at once
+    pub concurrency: usize,
+    /// HNSW parameters of the destination, defaults to the server's
+    pub hnsw: Option<HnswParams>,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            new_vector_size: None,
+            re_embed: false,
+            batch_size: SCROLL_PAGE_SIZE,
+            concurrency: 4,
+            hnsw: None,
+        }
+    }
+}
+
+/// Outcome of [`QdrantService::migrate_collection`]
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// Points written to the destination
+    pub migrated: usize,
+    /// Points that could not be migrated, by point id
+    pub failed: Vec<(String, Error)>,
+    /// Points without an id, a `text` payload (when re-embedding) or a stored vector
+    pub skipped: usize,
+}
+
+impl MigrationReport {
+    fn merge(&mut self, other: Self) {
+        self.migrated += other.migrated;
+        self.failed.extend(other.failed);
+        self.skipped += other.skipped;
+    }
+}
+
+/// Options for [`QdrantService::purge_stale`]
+#[derive(Debug, Clone)]
+pub struct PurgeOptions {
+    /// Payload field holding the point's last update time
+    pub timestamp_field: String,
+    /// Points last updated longer ago than this are stale
+    pub older_than: Duration,
+    /// Points scrolled and deleted per request
+    pub batch_size: u32,
+    /// Only count stale points instead of deleting them
+    pub dry_run: bool,
+}
+
+impl PurgeOptions {
+    /// Delete points whose `timestamp_field` is older than `older_than`, in
+    /// batches of the default scroll size
+    #[must_use]
+    pub fn new(timestamp_field: impl Into<String>, older_than: Duration) -> Self {
+        Self {
+            timestamp_field: timestamp_field.into(),
+            older_than,
+            batch_size: SCROLL_PAGE_SIZE,
+            dry_run: false,
+        }
+    }
+}
+
+/// Outcome of [`QdrantService::purge_stale`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    /// Points older than the cutoff
+    pub stale: usize,
+    /// Points deleted, 0 for a dry run
+    pub deleted: usize,
+    pub dry_run: bool,
+}
+
+/// One line of a [`QdrantService::export_collection`] file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedPoint {
+    pub id: String,
+    /// Absent when exported without vectors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+    #[serde(default)]
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ExportedPoint {
+    /// Split into the parts of a point ready to upsert
+    fn into_parts(self) -> Result<(PointId, Vec<f32>, Payload), Error> {
+        let vector = self
+            .vector
+            .filter(|vector| !vector.is_empty())
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "Point {} has no vector, export it with with_vectors",
+                    self.id
+                ))
+            })?;
+        let id: PointId = match self.id.parse::<u64>() {
+            Ok(num) => num.into(),
+            Err(_) => self.id.into(),
+        };
+        Ok((id, vector, Payload::from(self.payload)))
+    }
+}
+
+/// Options for [`QdrantService::export_collection`]
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Include stored vectors; required to import without re-embedding
+    pub with_vectors: bool,
+    /// Only export points matching this filter
+    pub filter: Option<Filter>,
+}
+
+/// Options for [`QdrantService::import_collection`]
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Points upserted per request
+    pub batch_size: usize,
+    /// Drop and create the collection before importing
+    pub recreate: bool,
+    /// Skip invalid lines instead of failing the import
+    pub lenient: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: SCROLL_PAGE_SIZE as usize,
+            recreate: false,
+            lenient: false,
+        }
+    }
+}
+
+/// Outcome of [`QdrantService::import_collection`]
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Points upserted
+    pub imported: usize,
+    /// Invalid lines skipped in lenient mode, by 1-based line number
+    pub skipped: Vec<(usize, Error)>,
+}
+
+/// Builder for [`QdrantService::collection_migration`]
+pub struct CollectionMigrationBuilder<'a, E = OpenAIService> {
+    service: &'a QdrantService<E>,
+    source: String,
+    target: String,
+    target_vector_size: u64,
+    batch_size: u32,
+    hnsw: Option<HnswParams>,
+}
+
+/// Progress of a [`CollectionMigrationBuilder::stream`], emitted after each batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationProgress {
+    /// Source points handled so far
+    pub processed: u64,
+    /// Source points when the migration started
+    pub total: u64,
+    /// Points not migrated, including those without a `text` payload
+    pub failed: u64,
+    pub elapsed: Duration,
+}
+
+impl<'a, E: EmbeddingService> CollectionMigrationBuilder<'a, E> {
+    /// Points scrolled, embedded and upserted per batch
+    #[must_use]
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = u32::try_from(batch_size).unwrap_or(u32::MAX).max(1);
+        self
+    }
+
+    /// HNSW parameters of the target collection
+    #[must_use]
+    pub const fn hnsw(mut self, params: HnswParams) -> Self {
+        self.hnsw = Some(params);
+        self
+    }
+
+    /// Create the target collection and migrate batch by batch.
+    ///
+    /// The stream yields a [`MigrationProgress`] after each batch. Failing to
+    /// inspect the source, create the target or scroll a page ends the
+    /// stream with that error; per-point failures are only counted.
+    pub fn stream(self) -> impl Stream<Item = Result<MigrationProgress, Error>> + 'a {
+        let started = Instant::now();
+        let options = MigrateOptions {
+            new_vector_size: Some(self.target_vector_size),
+            re_embed: true,
+            batch_size: self.batch_size,
+            concurrency: 1,
+            hnsw: self.hnsw,
+        };
+        let (source, target) = (self.source, self.target);
+        let migration = self
+            .service
+            .start_migration(source.clone(), target.clone(), options);
+
+        stream::once(migration)
+            .map_ok(move |(total, batches)| {
+                let mut progress = MigrationProgress {
+                    total,
+                    ..MigrationProgress::default()
+                };
+                let (source, target) = (source.clone(), target.clone());
+                batches.map_ok(move |report| {
+                    let failed = (report.failed.len() + report.skipped) as u64;
+                    progress.processed += report.migrated as u64 + failed;
+                    progress.failed += failed;
+                    progress.elapsed = started.elapsed();
+                    info!(
+                        source,
+                        target,
+                        processed = progress.processed,
+                        total = progress.total,
+                        failed = progress.failed,
+                        "Migrated batch"
+                    );
+                    progress
+                })
+            })
+            .try_flatten()
+    }
+}
+
+/// Options for [`QdrantService::facet`]
+#[derive(Debug, Clone, Default)]
+pub struct FacetOptions {
+    /// Only count points matching this filter
+    pub filter: Option<Filter>,
+    /// Maximum number of distinct values to return, most frequent first
+    pub limit: Option<u64>,
+    /// Ask Qdrant for exact rather than approximate counts
+    pub exact: bool,
+}
+
+/// A distinct payload value and the number of points carrying it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FacetValue {
+    pub value: serde_json::Value,
+    pub count: u64,
+}
+
+/// Look up a dotted `path` such as `metadata.category` in a JSON payload
+pub(crate) fn payload_value<'a>(
+    payload: &'a HashMap<String, serde_json::Value>,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    let mut segments = path.split('.');
+    let mut value = payload.get(segments.next()?)?;
+    for segment in segments {
+        value = value.get(segment)?;
+    }
+    Some(value)
+}
+
+/// Client-side distinct value counter used when no payload index exists
+#[derive(Default)]
+pub(crate) struct FacetCounter {
+    counts: HashMap<String, FacetValue>,
+}
+
+impl FacetCounter {
+    /// Count a payload value; arrays count each element, nulls are ignored
+    pub(crate) fn add(&mut self, value: Option<&serde_json::Value>) {
+        match value {
+            None | Some(serde_json::Value::Null) => {}
+            Some(serde_json::Value::Array(values)) => {
+                for value in values {
+                    self.add(Some(value));
+                }
+            }
+            Some(value) => {
+                self.counts
+                    .entry(value.to_string())
+                    .or_insert_with(|| FacetValue {
+                        value: value.clone(),
+                        count: 0,
+                    })
+                    .count += 1;
+            }
+        }
+    }
+
+    /// Values sorted by descending count, ties broken by value
+    pub(crate) fn into_values(self, limit: Option<u64>) -> Vec<FacetValue> {
+        let mut values: Vec<(String, FacetValue)> = self.counts.into_iter().collect();
+        values.sort_by(|(a_key, a), (b_key, b)| b.count.cmp(&a.count).then(a_key.cmp(b_key)));
+
+        let limit = limit.map_or(usize::MAX, |limit| {
+            usize::try_from(limit).unwrap_or(usize::MAX)
+        });
+        values
+            .into_iter()
+            .take(limit)
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
@@ -0,0 +1,161 @@
+use qdrant_client::qdrant::{
+    Filter, Fusion, PrefetchQuery, PrefetchQueryBuilder, Query, QueryPoints, QueryPointsBuilder,
+};
+
+/// What a [`QueryRequest`] stage ranks by
+#[derive(Debug, Clone)]
+enum StageQuery {
+    Nearest(Vec<f32>),
+    Fusion(Fusion),
+}
+
+/// A stage of a Qdrant Query API request.
+///
+/// The same type describes the final query and its nested prefetch stages,
+/// e.g. two-stage retrieval that fetches 200 candidates by one vector and
+/// re-ranks them by another:
+///
+/// ```ignore
+/// let request = QueryRequest::nearest(rerank_vector)
+///     .using("late")
+///     .prefetch(QueryRequest::nearest(dense_vector).using("dense").limit(200))
+///     .limit(10);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryRequest {
+    query: Option<StageQuery>,
+    using: Option<String>,
+    prefetch: Vec<Self>,
+    filter: Option<Filter>,
+    score_threshold: Option<f32>,
+    limit: Option<u64>,
+    with_vectors: bool,
+}
+
+impl QueryRequest {
+    /// Stage that only merges its prefetch results, ordered as Qdrant returns them
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rank by similarity to `vector`
+    #[must_use]
+    pub fn nearest(vector: Vec<f32>) -> Self {
+        Self {
+            query: Some(StageQuery::Nearest(vector)),
+            ..Self::default()
+        }
+    }
+
+    /// Combine the results of the prefetch stages with `fusion`
+    #[must_use]
+    pub fn fusion(fusion: Fusion) -> Self {
+        Self {
+            query: Some(StageQuery::Fusion(fusion)),
+            ..Self::default()
+        }
+    }
+
+    /// Add a stage whose results this stage ranks
+    #[must_use]
+    pub fn prefetch(mut self, stage: Self) -> Self {
+        self.prefetch.push(stage);
+        self
+    }
+
+    /// Name of the vector to search, for collections with named vectors
+    #[must_use]
+    pub fn using(mut self, vector_name: impl Into<String>) -> Self {
+        self.using = Some(vector_name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    #[must_use]
+    pub const fn score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = Some(score_threshold);
+        self
+    }
+
+    #[must_use]
+    pub const fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Return stored vectors with the results; ignored on prefetch stages
+    #[must_use]
+    pub const fn with_vectors(mut self, with_vectors: bool) -> Self {
+        self.with_vectors = with_vectors;
+        self
+    }
+
+    fn into_prefetch(self) -> PrefetchQuery {
+        let mut builder = PrefetchQueryBuilder::default().prefetch(
+            self.prefetch
+                .into_iter()
+                .map(Self::into_prefetch)
+                .collect::<Vec<_>>(),
+        );
+        if let Some(query) = self.query {
+            builder = builder.query(query.into_query());
+        }
+        if let Some(using) = self.using {
+            builder = builder.using(using);
+        }
+        if let Some(filter) = self.filter {
+            builder = builder.filter(filter);
+        }
+        if let Some(score_threshold) = self.score_threshold {
+            builder = builder.score_threshold(score_threshold);
+        }
+        if let Some(limit) = self.limit {
+            builder = builder.limit(limit);
+        }
+        builder.build()
+    }
+
+    /// Build the request for `collection_name`, returning payloads
+    pub(crate) fn into_query_points(self, collection_name: &str) -> QueryPoints {
+        let mut builder = QueryPointsBuilder::new(collection_name)
+            .prefetch(
+                self.prefetch
+                    .into_iter()
+                    .map(Self::into_prefetch)
+                    .collect::<Vec<_>>(),
+            )
+            .with_payload(true)
+            .with_vectors(self.with_vectors);
+        if let Some(query) = self.query {
+            builder = builder.query(query.into_query());
+        }
+        if let Some(using) = self.using {
+            builder = builder.using(using);
+        }
+        if let Some(filter) = self.filter {
+            builder = builder.filter(filter);
+        }
+        if let Some(score_threshold) = self.score_threshold {
+            builder = builder.score_threshold(score_threshold);
+        }
+        if let Some(limit) = self.limit {
+            builder = builder.limit(limit);
+        }
+        builder.build()
+    }
+}
+
+impl StageQuery {
+    fn into_query(self) -> Query {
+        match self {
+            Self::Nearest(vector) => Query::new_nearest(vector),
+            Self::Fusion(fusion) => Query::new_fusion(fusion),
+        }
+    }
+}
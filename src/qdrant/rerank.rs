@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use super::qdrant_service::SearchResult;
+
+/// Rank offset in Reciprocal Rank Fusion, damping the lead of the top ranks
+pub const RRF_K: f32 = 60.0;
+
+/// Cosine similarity between two vectors, 0.0 when either has zero length
+#[must_use]
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Re-rank search results with Maximal Marginal Relevance.
+///
+/// Picks `k` results, each maximising
+/// `lambda * sim(query, doc) - (1 - lambda) * max(sim(doc, selected))`, so a
+/// `lambda` of 1.0 is pure relevance and 0.0 is pure diversity. Results must
+/// have been fetched with `with_vectors(true)`; hits without a vector fall
+/// back to their raw score for relevance and are treated as dissimilar to
+/// everything else.
+pub fn mmr_rerank(
+    results: Vec<SearchResult>,
+    query_vector: &[f32],
+    lambda: f32,
+    k: usize,
+) -> Vec<SearchResult> {
+    let relevance: Vec<f32> = results
+        .iter()
+        .map(|result| {
+            result.vector.as_deref().map_or(result.score, |vector| {
+                cosine_similarity(query_vector, vector)
+            })
+        })
+        .collect();
+
+    let mut remaining: Vec<usize> = (0..results.len()).collect();
+    let mut selected: Vec<usize> = Vec::with_capacity(k.min(results.len()));
+
+    while selected.len() < k && !remaining.is_empty() {
+        let mut best_position = 0;
+        let mut best_score = f32::NEG_INFINITY;
+
+        for (position, &candidate) in remaining.iter().enumerate() {
+            let redundancy = selected
+                .iter()
+                .filter_map(|&chosen| {
+                    let candidate_vector = results[candidate].vector.as_deref()?;
+                    let chosen_vector = results[chosen].vector.as_deref()?;
+                    Some(cosine_similarity(candidate_vector, chosen_vector))
+                })
+                .reduce(f32::max)
+                .unwrap_or(0.0);
+
+            let score = lambda.mul_add(relevance[candidate], -(1.0 - lambda) * redundancy);
+            if score > best_score {
+                best_score = score;
+                best_position = position;
+            }
+        }
+
+        selected.push(remaining.remove(best_position));
+    }
+
+    let mut results: Vec<Option<SearchResult>> = results.into_iter().map(Some).collect();
+    selected
+        .into_iter()
+        .filter_map(|index| results[index].take())
+        .collect()
+}
+
+/// Merge two rankings with weighted Reciprocal Rank Fusion.
+///
+/// A hit at 0-based rank `r` in a list contributes `weight / (RRF_K + r + 1)`,
+/// with `vector_weight` for `vector_hits` and `1 - vector_weight` for
+/// `keyword_hits`; hits in both lists add up. Only ranks matter, so scores
+/// on different scales combine safely. Returns the best `limit` hits with
+/// the fused score as `score`, keeping the payload of the first list a hit
+/// appeared in.
+#[must_use]
+pub fn reciprocal_rank_fusion(
+    vector_hits: Vec<SearchResult>,
+    keyword_hits: Vec<SearchResult>,
+    vector_weight: f32,
+    limit: usize,
+) -> Vec<SearchResult> {
+    let mut fused: Vec<SearchResult> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for (hits, weight) in [
+        (vector_hits, vector_weight),
+        (keyword_hits, 1.0 - vector_weight),
+    ] {
+        for (rank, mut hit) in hits.into_iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let contribution = weight / (RRF_K + rank as f32 + 1.0);
+            if let Some(&position) = positions.get(&hit.id) {
+                fused[position].score += contribution;
+            } else {
+                positions.insert(hit.id.clone(), fused.len());
+                hit.score = contribution;
+                fused.push(hit);
+            }
+        }
+    }
+
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit);
+    fused
+}
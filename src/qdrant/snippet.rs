@@ -0,0 +1,162 @@
+use std::{collections::HashSet, hash::BuildHasher};
+
+/// Where [`SnippetOptions`] takes the terms that snippets are matched against
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum QueryTerms {
+    /// The text of the search query; vector searches have no terms, so their
+    /// snippets are the start of the text
+    #[default]
+    Query,
+    /// Terms from a separate string, e.g. the user's question when the search
+    /// ran on a rewritten query or a precomputed vector
+    Custom(String),
+}
+
+/// Options for [`QdrantSearchBuilder::with_snippets`](super::qdrant_service::QdrantSearchBuilder::with_snippets)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetOptions {
+    pub query_terms_from: QueryTerms,
+    /// Maximum snippet length in characters, before highlighting
+    pub max_chars: usize,
+    /// Wrap matched words in `**` markdown emphasis
+    pub highlight: bool,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        Self {
+            query_terms_from: QueryTerms::Query,
+            max_chars: 300,
+            highlight: false,
+        }
+    }
+}
+
+/// A word of the text with its byte and char positions
+struct Word {
+    byte_start: usize,
+    byte_end: usize,
+    char_start: usize,
+    char_end: usize,
+    lower: String,
+}
+
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+
+    for (char_index, (byte_index, c)) in text.char_indices().enumerate() {
+        match (c.is_alphanumeric(), current) {
+            (true, None) => current = Some((byte_index, char_index)),
+            (false, Some((byte_start, char_start))) => {
+                words.push(Word {
+                    byte_start,
+                    byte_end: byte_index,
+                    char_start,
+                    char_end: char_index,
+                    lower: text[byte_start..byte_index].to_lowercase(),
+                });
+                current = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((byte_start, char_start)) = current {
+        words.push(Word {
+            byte_start,
+            byte_end: text.len(),
+            char_start,
+            char_end: text.chars().count(),
+            lower: text[byte_start..].to_lowercase(),
+        });
+    }
+
+    words
+}
+
+/// Lowercased distinct words of `query`
+#[must_use]
+pub fn query_terms(query: &str) -> HashSet<String> {
+    words(query).into_iter().map(|word| word.lower).collect()
+}
+
+/// The window of at most `max_chars` characters of `text` that contains the
+/// most words from `terms`.
+///
+/// Windows start and end on word boundaries; ties go to the earliest window,
+/// so text without any matching word yields its beginning. A single word
+/// longer than `max_chars` is cut at a char boundary.
+#[must_use]
+pub fn extract_snippet<S: BuildHasher>(
+    text: &str,
+    terms: &HashSet<String, S>,
+    max_chars: usize,
+    highlight: bool,
+) -> String {
+    let words = words(text);
+    let Some(first) = words.first() else {
+        return text.chars().take(max_chars).collect();
+    };
+
+    let is_match = |word: &Word| terms.contains(&word.lower);
+    let mut best = (0, 0, 0);
+    let mut window_end = 0;
+    let mut matches = 0;
+    for start in 0..words.len() {
+        if window_end < start {
+            window_end = start;
+            matches = 0;
+        }
+        let limit = words[start].char_start + max_chars;
+        while window_end < words.len() && words[window_end].char_end <= limit {
+            matches += usize::from(is_match(&words[window_end]));
+            window_end += 1;
+        }
+        if matches > best.0 {
+            best = (matches, start, window_end);
+        }
+        if window_end > start {
+            matches -= usize::from(is_match(&words[start]));
+        }
+        // Later windows are subsets of this one and cannot score higher
+        if window_end == words.len() {
+            break;
+        }
+    }
+
+    let (_, start, end) = if best.2 > best.1 {
+        best
+    } else {
+        // No match anywhere: the first window that fits
+        let end = words
+            .iter()
+            .take_while(|word| word.char_end <= first.char_start + max_chars)
+            .count();
+        (0, 0, end)
+    };
+
+    if end == start {
+        // Even the first word is longer than max_chars
+        let byte_start = words[start].byte_start;
+        return text[byte_start..].chars().take(max_chars).collect();
+    }
+
+    let window = &words[start..end];
+    let byte_start = window[0].byte_start;
+    let byte_end = window[window.len() - 1].byte_end;
+    if !highlight {
+        return text[byte_start..byte_end].to_string();
+    }
+
+    let mut snippet = String::with_capacity(byte_end - byte_start);
+    let mut position = byte_start;
+    for word in window.iter().filter(|word| is_match(word)) {
+        snippet.push_str(&text[position..word.byte_start]);
+        snippet.push_str("**");
+        snippet.push_str(&text[word.byte_start..word.byte_end]);
+        snippet.push_str("**");
+        position = word.byte_end;
+    }
+    snippet.push_str(&text[position..byte_end]);
+    snippet
+}
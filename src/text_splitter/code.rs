@@ -0,0 +1,837 @@
+use anyhow::Result;
+use regex::Regex;
+use std::{collections::HashMap, ops::Range, path::Path};
+use tiktoken_rs::CoreBPE;
+use tracing::{debug, info};
+
+use super::text_service::{count_tokens, number_chunks, Doc, Headers, Metadata, Splitter};
+use crate::common::Encoding;
+
+/// Source languages [`CodeSplitter`] knows the items of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    /// TypeScript, and JavaScript, which has the same item syntax
+    TypeScript,
+}
+
+impl Language {
+    /// The language of files with `extension`, without the dot and compared
+    /// case-insensitively: `rs`, `py` and `pyi`, and `ts`, `tsx`, `mts`,
+    /// `cts`, `js`, `jsx`, `mjs` and `cjs`
+    #[must_use]
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "rs" => Some(Self::Rust),
+            "py" | "pyi" => Some(Self::Python),
+            "ts" | "tsx" | "mts" | "cts" | "js" | "jsx" | "mjs" | "cjs" => Some(Self::TypeScript),
+            _ => None,
+        }
+    }
+
+    /// The language of the file at `path`, from its extension
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    /// Separator of the item names in `Metadata::item_name`
+    const fn separator(self) -> &'static str {
+        match self {
+            Self::Rust => "::",
+            Self::Python | Self::TypeScript => ".",
+        }
+    }
+}
+
+/// What a line starts inside of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open {
+    Code,
+    Comment,
+    Str,
+}
+
+/// A line of source with its nesting: brace depth for Rust and TypeScript,
+/// indentation for Python
+#[derive(Debug)]
+struct CodeLine {
+    start: usize,
+    /// End of the line, its line break included
+    end: usize,
+    depth: usize,
+    open: Open,
+}
+
+/// An item found at one nesting level
+struct ItemStart {
+    /// First line of the item, its doc comments and attributes included
+    first: usize,
+    /// Line with the item's keyword
+    header: usize,
+    name: String,
+    /// Whether the item holds items of its own, like an `impl` block or a
+    /// class
+    container: bool,
+}
+
+/// Part of the text that is kept whole unless it fits a chunk with its
+/// neighbours
+struct Piece {
+    start: usize,
+    end: usize,
+    /// Names of the items the piece is in, outermost first
+    path: Vec<String>,
+    /// Set for the lines of an item too large for one chunk, which only
+    /// merge with each other
+    fragment_of: Option<usize>,
+}
+
+/// Splitter for source code that cuts between items rather than inside
+/// function bodies.
+///
+/// Items are found with regexes and brace depth, or indentation for Python,
+/// instead of a parser: functions, types, `impl` blocks and modules in Rust,
+/// functions and classes in Python, and functions, classes, interfaces and
+/// declarations in TypeScript. Doc comments, comments, attributes and
+/// decorators directly above an item stay with it.
+///
+/// Consecutive items are merged while they fit the limit. An item over the
+/// limit is split at the items it contains, such as the methods of an `impl`
+/// block or a class, and otherwise between lines. `Metadata::item_name`
+/// names the item each chunk starts in, qualified by the items around it,
+/// e.g. `SentenceSplitter::fragments` or `Parser.parse`.
+///
+/// [`CodeAwareSplitter`] uses it for files whose extension
+/// [`Language::from_extension`] recognizes.
+///
+/// ```
+/// use ai_utils::text_splitter::{CodeSplitter, Language, Splitter};
+///
+/// let code = "/// Adds one\nfn inc(x: u32) -> u32 {\n    x + 1\n}\n\nstruct Counter {\n    count: u32,\n}\n";
+/// let docs = CodeSplitter::new(Language::Rust).split(code, 24)?;
+///
+/// assert_eq!(docs.len(), 2);
+/// assert!(docs[0].text.starts_with("/// Adds one"));
+/// assert_eq!(docs[1].metadata.item_name.as_deref(), Some("Counter"));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct CodeSplitter {
+    language: Language,
+    tokenizer: &'static CoreBPE,
+    chat_overhead: bool,
+    /// Start of an item, capturing its keyword and name
+    item: Regex,
+    /// Start of a class member in TypeScript, capturing its name
+    member: Option<Regex>,
+}
+
+impl CodeSplitter {
+    /// Create a splitter for `language` counting tokens with `cl100k_base`,
+    /// like [`TextSplitter::new`](super::TextSplitter::new)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled tokenizer data fails to load.
+    #[must_use]
+    pub fn new(language: Language) -> Self {
+        let item = match language {
+            Language::Rust => {
+                r#"^(?:pub(?:\([^)]*\))?\s+)?(?:(?:default|const|async|unsafe|extern(?:\s+"[^"]*")?)\s+)*(fn|struct|enum|union|trait|type|mod|static|const|impl|macro_rules!)(?:\s+(?:mut\s+)?([A-Za-z_]\w*))?"#
+            }
+            Language::Python => r"^(?:async\s+)?(def|class)\s+([A-Za-z_]\w*)",
+            Language::TypeScript => {
+                r"^(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(function\*?|class|interface|type|enum|const|let|var|namespace|module)\s+([A-Za-z_$][\w$]*)"
+            }
+        };
+        let member = (language == Language::TypeScript).then(|| {
+            Regex::new(
+                r"^(?:(?:public|private|protected|static|readonly|abstract|override|async|get|set)\s+)*\*?([A-Za-z_$#][\w$]*)\s*(?:<[^>]*>)?\s*\(",
+            )
+            .unwrap()
+        });
+        Self {
+            language,
+            tokenizer: Encoding::default().shared(),
+            chat_overhead: false,
+            item: Regex::new(item).unwrap(),
+            member,
+        }
+    }
+
+    /// Count tokens with `encoding` instead of `cl100k_base`
+    #[must_use]
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.tokenizer = encoding.shared();
+        self
+    }
+
+    /// Count chunks with chat markup, see
+    /// [`TextSplitter::with_chat_overhead`](super::TextSplitter::with_chat_overhead)
+    #[must_use]
+    pub const fn with_chat_overhead(mut self, enabled: bool) -> Self {
+        self.chat_overhead = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn language(&self) -> Language {
+        self.language
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(self.tokenizer, text, self.chat_overhead)
+    }
+
+    fn fits(&self, text: &str, limit: usize) -> bool {
+        self.count_tokens(text) <= limit
+    }
+
+    /// The largest end, at most `end`, at which `text[start..end]` fits
+    /// `limit`; keeps at least one character. Probes grow from the start
+    /// and are then bisected, so each costs about the size of the chunk
+    /// rather than of the rest of the text.
+    fn fit_end(&self, text: &str, start: usize, end: usize, limit: usize) -> usize {
+        let boundary_from = |mut index: usize| {
+            while !text.is_char_boundary(index) {
+                index += 1;
+            }
+            index
+        };
+        let fits = |cut: usize| self.fits(&text[start..cut], limit);
+
+        let mut good = boundary_from(start + 1).min(end);
+        let mut bad = end + 1;
+        let mut step = 64;
+        while good < end {
+            let probe = boundary_from((good + step).min(end));
+            if !fits(probe) {
+                bad = probe;
+                break;
+            }
+            good = probe;
+            step *= 2;
+        }
+        while bad <= end {
+            let mut mid = boundary_from(good + (bad - good) / 2);
+            if mid <= good || mid >= bad {
+                mid = boundary_from(good + 1);
+            }
+            if mid >= bad {
+                break;
+            }
+            if fits(mid) {
+                good = mid;
+            } else {
+                bad = mid;
+            }
+        }
+        good
+    }
+
+    /// Name of the item whose keyword opens `line`, trimmed, at `depth`, and
+    /// whether it contains items of its own
+    fn item_name(&self, line: &str, depth: usize) -> Option<(String, bool)> {
+        if let Some(caps) = self.item.captures(line) {
+            let keyword = caps.get(1)?;
+            let container = match self.language {
+                Language::Rust => matches!(keyword.as_str(), "impl" | "trait" | "mod"),
+                Language::Python => keyword.as_str() == "class",
+                Language::TypeScript => matches!(
+                    keyword.as_str(),
+                    "class" | "interface" | "namespace" | "module"
+                ),
+            };
+            let name = if keyword.as_str() == "impl" {
+                let rest = &line[keyword.end()..];
+                rest.starts_with([' ', '<'])
+                    .then(|| impl_type(rest))
+                    .flatten()
+            } else {
+                caps.get(2).map(|name| name.as_str().to_string())
+            };
+            return name.map(|name| (name, container));
+        }
+        let caps = self.member.as_ref().filter(|_| depth > 0)?.captures(line)?;
+        let name = &caps[1];
+        let statement = [
+            "if", "for", "while", "switch", "catch", "return", "function",
+        ];
+        (!statement.contains(&name)).then(|| (name.to_string(), false))
+    }
+
+    /// Whether `line`, trimmed, belongs to the item below it: a doc comment,
+    /// comment, attribute or decorator
+    fn is_prefix(&self, line: &str) -> bool {
+        match self.language {
+            Language::Rust => {
+                (line.starts_with("//") && !line.starts_with("//!"))
+                    || line.starts_with("#[")
+                    || line.starts_with("/*")
+            }
+            Language::Python => line.starts_with('@') || line.starts_with('#'),
+            Language::TypeScript => {
+                line.starts_with("//") || line.starts_with("/*") || line.starts_with('@')
+            }
+        }
+    }
+
+    /// Lines of `text` with their nesting
+    fn lines(&self, text: &str) -> Vec<CodeLine> {
+        match self.language {
+            Language::Rust | Language::TypeScript => brace_lines(text, self.language),
+            Language::Python => indent_lines(text),
+        }
+    }
+}
+
+/// Cuts one text into [`Piece`]s, from the outermost items in
+struct Walk<'a> {
+    splitter: &'a CodeSplitter,
+    text: &'a str,
+    lines: &'a [CodeLine],
+    limit: usize,
+    pieces: Vec<Piece>,
+}
+
+impl Walk<'_> {
+    fn content(&self, line: &CodeLine) -> &str {
+        self.text[line.start..line.end].trim()
+    }
+
+    /// Items starting in `range` of the lines at nesting `depth`
+    fn item_starts(&self, range: Range<usize>, depth: usize) -> Vec<ItemStart> {
+        let mut starts: Vec<ItemStart> = Vec::new();
+        for index in range.clone() {
+            let line = &self.lines[index];
+            if line.open != Open::Code || line.depth != depth {
+                continue;
+            }
+            let Some((name, container)) = self.splitter.item_name(self.content(line), depth) else {
+                continue;
+            };
+            let floor = starts.last().map_or(range.start, |item| item.header + 1);
+            let mut first = index;
+            while first > floor {
+                let above = &self.lines[first - 1];
+                let content = self.content(above);
+                let attached = above.open == Open::Comment
+                    || (above.open == Open::Code
+                        && above.depth == depth
+                        && self.splitter.is_prefix(content));
+                if !attached || content.is_empty() {
+                    break;
+                }
+                first -= 1;
+            }
+            starts.push(ItemStart {
+                first,
+                header: index,
+                name,
+                container,
+            });
+        }
+        starts
+    }
+
+    /// Nesting of the items inside `item`, which ends before line `end`
+    fn child_depth(&self, item: &ItemStart, end: usize) -> Option<usize> {
+        let depth = self.lines[item.header].depth;
+        match self.splitter.language {
+            Language::Rust | Language::TypeScript => Some(depth + 1),
+            Language::Python => self.lines[item.header + 1..end]
+                .iter()
+                .find(|line| line.open == Open::Code && !self.content(line).is_empty())
+                .map(|line| line.depth)
+                .filter(|&body| body > depth),
+        }
+    }
+
+    /// Pieces of `range` of the lines, where items start at nesting `depth`
+    /// inside the items named by `path`
+    fn collect(&mut self, range: Range<usize>, depth: usize, path: &[String]) {
+        let starts = self.item_starts(range.clone(), depth);
+        let head_end = starts.first().map_or(range.end, |item| item.first);
+        self.push_segment(range.start..head_end, None, path);
+        for (index, item) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).map_or(range.end, |next| next.first);
+            self.push_segment(item.first..end, Some(item), path);
+        }
+    }
+
+    /// Pieces of one item, or of the lines before the first item when `item`
+    /// is `None`
+    fn push_segment(&mut self, range: Range<usize>, item: Option<&ItemStart>, path: &[String]) {
+        if range.is_empty() {
+            return;
+        }
+        let mut path = path.to_vec();
+        path.extend(item.map(|item| item.name.clone()));
+        let (start, end) = (self.lines[range.start].start, self.lines[range.end - 1].end);
+        if self.splitter.fits(&self.text[start..end], self.limit) {
+            self.pieces.push(Piece {
+                start,
+                end,
+                path,
+                fragment_of: None,
+            });
+            return;
+        }
+
+        if let Some(item) = item.filter(|item| item.container) {
+            let child = self.child_depth(item, range.end).filter(|&child| {
+                !self
+                    .item_starts(item.header + 1..range.end, child)
+                    .is_empty()
+            });
+            if let Some(child) = child {
+                self.collect(range, child, &path);
+                return;
+            }
+        }
+
+        debug!(
+            "{} lines of {} are over the limit, splitting between lines",
+            range.len(),
+            path.join(self.splitter.language.separator())
+        );
+        for line in &self.lines[range] {
+            let mut from = line.start;
+            if !self
+                .splitter
+                .fits(&self.text[line.start..line.end], self.limit)
+            {
+                loop {
+                    let cut = self.splitter.fit_end(self.text, from, line.end, self.limit);
+                    if cut >= line.end {
+                        break;
+                    }
+                    self.pieces.push(Piece {
+                        start: from,
+                        end: cut,
+                        path: path.clone(),
+                        fragment_of: Some(start),
+                    });
+                    from = cut;
+                }
+            }
+            self.pieces.push(Piece {
+                start: from,
+                end: line.end,
+                path: path.clone(),
+                fragment_of: Some(start),
+            });
+        }
+    }
+}
+
+/// Splits source files with a [`CodeSplitter`] for their language and other
+/// files with the wrapped splitter.
+///
+/// The language comes from the path given to [`Splitter::split_file`], as
+/// [`process_file`](super::process_file) calls it. [`Splitter::split`] has
+/// no path and always uses the wrapped splitter.
+///
+/// ```no_run
+/// use ai_utils::text_splitter::{process_file, CodeAwareSplitter, Encoding, TextSplitter};
+///
+/// let splitter = CodeAwareSplitter::new(TextSplitter::new(None)).with_encoding(Encoding::O200k);
+/// process_file("src/main.rs".as_ref(), &splitter, 500)?;
+/// process_file("README.md".as_ref(), &splitter, 500)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct CodeAwareSplitter<S> {
+    inner: S,
+    encoding: Encoding,
+    chat_overhead: bool,
+}
+
+impl<S: Splitter> CodeAwareSplitter<S> {
+    /// Split source files counting tokens with `cl100k_base` and everything
+    /// else with `inner`
+    #[must_use]
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            encoding: Encoding::default(),
+            chat_overhead: false,
+        }
+    }
+
+    /// Count tokens of source files with `encoding`, see
+    /// [`CodeSplitter::with_encoding`]
+    #[must_use]
+    pub const fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Count source chunks with chat markup, see
+    /// [`CodeSplitter::with_chat_overhead`]
+    #[must_use]
+    pub const fn with_chat_overhead(mut self, enabled: bool) -> Self {
+        self.chat_overhead = enabled;
+        self
+    }
+}
+
+impl<S: Splitter> Splitter for CodeAwareSplitter<S> {
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        self.inner.split(text, limit)
+    }
+
+    fn split_file(&self, path: &Path, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        Language::from_path(path).map_or_else(
+            || self.inner.split_file(path, text, limit),
+            |language| {
+                CodeSplitter::new(language)
+                    .with_encoding(self.encoding)
+                    .with_chat_overhead(self.chat_overhead)
+                    .split(text, limit)
+            },
+        )
+    }
+}
+
+impl Splitter for CodeSplitter {
+    /// Split `text` into chunks of whole items of at most `limit` tokens.
+    /// Chunks only exceed the limit when a single character does.
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        info!(
+            "Starting {:?} code split with limit: {} tokens",
+            self.language, limit
+        );
+        let lines = self.lines(text);
+        let mut walk = Walk {
+            splitter: self,
+            text,
+            lines: &lines,
+            limit,
+            pieces: Vec::new(),
+        };
+        walk.collect(0..lines.len(), 0, &[]);
+
+        let mut chunks: Vec<Piece> = Vec::new();
+        for piece in walk.pieces {
+            match chunks.last_mut() {
+                Some(last)
+                    if last.fragment_of == piece.fragment_of
+                        && self.fits(&text[last.start..piece.end], limit) =>
+                {
+                    last.end = piece.end;
+                }
+                _ => chunks.push(piece),
+            }
+        }
+
+        let mut docs: Vec<Doc> = chunks
+            .into_iter()
+            .filter_map(|piece| {
+                let (start, end) = trim_lines(text, piece.start, piece.end)?;
+                let chunk = &text[start..end];
+                Some(Doc {
+                    text: chunk.to_string(),
+                    metadata: Metadata {
+                        tokens: self.count_tokens(chunk),
+                        headers: Headers::default(),
+                        urls: Vec::new(),
+                        images: Vec::new(),
+                        id: None,
+                        fields: HashMap::new(),
+                        frontmatter: None,
+                        heading_path: Vec::new(),
+                        section_path: Vec::new(),
+                        start_offset: start,
+                        end_offset: end,
+                        contains_code: true,
+                        chunk_index: 0,
+                        total_chunks: 0,
+                        source: None,
+                        sentence_count: None,
+                        normalizations: Vec::new(),
+                        item_name: (!piece.path.is_empty())
+                            .then(|| piece.path.join(self.language.separator())),
+                    },
+                })
+            })
+            .collect();
+        number_chunks(&mut docs);
+
+        info!("Code split completed. Total chunks: {}", docs.len());
+        Ok(docs)
+    }
+}
+
+/// The type an `impl` block is for, from the text after `impl`
+fn impl_type(rest: &str) -> Option<String> {
+    let mut rest = rest.trim_start();
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let close = rest.char_indices().find_map(|(index, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(index)
+        })?;
+        rest = &rest[close + 1..];
+    }
+    let target = rest.split_once(" for ").map_or(rest, |(_, target)| target);
+    let path = target
+        .trim_start()
+        .split(|c: char| c == '<' || c == '{' || c.is_whitespace())
+        .next()?;
+    let name = path.rsplit("::").next()?.trim_start_matches(['&', '(']);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// `start..end` without its leading blank lines and trailing whitespace
+fn trim_lines(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let slice = &text[start..end];
+    let trimmed = slice.trim_end();
+    if trimmed.trim_start().is_empty() {
+        return None;
+    }
+    let first = start + (slice.len() - slice.trim_start().len());
+    let line_start = text[..first].rfind('\n').map_or(0, |index| index + 1);
+    Some((line_start.max(start), start + trimmed.len()))
+}
+
+/// Length of the Rust char literal `text` starts with, `None` when its
+/// quote starts a lifetime
+fn char_literal_len(text: &str) -> Option<usize> {
+    let rest = &text[1..];
+    let mut chars = rest.chars();
+    match chars.next()? {
+        '\\' => {
+            let escaped = chars.next()?.len_utf8();
+            let close = rest[1 + escaped..].find('\'').filter(|&close| close <= 8)?;
+            Some(1 + 1 + escaped + close + 1)
+        }
+        c => (chars.next() == Some('\'')).then(|| 1 + c.len_utf8() + 1),
+    }
+}
+
+/// Opening of a Rust raw string at `index`: the bytes up to and including
+/// its quote and its number of `#`
+fn raw_string_open(bytes: &[u8], index: usize) -> Option<(usize, usize)> {
+    let is_ident = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let standalone = match index {
+        0 => true,
+        1 => !is_ident(bytes[0]) || bytes[0] == b'b',
+        _ => {
+            !is_ident(bytes[index - 1]) || (bytes[index - 1] == b'b' && !is_ident(bytes[index - 2]))
+        }
+    };
+    if !standalone {
+        return None;
+    }
+    let hashes = bytes[index + 1..]
+        .iter()
+        .take_while(|&&byte| byte == b'#')
+        .count();
+    (bytes.get(index + 1 + hashes) == Some(&b'"')).then_some((hashes + 2, hashes))
+}
+
+/// Whether a `/` at `index` of TypeScript starts a regex literal rather than
+/// a division, judged by the token before it
+fn starts_regex(bytes: &[u8], index: usize) -> bool {
+    let is_word = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$';
+    let before = bytes[..index].trim_ascii_end();
+    let Some(&last) = before.last() else {
+        return true;
+    };
+    if is_word(last) {
+        let start = before
+            .iter()
+            .rposition(|&byte| !is_word(byte))
+            .map_or(0, |at| at + 1);
+        return matches!(
+            &before[start..],
+            b"return"
+                | b"typeof"
+                | b"instanceof"
+                | b"in"
+                | b"of"
+                | b"new"
+                | b"delete"
+                | b"void"
+                | b"throw"
+                | b"case"
+                | b"do"
+                | b"else"
+                | b"yield"
+                | b"await"
+        );
+    }
+    !matches!(last, b')' | b']' | b'"' | b'\'' | b'`')
+}
+
+/// Lines of Rust or TypeScript with the brace depth at their start, skipping
+/// braces in comments, strings, char literals and regex literals
+fn brace_lines(text: &str, language: Language) -> Vec<CodeLine> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Code,
+        LineComment,
+        BlockComment,
+        Str(u8),
+        RawStr(usize),
+        /// TypeScript regex literal, and whether inside a `[...]` class
+        Regex(bool),
+    }
+
+    let bytes = text.as_bytes();
+    let typescript = language == Language::TypeScript;
+    let mut lines = Vec::new();
+    let mut depth = 0usize;
+    let mut state = State::Code;
+    let mut line = (0, depth, Open::Code);
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        let next = bytes.get(index + 1).copied();
+        match state {
+            State::Code => match byte {
+                b'{' => depth += 1,
+                b'}' => depth = depth.saturating_sub(1),
+                b'/' if next == Some(b'/') => state = State::LineComment,
+                b'/' if next == Some(b'*') => {
+                    state = State::BlockComment;
+                    index += 1;
+                }
+                b'/' if typescript && starts_regex(bytes, index) => state = State::Regex(false),
+                b'"' => state = State::Str(b'"'),
+                b'\'' | b'`' if typescript => state = State::Str(byte),
+                b'\'' => {
+                    if let Some(len) = char_literal_len(&text[index..]) {
+                        index += len;
+                        continue;
+                    }
+                }
+                b'r' if !typescript => {
+                    if let Some((len, hashes)) = raw_string_open(bytes, index) {
+                        state = State::RawStr(hashes);
+                        index += len;
+                        continue;
+                    }
+                }
+                _ => {}
+            },
+            State::BlockComment if byte == b'*' && next == Some(b'/') => {
+                state = State::Code;
+                index += 1;
+            }
+            State::Str(_) | State::Regex(_) if byte == b'\\' => index += 1,
+            State::Regex(false) if byte == b'/' => state = State::Code,
+            State::Regex(false) if byte == b'[' => state = State::Regex(true),
+            State::Regex(true) if byte == b']' => state = State::Regex(false),
+            State::Str(quote) if byte == quote => state = State::Code,
+            State::RawStr(hashes)
+                if byte == b'"'
+                    && bytes[index + 1..]
+                        .iter()
+                        .take(hashes)
+                        .filter(|&&byte| byte == b'#')
+                        .count()
+                        == hashes =>
+            {
+                state = State::Code;
+                index += hashes;
+            }
+            _ => {}
+        }
+        if index < bytes.len() && bytes[index] == b'\n' {
+            // Only template literals span lines in TypeScript
+            if state == State::LineComment
+                || (typescript && matches!(state, State::Str(b'"' | b'\'') | State::Regex(_)))
+            {
+                state = State::Code;
+            }
+            lines.push(CodeLine {
+                start: line.0,
+                end: index + 1,
+                depth: line.1,
+                open: line.2,
+            });
+            let open = match state {
+                State::Code | State::LineComment => Open::Code,
+                State::BlockComment => Open::Comment,
+                State::Str(_) | State::RawStr(_) | State::Regex(_) => Open::Str,
+            };
+            line = (index + 1, depth, open);
+        }
+        index += 1;
+    }
+    if line.0 < text.len() {
+        lines.push(CodeLine {
+            start: line.0,
+            end: text.len(),
+            depth: line.1,
+            open: line.2,
+        });
+    }
+    lines
+}
+
+/// Lines of Python with their indentation, marking those that start inside
+/// a triple-quoted string
+fn indent_lines(text: &str) -> Vec<CodeLine> {
+    let mut lines = Vec::new();
+    let mut triple: Option<&str> = None;
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let open = if triple.is_some() {
+            Open::Str
+        } else {
+            Open::Code
+        };
+        let depth = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let mut rest = line;
+        while !rest.is_empty() {
+            if let Some(quote) = triple {
+                match rest.find(quote) {
+                    Some(close) => {
+                        rest = &rest[close + 3..];
+                        triple = None;
+                    }
+                    None => break,
+                }
+                continue;
+            }
+            let Some(at) = rest.find(['#', '"', '\'']) else {
+                break;
+            };
+            rest = &rest[at..];
+            if rest.starts_with('#') {
+                break;
+            }
+            if let Some(quote) = ["\"\"\"", "'''"]
+                .into_iter()
+                .find(|quote| rest.starts_with(quote))
+            {
+                triple = Some(quote);
+                rest = &rest[3..];
+                continue;
+            }
+            let quote = rest.as_bytes()[0];
+            let mut escaped = false;
+            let close = rest.bytes().skip(1).position(|byte| {
+                let closes = byte == quote && !escaped;
+                escaped = byte == b'\\' && !escaped;
+                closes
+            });
+            rest = close.map_or("", |close| &rest[close + 2..]);
+        }
+        lines.push(CodeLine {
+            start,
+            end: start + line.len(),
+            depth,
+            open,
+        });
+        start += line.len();
+    }
+    lines
+}
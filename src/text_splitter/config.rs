@@ -0,0 +1,142 @@
+use super::normalize::NormalizeOptions;
+use crate::{common::Encoding, error::Error};
+
+/// Token limit of a [`SplitterConfig`] unless [`SplitterConfigBuilder::token_limit`]
+/// overrides it
+pub const DEFAULT_TOKEN_LIMIT: usize = 1000;
+
+/// Validated settings for [`TextSplitter::with_config`](super::TextSplitter::with_config),
+/// created with [`SplitterConfig::builder`]
+///
+/// ```
+/// use ai_utils::text_splitter::{Encoding, NormalizeOptions, SplitterConfig, TextSplitter};
+///
+/// let config = SplitterConfig::builder()
+///     .token_limit(1000)
+///     .overlap(100)
+///     .tokenizer(Encoding::O200k)
+///     .min_chunk_tokens(100)
+///     .keep_links_inline(false)
+///     .normalize(NormalizeOptions::default().trim_trailing_whitespace(true))
+///     .build()?;
+/// let docs = TextSplitter::with_config(config).split_default("Some text to split.")?;
+///
+/// assert_eq!(docs.len(), 1);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SplitterConfig {
+    /// Limit used by [`TextSplitter::split_default`](super::TextSplitter::split_default)
+    pub token_limit: usize,
+    /// Tokens of each chunk's end repeated at the start of the next one
+    pub overlap: usize,
+    pub tokenizer: Encoding,
+    /// See [`TextSplitter::with_min_tokens`](super::TextSplitter::with_min_tokens)
+    pub min_chunk_tokens: usize,
+    /// Leave links and images in the chunk text instead of replacing them
+    /// with `{$urlN}` and `{$imgN}` placeholders
+    pub keep_links_inline: bool,
+    /// Cleanup applied before splitting, see
+    /// [`TextSplitter::with_normalization`](super::TextSplitter::with_normalization)
+    pub normalize: NormalizeOptions,
+}
+
+impl Default for SplitterConfig {
+    fn default() -> Self {
+        Self {
+            token_limit: DEFAULT_TOKEN_LIMIT,
+            overlap: 0,
+            tokenizer: Encoding::default(),
+            min_chunk_tokens: 0,
+            keep_links_inline: false,
+            normalize: NormalizeOptions::default(),
+        }
+    }
+}
+
+impl SplitterConfig {
+    #[must_use]
+    pub fn builder() -> SplitterConfigBuilder {
+        SplitterConfigBuilder::default()
+    }
+
+    /// Check that the limit is positive and the overlap and minimum chunk
+    /// size are below it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] naming the first setting that is out of range.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.token_limit == 0 {
+            return Err(Error::Config(
+                "Token limit must be greater than 0".to_string(),
+            ));
+        }
+        if self.overlap >= self.token_limit {
+            return Err(Error::Config(format!(
+                "Overlap of {} tokens must be less than the token limit of {}",
+                self.overlap, self.token_limit
+            )));
+        }
+        if self.min_chunk_tokens >= self.token_limit {
+            return Err(Error::Config(format!(
+                "Minimum chunk size of {} tokens must be less than the token limit of {}",
+                self.min_chunk_tokens, self.token_limit
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Fluent builder for [`SplitterConfig`], validating on [`SplitterConfigBuilder::build`]
+#[derive(Debug, Clone, Default)]
+pub struct SplitterConfigBuilder {
+    config: SplitterConfig,
+}
+
+impl SplitterConfigBuilder {
+    #[must_use]
+    pub const fn token_limit(mut self, token_limit: usize) -> Self {
+        self.config.token_limit = token_limit;
+        self
+    }
+
+    #[must_use]
+    pub const fn overlap(mut self, overlap: usize) -> Self {
+        self.config.overlap = overlap;
+        self
+    }
+
+    #[must_use]
+    pub const fn tokenizer(mut self, tokenizer: Encoding) -> Self {
+        self.config.tokenizer = tokenizer;
+        self
+    }
+
+    #[must_use]
+    pub const fn min_chunk_tokens(mut self, min_chunk_tokens: usize) -> Self {
+        self.config.min_chunk_tokens = min_chunk_tokens;
+        self
+    }
+
+    #[must_use]
+    pub const fn keep_links_inline(mut self, enabled: bool) -> Self {
+        self.config.keep_links_inline = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn normalize(mut self, options: NormalizeOptions) -> Self {
+        self.config.normalize = options;
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if the settings fail [`SplitterConfig::validate`].
+    pub fn build(self) -> crate::Result<SplitterConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
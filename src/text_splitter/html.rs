@@ -0,0 +1,441 @@
+use anyhow::Result;
+
+use super::text_service::{Doc, TextSplitter};
+
+/// Elements dropped with everything inside them
+const SKIPPED_ELEMENTS: [&str; 9] = [
+    "script", "style", "noscript", "template", "head", "nav", "footer", "svg", "iframe",
+];
+
+/// Elements that start and end a paragraph of their own
+const BLOCK_ELEMENTS: [&str; 13] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "header",
+    "aside",
+    "figure",
+    "figcaption",
+    "blockquote",
+    "table",
+    "dl",
+    "address",
+];
+
+impl TextSplitter {
+    /// Split an HTML document: it is converted with [`html_to_markdown`] and
+    /// the markdown goes through [`TextSplitter::split`], so headings, links
+    /// and images end up in the metadata as for markdown input. Offsets refer
+    /// to the converted markdown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if splitting the converted markdown fails.
+    pub fn split_html(&self, html: &str, limit: usize) -> Result<Vec<Doc>> {
+        self.split(&html_to_markdown(html), limit)
+    }
+}
+
+/// Convert HTML to markdown for splitting.
+///
+/// Scripts, styles, navigation, footers and the document head are dropped.
+/// Headings become `#` lines, links and images keep their targets, lists are
+/// indented by nesting, `<pre>` becomes a fenced code block (with the
+/// language of a `language-*` class) and table rows become `|` rows. Other
+/// tags are removed, keeping their text with whitespace collapsed.
+///
+/// ```
+/// use ai_utils::text_splitter::html_to_markdown;
+///
+/// let html = "<nav>Home</nav><h2>Setup</h2><p>Run <code>make</code>, \
+///             see <a href=\"https://example.com\">the docs</a>.</p>";
+/// assert_eq!(
+///     html_to_markdown(html),
+///     "## Setup\n\nRun `make`, see [the docs](https://example.com)."
+/// );
+/// ```
+#[must_use]
+pub fn html_to_markdown(html: &str) -> String {
+    let mut converter = Converter::default();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if let Some((tag, after)) = Tag::parse(rest) {
+            rest = after;
+            if !tag.closing && !tag.self_closing && SKIPPED_ELEMENTS.contains(&tag.name.as_str()) {
+                rest = skip_element(rest, &tag.name);
+            } else {
+                converter.tag(&tag);
+            }
+        } else {
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..]
+                .find('<')
+                .map_or(rest.len(), |index| index + first);
+            converter.text(&decode_entities(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    converter.finish()
+}
+
+/// A parsed start or end tag
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: String,
+}
+
+impl Tag {
+    /// Parse the tag at the start of `input`, returning it and the text after
+    /// it, or `None` when `input` does not start with a tag
+    fn parse(input: &str) -> Option<(Self, &str)> {
+        let body = input.strip_prefix('<')?;
+        let (closing, body) = body
+            .strip_prefix('/')
+            .map_or((false, body), |body| (true, body));
+        let name_len = body
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(body.len());
+        if name_len == 0 || !body.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        // Find the closing `>` outside quoted attribute values
+        let mut quote = None;
+        let end = body[name_len..].char_indices().find_map(|(index, c)| {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), _) if open == c => quote = None,
+                (None, '>') => return Some(name_len + index),
+                _ => {}
+            }
+            None
+        })?;
+
+        let attributes = body[name_len..end].trim();
+        Some((
+            Self {
+                name: body[..name_len].to_ascii_lowercase(),
+                closing,
+                self_closing: attributes.ends_with('/'),
+                attributes: attributes.trim_end_matches('/').to_string(),
+            },
+            &body[end + 1..],
+        ))
+    }
+
+    /// Value of the attribute `name`, entity-decoded
+    fn attribute(&self, name: &str) -> Option<String> {
+        let mut rest = self.attributes.as_str();
+        while !rest.is_empty() {
+            let key_len = rest
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = &rest[..key_len];
+            rest = rest[key_len..].trim_start();
+            let value = rest.strip_prefix('=').map(|after| {
+                let (value, remainder) = split_attribute_value(after.trim_start());
+                rest = remainder.trim_start();
+                value
+            });
+            if key.eq_ignore_ascii_case(name) {
+                return Some(decode_entities(value.unwrap_or_default()));
+            }
+        }
+        None
+    }
+}
+
+/// Split an attribute value, quoted or not, from the attributes after it
+fn split_attribute_value(input: &str) -> (&str, &str) {
+    if let Some(quote @ ('"' | '\'')) = input.chars().next() {
+        let inner = &input[1..];
+        let end = inner.find(quote).unwrap_or(inner.len());
+        (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+    } else {
+        let end = input.find(char::is_whitespace).unwrap_or(input.len());
+        input.split_at(end)
+    }
+}
+
+/// The text after the end tag of the element `name` whose start tag was just
+/// read. An element that is never closed is skipped on its own, leaving the
+/// text after its start tag.
+fn skip_element<'a>(input: &'a str, name: &str) -> &'a str {
+    let closing = format!("</{name}");
+    let lowercase = input.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lowercase[from..].find(&closing) {
+        let start = from + found;
+        let after = &input[start + closing.len()..];
+        if after.starts_with(|c: char| c == '>' || c.is_ascii_whitespace()) {
+            return after.find('>').map_or("", |end| &after[end + 1..]);
+        }
+        from = start + closing.len();
+    }
+    input
+}
+
+/// Replace character references like `&amp;` and `&#8212;`
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..=end])?, end + 2)));
+        if let Some((c, len)) = entity {
+            decoded.push(c);
+            rest = &rest[len..];
+        } else {
+            decoded.push('&');
+            rest = &rest[1..];
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "copy" => '©',
+        _ => return None,
+    })
+}
+
+/// Markdown being built while walking the tags
+#[derive(Default)]
+struct Converter {
+    out: String,
+    /// Open lists, `None` for unordered and the next number for ordered ones
+    lists: Vec<Option<usize>>,
+    /// `href` of each open `<a>`, `None` for anchors without one
+    links: Vec<Option<String>>,
+    in_pre: bool,
+    /// Whitespace was seen since the last word
+    space_pending: bool,
+    /// Cells written in the current table row
+    row_cells: usize,
+    row_is_header: bool,
+    /// Rows written in the current table
+    table_rows: usize,
+}
+
+impl Converter {
+    fn tag(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+        match (name, tag.closing) {
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                self.block();
+                let level = usize::from(name.as_bytes()[1] - b'0');
+                self.out.push_str(&"#".repeat(level));
+                self.out.push(' ');
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => self.block(),
+            ("br" | "dt" | "dd", _) | ("li", true) => self.line_break(),
+            ("hr", _) => {
+                self.block();
+                self.out.push_str("---");
+                self.block();
+            }
+            ("pre", false) => {
+                self.block();
+                self.out.push_str("```\n");
+                self.in_pre = true;
+            }
+            ("pre", true) => {
+                self.line_break();
+                self.out.push_str("```");
+                self.in_pre = false;
+                self.block();
+            }
+            ("code", false) if self.in_pre => {
+                if let Some(language) = tag.attribute("class").and_then(|class| language_of(&class))
+                {
+                    if self.out.ends_with("```\n") {
+                        self.out.pop();
+                        self.out.push_str(&language);
+                        self.out.push('\n');
+                    }
+                }
+            }
+            ("code", _) if self.in_pre => {}
+            ("code", false) => self.inline("`"),
+            ("strong" | "b", false) => self.inline("**"),
+            ("em" | "i", false) => self.inline("*"),
+            ("code", true) => self.out.push('`'),
+            ("strong" | "b", true) => self.out.push_str("**"),
+            ("em" | "i", true) => self.out.push('*'),
+            ("a", false) => {
+                let href = tag.attribute("href").filter(|href| !href.is_empty());
+                if href.is_some() {
+                    self.inline("[");
+                }
+                self.links.push(href);
+            }
+            ("a", true) => {
+                if let Some(Some(href)) = self.links.pop() {
+                    self.out.push_str("](");
+                    self.out.push_str(&href);
+                    self.out.push(')');
+                }
+            }
+            ("img", _) => {
+                if let Some(src) = tag.attribute("src") {
+                    let alt = tag.attribute("alt").unwrap_or_default();
+                    self.inline(&format!("![{alt}]({src})"));
+                }
+            }
+            ("ul" | "ol", false) => {
+                self.line_break();
+                self.lists.push((name == "ol").then_some(1));
+            }
+            ("ul" | "ol", true) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.block();
+                } else {
+                    self.line_break();
+                }
+            }
+            ("li", false) => self.list_item(),
+            ("tr", false) => {
+                self.line_break();
+                self.row_cells = 0;
+                self.row_is_header = false;
+            }
+            ("tr", true) => self.end_row(),
+            ("td" | "th", false) => {
+                self.space_pending = false;
+                self.out
+                    .push_str(if self.row_cells == 0 { "| " } else { " | " });
+                self.row_cells += 1;
+                self.row_is_header |= name == "th";
+            }
+            ("table", false) => {
+                self.block();
+                self.table_rows = 0;
+            }
+            (_, _) if BLOCK_ELEMENTS.contains(&name) => self.block(),
+            _ => {}
+        }
+    }
+
+    fn list_item(&mut self) {
+        self.line_break();
+        let depth = self.lists.len().saturating_sub(1);
+        self.out.push_str(&"  ".repeat(depth));
+        match self.lists.last_mut() {
+            Some(Some(number)) => {
+                self.out.push_str(&number.to_string());
+                self.out.push_str(". ");
+                *number += 1;
+            }
+            _ => self.out.push_str("- "),
+        }
+    }
+
+    fn end_row(&mut self) {
+        if self.row_cells == 0 {
+            return;
+        }
+        self.out.push_str(" |\n");
+        if self.table_rows == 0 && self.row_is_header {
+            self.out.push('|');
+            self.out.push_str(&" --- |".repeat(self.row_cells));
+            self.out.push('\n');
+        }
+        self.table_rows += 1;
+        self.row_cells = 0;
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.in_pre {
+            // A newline right after `<pre>` is not part of the content
+            let text = if self.out.ends_with('\n') {
+                text.strip_prefix('\n').unwrap_or(text)
+            } else {
+                text
+            };
+            self.out.push_str(text);
+            return;
+        }
+        self.space_pending |= text.starts_with(char::is_whitespace);
+        for (index, word) in text.split_whitespace().enumerate() {
+            self.space_pending |= index > 0;
+            self.inline(word);
+        }
+        self.space_pending |= text.ends_with(char::is_whitespace);
+    }
+
+    /// Append a word or an opening marker, after the space collapsed from
+    /// any whitespace before it
+    fn inline(&mut self, text: &str) {
+        if self.space_pending && !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+        self.space_pending = false;
+        self.out.push_str(text);
+    }
+
+    fn line_break(&mut self) {
+        self.space_pending = false;
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// End the current paragraph with a blank line
+    fn block(&mut self) {
+        self.line_break();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        self.out
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+}
+
+/// Language named by a `language-*` or `lang-*` class
+fn language_of(class: &str) -> Option<String> {
+    class.split_whitespace().find_map(|class| {
+        class
+            .strip_prefix("language-")
+            .or_else(|| class.strip_prefix("lang-"))
+            .map(str::to_string)
+    })
+}
@@ -1,47 +1,178 @@
-#![allow(dead_code)]
+//! Token-aware splitting of markdown and CSV into embeddable chunks.
+//!
+//! ```
+//! use ai_utils::text_splitter::TextSplitter;
+//!
+//! let splitter = TextSplitter::new(None);
+//! let docs = splitter
+//!     .split("# Intro\nHello world\n## Details\nMore text\n", 100)
+//!     .unwrap();
+//!
+//! assert_eq!(docs.len(), 1);
+//! assert!(docs[0].metadata.tokens <= 100);
+//! assert_eq!(docs[0].metadata.headers.get(1), Some(&["Intro".to_string()][..]));
+//! ```
 
 use anyhow::{Context, Result};
-use std::{fs, path::PathBuf};
-use text_service::TextSplitter;
+use futures::{stream, StreamExt};
+#[cfg(feature = "qdrant")]
+use std::{collections::HashMap, path::Component};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+#[cfg(feature = "qdrant")]
+use crate::qdrant::PointInput;
+
+pub use crate::common::Encoding;
+pub use code::{CodeAwareSplitter, CodeSplitter, Language};
+pub use config::{SplitterConfig, SplitterConfigBuilder, DEFAULT_TOKEN_LIMIT};
+#[cfg(feature = "html")]
+pub use html::html_to_markdown;
+pub use normalize::{Normalization, NormalizeOptions};
+pub use progress::{SplitOutcome, SplitProgress, StreamOutcome};
+pub use recursive::{RecursiveCharacterSplitter, DEFAULT_SEPARATORS};
+pub use sentence::{SentenceSplitter, ABBREVIATIONS};
+pub use text_service::{
+    token_count, Doc, HeaderSplitOptions, Headers, Metadata, Splitter, StableIds, TextSplitter,
+};
+
+mod code;
+mod config;
+#[cfg(feature = "html")]
+mod html;
+mod normalize;
+mod progress;
+mod recursive;
+mod sentence;
 mod text_service;
 
-#[derive(Debug)]
-struct Report {
-    file: String,
-    avg_chunk_size: f64,
-    median_chunk_size: usize,
-    min_chunk_size: usize,
-    max_chunk_size: usize,
-    total_chunks: usize,
+/// Chunk size statistics, in tokens, for one file split by [`process_file`]
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// File name without its directory, or the path relative to the
+    /// directory for [`process_directory`]
+    pub file: String,
+    pub avg_chunk_size: f64,
+    pub median_chunk_size: usize,
+    pub min_chunk_size: usize,
+    pub max_chunk_size: usize,
+    pub total_chunks: usize,
+    /// Why the file could not be processed, with all sizes zero. Only set
+    /// by [`process_directory`], which reports failures instead of stopping.
+    pub error: Option<String>,
+}
+
+/// Options for [`process_directory`]
+#[derive(Debug, Clone)]
+pub struct SplitJobOptions {
+    /// Maximum tokens per chunk
+    pub token_limit: usize,
+    /// File extensions to split, without the dot, compared case-insensitively
+    pub extensions: Vec<String>,
+    /// Whether to descend into subdirectories
+    pub recursive: bool,
+    /// Maximum number of files split at the same time
+    pub concurrency: usize,
+    /// Split source files [`Language::from_path`] recognizes with a
+    /// [`CodeSplitter`] instead of the splitter, see [`CodeAwareSplitter`]
+    pub split_code: bool,
+    /// Encoding code chunks are counted with, see
+    /// [`CodeSplitter::with_encoding`]
+    pub code_encoding: Encoding,
+    /// Count code chunks with chat markup, see
+    /// [`CodeSplitter::with_chat_overhead`]
+    pub code_chat_overhead: bool,
 }
 
-fn process_file(file_path: &PathBuf, splitter: &TextSplitter, limit: usize) -> Result<Report> {
+impl Default for SplitJobOptions {
+    /// 1000 tokens, markdown files, recursive, one file per available core,
+    /// no code splitting
+    fn default() -> Self {
+        Self {
+            token_limit: 1000,
+            extensions: vec!["md".to_string()],
+            recursive: true,
+            concurrency: std::thread::available_parallelism()
+                .map_or(4, std::num::NonZeroUsize::get),
+            split_code: false,
+            code_encoding: Encoding::default(),
+            code_chat_overhead: false,
+        }
+    }
+}
+
+/// Split the file at `file_path` with any [`Splitter`] and write the chunks as
+/// pretty-printed JSON next to it.
+///
+/// The output is named after the full file name plus `.json` (`guide.md`
+/// writes `guide.md.json`), so files differing only in extension never share
+/// an output.
+///
+/// The file goes through [`Splitter::split_file`], so a [`CodeAwareSplitter`]
+/// splits source files with a [`CodeSplitter`] for their language.
+///
+/// The JSON is written to a temporary file first and renamed into place, so
+/// an interrupted run never leaves a half-written file.
+///
+/// Each chunk's `Metadata::source` is set to `file_path`.
+///
+/// An empty file yields a report with zero chunks and zero sizes.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, split or serialized, or the
+/// JSON file cannot be written.
+///
+/// # Example
+///
+/// ```no_run
+/// use ai_utils::text_splitter::{process_file, TextSplitter};
+///
+/// let report = process_file("article.md".as_ref(), &TextSplitter::new(None), 1000)?;
+/// println!("{}: {} chunks", report.file, report.total_chunks);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn process_file<S: Splitter>(file_path: &Path, splitter: &S, limit: usize) -> Result<Report> {
     let text = fs::read_to_string(file_path)
         .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
 
-    let docs = splitter.split(&text, limit)?;
+    let mut docs = splitter.split_file(file_path, &text, limit)?;
+    let source = file_path.display().to_string();
+    for doc in &mut docs {
+        doc.metadata.source = Some(source.clone());
+    }
 
-    let json_path = file_path.with_extension("json");
-    fs::write(
+    let json_path = json_output_path(file_path);
+    write_atomically(
         &json_path,
-        serde_json::to_string_pretty(&docs)
-            .with_context(|| "Failed to serialize chunks to JSON")?,
+        &serde_json::to_vec_pretty(&docs).with_context(|| "Failed to serialize chunks to JSON")?,
     )
     .with_context(|| format!("Failed to write JSON file: {}", json_path.display()))?;
 
     let chunk_sizes: Vec<usize> = docs.iter().map(|doc| doc.metadata.tokens).collect();
-    let avg_chunk_size = chunk_sizes.iter().sum::<usize>() as f64 / chunk_sizes.len() as f64;
+    #[allow(clippy::cast_precision_loss)]
+    let avg_chunk_size = if chunk_sizes.is_empty() {
+        0.0
+    } else {
+        chunk_sizes.iter().sum::<usize>() as f64 / chunk_sizes.len() as f64
+    };
     let min_chunk_size = *chunk_sizes.iter().min().unwrap_or(&0);
     let max_chunk_size = *chunk_sizes.iter().max().unwrap_or(&0);
     let mut sorted_sizes = chunk_sizes.clone();
     sorted_sizes.sort_unstable();
-    let median_chunk_size = sorted_sizes[sorted_sizes.len() / 2];
+    let median_chunk_size = sorted_sizes
+        .get(sorted_sizes.len() / 2)
+        .copied()
+        .unwrap_or(0);
 
     Ok(Report {
         file: file_path
             .file_name()
-            .unwrap()
+            .unwrap_or(file_path.as_os_str())
             .to_string_lossy()
             .into_owned(),
         avg_chunk_size,
@@ -49,9 +180,233 @@ fn process_file(file_path: &PathBuf, splitter: &TextSplitter, limit: usize) -> R
         min_chunk_size,
         max_chunk_size,
         total_chunks: chunk_sizes.len(),
+        error: None,
     })
 }
 
+/// `file_path` with `.json` appended to its file name
+fn json_output_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".json");
+    file_path.with_file_name(name)
+}
+
+/// Write `contents` to a temporary file in the directory of `path`, then
+/// rename it over `path`
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let mut file = tempfile::NamedTempFile::new_in(dir.unwrap_or_else(|| Path::new(".")))?;
+    file.write_all(contents)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// Split every matching file under `dir` with [`process_file`].
+///
+/// Up to `options.concurrency` files are split at a time on the blocking
+/// thread pool, since tokenization is CPU-bound. With `options.split_code`,
+/// `splitter` is wrapped in a [`CodeAwareSplitter`], so functions in source
+/// files are not cut in half.
+///
+/// A file that fails is reported with [`Report::error`] set and does not stop
+/// the run. Reports are sorted by path, each `file` being relative to `dir`.
+///
+/// # Errors
+///
+/// Returns an error if `dir` or one of its subdirectories cannot be listed.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use ai_utils::text_splitter::{process_directory, SplitJobOptions, TextSplitter};
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let splitter = Arc::new(TextSplitter::new(None));
+/// let reports = process_directory("docs".as_ref(), splitter, SplitJobOptions::default()).await?;
+/// for report in reports.iter().filter(|report| report.error.is_some()) {
+///     eprintln!("{}: {}", report.file, report.error.as_deref().unwrap_or_default());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn process_directory<S>(
+    dir: &Path,
+    splitter: Arc<S>,
+    options: SplitJobOptions,
+) -> Result<Vec<Report>>
+where
+    S: Splitter + Send + Sync + 'static,
+{
+    let files = collect_files(dir, &options).await?;
+    let limit = options.token_limit;
+    let (split_code, code_encoding, code_chat_overhead) = (
+        options.split_code,
+        options.code_encoding,
+        options.code_chat_overhead,
+    );
+
+    let mut reports: Vec<Report> = stream::iter(files)
+        .map(|path| {
+            let splitter = Arc::clone(&splitter);
+            let file = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    if split_code {
+                        let splitter = CodeAwareSplitter::new(&*splitter)
+                            .with_encoding(code_encoding)
+                            .with_chat_overhead(code_chat_overhead);
+                        process_file(&path, &splitter, limit)
+                    } else {
+                        process_file(&path, &*splitter, limit)
+                    }
+                })
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+                match result {
+                    Ok(report) => Report { file, ..report },
+                    Err(e) => Report {
+                        file,
+                        avg_chunk_size: 0.0,
+                        median_chunk_size: 0,
+                        min_chunk_size: 0,
+                        max_chunk_size: 0,
+                        total_chunks: 0,
+                        error: Some(format!("{e:#}")),
+                    },
+                }
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    reports.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(reports)
+}
+
+/// Files under `dir` with one of `options.extensions`
+async fn collect_files(dir: &Path, options: &SplitJobOptions) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&current)
+            .await
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                if options.recursive {
+                    pending.push(path);
+                }
+            } else if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    options
+                        .extensions
+                        .iter()
+                        .any(|wanted| wanted.eq_ignore_ascii_case(ext))
+                })
+            {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// How [`split_file_to_points`] turns chunks into points
+#[cfg(feature = "qdrant")]
+#[derive(Debug, Clone, Default)]
+pub struct PointMapping {
+    /// Mixed into every point id, so the same file ingested under two
+    /// prefixes, e.g. per tenant, gets distinct points
+    pub id_prefix: String,
+    /// Added to the metadata of every point, e.g. a tenant or document type
+    pub metadata_fields: HashMap<String, serde_json::Value>,
+}
+
+/// Read and split the file at `path` into points for Qdrant.
+///
+/// The file is split with `config` at its token limit, and each chunk becomes a
+/// [`PointInput`] ready for
+/// [`QdrantService::upsert_points_batch`](crate::qdrant::QdrantService::upsert_points_batch).
+/// Link and image placeholders in the point text are replaced by their
+/// targets. Each point's metadata holds `source`, the normalized path (`path`
+/// with `/` separators and without `.` components or a leading root), along
+/// with `chunk_index`, `total_chunks`, `tokens`, `headers` (the heading path
+/// joined with `" > "`) and `mapping.metadata_fields`. Ids are UUIDs
+/// derived by [`StableIds`] from `mapping.id_prefix` and `source`, so
+/// re-ingesting an unchanged file overwrites its points; repeated identical
+/// chunks get distinct ids. Pass `path` relative to the root of the corpus,
+/// so that `a/README.md` and `b/README.md` get distinct ids that do not
+/// depend on where the corpus is stored.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or split.
+///
+/// # Example
+///
+/// ```no_run
+/// use ai_utils::text_splitter::{split_file_to_points, PointMapping, SplitterConfig};
+///
+/// let config = SplitterConfig::builder().token_limit(500).build()?;
+/// let points = split_file_to_points("article.md".as_ref(), config, &PointMapping::default())?;
+/// println!("{} points", points.len());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[cfg(feature = "qdrant")]
+pub fn split_file_to_points(
+    path: &Path,
+    config: SplitterConfig,
+    mapping: &PointMapping,
+) -> Result<Vec<PointInput>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let docs = TextSplitter::with_config(config).split_default(&text)?;
+
+    let source = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut ids = StableIds::new(format!("{}{source}", mapping.id_prefix));
+
+    Ok(docs
+        .into_iter()
+        .map(|doc| {
+            let id = ids.next_id(&doc);
+            let mut metadata = mapping.metadata_fields.clone();
+            metadata.extend([
+                ("source".to_string(), source.clone().into()),
+                ("chunk_index".to_string(), doc.metadata.chunk_index.into()),
+                ("total_chunks".to_string(), doc.metadata.total_chunks.into()),
+                ("tokens".to_string(), doc.metadata.tokens.into()),
+                (
+                    "headers".to_string(),
+                    doc.metadata.heading_path.join(" > ").into(),
+                ),
+            ]);
+            PointInput {
+                id,
+                text: text_service::restore_placeholders(&doc.text, &doc.metadata),
+                metadata,
+            }
+        })
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -113,4 +468,1629 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_split_csv() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let csv = "sku,name,description,price\n\
+                   A1,Kettle,\"Boils water, fast\",19.99\n\
+                   B2,Toaster,Two slots,24.50\n\
+                   C3,Empty,,1.00\n";
+
+        let docs = splitter.split_csv(csv, &["name", "description"], Some("sku"), 100)?;
+        assert_eq!(docs.len(), 3);
+
+        assert_eq!(docs[0].text, "Kettle Boils water, fast");
+        assert_eq!(docs[0].metadata.id.as_deref(), Some("A1"));
+        assert_eq!(docs[0].metadata.fields.len(), 1);
+        assert_eq!(docs[0].metadata.fields["price"], "19.99");
+        assert_eq!(docs[2].text, "Empty");
+
+        let long_description = "This sentence is padded out to be long. ".repeat(50);
+        let csv = format!("id,body\nrow,\"{long_description}\"\n");
+        let docs = splitter.split_csv(&csv, &["body"], Some("id"), 100)?;
+        assert!(docs.len() > 1);
+        assert_eq!(docs[0].metadata.id.as_deref(), Some("row-0"));
+        assert_eq!(docs[1].metadata.id.as_deref(), Some("row-1"));
+        for doc in &docs {
+            assert!(doc.metadata.tokens <= 100);
+            assert!(doc.text.ends_with("long."));
+            assert!(doc.metadata.sentence_count.is_some_and(|count| count > 0));
+        }
+
+        assert!(splitter.split_csv(&csv, &["missing"], None, 100).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_frontmatter() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let markdown = "---\ntitle: Getting started\ntags: [rust, ai]\ndraft: false\n---\n# Intro\nHello world\n";
+
+        let docs = splitter.split(markdown, 100)?;
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].text, "# Intro\nHello world\n");
+        assert_eq!(
+            docs[0].metadata.frontmatter,
+            Some(serde_json::json!({
+                "title": "Getting started",
+                "tags": ["rust", "ai"],
+                "draft": false,
+            }))
+        );
+        let body_only = splitter.split("# Intro\nHello world\n", 100)?;
+        assert_eq!(docs[0].metadata.tokens, body_only[0].metadata.tokens);
+        assert_eq!(body_only[0].metadata.frontmatter, None);
+
+        // A horizontal rule later in the text is not front-matter
+        let docs = splitter.split("Intro\n---\nMore\n", 100)?;
+        assert_eq!(docs[0].text, "Intro\n---\nMore\n");
+        assert_eq!(docs[0].metadata.frontmatter, None);
+
+        // Unclosed blocks are kept as body text
+        let docs = splitter.split("---\ntitle: x\nbody\n", 100)?;
+        assert_eq!(docs[0].text, "---\ntitle: x\nbody\n");
+        assert_eq!(docs[0].metadata.frontmatter, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_keeps_code_fences_balanced() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let prose = "Some explanation of what the code below does.\n".repeat(12);
+        let code = format!(
+            "```rust\n{}```\n",
+            "let value = compute(input, 42);\n".repeat(15)
+        );
+        let text = format!("{prose}{code}{prose}~~~\nplain fence\n~~~\n{prose}");
+
+        let docs = splitter.split(&text, 200)?;
+        assert!(docs.len() > 1);
+        for doc in &docs {
+            let fences = doc
+                .text
+                .lines()
+                .filter(|line| line.starts_with("```") || line.starts_with("~~~"))
+                .count();
+            assert_eq!(fences % 2, 0, "unbalanced fence in chunk: {:?}", doc.text);
+        }
+        assert!(docs.iter().any(|doc| doc.text.contains(&code)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_keeps_blocks_atomic() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let prose = "Some explanation of what the code below does.\n".repeat(12);
+        let code_lines = (0..150)
+            .map(|i| format!("result_{i} = compute_value(data[{i}], factor={i})\n"))
+            .collect::<Vec<_>>()
+            .concat();
+        let table = (0..12)
+            .map(|i| format!("| row {i} | value {i} |\n"))
+            .collect::<Vec<_>>()
+            .concat();
+        let table = format!("| name | value |\n|---|---|\n{table}");
+        let text = format!("{prose}```python\n{code_lines}```\n{prose}{table}{prose}");
+        let tokenizer = tiktoken_rs::cl100k_base().unwrap();
+        assert!(text_service::count_tokens(&tokenizer, &code_lines, false) > 2_000);
+
+        let docs = splitter.split(&text, 300)?;
+        for doc in &docs {
+            assert!(doc.metadata.tokens <= 300);
+            let fences = doc
+                .text
+                .lines()
+                .filter(|line| line.starts_with("```"))
+                .count();
+            assert_eq!(fences % 2, 0, "unbalanced fence in chunk: {:?}", doc.text);
+            assert!(doc
+                .text
+                .lines()
+                .filter(|line| line.starts_with("result_"))
+                .all(|line| line.ends_with(')')));
+            let source = &text[doc.metadata.start_offset..doc.metadata.end_offset];
+            assert_eq!(doc.metadata.contains_code, source.contains("result_"));
+        }
+
+        let code_chunks: Vec<_> = docs
+            .iter()
+            .filter(|doc| doc.metadata.contains_code)
+            .collect();
+        assert!(code_chunks.len() > 5);
+        assert!(code_chunks
+            .iter()
+            .all(|doc| doc.text.starts_with("```python\n")));
+        // Every chunk cut inside the block closes it; the last may run on
+        assert!(code_chunks[..code_chunks.len() - 1]
+            .iter()
+            .all(|doc| doc.text.ends_with("```\n")));
+        assert!(docs.iter().any(|doc| doc.text.contains(&table)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_heading_path() -> Result<()> {
+        let setup = "Install the toolchain and configure the project settings.\n".repeat(30);
+        let text =
+            format!("# Guide\nWelcome to the guide.\n## Setup\n{setup}## Usage\nRun the binary.\n");
+
+        let docs = TextSplitter::new(None).split(&text, 150)?;
+        assert!(docs.len() > 2);
+        assert_eq!(docs[0].metadata.heading_path, ["Guide"]);
+        // Later chunks start inside Setup, even the one that reaches Usage
+        assert!(docs[1..]
+            .iter()
+            .all(|doc| doc.metadata.heading_path == ["Guide", "Setup"]));
+        assert!(!docs[1].text.starts_with("Guide"));
+
+        let with_breadcrumbs = TextSplitter::new(None)
+            .with_heading_breadcrumbs(true)
+            .split(&text, 150)?;
+        assert!(!with_breadcrumbs[0].text.starts_with("Guide >"));
+        assert!(with_breadcrumbs[0].text.starts_with("Guide\n\n# Guide"));
+        assert!(with_breadcrumbs[1..]
+            .iter()
+            .all(|doc| doc.text.starts_with("Guide > Setup\n\n")));
+        assert!(with_breadcrumbs
+            .iter()
+            .all(|doc| doc.metadata.tokens <= 150));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear_lower_headers() {
+        let mut headers = Headers::default();
+        for level in 1..=4 {
+            headers.insert(format!("h{level}"), format!("Level {level}"));
+        }
+
+        headers.clear_lower_headers(2);
+        assert_eq!(headers.path(), ["Level 1", "Level 2"]);
+        headers.clear_lower_headers(6);
+        assert_eq!(headers.path(), ["Level 1", "Level 2"]);
+        headers.clear_lower_headers(0);
+        assert!(headers.path().is_empty());
+    }
+
+    #[test]
+    fn test_split_by_headers() -> Result<()> {
+        let lines = |topic: &str| -> String {
+            (1..=12)
+                .map(|i| format!("The {topic} section explains point {i} in some detail.\n"))
+                .collect::<Vec<_>>()
+                .concat()
+        };
+        let text = format!(
+            "Preface text.\n# Intro\nOpening words.\n## Background\n{}### Detail\n{}\
+             ## Method\n```\n## not a heading\n```\n{}## Results\nShort.\n",
+            lines("background"),
+            lines("detail"),
+            lines("method"),
+        );
+        let options = HeaderSplitOptions {
+            max_level: 2,
+            token_limit: 80,
+        };
+
+        let docs = TextSplitter::new(None).split_by_headers(&text, options)?;
+        assert!(docs.iter().all(|doc| doc.metadata.tokens <= 80));
+        assert_eq!(docs[0].text, "Preface text.\n");
+        assert!(docs[0].metadata.section_path.is_empty());
+        assert_eq!(docs[1].metadata.section_path, ["Intro"]);
+
+        let sections = ["Background", "Method", "Results"];
+        let h2_starts: Vec<usize> = sections
+            .iter()
+            .map(|title| text.find(&format!("## {title}")).unwrap())
+            .collect();
+        for doc in &docs {
+            let (start, end) = (doc.metadata.start_offset, doc.metadata.end_offset);
+            assert_eq!(&text[start..end], doc.text);
+            assert!(
+                h2_starts.iter().all(|&h2| h2 <= start || h2 >= end),
+                "chunk spans two sections: {:?}",
+                doc.text
+            );
+            if let Some(index) = h2_starts.iter().rposition(|&h2| h2 <= start) {
+                assert_eq!(doc.metadata.section_path, ["Intro", sections[index]]);
+                assert_eq!(
+                    doc.metadata.headers.get(1),
+                    Some(&["Intro".to_string()][..])
+                );
+            }
+        }
+        // The h3 stays within its h2 section and the fenced heading is ignored
+        assert!(docs
+            .iter()
+            .any(|doc| doc.metadata.heading_path == ["Intro", "Background", "Detail"]));
+        assert!(docs.iter().any(|doc| doc.text.contains("## not a heading")));
+        assert_eq!(
+            docs.iter().map(|doc| doc.text.as_str()).collect::<String>(),
+            text
+        );
+
+        let options = HeaderSplitOptions {
+            max_level: 7,
+            token_limit: 80,
+        };
+        assert!(TextSplitter::new(None)
+            .split_by_headers(&text, options)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_trailing_whitespace() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let text = format!("# Title\nSome closing words.{}", "\n".repeat(5_000));
+
+        let docs = splitter.split(&text, 200)?;
+        assert!(docs.iter().all(|doc| !doc.text.trim().is_empty()));
+        assert!(docs.last().unwrap().text.contains("Some closing words."));
+
+        let docs = splitter.split(&format!("Text.{}", " \n\t".repeat(500)), 200)?;
+        assert_eq!(docs.len(), 1);
+
+        assert!(splitter.split("\n\n   \n", 200)?.is_empty());
+        assert!(splitter.split("", 200)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_tokens_merges_tail() -> Result<()> {
+        let sentences = |count: usize| -> String {
+            (1..=count)
+                .map(|i| format!("Sentence number {i}.\n"))
+                .collect::<Vec<_>>()
+                .concat()
+        };
+
+        // The slack only applies outside strict mode
+        let splitter = || TextSplitter::new(None).with_strict(false);
+        let text = sentences(40);
+        let docs = splitter().split(&text, 100)?;
+        let tail = docs.last().unwrap().metadata.tokens;
+        assert!(tail < 40);
+
+        let merged = splitter().with_min_tokens(40).split(&text, 100)?;
+        assert_eq!(merged.len(), docs.len() - 1);
+        assert!(merged.last().unwrap().metadata.tokens <= 110);
+        assert_eq!(
+            merged
+                .iter()
+                .map(|doc| doc.text.as_str())
+                .collect::<String>(),
+            text
+        );
+
+        // A merge that would overshoot the limit by more than the slack is skipped
+        let text = sentences(45);
+        let docs = splitter().split(&text, 100)?;
+        let kept = splitter().with_min_tokens(100).split(&text, 100)?;
+        assert_eq!(kept.len(), docs.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stable_ids() -> Result<()> {
+        let options = HeaderSplitOptions {
+            max_level: 2,
+            token_limit: 200,
+        };
+        let ids = |text: &str| -> Result<Vec<String>> {
+            let mut docs = TextSplitter::new(None).split_by_headers(text, options)?;
+            StableIds::new("guide.md").assign(&mut docs);
+            Ok(docs
+                .into_iter()
+                .map(|doc| doc.metadata.id.unwrap())
+                .collect())
+        };
+
+        let text = "# Guide\n## Install\nRun the [installer](https://example.com/a).\n\
+                    ## Usage\nStart the app.\n## FAQ\nAsk away.\n";
+        let first = ids(text)?;
+        assert_eq!(first.len(), 4);
+        assert!(first.iter().all(|id| uuid::Uuid::parse_str(id).is_ok()));
+        assert_eq!(ids(text)?, first);
+
+        // Only the edited section gets a new id
+        let edited = ids(&text.replace("Start the app.", "Start the app twice."))?;
+        assert_eq!(edited[..2], first[..2]);
+        assert_ne!(edited[2], first[2]);
+        assert_eq!(edited[3], first[3]);
+
+        // Reflowed whitespace keeps the id, a new link target does not
+        assert_eq!(
+            ids(&text.replace("Start the app.", "Start   the\napp."))?,
+            first
+        );
+        let relinked = ids(&text.replace("example.com/a", "example.com/b"))?;
+        assert_ne!(relinked[1], first[1]);
+        assert_eq!(relinked[2..], first[2..]);
+
+        let docs = TextSplitter::new(None).split_by_headers(text, options)?;
+        assert!(docs[0].metadata.id.is_none());
+        assert_eq!(docs[1].stable_id("guide.md"), first[1]);
+        assert_ne!(docs[1].stable_id("other.md"), first[1]);
+
+        // Repeated chunks under one heading get distinct ids, the first
+        // keeping the plain one
+        let repeated = ids("# Guide\n## FAQ\nAsk away.\n## FAQ\nAsk away.\n")?;
+        assert_eq!(repeated.len(), 3);
+        assert_ne!(repeated[1], repeated[2]);
+        assert_eq!(repeated[..2], ids("# Guide\n## FAQ\nAsk away.\n")?[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_segmentation() {
+        let text = "Dr. Smith paid $3.50 for apples, e.g. Fuji ones. He left!  \
+                    Did he?! \"Yes.\" Wait... what? J. R. R. Tolkien wrote it (see fig. 2). \
+                    東京です。次の文！\n\nNew paragraph without a stop\nLast line.";
+        assert_eq!(
+            SentenceSplitter::sentences(text),
+            [
+                "Dr. Smith paid $3.50 for apples, e.g. Fuji ones.",
+                "He left!",
+                "Did he?!",
+                "\"Yes.\"",
+                "Wait... what?",
+                "J. R. R. Tolkien wrote it (see fig. 2).",
+                "東京です。",
+                "次の文！",
+                "New paragraph without a stop\nLast line.",
+            ]
+        );
+        assert!(SentenceSplitter::sentences("  \n\n ").is_empty());
+    }
+
+    #[test]
+    fn test_sentence_splitter() -> Result<()> {
+        let splitter = SentenceSplitter::new();
+        let sentence = "The quick brown fox jumps over the lazy dog.";
+        let text = format!("{sentence} ").repeat(6);
+        let docs = splitter.split(&text, 25)?;
+        assert_eq!(docs.len(), 3);
+        for doc in &docs {
+            assert_eq!(doc.text, format!("{sentence} {sentence}"));
+            assert_eq!(doc.metadata.sentence_count, Some(2));
+            assert_eq!(
+                &text[doc.metadata.start_offset..doc.metadata.end_offset],
+                doc.text
+            );
+        }
+
+        // An oversized sentence is cut between words, only its last piece ends it
+        let long = format!("{} end.", "word ".repeat(40).trim_end());
+        let docs = splitter.split(&format!("{long} Short one."), 15)?;
+        assert!(docs.len() > 2);
+        assert!(docs.iter().all(|doc| doc.metadata.tokens <= 15));
+        assert!(docs.iter().all(|doc| !doc.text.starts_with("ord")));
+        let counts: Vec<_> = docs
+            .iter()
+            .map(|doc| doc.metadata.sentence_count.unwrap())
+            .collect();
+        assert_eq!(counts.iter().sum::<usize>(), 2);
+        assert_eq!(counts[0], 0);
+        Ok(())
+    }
+
+    /// Markdown section `index` of a synthetic document, with a heading,
+    /// paragraphs and every few sections a code block or table
+    fn synthetic_section(index: usize) -> String {
+        use std::fmt::Write;
+
+        let mut section = String::new();
+        if index.is_multiple_of(50) {
+            writeln!(section, "# Part {}\n", index / 50).unwrap();
+        }
+        writeln!(section, "## Section {index}\n").unwrap();
+        for line in 0..12 {
+            writeln!(
+                section,
+                "Line {line} of section {index} covers topic {}.",
+                (index * 7 + line) % 13
+            )
+            .unwrap();
+        }
+        if index.is_multiple_of(5) {
+            writeln!(
+                section,
+                "\n```rust\n// # not a heading\nfn section_{index}() {{}}\n```"
+            )
+            .unwrap();
+        }
+        if index.is_multiple_of(7) {
+            writeln!(section, "\n| id | name |\n| -- | ---- |\n| {index} | row |").unwrap();
+        }
+        section.push('\n');
+        section
+    }
+
+    /// Reader generating synthetic sections until `len` bytes were produced
+    struct SyntheticMarkdown {
+        len: usize,
+        produced: usize,
+        next_section: usize,
+        pending: Vec<u8>,
+    }
+
+    impl std::io::Read for SyntheticMarkdown {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() && self.produced < self.len {
+                self.pending = synthetic_section(self.next_section).into_bytes();
+                self.next_section += 1;
+            }
+            let count = buf.len().min(self.pending.len());
+            buf[..count].copy_from_slice(&self.pending[..count]);
+            self.pending.drain(..count);
+            self.produced += count;
+            Ok(count)
+        }
+    }
+
+    fn collect_stream(splitter: &TextSplitter, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        let mut docs = Vec::new();
+        splitter.split_stream(text.as_bytes(), limit, |doc| {
+            docs.push(doc);
+            Ok(())
+        })?;
+        Ok(docs)
+    }
+
+    #[test]
+    fn test_split_stream_matches_split() -> Result<()> {
+        let body: String = (0..40).map(synthetic_section).collect();
+        let text = format!("---\ntitle: Stream\n---\n{body}");
+        let splitter = TextSplitter::new(None).with_stream_window(1 << 20);
+
+        let streamed = collect_stream(&splitter, &text, 150)?;
+        let mut expected = splitter.split(&text, 150)?;
+        for doc in &mut expected {
+            doc.metadata.total_chunks = 0;
+        }
+        assert_eq!(
+            serde_json::to_value(&streamed)?,
+            serde_json::to_value(&expected)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalization() -> Result<()> {
+        let body: String = (0..20).map(synthetic_section).collect();
+        let unix = format!("---\ntitle: Notes\n---\n{body}");
+        let windows = unix.replace('\n', "\r\n");
+        let splitter = TextSplitter::new(None).with_stream_window(2 * 1024);
+        let options = HeaderSplitOptions {
+            max_level: 2,
+            token_limit: 120,
+        };
+
+        // CRLF input chunks exactly like its LF version by default
+        let json = |docs: Vec<Doc>| serde_json::to_value(docs).unwrap();
+        assert_eq!(
+            json(splitter.split(&windows, 120)?),
+            json(splitter.split(&unix, 120)?)
+        );
+        assert_eq!(
+            json(splitter.split_by_headers(&windows, options)?),
+            json(splitter.split_by_headers(&unix, options)?)
+        );
+        assert_eq!(
+            json(collect_stream(&splitter, &windows, 120)?),
+            json(collect_stream(&splitter, &unix, 120)?)
+        );
+        let docs = splitter.split(&windows, 120)?;
+        assert!(docs.iter().all(|doc| !doc.text.contains('\r')));
+        assert_eq!(
+            docs[0].metadata.normalizations,
+            [Normalization::LineEndings]
+        );
+
+        let raw = TextSplitter::new(None).with_normalization(NormalizeOptions::none());
+        let docs = raw.split(&windows, 120)?;
+        assert!(docs[0].text.contains("\r\n"));
+        assert!(docs[0].metadata.normalizations.is_empty());
+
+        // The other passes are opt-in
+        let text = "Caf\u{65}\u{301}  \r\n\r\n \r\n\r\nEnd\t\n";
+        assert_eq!(
+            NormalizeOptions::default().apply(text),
+            "Caf\u{65}\u{301}  \n\n \n\nEnd\t\n"
+        );
+        assert_eq!(NormalizeOptions::all().apply(text), "Caf\u{e9}\n\nEnd\n");
+        let config = SplitterConfig::builder()
+            .normalize(NormalizeOptions::all())
+            .build()?;
+        let docs = TextSplitter::with_config(config).split_default(text)?;
+        assert_eq!(docs[0].text, "Caf\u{e9}\n\nEnd\n");
+        assert_eq!(
+            serde_json::to_value(&docs[0].metadata.normalizations)?,
+            serde_json::json!(["line_endings", "blank_lines", "trailing_whitespace", "nfc"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_progress_and_cancellation() -> Result<()> {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        };
+
+        let text: String = (0..30).map(synthetic_section).collect();
+        let full = TextSplitter::new(None).split(&text, 120)?;
+        assert!(full.len() > 3);
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&reports);
+        let splitter = TextSplitter::new(None)
+            .with_progress(move |progress| recorded.lock().unwrap().push(progress));
+        let outcome = splitter.split_cancellable(&text, 120)?;
+        assert!(!outcome.cancelled);
+        let progress = reports.lock().unwrap().clone();
+        assert_eq!(progress.len(), full.len());
+        assert!(progress
+            .windows(2)
+            .all(|pair| pair[0].bytes_processed < pair[1].bytes_processed));
+        for (index, (report, doc)) in progress.iter().zip(&full).enumerate() {
+            assert_eq!(report.chunks_emitted, index + 1);
+            assert_eq!(report.total_bytes, Some(text.len()));
+            assert_eq!(report.bytes_processed, doc.metadata.end_offset);
+        }
+
+        // Cancelling from the callback keeps the chunks emitted so far
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancel);
+        let splitter = TextSplitter::new(None)
+            .with_cancellation(Arc::clone(&cancel))
+            .with_progress(move |progress| {
+                if progress.chunks_emitted == 3 {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            });
+        let outcome = splitter.split_cancellable(&text, 120)?;
+        assert!(outcome.cancelled);
+        assert_eq!(outcome.docs.len(), 3);
+        for (partial, doc) in outcome.docs.iter().zip(&full) {
+            assert_eq!(partial.text, doc.text);
+            assert_eq!(partial.metadata.end_offset, doc.metadata.end_offset);
+        }
+
+        cancel.store(false, Ordering::Relaxed);
+        let flag = Arc::clone(&cancel);
+        let splitter = TextSplitter::new(None)
+            .with_stream_window(2 * 1024)
+            .with_cancellation(Arc::clone(&cancel))
+            .with_progress(move |progress| {
+                assert_eq!(progress.total_bytes, None);
+                if progress.chunks_emitted == 2 {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            });
+        assert_eq!(collect_stream(&splitter, &text, 120)?.len(), 2);
+        cancel.store(false, Ordering::Relaxed);
+        let outcome = splitter.split_stream_cancellable(text.as_bytes(), 120, |_| Ok(()))?;
+        assert_eq!(
+            outcome,
+            StreamOutcome {
+                chunks: 2,
+                cancelled: true
+            }
+        );
+
+        // A flag set with the last chunk does not count as cancelling
+        cancel.store(false, Ordering::Relaxed);
+        let flag = Arc::clone(&cancel);
+        let last = full.len();
+        let splitter = TextSplitter::new(None)
+            .with_cancellation(Arc::clone(&cancel))
+            .with_progress(move |progress| {
+                if progress.chunks_emitted == last {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            });
+        let outcome = splitter.split_cancellable(&text, 120)?;
+        assert!(!outcome.cancelled);
+        assert_eq!(outcome.docs.len(), full.len());
+        cancel.store(false, Ordering::Relaxed);
+        let outcome = splitter.split_stream_cancellable(text.as_bytes(), 120, |_| Ok(()))?;
+        assert!(!outcome.cancelled);
+
+        // Header splits stop at the next chunk of any section
+        let options = HeaderSplitOptions {
+            max_level: 2,
+            token_limit: 120,
+        };
+        let sections = TextSplitter::new(None).split_by_headers(&text, options)?;
+        cancel.store(false, Ordering::Relaxed);
+        let flag = Arc::clone(&cancel);
+        let splitter = TextSplitter::new(None)
+            .with_cancellation(Arc::clone(&cancel))
+            .with_progress(move |progress| {
+                if progress.chunks_emitted == 3 {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            });
+        let outcome = splitter.split_by_headers_cancellable(&text, options)?;
+        assert!(outcome.cancelled);
+        assert_eq!(outcome.docs.len(), 3);
+        assert!(sections.len() > 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_stream_windows() -> Result<()> {
+        let text: String = (0..300).map(synthetic_section).collect();
+        let splitter = TextSplitter::new(None).with_stream_window(4 * 1024);
+        let docs = collect_stream(&splitter, &text, 150)?;
+        assert!(docs.len() > 100);
+
+        let mut previous_end = 0;
+        for (index, doc) in docs.iter().enumerate() {
+            let meta = &doc.metadata;
+            assert_eq!(meta.chunk_index, index);
+            assert!(meta.tokens <= 150);
+            assert!(text[previous_end..meta.start_offset].trim().is_empty());
+            previous_end = meta.end_offset;
+
+            let source = &text[meta.start_offset..meta.end_offset];
+            let fence_lines = source
+                .lines()
+                .filter(|line| line.starts_with("```"))
+                .count();
+            assert_eq!(fence_lines % 2, 0, "chunk {index} splits a code block");
+
+            // Headings carry over window boundaries
+            let before = &text[..meta.end_offset];
+            let section = before.rfind("\n## Section ").map_or(0, |at| at + 1);
+            let part = before.rfind("# Part ").unwrap();
+            let heading = before[section + 3..].lines().next().unwrap();
+            let expected = (section > part).then(|| heading.to_string());
+            assert_eq!(
+                meta.headers.get(2).and_then(|headings| headings.last()),
+                expected.as_ref()
+            );
+            let part = before[part + 2..].lines().next().unwrap();
+            assert_eq!(
+                meta.headers.get(1).and_then(|headings| headings.last()),
+                Some(&part.to_string())
+            );
+        }
+        assert!(text[previous_end..].trim().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "splits 50 MB, which takes minutes in debug builds"]
+    fn test_split_stream_large_input() -> Result<()> {
+        const LEN: usize = 50 * 1024 * 1024;
+        let reader = std::io::BufReader::new(SyntheticMarkdown {
+            len: LEN,
+            produced: 0,
+            next_section: 0,
+            pending: Vec::new(),
+        });
+        let splitter = TextSplitter::new(None);
+
+        let mut count = 0;
+        let mut last_end = 0;
+        let mut last_heading = None;
+        let total = splitter.split_stream(reader, 2000, |doc| {
+            assert_eq!(doc.metadata.chunk_index, count);
+            assert!(doc.metadata.tokens <= 2000);
+            assert!(doc.metadata.start_offset >= last_end);
+            count += 1;
+            last_end = doc.metadata.end_offset;
+            last_heading = doc.metadata.headers.get(2).and_then(|h| h.last().cloned());
+            Ok(())
+        })?;
+
+        assert_eq!(total, count);
+        assert!(last_end > LEN - 1024);
+        assert!(last_heading.is_some_and(|heading| heading.starts_with("Section ")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_offsets() -> Result<()> {
+        let frontmatter = "---\ntitle: Offsets\n---\n";
+        let body = "Plain sentence without any links in it.\n".repeat(40);
+        let text = format!("{frontmatter}{body}");
+
+        let docs = TextSplitter::new(None).split(&text, 100)?;
+        assert!(docs.len() > 1);
+        assert_eq!(docs[0].metadata.start_offset, frontmatter.len());
+        assert_eq!(docs.last().unwrap().metadata.end_offset, text.len());
+        for doc in &docs {
+            let range = doc.metadata.start_offset..doc.metadata.end_offset;
+            assert_eq!(&text[range], doc.text);
+        }
+        for pair in docs.windows(2) {
+            assert_eq!(pair[0].metadata.end_offset, pair[1].metadata.start_offset);
+        }
+        for (index, doc) in docs.iter().enumerate() {
+            assert_eq!(doc.metadata.chunk_index, index);
+            assert_eq!(doc.metadata.total_chunks, docs.len());
+            assert_eq!(doc.metadata.source, None);
+        }
+
+        let merged = TextSplitter::new(None)
+            .with_min_tokens(1_000)
+            .split(&text, 100)?;
+        assert_eq!(merged.last().unwrap().metadata.end_offset, text.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_splitter_rust() -> Result<()> {
+        let source = include_str!("sentence.rs");
+        let splitter = CodeSplitter::new(Language::Rust);
+        // Every function of the file fits this limit
+        let docs = splitter.split(source, 600)?;
+        assert!(docs.len() > 3);
+
+        for doc in &docs {
+            let start = doc.metadata.start_offset;
+            assert_eq!(&source[start..doc.metadata.end_offset], doc.text);
+            assert!(doc.metadata.tokens <= 600);
+            // Chunks start at the top level or at a method of an impl block,
+            // never inside a function body
+            let before = &source[..start];
+            let depth = before.matches('{').count() - before.matches('}').count();
+            let item = doc
+                .text
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.starts_with("//") && !line.starts_with("#["))
+                .unwrap();
+            match depth {
+                0 => {}
+                1 => assert!(
+                    ["fn ", "pub fn ", "pub const fn "]
+                        .iter()
+                        .any(|keyword| item.starts_with(keyword)),
+                    "chunk starts inside an item: {item}"
+                ),
+                _ => panic!("chunk starts inside a function body: {item}"),
+            }
+        }
+
+        // Doc comments stay with their item, which names the chunk
+        let ranges = docs
+            .iter()
+            .find(|doc| doc.text.contains("fn sentence_ranges("))
+            .unwrap();
+        assert!(ranges.text.starts_with("/// Byte ranges of the sentences"));
+        assert_eq!(
+            ranges.metadata.item_name.as_deref(),
+            Some("sentence_ranges")
+        );
+        assert!(docs.iter().any(|doc| doc
+            .metadata
+            .item_name
+            .as_deref()
+            .is_some_and(|name| name.starts_with("SentenceSplitter::"))));
+
+        // A function over the limit falls back to whole lines
+        let docs = splitter.split(source, 60)?;
+        let ranges: Vec<_> = docs
+            .iter()
+            .filter(|doc| doc.metadata.item_name.as_deref() == Some("sentence_ranges"))
+            .collect();
+        assert!(ranges.len() > 1);
+        assert!(ranges[0]
+            .text
+            .starts_with("/// Byte ranges of the sentences"));
+        for doc in ranges {
+            assert!(doc.metadata.tokens <= 60);
+            assert!(source[..doc.metadata.start_offset].ends_with('\n'));
+        }
+
+        // A minified line is cut within the line, and every chunk fits
+        let minified = format!(
+            "fn table() -> &'static [u32] {{ &[{}] }}\n",
+            (0..5_000)
+                .map(|n| format!("{n}é"))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let docs = splitter.split(&minified, 100)?;
+        assert!(docs.len() > 10);
+        let mut end = 0;
+        for doc in &docs {
+            assert!(doc.metadata.tokens <= 100);
+            assert!(doc.metadata.start_offset >= end);
+            end = doc.metadata.end_offset;
+        }
+        assert_eq!(end, minified.trim_end().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_splitter_python_and_typescript() -> Result<()> {
+        let python = r#"import os
+
+
+@dataclass
+class Parser:
+    """Parses things.
+
+def not_an_item():
+    """
+
+    # Entry point
+    @staticmethod
+    def parse(text):
+        return text.split()
+
+    def reset(self):
+        self.state = None
+
+
+async def main():
+    print(Parser.parse("a b"))
+"#;
+        let docs = CodeSplitter::new(Language::Python).split(python, 20)?;
+        let name = |name: &str| {
+            docs.iter()
+                .find(|doc| doc.metadata.item_name.as_deref() == Some(name))
+                .unwrap_or_else(|| panic!("no chunk of {name}"))
+        };
+        assert!(name("Parser").text.starts_with("@dataclass\nclass Parser:"));
+        assert!(name("Parser.parse")
+            .text
+            .starts_with("    # Entry point\n    @staticmethod\n    def parse"));
+        assert!(name("Parser.reset")
+            .text
+            .starts_with("    def reset(self):"));
+        assert!(name("main").text.starts_with("async def main():"));
+        // A def inside a docstring is not an item
+        assert!(docs
+            .iter()
+            .all(|doc| doc.metadata.item_name.as_deref() != Some("not_an_item")));
+
+        let typescript = r#"import { readFile } from "fs";
+
+/** Loads a config file. */
+export async function load(path: string): Promise<Config> {
+  const text = await readFile(path, "utf8");
+  return JSON.parse(text) as Config;
+}
+
+export class Store {
+  private items = new Map<string, string>();
+
+  get(key: string): string | undefined {
+    return this.items.get(key);
+  }
+
+  set(key: string, value: string): void {
+    this.items.set(key, `${key}}`);
+  }
+}
+"#;
+        let docs = CodeSplitter::new(Language::TypeScript).split(typescript, 30)?;
+        let names: Vec<_> = docs
+            .iter()
+            .map(|doc| doc.metadata.item_name.as_deref())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                None,
+                Some("load"),
+                Some("load"),
+                Some("Store"),
+                Some("Store.get"),
+                Some("Store.set")
+            ]
+        );
+        assert!(docs[1].text.starts_with("/** Loads a config file. */"));
+        // The brace in the template literal does not close the class
+        assert!(docs[5].text.ends_with("  }\n}"));
+
+        // Braces and slashes in regex literals are not counted
+        let typescript = r"const open = /\{/;
+const path = /[/{]+$/g.test(url) ? 1 : 2 / 3;
+
+function first(): number {
+  return 1;
+}
+
+function second(): number {
+  return 2;
+}
+";
+        let docs = CodeSplitter::new(Language::TypeScript).split(typescript, 12)?;
+        for name in ["first", "second"] {
+            let doc = docs
+                .iter()
+                .find(|doc| doc.text.starts_with(&format!("function {name}")))
+                .unwrap_or_else(|| panic!("{name} does not start a chunk"));
+            assert_eq!(doc.metadata.item_name.as_deref(), Some(name));
+        }
+
+        assert_eq!(Language::from_extension("TSX"), Some(Language::TypeScript));
+        assert_eq!(
+            Language::from_path(Path::new("src/lib.rs")),
+            Some(Language::Rust)
+        );
+        assert_eq!(Language::from_extension("md"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_directory_splits_code() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.py");
+        let function =
+            |index: usize| format!("def step_{index}(value):\n    return value + {index}\n\n\n");
+        fs::write(&path, (0..20).map(function).collect::<String>())?;
+        let read_docs = || -> Result<Vec<Doc>> {
+            Ok(serde_json::from_str(&fs::read_to_string(
+                json_output_path(&path),
+            )?)?)
+        };
+        let splitter = Arc::new(TextSplitter::new(None));
+        let options = SplitJobOptions {
+            token_limit: 40,
+            extensions: vec!["py".to_string()],
+            ..SplitJobOptions::default()
+        };
+
+        // Off by default
+        process_directory(dir.path(), Arc::clone(&splitter), options.clone()).await?;
+        assert!(read_docs()?
+            .iter()
+            .all(|doc| doc.metadata.item_name.is_none()));
+
+        let options = SplitJobOptions {
+            split_code: true,
+            code_encoding: Encoding::O200k,
+            ..options
+        };
+        let reports = process_directory(dir.path(), splitter, options).await?;
+        assert!(reports[0].error.is_none());
+        let docs = read_docs()?;
+        assert!(docs.len() > 1);
+        for doc in &docs {
+            assert!(doc.text.starts_with("def step_"));
+            assert!(doc
+                .metadata
+                .item_name
+                .as_deref()
+                .unwrap()
+                .starts_with("step_"));
+            assert!(doc.metadata.tokens <= 40);
+        }
+
+        // process_file picks the splitter by extension through the wrapper
+        let wrapped = CodeAwareSplitter::new(TextSplitter::new(None));
+        let report = process_file(&path, &wrapped, 40)?;
+        assert_eq!(report.total_chunks, docs.len());
+        assert!(read_docs()?
+            .iter()
+            .all(|doc| doc.metadata.item_name.is_some()));
+        assert!(wrapped.split("def f():\n    pass\n", 40)?[0]
+            .metadata
+            .item_name
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_output_names() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let markdown = dir.path().join("foo.md");
+        let rust = dir.path().join("foo.rs");
+        fs::write(&markdown, "A markdown paragraph.\n")?;
+        fs::write(&rust, "fn main() {}\n")?;
+
+        let splitter = TextSplitter::new(None);
+        process_file(&markdown, &splitter, 100)?;
+        process_file(&rust, &splitter, 100)?;
+
+        for (path, text) in [(&markdown, "markdown"), (&rust, "main")] {
+            let json_path = dir.path().join(format!(
+                "{}.json",
+                path.file_name().unwrap().to_string_lossy()
+            ));
+            let docs: Vec<Doc> = serde_json::from_str(&fs::read_to_string(json_path)?)?;
+            assert!(docs[0].text.contains(text));
+        }
+        assert!(!dir.path().join("foo.json").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_metadata() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.md");
+        let text = "A sentence with a [link](https://example.com) in it.\n".repeat(30);
+        fs::write(&path, &text)?;
+
+        let report = process_file(&path, &TextSplitter::new(None), 100)?;
+        let docs: Vec<Doc> = serde_json::from_str(&fs::read_to_string(json_output_path(&path))?)?;
+        assert_eq!(docs.len(), report.total_chunks);
+        assert!(docs.len() > 1);
+
+        let mut end = 0;
+        for (index, doc) in docs.iter().enumerate() {
+            let metadata = &doc.metadata;
+            assert_eq!(metadata.source.as_deref(), Some(path.to_str().unwrap()));
+            assert_eq!(
+                (metadata.chunk_index, metadata.total_chunks),
+                (index, docs.len())
+            );
+            assert_eq!(metadata.start_offset, end);
+            end = metadata.end_offset;
+
+            // Replacing the placeholders restores the source slice
+            let restored = metadata
+                .urls
+                .iter()
+                .enumerate()
+                .fold(doc.text.clone(), |text, (index, url)| {
+                    text.replacen(&format!("{{$url{index}}}"), url, 1)
+                });
+            assert_eq!(restored, text[metadata.start_offset..metadata.end_offset]);
+        }
+        assert_eq!(end, text.len());
+        Ok(())
+    }
+
+    #[cfg(feature = "qdrant")]
+    #[test]
+    fn test_split_file_to_points() -> Result<()> {
+        let path = Path::new("example_article.md");
+        let config = SplitterConfig::builder().token_limit(300).build()?;
+        let mapping = PointMapping {
+            id_prefix: "blog/".to_string(),
+            metadata_fields: HashMap::from([("lang".to_string(), "en".into())]),
+        };
+        let points = split_file_to_points(path, config, &mapping)?;
+        assert!(points.len() > 1);
+
+        let ids: std::collections::HashSet<&str> =
+            points.iter().map(|point| point.id.as_str()).collect();
+        assert_eq!(ids.len(), points.len());
+        assert!(ids.iter().all(|id| uuid::Uuid::parse_str(id).is_ok()));
+        for (index, point) in points.iter().enumerate() {
+            let metadata = &point.metadata;
+            assert_eq!(metadata["source"], "example_article.md");
+            assert_eq!(metadata["chunk_index"], index);
+            assert_eq!(metadata["total_chunks"], points.len());
+            assert!(metadata["tokens"]
+                .as_u64()
+                .is_some_and(|tokens| tokens <= 300));
+            assert!(metadata["headers"].is_string());
+            assert_eq!(metadata["lang"], "en");
+        }
+        assert_eq!(
+            points[1].metadata["headers"],
+            "Indie Hacker's toolstack 2024 > Text editor(s)"
+        );
+
+        // Ids are deterministic and depend on the prefix
+        let again = split_file_to_points(path, config, &mapping)?;
+        assert!(points.iter().zip(&again).all(|(a, b)| a.id == b.id));
+        let other = split_file_to_points(path, config, &PointMapping::default())?;
+        assert!(points.iter().zip(&other).all(|(a, b)| a.id != b.id));
+
+        // Identical chunks still get distinct ids
+        let dir = tempfile::tempdir()?;
+        let repeated = dir.path().join("repeated.md");
+        fs::write(&repeated, "Same paragraph.\n\n".repeat(3))?;
+        let config = SplitterConfig::builder().token_limit(4).build()?;
+        let points = split_file_to_points(&repeated, config, &mapping)?;
+        assert_eq!(points.len(), 3);
+        assert!(points[0].id != points[1].id && points[1].id != points[2].id);
+        assert!(points[0].id != points[2].id);
+
+        // Files with the same name in different directories do not collide,
+        // and placeholders are replaced by their targets
+        let config = SplitterConfig::builder().token_limit(300).build()?;
+        let text = "See [the docs](https://example.com/docs) and ![logo](logo.png).";
+        let mut ids = Vec::new();
+        for sub in ["a", "b"] {
+            fs::create_dir(dir.path().join(sub))?;
+            let path = dir.path().join(sub).join("README.md");
+            fs::write(&path, text)?;
+            let points = split_file_to_points(&path, config, &PointMapping::default())?;
+            assert_eq!(points.len(), 1);
+            assert_eq!(
+                points[0].text,
+                "See [the docs](https://example.com/docs) and ![logo](logo.png)."
+            );
+            ids.push(points[0].id.clone());
+        }
+        assert_ne!(ids[0], ids[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let nested = dir.path().join("guides/advanced");
+        fs::create_dir_all(&nested)?;
+        let text = "# Title\nA short paragraph about the topic.\n".repeat(20);
+        fs::write(dir.path().join("intro.md"), &text)?;
+        fs::write(dir.path().join("guides/setup.MD"), &text)?;
+        fs::write(nested.join("tuning.md"), &text)?;
+        fs::write(nested.join("broken.md"), [0xff, 0xfe, 0x00])?;
+        fs::write(dir.path().join("notes.txt"), &text)?;
+
+        let splitter = Arc::new(TextSplitter::new(None));
+        let options = SplitJobOptions {
+            token_limit: 50,
+            concurrency: 2,
+            ..SplitJobOptions::default()
+        };
+        let reports = process_directory(dir.path(), Arc::clone(&splitter), options.clone()).await?;
+
+        let files: Vec<&str> = reports.iter().map(|report| report.file.as_str()).collect();
+        assert_eq!(
+            files,
+            [
+                "guides/advanced/broken.md",
+                "guides/advanced/tuning.md",
+                "guides/setup.MD",
+                "intro.md"
+            ]
+        );
+        assert!(reports[0].error.is_some());
+        assert!(!nested.join("broken.md.json").exists());
+        for report in &reports[1..] {
+            assert!(report.error.is_none());
+            assert!(report.total_chunks > 1);
+        }
+        let docs: Vec<Doc> =
+            serde_json::from_str(&fs::read_to_string(nested.join("tuning.md.json"))?)?;
+        assert_eq!(docs.len(), reports[1].total_chunks);
+        assert!(!dir.path().join("notes.txt.json").exists());
+
+        // No temporary files are left next to the outputs
+        let leftovers = fs::read_dir(&nested)?
+            .filter_map(Result::ok)
+            .filter(|entry| !entry.file_name().to_string_lossy().contains(".md"))
+            .filter(|entry| !entry.file_name().to_string_lossy().ends_with(".json"))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        let shallow = SplitJobOptions {
+            recursive: false,
+            ..options
+        };
+        let reports = process_directory(dir.path(), splitter, shallow).await?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].file, "intro.md");
+        Ok(())
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn test_split_html() -> Result<()> {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head><title>Guide</title><style>body { color: red; }</style></head>
+<body>
+  <nav><a href="/">Home</a> | <a href="/blog">Blog</a></nav>
+  <script>window.track("visit");</script>
+  <!-- build 1234 -->
+  <main>
+    <h1>User Guide</h1>
+    <p>Read the <a href="https://example.com/intro">introduction</a> first &amp; then
+       install.</p>
+    <img src="https://example.com/logo.png" alt="Logo">
+    <h2>Install</h2>
+    <ol>
+      <li>Download the <b>archive</b></li>
+      <li>Unpack it
+        <ul><li>on Linux</li><li>on macOS</li></ul>
+      </li>
+    </ol>
+    <pre><code class="language-bash">tar xf app.tgz &amp;&amp; ./app
+./app --help</code></pre>
+    <h2>Options</h2>
+    <table>
+      <tr><th>Flag</th><th>Meaning</th></tr>
+      <tr><td><code>-v</code></td><td>Verbose</td></tr>
+    </table>
+  </main>
+  <footer>Copyright 2024</footer>
+</body>
+</html>"#;
+
+        assert_eq!(
+            html_to_markdown(html),
+            "# User Guide\n\n\
+             Read the [introduction](https://example.com/intro) first & then install.\n\n\
+             ![Logo](https://example.com/logo.png)\n\n\
+             ## Install\n\n\
+             1. Download the **archive**\n\
+             2. Unpack it\n  - on Linux\n  - on macOS\n\n\
+             ```bash\ntar xf app.tgz && ./app\n./app --help\n```\n\n\
+             ## Options\n\n\
+             | Flag | Meaning |\n| --- | --- |\n| `-v` | Verbose |"
+        );
+
+        let docs = TextSplitter::new(None).split_html(html, 1000)?;
+        assert_eq!(docs.len(), 1);
+        let metadata = &docs[0].metadata;
+        assert_eq!(
+            metadata.headers.get(1),
+            Some(&["User Guide".to_string()][..])
+        );
+        assert_eq!(
+            metadata.headers.get(2),
+            Some(&["Install".to_string(), "Options".to_string()][..])
+        );
+        assert!(metadata
+            .urls
+            .contains(&"https://example.com/intro".to_string()));
+        assert_eq!(metadata.images, ["https://example.com/logo.png"]);
+        for boilerplate in [
+            "Home",
+            "track",
+            "color",
+            "build 1234",
+            "Copyright",
+            "Guide</",
+        ] {
+            assert!(
+                !docs[0].text.contains(boilerplate),
+                "{boilerplate} was kept"
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn test_html_to_markdown_non_ascii() {
+        assert_eq!(html_to_markdown("<p>é</p>"), "é");
+        assert_eq!(
+            html_to_markdown("<p>Über <b>Straße</b></p>"),
+            "Über **Straße**"
+        );
+        assert_eq!(html_to_markdown("日本語"), "日本語");
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn test_html_to_markdown_unclosed_skipped_element() {
+        // `</header>` does not close `<head>`, which is skipped on its own
+        assert_eq!(
+            html_to_markdown("<head><header>Intro</header><p>Body</p>"),
+            "Intro\n\nBody"
+        );
+        assert_eq!(
+            html_to_markdown("<head><title>Doc</title></head ><header>Intro</header>"),
+            "Intro"
+        );
+    }
+
+    #[test]
+    fn test_recursive_character_splitter() -> Result<()> {
+        let splitter = RecursiveCharacterSplitter::new();
+        let paragraph = "Short words fill this plain paragraph of text. ".repeat(6);
+        let text = format!("{paragraph}\n\n{paragraph}\n\n{paragraph}");
+
+        // Paragraphs that fit are kept intact, one per chunk
+        let docs = splitter.split(&text, 100)?;
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0].text, paragraph.trim());
+        for doc in &docs {
+            assert!(doc.metadata.tokens <= 100);
+            let range = doc.metadata.start_offset..doc.metadata.end_offset;
+            assert_eq!(&text[range], doc.text);
+        }
+
+        // Smaller limits fall back to words, then characters
+        let docs = splitter.split(&text, 30)?;
+        assert!(docs.len() > 3);
+        assert!(docs.iter().all(|doc| doc.metadata.tokens <= 30));
+        let docs = splitter.split(&"x".repeat(400), 25)?;
+        assert!(docs.len() > 1);
+        assert_eq!(docs.iter().map(|doc| doc.text.len()).sum::<usize>(), 400);
+
+        // Without a character fallback an unsplittable piece stays whole
+        let docs = RecursiveCharacterSplitter::new()
+            .with_separators(["\n"])
+            .split(&"x".repeat(400), 25)?;
+        assert_eq!(docs.len(), 1);
+
+        assert!(splitter.split("  \n\n ", 100)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_multibyte_text() -> Result<()> {
+        let splitter = TextSplitter::new(None);
+        let emoji = "# Emoji 🎉\nParty 🎉🎊🥳 time 👩‍👩‍👧‍👦 with friends 🇵🇱!\n".repeat(30);
+        let cjk = "## 日本語の見出し\n吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。\n".repeat(30);
+        let polish = "Zażółć gęślą jaźń — źdźbło żółwia.\n".repeat(30);
+        let unbroken = "漢字".repeat(500);
+
+        for text in [&emoji, &cjk, &polish, &unbroken] {
+            for limit in [64, 100, 250] {
+                let docs = splitter.split(text, limit)?;
+                assert!(!docs.is_empty());
+                for doc in &docs {
+                    let range = doc.metadata.start_offset..doc.metadata.end_offset;
+                    assert_eq!(&text[range], doc.text);
+                    assert!(doc.metadata.tokens <= limit, "{limit}: {:?}", doc.text);
+                }
+                for pair in docs.windows(2) {
+                    assert_eq!(pair[0].metadata.end_offset, pair[1].metadata.start_offset);
+                }
+            }
+        }
+
+        // Limits below the chat formatting overhead still terminate
+        let docs = splitter.split("🎉🎊", 1)?;
+        assert_eq!(
+            docs.iter().map(|doc| doc.text.as_str()).collect::<String>(),
+            "🎉🎊"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_model_name() {
+        let text = "Tokenizers differ: ünïcödé, 日本語, and emoji 🎉 split unevenly.";
+        let count = |model: &str| {
+            TextSplitter::from_model_name(model)
+                .unwrap()
+                .split(text, 1_000)
+                .unwrap()[0]
+                .metadata
+                .tokens
+        };
+
+        assert_eq!(count("gpt-4"), count("text-embedding-3-large"));
+        assert_eq!(count("gpt-4o"), count("gpt-4o-mini"));
+        assert_ne!(count("gpt-4"), count("gpt-4o"));
+        assert_ne!(count("gpt-4"), count("text-davinci-003"));
+        assert_eq!(count("o3-mini"), count("gpt-4o"));
+        assert_eq!(
+            TextSplitter::from_model_name("gpt-4o")
+                .unwrap()
+                .model_name(),
+            Some("gpt-4o")
+        );
+
+        assert!(matches!(
+            TextSplitter::from_model_name("llama-3"),
+            Err(crate::Error::Config(_))
+        ));
+        assert!(TextSplitter::from_model_name("o1x").is_err());
+        // `new` keeps working for unknown names
+        assert_eq!(
+            TextSplitter::new(Some("llama-3".into())).model_name(),
+            Some("llama-3")
+        );
+    }
+
+    #[test]
+    fn test_splitter_config_defaults() -> Result<()> {
+        let config = SplitterConfig::builder().build()?;
+        assert_eq!(config, SplitterConfig::default());
+        assert_eq!(config.token_limit, DEFAULT_TOKEN_LIMIT);
+        assert_eq!(config.overlap, 0);
+        assert_eq!(config.tokenizer, Encoding::Cl100k);
+        assert_eq!(config.min_chunk_tokens, 0);
+        assert!(!config.keep_links_inline);
+
+        // The default config splits like a default splitter at the default limit
+        let text = "See [the docs](https://example.com/docs). ".repeat(300);
+        let configured = TextSplitter::with_config(config).split_default(&text)?;
+        let plain = TextSplitter::new(None).split(&text, DEFAULT_TOKEN_LIMIT)?;
+        assert_eq!(
+            configured.iter().map(|doc| &doc.text).collect::<Vec<_>>(),
+            plain.iter().map(|doc| &doc.text).collect::<Vec<_>>()
+        );
+        assert!(configured[0].text.contains("{$url0}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_splitter_config_validation() {
+        let is_config_error = |builder: SplitterConfigBuilder| {
+            matches!(builder.build(), Err(crate::Error::Config(_)))
+        };
+
+        assert!(is_config_error(SplitterConfig::builder().token_limit(0)));
+        assert!(is_config_error(
+            SplitterConfig::builder().token_limit(100).overlap(100)
+        ));
+        assert!(is_config_error(
+            SplitterConfig::builder().token_limit(100).overlap(150)
+        ));
+        assert!(is_config_error(
+            SplitterConfig::builder()
+                .token_limit(100)
+                .min_chunk_tokens(100)
+        ));
+        assert!(SplitterConfig::builder()
+            .token_limit(100)
+            .overlap(99)
+            .min_chunk_tokens(99)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_splitter_config_options() -> Result<()> {
+        let config = SplitterConfig::builder()
+            .token_limit(50)
+            .overlap(10)
+            .tokenizer(Encoding::O200k)
+            .keep_links_inline(true)
+            .build()?;
+        let splitter = TextSplitter::with_config(config);
+        let text = (0..60)
+            .map(|i| format!("Sentence {i} links [here](https://example.com/{i})."))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let docs = splitter.split_default(&text)?;
+
+        assert!(docs.len() > 1);
+        assert!(docs.iter().all(|doc| doc.metadata.tokens <= 50));
+        assert!(docs[0].text.contains("(https://example.com/0)"));
+        assert_eq!(docs[0].metadata.urls[0], "https://example.com/0");
+        for pair in docs.windows(2) {
+            let (previous, next) = (&pair[0].metadata, &pair[1].metadata);
+            assert!(next.start_offset < previous.end_offset);
+            assert!(next.start_offset > previous.start_offset);
+            let overlap = &text[next.start_offset..previous.end_offset];
+            assert!(tiktoken_rs::o200k_base()?.encode_ordinary(overlap).len() <= 10);
+        }
+        assert_eq!(docs.last().unwrap().metadata.end_offset, text.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_counts() -> Result<()> {
+        let text = "Tokenizers differ: ünïcödé, 日本語, and emoji 🎉 split unevenly.";
+        let count = |splitter: TextSplitter| -> Result<usize> {
+            Ok(splitter.split(text, 1_000)?[0].metadata.tokens)
+        };
+
+        assert_eq!(count(TextSplitter::from_model_name("gpt-4")?)?, 26);
+        assert_eq!(count(TextSplitter::from_model_name("gpt-4o")?)?, 20);
+        let custom = TextSplitter::from_tokenizer(tiktoken_rs::o200k_base()?);
+        assert_eq!(custom.model_name(), None);
+        assert_eq!(count(custom)?, 20);
+
+        // The chat markup is plain text to cl100k_base, so it costs 26 tokens
+        let splitter = TextSplitter::from_model_name("gpt-4")?.with_chat_overhead(true);
+        assert_eq!(splitter.count_tokens(text), 52);
+        assert_eq!(splitter.count_tokens_raw(text), 26);
+        assert_eq!(count(splitter)?, 52);
+        let splitter = TextSplitter::from_model_name("gpt-4o")?;
+        assert_eq!(splitter.count_tokens(text), 20);
+        assert_eq!(splitter.with_chat_overhead(true).count_tokens(text), 46);
+
+        assert_eq!(token_count(text, "gpt-4")?, 26);
+        assert_eq!(token_count(text, "gpt-4o-mini")?, 20);
+        assert_eq!(token_count(text, "o3")?, 20);
+        assert_eq!(token_count(text, "text-davinci-003")?, 30);
+        assert_eq!(token_count("", "gpt-4")?, 0);
+        assert!(matches!(
+            token_count(text, "llama-3"),
+            Err(crate::Error::Config(_))
+        ));
+        Ok(())
+    }
+
+    /// Markdown with headings, links, images, code and multi-byte words,
+    /// generated from `seed` with a xorshift generator
+    fn random_markdown(seed: u64, blocks: usize) -> String {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            usize::try_from(state % bound as u64).unwrap()
+        };
+        let words = [
+            "token",
+            "limit",
+            "chunk",
+            "split",
+            "żółw",
+            "日本語",
+            "🎉",
+            "the",
+            "a",
+            "x",
+            "embedding",
+            "vector",
+            "👩‍👩‍👧‍👦",
+            "paragraph",
+        ];
+
+        let mut text = String::new();
+        for _ in 0..blocks {
+            let mut line: Vec<String> = (0..=next(60))
+                .map(|_| match next(12) {
+                    0 => format!("[{}](u)", words[next(words.len())]),
+                    1 => format!("![{}](i.png)", words[next(words.len())]),
+                    _ => words[next(words.len())].to_string(),
+                })
+                .collect();
+            match next(6) {
+                0 => line.insert(0, "#".repeat(next(3) + 1)),
+                1 => {
+                    line.insert(0, "```\n".to_string());
+                    line.push("\n```".to_string());
+                }
+                2 => line.insert(0, "-".to_string()),
+                _ => {}
+            }
+            text.push_str(&line.join(" "));
+            text.push_str(if next(3) == 0 { "\n\n" } else { "\n" });
+        }
+        text
+    }
+
+    #[test]
+    fn test_strict_token_limit() -> Result<()> {
+        for seed in 0..4 {
+            let text = random_markdown(seed, 40);
+            for limit in [128, 512, 1000] {
+                for breadcrumbs in [false, true] {
+                    let docs = TextSplitter::new(None)
+                        .with_heading_breadcrumbs(breadcrumbs)
+                        .with_min_tokens(limit / 2)
+                        .split(&text, limit)?;
+                    for doc in &docs {
+                        assert!(
+                            doc.metadata.tokens <= limit,
+                            "seed {seed}, limit {limit}: {} tokens",
+                            doc.metadata.tokens
+                        );
+                    }
+                    // Nothing but trailing whitespace is left out
+                    for pair in docs.windows(2) {
+                        assert_eq!(pair[0].metadata.end_offset, pair[1].metadata.start_offset);
+                    }
+                    assert!(docs.last().unwrap().metadata.end_offset >= text.trim_end().len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_from_doc() -> Result<()> {
+        let docs = TextSplitter::new(None).split("# Title\nSee [docs](https://x.io).\n", 100)?;
+        let document = crate::common::Document::from(docs[0].clone());
+
+        assert_eq!(document.content, docs[0].text);
+        assert!(uuid::Uuid::parse_str(&document.id).is_ok());
+        assert_eq!(document.metadata["tokens"], docs[0].metadata.tokens);
+        assert_eq!(
+            document.metadata["urls"],
+            serde_json::json!(["https://x.io"])
+        );
+        assert_eq!(
+            document.metadata["heading_path"],
+            serde_json::json!(["Title"])
+        );
+        assert!(!document.metadata.contains_key("id"));
+
+        let rows = TextSplitter::new(None).split_csv(
+            "sku,name\nA1,Kettle\n",
+            &["name"],
+            Some("sku"),
+            100,
+        )?;
+        assert_eq!(crate::common::Document::from(rows[0].clone()).id, "A1");
+        Ok(())
+    }
 }
@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+use serde::{Deserialize, Serialize};
+
+/// A cleanup pass of [`NormalizeOptions`], as listed in
+/// [`Metadata::normalizations`](super::Metadata::normalizations)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// `\r\n` line endings become `\n`
+    LineEndings,
+    /// Runs of blank lines become a single blank line
+    BlankLines,
+    /// Spaces and tabs at the end of lines are removed
+    TrailingWhitespace,
+    /// Text is put in Unicode normalization form C
+    Nfc,
+}
+
+/// Cleanup applied to text before it is split
+///
+/// Set with [`TextSplitter::with_normalization`](super::TextSplitter::with_normalization)
+/// or [`SplitterConfigBuilder::normalize`](super::SplitterConfigBuilder::normalize).
+/// The default only turns `\r\n` into `\n`, so documents saved on Windows
+/// split, count and hash like their `\n` versions. The other passes change
+/// text that can matter in markdown, such as blank lines in code blocks or
+/// two trailing spaces marking a line break, and are off by default.
+///
+/// ```
+/// use ai_utils::text_splitter::{Normalization, NormalizeOptions};
+///
+/// let options = NormalizeOptions::default().trim_trailing_whitespace(true);
+/// assert_eq!(options.apply("Title  \r\nBody\r\n"), "Title\nBody\n");
+/// assert_eq!(
+///     options.applied(),
+///     [Normalization::LineEndings, Normalization::TrailingWhitespace]
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+#[allow(clippy::struct_excessive_bools)]
+pub struct NormalizeOptions {
+    pub line_endings: bool,
+    pub collapse_blank_lines: bool,
+    pub trim_trailing_whitespace: bool,
+    pub unicode_nfc: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self::none().line_endings(true)
+    }
+}
+
+impl NormalizeOptions {
+    /// Leave text as it is
+    #[must_use]
+    pub const fn none() -> Self {
+        Self {
+            line_endings: false,
+            collapse_blank_lines: false,
+            trim_trailing_whitespace: false,
+            unicode_nfc: false,
+        }
+    }
+
+    /// Every pass
+    #[must_use]
+    pub const fn all() -> Self {
+        Self {
+            line_endings: true,
+            collapse_blank_lines: true,
+            trim_trailing_whitespace: true,
+            unicode_nfc: true,
+        }
+    }
+
+    #[must_use]
+    pub const fn line_endings(mut self, enabled: bool) -> Self {
+        self.line_endings = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn collapse_blank_lines(mut self, enabled: bool) -> Self {
+        self.collapse_blank_lines = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn trim_trailing_whitespace(mut self, enabled: bool) -> Self {
+        self.trim_trailing_whitespace = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn unicode_nfc(mut self, enabled: bool) -> Self {
+        self.unicode_nfc = enabled;
+        self
+    }
+
+    /// The enabled passes, in the order they run
+    #[must_use]
+    pub fn applied(&self) -> Vec<Normalization> {
+        [
+            (self.line_endings, Normalization::LineEndings),
+            (self.collapse_blank_lines, Normalization::BlankLines),
+            (
+                self.trim_trailing_whitespace,
+                Normalization::TrailingWhitespace,
+            ),
+            (self.unicode_nfc, Normalization::Nfc),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, pass)| enabled.then_some(pass))
+        .collect()
+    }
+
+    /// Run the enabled passes over `text`
+    #[must_use]
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if *self == Self::none() {
+            return Cow::Borrowed(text);
+        }
+        let mut lines = LineNormalizer::new(*self);
+        let mut normalized = String::with_capacity(text.len());
+        for line in text.split_inclusive('\n') {
+            lines.push_line(line, &mut normalized);
+        }
+        Cow::Owned(normalized)
+    }
+}
+
+/// Applies [`NormalizeOptions`] one line at a time, for input read in pieces
+pub(super) struct LineNormalizer {
+    options: NormalizeOptions,
+    /// Blank lines since the last line with text
+    blank_run: usize,
+}
+
+impl LineNormalizer {
+    pub(super) const fn new(options: NormalizeOptions) -> Self {
+        Self {
+            options,
+            blank_run: 0,
+        }
+    }
+
+    /// Append the normalized form of `line`, which ends with its line break
+    /// unless it is the last line of the input
+    pub(super) fn push_line(&mut self, line: &str, out: &mut String) {
+        let (body, ending) = match line.strip_suffix("\r\n") {
+            Some(body) if !self.options.line_endings => (body, "\r\n"),
+            Some(body) => (body, "\n"),
+            None => line
+                .strip_suffix('\n')
+                .map_or((line, ""), |body| (body, "\n")),
+        };
+
+        let body = if self.options.trim_trailing_whitespace {
+            body.trim_end_matches([' ', '\t'])
+        } else {
+            body
+        };
+
+        if self.options.collapse_blank_lines && !ending.is_empty() {
+            if body.trim().is_empty() {
+                self.blank_run += 1;
+                if self.blank_run > 1 {
+                    return;
+                }
+            } else {
+                self.blank_run = 0;
+            }
+        }
+
+        if self.options.unicode_nfc {
+            out.push_str(&ComposingNormalizerBorrowed::new_nfc().normalize(body));
+        } else {
+            out.push_str(body);
+        }
+        out.push_str(ending);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::text_service::Doc;
+
+/// Callback set with [`TextSplitter::with_progress`](super::TextSplitter::with_progress)
+pub(super) type ProgressCallback = Arc<dyn Fn(SplitProgress) + Send + Sync>;
+
+/// How far a split has got, reported after each chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitProgress {
+    /// Input bytes up to the end of the latest chunk, counted in the
+    /// normalized text like `Metadata::end_offset`
+    pub bytes_processed: usize,
+    /// Size of the normalized input; `None` when splitting a stream
+    pub total_bytes: Option<usize>,
+    pub chunks_emitted: usize,
+}
+
+/// Chunks of a cancellable split and whether it stopped early.
+///
+/// Returned by [`TextSplitter::split_cancellable`](super::TextSplitter::split_cancellable)
+/// and [`TextSplitter::split_by_headers_cancellable`](super::TextSplitter::split_by_headers_cancellable).
+#[derive(Debug, Clone)]
+pub struct SplitOutcome {
+    pub docs: Vec<Doc>,
+    /// The cancellation flag stopped the split before the last chunk, so
+    /// `docs` only covers the start of the input. A flag set after the last
+    /// chunk does not count.
+    pub cancelled: bool,
+}
+
+/// Chunk count of [`TextSplitter::split_stream_cancellable`](super::TextSplitter::split_stream_cancellable)
+/// and whether the split stopped early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOutcome {
+    /// Chunks handed to the sink
+    pub chunks: usize,
+    /// As [`SplitOutcome::cancelled`]
+    pub cancelled: bool,
+}
+
+/// Reports progress and watches the cancellation flag during one split
+pub(super) struct SplitTracker<'a> {
+    on_progress: Option<&'a ProgressCallback>,
+    cancel: Option<&'a AtomicBool>,
+    total_bytes: Option<usize>,
+    chunks_emitted: usize,
+    stopped: bool,
+}
+
+impl<'a> SplitTracker<'a> {
+    pub(super) fn new(
+        on_progress: Option<&'a ProgressCallback>,
+        cancel: Option<&'a AtomicBool>,
+        total_bytes: Option<usize>,
+    ) -> Self {
+        Self {
+            on_progress,
+            cancel,
+            total_bytes,
+            chunks_emitted: 0,
+            stopped: false,
+        }
+    }
+
+    /// Whether the split should stop before its next chunk, to be asked only
+    /// when one is left, as a true answer records that the split stopped
+    /// early. Stays true once the flag has been seen.
+    pub(super) fn is_cancelled(&mut self) -> bool {
+        self.stopped |= self
+            .cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+        self.stopped
+    }
+
+    /// Whether [`SplitTracker::is_cancelled`] stopped the split
+    pub(super) const fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Report a chunk ending `end` bytes into the input
+    pub(super) fn chunk_done(&mut self, end: usize) {
+        self.chunks_emitted += 1;
+        if let Some(on_progress) = self.on_progress {
+            on_progress(SplitProgress {
+                bytes_processed: end,
+                total_bytes: self.total_bytes,
+                chunks_emitted: self.chunks_emitted,
+            });
+        }
+    }
+}
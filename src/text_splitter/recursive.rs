@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use tracing::{debug, info};
+
+use super::text_service::{count_tokens, number_chunks, Doc, Headers, Metadata, Splitter};
+
+/// Paragraphs, then lines, then words, then characters
+pub const DEFAULT_SEPARATORS: [&str; 4] = ["\n\n", "\n", " ", ""];
+
+/// Plain-text splitter that cuts on the first separator of a hierarchy found
+/// in the text and recurses into pieces that are still over the limit.
+///
+/// Unlike [`TextSplitter`](super::TextSplitter) it knows nothing about
+/// markdown: chunks are exact (whitespace-trimmed) slices of the input with no
+/// headers, links or images extracted.
+///
+/// ```
+/// use ai_utils::text_splitter::{RecursiveCharacterSplitter, Splitter};
+///
+/// let text = "fn main() {\n    println!(\"hi\");\n}\n\n".repeat(50);
+/// let docs = RecursiveCharacterSplitter::new().split(&text, 100)?;
+///
+/// assert!(docs.len() > 1);
+/// assert!(docs.iter().all(|doc| doc.metadata.tokens <= 100));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct RecursiveCharacterSplitter {
+    tokenizer: CoreBPE,
+    separators: Vec<String>,
+    chat_overhead: bool,
+}
+
+impl Default for RecursiveCharacterSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecursiveCharacterSplitter {
+    /// Create a splitter with [`DEFAULT_SEPARATORS`], counting tokens like
+    /// [`TextSplitter`](super::TextSplitter)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled tokenizer data fails to load.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            tokenizer: cl100k_base().unwrap(),
+            separators: DEFAULT_SEPARATORS.map(str::to_string).to_vec(),
+            chat_overhead: false,
+        }
+    }
+
+    /// Separators to try, coarsest first. Without a trailing `""` a piece
+    /// that contains none of them is kept whole even if over the limit.
+    #[must_use]
+    pub fn with_separators<S: Into<String>>(
+        mut self,
+        separators: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.separators = separators.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Count chunks with chat markup, see
+    /// [`TextSplitter::with_chat_overhead`](super::TextSplitter::with_chat_overhead)
+    #[must_use]
+    pub const fn with_chat_overhead(mut self, enabled: bool) -> Self {
+        self.chat_overhead = enabled;
+        self
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(&self.tokenizer, text, self.chat_overhead)
+    }
+
+    fn fits(&self, text: &str, limit: usize) -> bool {
+        self.count_tokens(text) <= limit
+    }
+
+    /// Byte ranges of the pieces of `text[start..end]` cut at `separator`;
+    /// an empty separator cuts between characters
+    fn pieces(text: &str, start: usize, end: usize, separator: &str) -> Vec<(usize, usize)> {
+        let slice = &text[start..end];
+        if separator.is_empty() {
+            return slice
+                .char_indices()
+                .map(|(index, c)| (start + index, start + index + c.len_utf8()))
+                .collect();
+        }
+
+        let mut pieces = Vec::new();
+        let mut piece_start = start;
+        for (index, _) in slice.match_indices(separator) {
+            pieces.push((piece_start, start + index));
+            piece_start = start + index + separator.len();
+        }
+        pieces.push((piece_start, end));
+        pieces
+    }
+
+    /// Append the chunk ranges of `text[start..end]` to `chunks`, merging
+    /// neighbouring pieces while they fit
+    fn split_range(
+        &self,
+        text: &str,
+        (start, end): (usize, usize),
+        separators: &[String],
+        limit: usize,
+        chunks: &mut Vec<(usize, usize)>,
+    ) {
+        let slice = &text[start..end];
+        let Some(index) = separators
+            .iter()
+            .position(|separator| separator.is_empty() || slice.contains(separator.as_str()))
+        else {
+            debug!(
+                "No separator left for {} bytes, keeping them whole",
+                end - start
+            );
+            chunks.push((start, end));
+            return;
+        };
+        let finer = &separators[index + 1..];
+
+        let mut current: Option<(usize, usize)> = None;
+        for piece in Self::pieces(text, start, end, &separators[index]) {
+            if text[piece.0..piece.1].trim().is_empty() {
+                continue;
+            }
+            if let Some((current_start, _)) = current {
+                if self.fits(&text[current_start..piece.1], limit) {
+                    current = Some((current_start, piece.1));
+                    continue;
+                }
+                chunks.extend(current.take());
+            }
+
+            if self.fits(&text[piece.0..piece.1], limit) {
+                current = Some(piece);
+            } else if finer.is_empty() {
+                chunks.push(piece);
+            } else {
+                self.split_range(text, piece, finer, limit, chunks);
+            }
+        }
+        chunks.extend(current);
+    }
+}
+
+impl Splitter for RecursiveCharacterSplitter {
+    /// Split `text` into chunks of at most `limit` tokens. Chunks only exceed
+    /// the limit when a piece has none of the separators left to cut at.
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        info!("Starting recursive split with limit: {} tokens", limit);
+        let mut ranges = Vec::new();
+        self.split_range(text, (0, text.len()), &self.separators, limit, &mut ranges);
+
+        let mut docs: Vec<Doc> = ranges
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let chunk = &text[start..end];
+                let trimmed = chunk.trim();
+                if trimmed.is_empty() {
+                    return None;
+                }
+                let start = start + (chunk.len() - chunk.trim_start().len());
+                Some(Doc {
+                    text: trimmed.to_string(),
+                    metadata: Metadata {
+                        tokens: self.count_tokens(trimmed),
+                        headers: Headers::default(),
+                        urls: Vec::new(),
+                        images: Vec::new(),
+                        id: None,
+                        fields: HashMap::new(),
+                        frontmatter: None,
+                        heading_path: Vec::new(),
+                        section_path: Vec::new(),
+                        start_offset: start,
+                        end_offset: start + trimmed.len(),
+                        contains_code: trimmed.contains("```") || trimmed.contains("~~~"),
+                        chunk_index: 0,
+                        total_chunks: 0,
+                        source: None,
+                        sentence_count: None,
+                        normalizations: Vec::new(),
+                        item_name: None,
+                    },
+                })
+            })
+            .collect();
+        number_chunks(&mut docs);
+
+        info!("Recursive split completed. Total chunks: {}", docs.len());
+        Ok(docs)
+    }
+}
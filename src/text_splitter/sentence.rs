@@ -0,0 +1,321 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use tracing::{debug, info};
+
+use super::text_service::{count_tokens, number_chunks, Doc, Headers, Metadata, Splitter};
+
+/// Words that do not end a sentence when followed by a period, compared
+/// case-insensitively without the final period
+pub const ABBREVIATIONS: [&str; 14] = [
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "e.g", "i.e", "cf", "fig", "approx",
+];
+
+/// Splitter that packs whole sentences into chunks, for small-context
+/// embedding models or sentence-level citations.
+///
+/// Sentences end at `.`, `!`, `?` or `…` followed by whitespace, and at the
+/// CJK full stops `。！？` anywhere; closing quotes and brackets stay with
+/// the sentence they close. A period does not end a sentence after an
+/// abbreviation from [`ABBREVIATIONS`] or a single-letter initial, nor
+/// inside a number like `3.14`, and no terminator ends one before a
+/// lowercase word (`"Wait... what?"`). Blank lines always end one.
+///
+/// Consecutive sentences are merged while they fit the limit. A sentence
+/// over the limit on its own is cut between words, or between characters
+/// for a single oversized word. `Metadata::sentence_count` counts the
+/// sentences that end in each chunk.
+///
+/// ```
+/// use ai_utils::text_splitter::{SentenceSplitter, Splitter};
+///
+/// let text = "Dr. Smith measured 3.5 cm. The sample grew! Was it the light?";
+/// assert_eq!(SentenceSplitter::sentences(text).len(), 3);
+///
+/// let docs = SentenceSplitter::new().split(text, 10)?;
+/// assert!(docs.iter().all(|doc| doc.metadata.tokens <= 10));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct SentenceSplitter {
+    tokenizer: CoreBPE,
+    chat_overhead: bool,
+}
+
+impl Default for SentenceSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SentenceSplitter {
+    /// Create a splitter counting tokens like
+    /// [`TextSplitter`](super::TextSplitter)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled tokenizer data fails to load.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::from_tokenizer(cl100k_base().unwrap())
+    }
+
+    /// Create a splitter counting tokens with `tokenizer`
+    #[must_use]
+    pub const fn from_tokenizer(tokenizer: CoreBPE) -> Self {
+        Self {
+            tokenizer,
+            chat_overhead: false,
+        }
+    }
+
+    /// Count chunks with chat markup, see
+    /// [`TextSplitter::with_chat_overhead`](super::TextSplitter::with_chat_overhead)
+    #[must_use]
+    pub const fn with_chat_overhead(mut self, enabled: bool) -> Self {
+        self.chat_overhead = enabled;
+        self
+    }
+
+    /// The sentences of `text`, whitespace-trimmed, in order
+    #[must_use]
+    pub fn sentences(text: &str) -> Vec<&str> {
+        sentence_ranges(text)
+            .into_iter()
+            .map(|(start, end)| &text[start..end])
+            .collect()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(&self.tokenizer, text, self.chat_overhead)
+    }
+
+    fn fits(&self, text: &str, limit: usize) -> bool {
+        self.count_tokens(text) <= limit
+    }
+
+    /// Cut an oversized sentence into ranges that fit, between words where
+    /// possible
+    fn fragments(
+        &self,
+        text: &str,
+        (start, end): (usize, usize),
+        limit: usize,
+    ) -> Vec<(usize, usize)> {
+        debug!(
+            "Sentence of {} bytes is over the limit, cutting it",
+            end - start
+        );
+        let mut words = Vec::new();
+        let mut word_start = None;
+        for (index, c) in text[start..end].char_indices() {
+            match (c.is_whitespace(), word_start) {
+                (true, Some(from)) => {
+                    words.push((from, start + index));
+                    word_start = None;
+                }
+                (false, None) => word_start = Some(start + index),
+                _ => {}
+            }
+        }
+        words.extend(word_start.map(|from| (from, end)));
+
+        let mut pieces = Vec::new();
+        for word in words {
+            if self.fits(&text[word.0..word.1], limit) {
+                pieces.push(word);
+            } else {
+                pieces.extend(
+                    text[word.0..word.1]
+                        .char_indices()
+                        .map(|(index, c)| (word.0 + index, word.0 + index + c.len_utf8())),
+                );
+            }
+        }
+
+        let mut fragments: Vec<(usize, usize)> = Vec::new();
+        for piece in pieces {
+            match fragments.last_mut() {
+                Some(last) if self.fits(&text[last.0..piece.1], limit) => last.1 = piece.1,
+                _ => fragments.push(piece),
+            }
+        }
+        fragments
+    }
+}
+
+impl Splitter for SentenceSplitter {
+    /// Split `text` into chunks of whole sentences of at most `limit` tokens.
+    /// Chunks only exceed the limit when a single character does.
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        info!("Starting sentence split with limit: {} tokens", limit);
+
+        // (start, end, sentences ending in the range)
+        let mut pieces = Vec::new();
+        for sentence in sentence_ranges(text) {
+            if self.fits(&text[sentence.0..sentence.1], limit) {
+                pieces.push((sentence.0, sentence.1, 1));
+            } else {
+                let fragments = self.fragments(text, sentence, limit);
+                let last = fragments.len() - 1;
+                pieces.extend(
+                    fragments
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, (start, end))| (start, end, usize::from(index == last))),
+                );
+            }
+        }
+
+        let mut chunks: Vec<(usize, usize, usize)> = Vec::new();
+        for piece in pieces {
+            match chunks.last_mut() {
+                Some(last) if self.fits(&text[last.0..piece.1], limit) => {
+                    last.1 = piece.1;
+                    last.2 += piece.2;
+                }
+                _ => chunks.push(piece),
+            }
+        }
+
+        let mut docs: Vec<Doc> = chunks
+            .into_iter()
+            .map(|(start, end, sentences)| {
+                let chunk = &text[start..end];
+                Doc {
+                    text: chunk.to_string(),
+                    metadata: Metadata {
+                        tokens: self.count_tokens(chunk),
+                        headers: Headers::default(),
+                        urls: Vec::new(),
+                        images: Vec::new(),
+                        id: None,
+                        fields: HashMap::new(),
+                        frontmatter: None,
+                        heading_path: Vec::new(),
+                        section_path: Vec::new(),
+                        start_offset: start,
+                        end_offset: end,
+                        contains_code: chunk.contains("```") || chunk.contains("~~~"),
+                        chunk_index: 0,
+                        total_chunks: 0,
+                        source: None,
+                        sentence_count: Some(sentences),
+                        normalizations: Vec::new(),
+                        item_name: None,
+                    },
+                }
+            })
+            .collect();
+        number_chunks(&mut docs);
+
+        info!("Sentence split completed. Total chunks: {}", docs.len());
+        Ok(docs)
+    }
+}
+
+const fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '…') || is_cjk_terminator(c)
+}
+
+const fn is_cjk_terminator(c: char) -> bool {
+    matches!(c, '。' | '！' | '？')
+}
+
+const fn is_closing(c: char) -> bool {
+    matches!(c, '"' | '\'' | '”' | '’' | ')' | ']' | '»' | '」' | '』')
+}
+
+fn starts_lowercase(rest: &str) -> bool {
+    rest.trim_start()
+        .chars()
+        .next()
+        .is_some_and(char::is_lowercase)
+}
+
+/// Whether the lone period at `dot` ends a sentence, `rest` being the text
+/// after it
+fn period_ends_sentence(text: &str, dot: usize, rest: &str) -> bool {
+    let word_start = text[..dot]
+        .rfind(|c: char| c.is_whitespace() || matches!(c, '(' | '[' | '"' | '\'' | '“' | '‘'))
+        .map_or(0, |index| {
+            index + text[index..].chars().next().map_or(1, char::len_utf8)
+        });
+    let word = text[word_start..dot].to_lowercase();
+    if ABBREVIATIONS.contains(&word.as_str()) {
+        return false;
+    }
+    let mut letters = word.chars();
+    if matches!((letters.next(), letters.next()), (Some(c), None) if c.is_alphabetic()) {
+        return false;
+    }
+    !starts_lowercase(rest)
+}
+
+/// Byte ranges of the sentences of `text`, whitespace-trimmed
+fn sentence_ranges(text: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (position, c) = chars[i];
+        if c == '\n' {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j].1 != '\n' && chars[j].1.is_whitespace() {
+                j += 1;
+            }
+            if j < chars.len() && chars[j].1 == '\n' {
+                ranges.push((start, position));
+                start = position;
+                i = j;
+                continue;
+            }
+        } else if is_terminator(c) {
+            let mut j = i + 1;
+            while j < chars.len() && is_terminator(chars[j].1) {
+                j += 1;
+            }
+            let run_end = j;
+            while j < chars.len() && is_closing(chars[j].1) {
+                j += 1;
+            }
+            let next = chars.get(j).map(|&(_, next)| next);
+            let ends = match next {
+                None => true,
+                Some(_) if is_cjk_terminator(chars[run_end - 1].1) => true,
+                Some(next) if next.is_whitespace() => {
+                    let rest = &text[chars[j].0..];
+                    if c == '.' && run_end == i + 1 {
+                        period_ends_sentence(text, position, rest)
+                    } else {
+                        !starts_lowercase(rest)
+                    }
+                }
+                Some(_) => false,
+            };
+            if ends {
+                let end = chars.get(j).map_or(text.len(), |&(index, _)| index);
+                ranges.push((start, end));
+                start = end;
+            }
+            i = j;
+            continue;
+        }
+        i += 1;
+    }
+    ranges.push((start, text.len()));
+
+    ranges
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let slice = &text[start..end];
+            let trimmed = slice.trim();
+            if trimmed.is_empty() {
+                return None;
+            }
+            let start = start + (slice.len() - slice.trim_start().len());
+            Some((start, start + trimmed.len()))
+        })
+        .collect()
+}
@@ -1,90 +1,1073 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use tiktoken_rs::cl100k_base;
-use tracing::{debug, info};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, LazyLock},
+};
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
+use super::{
+    config::{SplitterConfig, DEFAULT_TOKEN_LIMIT},
+    normalize::{LineNormalizer, Normalization, NormalizeOptions},
+    progress::{ProgressCallback, SplitOutcome, SplitProgress, SplitTracker, StreamOutcome},
+    sentence::SentenceSplitter,
+};
+use crate::{
+    common::{Document, Encoding},
+    error::Error,
+};
+
+/// How far over the limit, in percent of it, a merged trailing chunk may grow
+const MIN_TOKENS_SLACK_PERCENT: usize = 10;
+
+/// Bytes of input [`TextSplitter::split_stream`] holds at once by default
+pub const DEFAULT_STREAM_WINDOW: usize = 32 * 1024;
+
+/// How many times its window [`TextSplitter::split_stream`] may grow to fit
+/// a code block or table that spans the window end
+const MAX_STREAM_WINDOW_GROWTH: usize = 8;
+
+/// `{$urlN}` and `{$imgN}` placeholders left by link extraction
+static PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\$(url|img)(\d+)\}").unwrap());
+
+/// Markdown heading line, capturing its level and text
+static HEADING_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^(#{1,6})\s+(.*)$").unwrap());
+
+/// Start of a markdown heading line
+static HEADING_START_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^#{1,6}\s+").unwrap());
+
+/// Strategy for cutting text into token-limited chunks, so callers can pick
+/// a splitter per content type
+pub trait Splitter {
+    /// Split `text` into chunks of at most `limit` tokens
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the splitter cannot process `text`.
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>>;
+
+    /// Split `text`, the contents of the file at `path`, as
+    /// [`process_file`](super::process_file) does; the default ignores the
+    /// path, while [`CodeAwareSplitter`](super::CodeAwareSplitter) picks a
+    /// splitter by extension
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the splitter cannot process `text`.
+    fn split_file(&self, _path: &Path, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        self.split(text, limit)
+    }
+}
+
+impl<S: Splitter + ?Sized> Splitter for &S {
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        (**self).split(text, limit)
+    }
+
+    fn split_file(&self, path: &Path, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        (**self).split_file(path, text, limit)
+    }
+}
+
+/// Tokens of `text`, the measure chunk limits use. With `chat_overhead` the
+/// text is counted wrapped in the chat markup a user message is sent with.
+pub(super) fn count_tokens(tokenizer: &CoreBPE, text: &str, chat_overhead: bool) -> usize {
+    if chat_overhead {
+        tokenizer
+            .encode_with_special_tokens(&format_for_tokenization(text))
+            .len()
+    } else {
+        tokenizer.encode_ordinary(text).len()
+    }
+}
+
+/// Tokens of `text` for `model`, counted with the encoding
+/// [`Encoding::for_model`] picks and without chat markup.
+///
+/// ```
+/// use ai_utils::text_splitter::token_count;
+///
+/// assert_eq!(token_count("Hello, world!", "gpt-4o")?, 4);
+/// # Ok::<(), ai_utils::Error>(())
+/// ```
+///
+/// # Errors
+///
+/// Returns [`Error::Config`] for models [`Encoding::for_model`] does not know.
+pub fn token_count(text: &str, model: &str) -> crate::Result<usize> {
+    let encoding = Encoding::for_model(model).ok_or_else(|| {
+        Error::Config(format!(
+            "Unknown model '{model}', no tokenizer to count tokens with"
+        ))
+    })?;
+    Ok(count_tokens(encoding.shared(), text, false))
+}
+
+/// Fill in `chunk_index` and `total_chunks` once all chunks are known
+pub(super) fn number_chunks(docs: &mut [Doc]) {
+    let total = docs.len();
+    for (index, doc) in docs.iter_mut().enumerate() {
+        doc.metadata.chunk_index = index;
+        doc.metadata.total_chunks = total;
+    }
+}
+
+fn format_for_tokenization(text: &str) -> String {
+    format!(
+        "<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant<|im_end|>",
+        text
+    )
+}
+
+/// A chunk of text produced by a [`Splitter`]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Doc {
     pub text: String,
     pub metadata: Metadata,
 }
 
+impl Doc {
+    /// Deterministic UUID for the chunk, derived from `source`, its section
+    /// and its text, so re-ingesting a document overwrites the same points.
+    ///
+    /// The id is a truncated SHA-256 of:
+    /// - `source`, which tells apart identical chunks of different documents;
+    /// - `Metadata::section_path`, or `Metadata::heading_path` when the chunk
+    ///   was not split by headers;
+    /// - the text with whitespace runs collapsed to one space and link and
+    ///   image placeholders replaced by their targets.
+    ///
+    /// Reflowing a chunk or renumbering its placeholders keeps the id, while
+    /// editing its words, link targets or headings changes it. Offsets,
+    /// token counts and chunk positions are not hashed, so edits elsewhere in
+    /// the document leave the id alone unless they move the chunk boundary.
+    /// Identical chunks under the same heading share the id; use
+    /// [`StableIds`] to tell them apart.
+    #[must_use]
+    pub fn stable_id(&self, source: &str) -> String {
+        let path = if self.metadata.section_path.is_empty() {
+            &self.metadata.heading_path
+        } else {
+            &self.metadata.section_path
+        };
+        let text = restore_placeholders(&self.text, &self.metadata);
+
+        let mut hasher = Sha256::new();
+        hasher.update(source.as_bytes());
+        hasher.update([0]);
+        for title in path {
+            hasher.update(title.trim().as_bytes());
+            hasher.update([0x1f]);
+        }
+        hasher.update([0]);
+        for (index, word) in text.split_whitespace().enumerate() {
+            if index > 0 {
+                hasher.update(b" ");
+            }
+            hasher.update(word.as_bytes());
+        }
+
+        let digest = hasher.finalize();
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Uuid::from_bytes(bytes).to_string()
+    }
+}
+
+/// [`Doc::stable_id`]s for the chunks of one document, in order.
+///
+/// The n-th repeat of an id is replaced by the id of `"{source}#{n}"`, so
+/// identical chunks under the same heading get distinct ids that stay put as
+/// long as the repeats keep their order.
+///
+/// # Example
+///
+/// ```
+/// use ai_utils::text_splitter::{StableIds, TextSplitter};
+///
+/// let mut docs = TextSplitter::new(None).split("Hello.", 100)?;
+/// StableIds::new("hello.md").assign(&mut docs);
+/// assert!(docs[0].metadata.id.is_some());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct StableIds {
+    source: String,
+    seen: HashMap<String, usize>,
+}
+
+impl StableIds {
+    /// Ids for the document named `source`, such as its path or URL
+    #[must_use]
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Id of `doc`, the next chunk of the document
+    pub fn next_id(&mut self, doc: &Doc) -> String {
+        let id = doc.stable_id(&self.source);
+        let repeats = self.seen.entry(id.clone()).or_default();
+        *repeats += 1;
+        if *repeats > 1 {
+            doc.stable_id(&format!("{}#{}", self.source, *repeats - 1))
+        } else {
+            id
+        }
+    }
+
+    /// Set `Metadata::id` of each of `docs`, the next chunks of the document
+    pub fn assign(&mut self, docs: &mut [Doc]) {
+        for doc in docs {
+            doc.metadata.id = Some(self.next_id(doc));
+        }
+    }
+}
+
+/// Swap `{$urlN}` and `{$imgN}` placeholders back for the targets they stand
+/// for; unknown indices are left as they are
+pub(super) fn restore_placeholders(text: &str, metadata: &Metadata) -> String {
+    PLACEHOLDER_REGEX
+        .replace_all(text, |caps: &regex::Captures| {
+            let targets = if &caps[1] == "url" {
+                &metadata.urls
+            } else {
+                &metadata.images
+            };
+            caps[2]
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| targets.get(index))
+                .map_or_else(|| caps[0].to_string(), Clone::clone)
+        })
+        .into_owned()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Metadata {
+    /// Token count of `Doc::text`, plus the chat formatting overhead if the
+    /// splitter counts it
     pub tokens: usize,
+    /// Headings seen up to the end of the chunk
     pub headers: Headers,
+    /// Link targets, replaced by placeholders in the chunk text
     pub urls: Vec<String>,
+    /// Image sources, replaced by placeholders in the chunk text
     pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Extra key-value pairs, e.g. the non-text columns of a CSV row
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, String>,
+    /// YAML front-matter of the source document, shared by all its chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<serde_json::Value>,
+    /// Titles of the sections the chunk starts in, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub heading_path: Vec<String>,
+    /// Titles of the section the chunk belongs to, outermost first, down to
+    /// the level set by [`HeaderSplitOptions::max_level`]. Only set by
+    /// [`TextSplitter::split_by_headers`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub section_path: Vec<String>,
+    /// Byte range of the chunk in the text given to [`Splitter::split`] after
+    /// normalization, front-matter included; for CSV rows, in the row's
+    /// joined text
+    #[serde(default)]
+    pub start_offset: usize,
+    #[serde(default)]
+    pub end_offset: usize,
+    /// Whether the chunk holds part of a fenced code block
+    #[serde(default)]
+    pub contains_code: bool,
+    /// Position of the chunk among those split from the same text, from 0
+    #[serde(default)]
+    pub chunk_index: usize,
+    #[serde(default)]
+    pub total_chunks: usize,
+    /// File the text was read from, set by [`process_file`](super::process_file)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Sentences in the chunk, set by [`SentenceSplitter`](super::SentenceSplitter)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentence_count: Option<usize>,
+    /// Cleanup applied to the text before splitting, see [`NormalizeOptions`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub normalizations: Vec<Normalization>,
+    /// Item the chunk starts in, with the items around it, e.g.
+    /// `TextSplitter::split`; set by [`CodeSplitter`](super::CodeSplitter)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Markdown headings by level, serialized as `{"h1": [...], "h2": [...]}`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Headers(HashMap<String, Vec<String>>);
 
-#[allow(dead_code)]
 impl Headers {
     fn new() -> Self {
         Headers(HashMap::new())
     }
 
-    fn insert(&mut self, key: String, value: String) {
+    pub(crate) fn insert(&mut self, key: String, value: String) {
         self.0.entry(key).or_insert_with(Vec::new).push(value);
     }
 
-    fn clear_lower_headers(&mut self, level: usize) {
+    /// Forget headings below `level`, as a new heading of `level` ends their
+    /// sections
+    pub(crate) fn clear_lower_headers(&mut self, level: usize) {
         for l in (level + 1)..=6 {
             self.0.remove(&format!("h{}", l));
         }
     }
+
+    /// Headings of `level` (1 to 6) in document order
+    #[must_use]
+    pub fn get(&self, level: usize) -> Option<&[String]> {
+        self.0.get(&format!("h{level}")).map(Vec::as_slice)
+    }
+
+    /// Levels that have headings with their headings, from h1 down
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[String])> {
+        (1..=6).filter_map(|level| Some((level, self.get(level)?)))
+    }
+
+    /// The latest heading of each level, from h1 down
+    #[must_use]
+    pub fn path(&self) -> Vec<String> {
+        self.path_to(6)
+    }
+
+    fn path_to(&self, max_level: usize) -> Vec<String> {
+        self.iter()
+            .filter(|&(level, _)| level <= max_level)
+            .filter_map(|(_, headings)| headings.last().cloned())
+            .collect()
+    }
+}
+
+/// Options for [`TextSplitter::split_by_headers`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderSplitOptions {
+    /// Deepest heading level (1 to 6) that starts a new section
+    pub max_level: usize,
+    /// Token limit of each chunk, as for [`TextSplitter::split`]
+    pub token_limit: usize,
+}
+
+/// A fenced code block: its byte range, from the start of the opening fence
+/// line to the end of the closing one, and the fence lines themselves
+struct CodeFence {
+    start: usize,
+    end: usize,
+    /// Opening line with its info string, such as the language
+    opening: String,
+    closing: String,
 }
 
-#[allow(dead_code)]
+/// Splits markdown into chunks that fit a token limit, tracking headings,
+/// links and images per chunk
+#[allow(clippy::struct_excessive_bools)]
 pub struct TextSplitter {
-    tokenizer: tiktoken_rs::CoreBPE,
-    model_name: String,
+    tokenizer: CoreBPE,
+    model_name: Option<String>,
+    chat_overhead: bool,
+    heading_breadcrumbs: bool,
+    min_tokens: usize,
+    strict: bool,
+    stream_window: usize,
+    default_limit: usize,
+    overlap: usize,
+    keep_links_inline: bool,
+    normalize: NormalizeOptions,
+    on_progress: Option<ProgressCallback>,
+    cancel: Option<Arc<AtomicBool>>,
 }
 
-#[allow(dead_code)]
 impl TextSplitter {
+    /// Create a splitter with the tokenizer of `model_name`, see
+    /// [`TextSplitter::from_model_name`]. `None` means `"gpt-4"`; unknown
+    /// names fall back to `cl100k_base` with a warning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled tokenizer data fails to load.
+    #[must_use]
     pub fn new(model_name: Option<String>) -> Self {
+        let model_name = model_name.unwrap_or_else(|| "gpt-4".to_string());
+        Self::from_model_name(model_name.clone()).unwrap_or_else(|err| {
+            warn!("{err}, counting tokens with cl100k_base");
+            Self::from_tokenizer(cl100k_base().unwrap()).with_model_name(model_name)
+        })
+    }
+
+    /// Create a splitter with the tokenizer `model_name` uses, see
+    /// [`Encoding::for_model`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] for unknown model names and [`Error::Other`]
+    /// if the tokenizer data fails to load.
+    pub fn from_model_name(model_name: impl Into<String>) -> crate::Result<Self> {
+        let model_name = model_name.into();
+        let tokenizer = Encoding::for_model(&model_name)
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Unknown model '{model_name}', no tokenizer to count tokens with"
+                ))
+            })?
+            .tokenizer()?;
+
+        Ok(Self::from_tokenizer(tokenizer).with_model_name(model_name))
+    }
+
+    /// Create a splitter that counts tokens with `tokenizer`, for encodings
+    /// [`TextSplitter::from_model_name`] does not map a model to
+    ///
+    /// ```
+    /// use ai_utils::text_splitter::TextSplitter;
+    ///
+    /// let splitter = TextSplitter::from_tokenizer(tiktoken_rs::o200k_base()?);
+    /// assert_eq!(splitter.model_name(), None);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    #[must_use]
+    pub fn from_tokenizer(tokenizer: CoreBPE) -> Self {
         Self {
-            tokenizer: cl100k_base().unwrap(),
-            model_name: model_name.unwrap_or_else(|| "gpt-4".to_string()),
+            tokenizer,
+            model_name: None,
+            chat_overhead: false,
+            heading_breadcrumbs: false,
+            min_tokens: 0,
+            strict: true,
+            stream_window: DEFAULT_STREAM_WINDOW,
+            default_limit: DEFAULT_TOKEN_LIMIT,
+            overlap: 0,
+            keep_links_inline: false,
+            normalize: NormalizeOptions::default(),
+            on_progress: None,
+            cancel: None,
         }
     }
 
-    fn count_tokens(&self, text: &str) -> usize {
-        let formatted_content = self.format_for_tokenization(text);
-        self.tokenizer
-            .encode_with_special_tokens(&formatted_content)
-            .len()
+    /// Create a splitter with the tokenizer, default limit, overlap, minimum
+    /// chunk size, link handling and normalization of `config`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled tokenizer data fails to load.
+    #[must_use]
+    pub fn with_config(config: SplitterConfig) -> Self {
+        let mut splitter = Self::from_tokenizer(config.tokenizer.tokenizer().unwrap())
+            .with_min_tokens(config.min_chunk_tokens);
+        splitter.default_limit = config.token_limit;
+        splitter.overlap = config.overlap;
+        splitter.keep_links_inline = config.keep_links_inline;
+        splitter.normalize = config.normalize;
+        splitter
+    }
+
+    fn with_model_name(mut self, model_name: String) -> Self {
+        self.model_name = Some(model_name);
+        self
     }
 
-    fn format_for_tokenization(&self, text: &str) -> String {
-        format!(
-            "<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant<|im_end|>",
-            text
+    /// Name of the model the splitter was created for; `None` for splitters
+    /// created with [`TextSplitter::from_tokenizer`]
+    #[must_use]
+    pub fn model_name(&self) -> Option<&str> {
+        self.model_name.as_deref()
+    }
+
+    /// Count every chunk as a chat user message, adding the tokens of the
+    /// `<|im_start|>user ... <|im_end|>` markup around it: a fixed amount per
+    /// chunk that depends on the encoding (26 with `cl100k_base`). Use it
+    /// when chunks are sent one per message and the limit is a context budget;
+    /// off by default, so counts are of the chunk text alone.
+    #[must_use]
+    pub const fn with_chat_overhead(mut self, enabled: bool) -> Self {
+        self.chat_overhead = enabled;
+        self
+    }
+
+    /// Prepend each chunk's heading path (e.g. `"Guide > Setup > Linux\n\n"`)
+    /// to its text, so the section context is embedded with the chunk. The
+    /// breadcrumb counts towards the token limit; in strict mode it is left
+    /// out of chunks where it would take more than half the limit.
+    #[must_use]
+    pub const fn with_heading_breadcrumbs(mut self, enabled: bool) -> Self {
+        self.heading_breadcrumbs = enabled;
+        self
+    }
+
+    /// Merge a final chunk smaller than `min_tokens` into the one before it,
+    /// as long as the result stays within 10% over the limit (within the
+    /// limit in strict mode). 0 disables it.
+    #[must_use]
+    pub const fn with_min_tokens(mut self, min_tokens: usize) -> Self {
+        self.min_tokens = min_tokens;
+        self
+    }
+
+    /// In strict mode (the default) every chunk is re-counted as it will be
+    /// returned, placeholders and breadcrumb included, and cut back until it
+    /// fits, so `metadata.tokens <= limit` holds for any limit above the chat
+    /// formatting overhead, if counted. Without it the limit is a close target that
+    /// newline and code-fence adjustments may overshoot.
+    #[must_use]
+    pub const fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Bytes of input [`TextSplitter::split_stream`] splits at once, 32 KiB
+    /// by default. Larger windows place chunk boundaries closer to where
+    /// [`TextSplitter::split`] would, at the cost of re-counting more text
+    /// per chunk.
+    #[must_use]
+    pub const fn with_stream_window(mut self, bytes: usize) -> Self {
+        self.stream_window = bytes;
+        self
+    }
+
+    /// Clean up text with `options` before splitting it; by default only
+    /// `\r\n` line endings become `\n`. Applies to [`TextSplitter::split`],
+    /// [`TextSplitter::split_by_headers`] and [`TextSplitter::split_stream`],
+    /// and each chunk lists the passes in `Metadata::normalizations`.
+    ///
+    /// `Metadata::start_offset` and `end_offset` are positions in the
+    /// normalized text. They index the original input only when no pass
+    /// changed it, e.g. a file without `\r\n` under the default options.
+    #[must_use]
+    pub const fn with_normalization(mut self, options: NormalizeOptions) -> Self {
+        self.normalize = options;
+        self
+    }
+
+    /// Call `on_progress` after each chunk of [`TextSplitter::split`],
+    /// [`TextSplitter::split_by_headers`] and [`TextSplitter::split_stream`]
+    #[must_use]
+    pub fn with_progress(
+        mut self,
+        on_progress: impl Fn(SplitProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    /// Stop splitting at the next chunk boundary once `cancel` is set,
+    /// returning the chunks produced so far; see
+    /// [`TextSplitter::split_cancellable`] to tell a cancelled split apart
+    #[must_use]
+    pub fn with_cancellation(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn tracker(&self, total_bytes: Option<usize>) -> SplitTracker<'_> {
+        SplitTracker::new(
+            self.on_progress.as_ref(),
+            self.cancel.as_deref(),
+            total_bytes,
         )
     }
 
+    /// Tokens of `text` as chunks are counted: with the chat markup around
+    /// it if [`TextSplitter::with_chat_overhead`] is on
+    #[must_use]
+    pub fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(&self.tokenizer, text, self.chat_overhead)
+    }
+
+    /// Tokens of `text` alone, without chat markup whatever the splitter's
+    /// settings
+    #[must_use]
+    pub fn count_tokens_raw(&self, text: &str) -> usize {
+        count_tokens(&self.tokenizer, text, false)
+    }
+
+    /// Split markdown text into chunks of at most `limit` tokens.
+    ///
+    /// Leading YAML front-matter (delimited by `---` lines) is parsed into
+    /// `Metadata::frontmatter` on every chunk and excluded from the chunk
+    /// text and token counts.
+    ///
+    /// Chunks do not end inside fenced code blocks or tables. A block too
+    /// large for one chunk is cut at line breaks, and each part of a code
+    /// block is wrapped in its own opening and closing fence.
+    ///
+    /// `Metadata::start_offset` and `end_offset` locate each chunk in `text`
+    /// as normalized by [`TextSplitter::with_normalization`].
+    /// The chunk text itself can differ from that range: links and images
+    /// are replaced by placeholders, breadcrumbs are prepended and split code
+    /// blocks get fences added.
+    ///
+    /// # Errors
+    ///
+    /// Does not fail at present; the `Result` leaves room for stricter input
+    /// validation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ai_utils::text_splitter::TextSplitter;
+    ///
+    /// let text = "Lorem ipsum dolor sit amet. ".repeat(200);
+    /// let docs = TextSplitter::new(None).split(&text, 100)?;
+    ///
+    /// assert!(docs.len() > 1);
+    /// assert!(docs.iter().all(|doc| doc.metadata.tokens <= 100));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
     pub fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        Ok(self.split_cancellable(text, limit)?.docs)
+    }
+
+    /// [`TextSplitter::split`] that also says whether the flag of
+    /// [`TextSplitter::with_cancellation`] stopped it early. A cancelled
+    /// split returns the chunks before the cancellation, numbered among
+    /// themselves.
+    ///
+    /// ```
+    /// use std::sync::{
+    ///     atomic::{AtomicBool, Ordering},
+    ///     Arc,
+    /// };
+    /// use ai_utils::text_splitter::TextSplitter;
+    ///
+    /// let cancel = Arc::new(AtomicBool::new(false));
+    /// let flag = Arc::clone(&cancel);
+    /// let splitter = TextSplitter::new(None)
+    ///     .with_cancellation(cancel)
+    ///     .with_progress(move |progress| {
+    ///         if progress.chunks_emitted == 2 {
+    ///             flag.store(true, Ordering::Relaxed);
+    ///         }
+    ///     });
+    ///
+    /// let outcome = splitter.split_cancellable(&"Lorem ipsum. ".repeat(500), 100)?;
+    /// assert!(outcome.cancelled);
+    /// assert_eq!(outcome.docs.len(), 2);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// See [`TextSplitter::split`].
+    pub fn split_cancellable(&self, text: &str, limit: usize) -> Result<SplitOutcome> {
         info!("Starting split process with limit: {} tokens", limit);
+        let text = self.normalize.apply(text);
+        let source_length = text.len();
+        let mut tracker = self.tracker(Some(source_length));
+        let (frontmatter, text) = Self::split_frontmatter(&text);
+        let body_offset = source_length - text.len();
+        let mut chunks = self.split_section(
+            text,
+            limit,
+            frontmatter.as_ref(),
+            body_offset,
+            &mut Headers::new(),
+            Some(&mut tracker),
+        )?;
+        number_chunks(&mut chunks);
+
+        info!("Split process completed. Total chunks: {}", chunks.len());
+        Ok(SplitOutcome {
+            docs: chunks,
+            cancelled: tracker.stopped(),
+        })
+    }
+
+    /// [`TextSplitter::split`] with the token limit of the splitter's
+    /// [`SplitterConfig`], [`DEFAULT_TOKEN_LIMIT`] for splitters not created
+    /// with [`TextSplitter::with_config`]
+    ///
+    /// # Errors
+    ///
+    /// See [`TextSplitter::split`].
+    pub fn split_default(&self, text: &str) -> Result<Vec<Doc>> {
+        self.split(text, self.default_limit)
+    }
+
+    /// Split `text` into sections at headings of `options.max_level` or
+    /// above, then split each section to `options.token_limit`, so no chunk
+    /// spans two sections. Headings inside fenced code blocks do not start
+    /// sections. Each chunk's `Metadata::section_path` names its section;
+    /// text before the first heading has an empty path. Offsets are in the
+    /// normalized text, as for [`TextSplitter::split`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `options.max_level` is not between 1 and 6.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ai_utils::text_splitter::{HeaderSplitOptions, TextSplitter};
+    ///
+    /// let text = "# Guide\n## Install\nRun the installer.\n## Usage\nStart the app.\n";
+    /// let options = HeaderSplitOptions { max_level: 2, token_limit: 100 };
+    /// let docs = TextSplitter::new(None).split_by_headers(text, options)?;
+    ///
+    /// assert_eq!(docs.len(), 3);
+    /// assert_eq!(docs[2].metadata.section_path, ["Guide", "Usage"]);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn split_by_headers(&self, text: &str, options: HeaderSplitOptions) -> Result<Vec<Doc>> {
+        Ok(self.split_by_headers_cancellable(text, options)?.docs)
+    }
+
+    /// [`TextSplitter::split_by_headers`] that also says whether the flag of
+    /// [`TextSplitter::with_cancellation`] stopped it early, as
+    /// [`TextSplitter::split_cancellable`] does
+    ///
+    /// # Errors
+    ///
+    /// See [`TextSplitter::split_by_headers`].
+    pub fn split_by_headers_cancellable(
+        &self,
+        text: &str,
+        options: HeaderSplitOptions,
+    ) -> Result<SplitOutcome> {
+        if !(1..=6).contains(&options.max_level) {
+            bail!(
+                "Heading level must be between 1 and 6, got {}",
+                options.max_level
+            );
+        }
+        info!(
+            "Starting header split at level {} with limit: {} tokens",
+            options.max_level, options.token_limit
+        );
+        let text = self.normalize.apply(text);
+        let source_length = text.len();
+        let mut tracker = self.tracker(Some(source_length));
+        let (frontmatter, text) = Self::split_frontmatter(&text);
+        let body_offset = source_length - text.len();
+
+        let mut boundaries = Self::section_starts(text, options.max_level);
+        boundaries.insert(0, 0);
+        boundaries.push(text.len());
+        boundaries.dedup();
+
+        let mut chunks = Vec::new();
+        let mut current_headers = Headers::new();
+        for section in boundaries.windows(2) {
+            let (start, end) = (section[0], section[1]);
+            let section_text = &text[start..end];
+            if section_text.trim().is_empty() {
+                continue;
+            }
+
+            let heading = section_text.lines().next().unwrap_or_default();
+            let mut section_headers = current_headers.clone();
+            self.update_current_headers(&mut section_headers, &self.extract_headers(heading));
+            let section_path = section_headers.path_to(options.max_level);
+            debug!("Splitting section {:?}", section_path);
+
+            let mut docs = self.split_section(
+                section_text,
+                options.token_limit,
+                frontmatter.as_ref(),
+                body_offset + start,
+                &mut current_headers,
+                Some(&mut tracker),
+            )?;
+            for doc in &mut docs {
+                doc.metadata.section_path.clone_from(&section_path);
+            }
+            chunks.append(&mut docs);
+            if tracker.stopped() {
+                break;
+            }
+        }
+        number_chunks(&mut chunks);
+
+        info!("Header split completed. Total chunks: {}", chunks.len());
+        Ok(SplitOutcome {
+            docs: chunks,
+            cancelled: tracker.stopped(),
+        })
+    }
+
+    /// Split markdown read from `reader` like [`TextSplitter::split`],
+    /// handing each chunk to `sink` as soon as it is final, and return the
+    /// number of chunks.
+    ///
+    /// Only a window of about [`TextSplitter::with_stream_window`] bytes is
+    /// held in memory. Each window is split, every chunk but the last is
+    /// emitted, and splitting resumes at the last chunk with more input and
+    /// the headings in effect there. A window ending inside a code block or
+    /// table is grown, up to 8 times, to keep the block whole; larger blocks
+    /// are cut at the window end like plain text.
+    ///
+    /// Input shorter than the window yields the same chunks as `split`.
+    /// Longer input may place boundaries differently, since chunk sizes are
+    /// estimated from the text at hand. `Metadata::total_chunks` is left at
+    /// 0, as the count is only known at the end. Offsets count bytes of the
+    /// normalized stream, as for [`TextSplitter::split`].
+    ///
+    /// Once the flag of [`TextSplitter::with_cancellation`] is set, no more
+    /// chunks go to `sink` and the count of those that did is returned;
+    /// [`TextSplitter::split_stream_cancellable`] also says whether that
+    /// happened.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, the input is not valid UTF-8, or
+    /// `sink` returns one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ai_utils::text_splitter::TextSplitter;
+    ///
+    /// let text = "# Log\n".to_string() + &"An entry of the log.\n".repeat(5_000);
+    /// let mut chunks = Vec::new();
+    /// let count = TextSplitter::new(None)
+    ///     .with_stream_window(16 * 1024)
+    ///     .split_stream(text.as_bytes(), 200, |doc| {
+    ///         chunks.push(doc);
+    ///         Ok(())
+    ///     })?;
+    ///
+    /// assert_eq!(count, chunks.len());
+    /// assert!(chunks.iter().all(|doc| doc.metadata.headers.get(1).is_some()));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn split_stream(
+        &self,
+        reader: impl BufRead,
+        limit: usize,
+        sink: impl FnMut(Doc) -> Result<()>,
+    ) -> Result<usize> {
+        Ok(self.split_stream_cancellable(reader, limit, sink)?.chunks)
+    }
+
+    /// [`TextSplitter::split_stream`] that also says whether the flag of
+    /// [`TextSplitter::with_cancellation`] stopped it early, as
+    /// [`TextSplitter::split_cancellable`] does
+    ///
+    /// # Errors
+    ///
+    /// See [`TextSplitter::split_stream`].
+    pub fn split_stream_cancellable(
+        &self,
+        mut reader: impl BufRead,
+        limit: usize,
+        mut sink: impl FnMut(Doc) -> Result<()>,
+    ) -> Result<StreamOutcome> {
+        info!(
+            "Starting stream split with limit: {} tokens, window: {} bytes",
+            limit, self.stream_window
+        );
+        let window = self.stream_window.max(1);
+        let mut normalizer = LineNormalizer::new(self.normalize);
+        let mut buffer = String::new();
+        // Input bytes before `buffer`
+        let mut consumed = 0;
+        let mut frontmatter = None;
+        let mut headers = Headers::new();
+        let mut emitted = 0;
+        let mut tracker = self.tracker(None);
+        let mut first = true;
+        let mut eof = false;
+
+        while !eof || !buffer.is_empty() {
+            let mut target = window;
+            let chunks = loop {
+                while !eof && buffer.len() < target {
+                    eof = Self::read_normalized_line(&mut reader, &mut normalizer, &mut buffer)?;
+                }
+                if first {
+                    let (parsed, body) = Self::split_frontmatter(&buffer);
+                    let body_offset = buffer.len() - body.len();
+                    frontmatter = parsed;
+                    buffer.drain(..body_offset);
+                    consumed += body_offset;
+                    first = false;
+                }
+
+                let mut chunk_headers = headers.clone();
+                let mut chunks = self.split_section(
+                    &buffer,
+                    limit,
+                    frontmatter.as_ref(),
+                    consumed,
+                    &mut chunk_headers,
+                    None,
+                )?;
+                if eof {
+                    break chunks;
+                }
+
+                // The last chunk may continue past the window, and so may a
+                // block reaching its end
+                let fences = Self::find_code_fences(&buffer);
+                let resume_limit = Self::atomic_blocks(&buffer, &fences)
+                    .iter()
+                    .find(|&&(_, end)| end == buffer.len())
+                    .map_or(buffer.len(), |&(start, _)| start);
+                let settled = chunks[..chunks.len().saturating_sub(1)]
+                    .iter()
+                    .take_while(|doc| doc.metadata.end_offset - consumed <= resume_limit)
+                    .count();
+                if settled > 0 {
+                    chunks.truncate(settled);
+                    break chunks;
+                }
+                if target >= window * MAX_STREAM_WINDOW_GROWTH {
+                    debug!("Block spans more than the window, cutting it at the window end");
+                    if chunks.len() > 1 {
+                        chunks.pop();
+                    }
+                    break chunks;
+                }
+                debug!("No final chunk in {} bytes, reading more", buffer.len());
+                target = buffer.len() + window;
+            };
+
+            let resume = chunks
+                .last()
+                .map_or(buffer.len(), |doc| doc.metadata.end_offset - consumed);
+            if let Some(last) = chunks.last() {
+                headers.clone_from(&last.metadata.headers);
+            }
+            for mut doc in chunks {
+                if tracker.is_cancelled() {
+                    break;
+                }
+                doc.metadata.chunk_index = emitted;
+                let end = doc.metadata.end_offset;
+                sink(doc)?;
+                emitted += 1;
+                tracker.chunk_done(end);
+            }
+            if tracker.stopped() {
+                info!("Stream split cancelled after {} chunks", emitted);
+                break;
+            }
+            buffer.drain(..resume);
+            consumed += resume;
+            if eof {
+                break;
+            }
+        }
+
+        info!("Stream split completed. Total chunks: {}", emitted);
+        Ok(StreamOutcome {
+            chunks: emitted,
+            cancelled: tracker.stopped(),
+        })
+    }
+
+    /// Byte offsets of the headings of `max_level` or above outside fenced
+    /// code blocks
+    fn section_starts(text: &str, max_level: usize) -> Vec<usize> {
+        let fences = Self::find_code_fences(text);
+        let header_regex = Regex::new(r"(?m)^(#{1,6})\s+").unwrap();
+        header_regex
+            .captures_iter(text)
+            .filter(|cap| cap[1].len() <= max_level)
+            .map(|cap| cap.get(0).unwrap().start())
+            .filter(|&start| {
+                !fences
+                    .iter()
+                    .any(|fence| (fence.start..fence.end).contains(&start))
+            })
+            .collect()
+    }
+
+    /// Chunks of `text`, which starts `offset` bytes into the original input,
+    /// with `current_headers` holding the headings in effect at its start and
+    /// left holding those in effect at its end
+    fn split_section(
+        &self,
+        text: &str,
+        limit: usize,
+        frontmatter: Option<&serde_json::Value>,
+        offset: usize,
+        current_headers: &mut Headers,
+        mut tracker: Option<&mut SplitTracker<'_>>,
+    ) -> Result<Vec<Doc>> {
         let mut chunks = Vec::new();
         let mut position = 0;
         let total_length = text.len();
-        let mut current_headers = Headers::new();
+        let fences = Self::find_code_fences(text);
+        let blocks = Self::atomic_blocks(text, &fences);
+        // Source range, prefix and suffix of each chunk, for merging the tail
+        let mut spans = Vec::new();
 
         while position < total_length {
+            if text[position..].trim().is_empty() {
+                debug!("Only whitespace remains at position {}, stopping", position);
+                break;
+            }
+            if tracker
+                .as_deref_mut()
+                .is_some_and(SplitTracker::is_cancelled)
+            {
+                info!("Split cancelled at position: {}", position);
+                break;
+            }
             info!("Processing chunk starting at position: {}", position);
-            let (chunk_text, chunk_end) = self.get_chunk(text, position, limit)?;
-            let tokens = self.count_tokens(&chunk_text);
-            debug!("Chunk tokens: {}", tokens);
+            let heading_path = self.heading_path_at(text, position, current_headers);
+            let (breadcrumb, mut reserved) = self.breadcrumb(&heading_path, limit);
+            // A chunk starting inside a code block that is too large for one
+            // chunk reopens and closes the fence around its part of the block
+            let fence = fences
+                .iter()
+                .find(|fence| fence.start <= position && position < fence.end);
+            let reopen = fence
+                .filter(|fence| fence.start < position)
+                .map(|fence| format!("{}\n", fence.opening))
+                .unwrap_or_default();
+            let close = fence
+                .map(|fence| format!("\n{}\n", fence.closing))
+                .unwrap_or_default();
+            reserved += self.count_tokens(&format!("{reopen}{close}")) - self.count_tokens("");
+            let chunk_limit = limit.saturating_sub(reserved).max(1);
+            let prefix = format!("{breadcrumb}{reopen}");
+
+            let (mut chunk_text, mut chunk_end) =
+                self.get_chunk(text, position, chunk_limit, &blocks)?;
+            if self.strict {
+                let fitted = self.fit_chunk_end(text, position, chunk_end, &prefix, &close, limit);
+                let fitted = Self::snap_to_block_boundary(text, position, fitted, &blocks);
+                if fitted != chunk_end {
+                    debug!("Cutting chunk back from {} to {} to fit", chunk_end, fitted);
+                    chunk_end = fitted;
+                    chunk_text = text[position..chunk_end].to_string();
+                }
+            }
+            let suffix = match fence {
+                Some(fence) if chunk_end < fence.end && chunk_text.ends_with('\n') => {
+                    close[1..].to_string()
+                }
+                Some(fence) if chunk_end < fence.end => close.clone(),
+                _ => String::new(),
+            };
+            let contains_code = fences
+                .iter()
+                .any(|fence| fence.start < chunk_end && position < fence.end);
 
             let headers_in_chunk = self.extract_headers(&chunk_text);
-            self.update_current_headers(&mut current_headers, &headers_in_chunk);
+            self.update_current_headers(current_headers, &headers_in_chunk);
 
             let (content, urls, images) = self.extract_urls_and_images(&chunk_text);
+            let content = format!("{prefix}{content}{suffix}");
+            let tokens = self.count_tokens(&content);
+            debug!("Chunk tokens: {}", tokens);
 
             chunks.push(Doc {
                 text: content,
@@ -93,38 +1076,359 @@ impl TextSplitter {
                     headers: current_headers.clone(),
                     urls,
                     images,
+                    id: None,
+                    fields: HashMap::new(),
+                    frontmatter: frontmatter.cloned(),
+                    heading_path,
+                    section_path: Vec::new(),
+                    start_offset: offset + position,
+                    end_offset: offset + chunk_end,
+                    contains_code,
+                    chunk_index: 0,
+                    total_chunks: 0,
+                    source: None,
+                    sentence_count: None,
+                    normalizations: self.normalize.applied(),
+                    item_name: None,
                 },
             });
 
+            spans.push((position, chunk_end, prefix, suffix));
+            if let Some(tracker) = tracker.as_deref_mut() {
+                tracker.chunk_done(offset + chunk_end);
+            }
             info!("Chunk processed. New position: {}", chunk_end);
-            position = chunk_end;
+            position = if self.overlap > 0 && chunk_end < total_length {
+                self.overlap_start(text, position, chunk_end)
+            } else {
+                chunk_end
+            };
         }
 
-        info!("Split process completed. Total chunks: {}", chunks.len());
+        self.merge_small_tail(text, &mut chunks, &spans, limit);
         Ok(chunks)
     }
 
-    fn get_chunk(&self, text: &str, start: usize, limit: usize) -> Result<(String, usize)> {
+    /// Start of the next chunk after the chunk `start..end`: the earliest
+    /// word start whose text up to `end` fits the overlap, or `end` if no
+    /// word does. Always after `start`, so splitting makes progress.
+    fn overlap_start(&self, text: &str, start: usize, end: usize) -> usize {
+        let candidates: Vec<usize> = text[start..end]
+            .char_indices()
+            .zip(text[start..end].chars().skip(1))
+            .filter(|&((_, c), next)| c.is_whitespace() && !next.is_whitespace())
+            .map(|((index, c), _)| start + index + c.len_utf8())
+            .collect();
+        let empty = self.count_tokens("");
+        let first_fitting = candidates.partition_point(|&candidate| {
+            self.count_tokens(&text[candidate..end]) - empty > self.overlap
+        });
+        candidates.get(first_fitting).copied().unwrap_or(end)
+    }
+
+    /// The largest end, at most `end`, whose chunk fits `limit` once links and
+    /// images are replaced and `prefix` and `suffix` are added. Prefers
+    /// cutting after whitespace in the second half of the chunk; keeps at
+    /// least one character.
+    fn fit_chunk_end(
+        &self,
+        text: &str,
+        start: usize,
+        end: usize,
+        prefix: &str,
+        suffix: &str,
+        limit: usize,
+    ) -> usize {
+        let fits = |end: usize| {
+            let (content, _, _) = self.extract_urls_and_images(&text[start..end]);
+            self.count_tokens(&format!("{prefix}{content}{suffix}")) <= limit
+        };
+        if fits(end) {
+            return end;
+        }
+
+        // Token counts grow with the text, so search for the last fitting end
+        // and then step back over any non-monotonic spots
+        let ends: Vec<usize> = text[start..end]
+            .char_indices()
+            .skip(1)
+            .map(|(index, _)| start + index)
+            .collect();
+        let mut fitting = ends.partition_point(|&end| fits(end));
+        while fitting > 0 && !fits(ends[fitting - 1]) {
+            fitting -= 1;
+        }
+        if fitting == 0 {
+            return start + text[start..].chars().next().map_or(0, char::len_utf8);
+        }
+        let largest = ends[fitting - 1];
+
+        let half = start + (largest - start) / 2;
+        text[start..largest]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(index, c)| start + index + c.len_utf8())
+            .filter(|&cut| cut > half)
+            .unwrap_or(largest)
+    }
+
+    /// Re-split the last two chunks as one if the last is below `min_tokens`
+    /// and the merged chunk fits the limit plus slack
+    fn merge_small_tail(
+        &self,
+        text: &str,
+        chunks: &mut Vec<Doc>,
+        spans: &[(usize, usize, String, String)],
+        limit: usize,
+    ) {
+        let [.., (start, _, prefix, _), (_, end, _, suffix)] = spans else {
+            return;
+        };
+        let Some(last) = chunks.last() else {
+            return;
+        };
+        if last.metadata.tokens >= self.min_tokens {
+            return;
+        }
+
+        let (content, urls, images) = self.extract_urls_and_images(&text[*start..*end]);
+        let content = format!("{prefix}{content}{suffix}");
+        let tokens = self.count_tokens(&content);
+        let slack = if self.strict {
+            0
+        } else {
+            limit * MIN_TOKENS_SLACK_PERCENT / 100
+        };
+        if tokens > limit + slack {
+            debug!(
+                "Not merging {} token tail: merged chunk would have {} tokens",
+                last.metadata.tokens, tokens
+            );
+            return;
+        }
+
+        debug!(
+            "Merging {} token tail into previous chunk",
+            last.metadata.tokens
+        );
+        let last = chunks.pop().unwrap();
+        let previous = chunks.last_mut().unwrap();
+        previous.text = content;
+        previous.metadata.tokens = tokens;
+        previous.metadata.headers = last.metadata.headers;
+        previous.metadata.urls = urls;
+        previous.metadata.images = images;
+        previous.metadata.end_offset = last.metadata.end_offset;
+        previous.metadata.contains_code |= last.metadata.contains_code;
+    }
+
+    /// Split CSV data into one `Doc` per row.
+    ///
+    /// The values of `text_columns` are joined with spaces to form the chunk
+    /// text, `id_column` (if given) becomes `Metadata::id`, and every other
+    /// column is stored in `Metadata::fields`. Rows whose text exceeds `limit`
+    /// tokens are split further at sentence boundaries with a
+    /// [`SentenceSplitter`] counting tokens like this splitter; the resulting
+    /// docs share the row's fields and get ids suffixed with `-{index}`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV cannot be parsed or a named column is
+    /// missing from its header row.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ai_utils::text_splitter::TextSplitter;
+    ///
+    /// let csv = "sku,name,price\nA1,Kettle,19.99\n";
+    /// let docs = TextSplitter::new(None).split_csv(csv, &["name"], Some("sku"), 100)?;
+    ///
+    /// assert_eq!(docs[0].text, "Kettle");
+    /// assert_eq!(docs[0].metadata.fields["price"], "19.99");
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn split_csv(
+        &self,
+        csv: &str,
+        text_columns: &[&str],
+        id_column: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Doc>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let header_row = reader
+            .headers()
+            .context("Failed to read CSV header row")?
+            .clone();
+
+        let column_index = |name: &str| header_row.iter().position(|header| header == name);
+        let mut text_indices = Vec::with_capacity(text_columns.len());
+        for column in text_columns {
+            match column_index(column) {
+                Some(index) => text_indices.push(index),
+                None => bail!("Text column '{column}' not found in CSV header"),
+            }
+        }
+        let id_index = match id_column {
+            Some(column) => match column_index(column) {
+                Some(index) => Some(index),
+                None => bail!("Id column '{column}' not found in CSV header"),
+            },
+            None => None,
+        };
+
+        let mut docs = Vec::new();
+        let mut sentence_splitter = None;
+        for (row_number, record) in reader.records().enumerate() {
+            let record =
+                record.with_context(|| format!("Failed to parse CSV row {}", row_number + 1))?;
+
+            let text = text_indices
+                .iter()
+                .filter_map(|&index| record.get(index))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            if text.is_empty() {
+                continue;
+            }
+
+            let id = id_index
+                .and_then(|index| record.get(index))
+                .map(str::to_string);
+            let fields: HashMap<String, String> = header_row
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !text_indices.contains(index) && Some(*index) != id_index)
+                .filter_map(|(index, header)| {
+                    record
+                        .get(index)
+                        .map(|value| (header.to_string(), value.to_string()))
+                })
+                .collect();
+
+            let tokens = self.count_tokens(&text);
+            if tokens <= limit {
+                docs.push(Doc {
+                    metadata: Metadata {
+                        tokens,
+                        headers: Headers::new(),
+                        urls: Vec::new(),
+                        images: Vec::new(),
+                        id,
+                        fields,
+                        frontmatter: None,
+                        heading_path: Vec::new(),
+                        section_path: Vec::new(),
+                        start_offset: 0,
+                        end_offset: text.len(),
+                        contains_code: false,
+                        chunk_index: 0,
+                        total_chunks: 0,
+                        source: None,
+                        sentence_count: None,
+                        normalizations: Vec::new(),
+                        item_name: None,
+                    },
+                    text,
+                });
+                continue;
+            }
+
+            let sentence_splitter = sentence_splitter.get_or_insert_with(|| {
+                SentenceSplitter::from_tokenizer(self.tokenizer.clone())
+                    .with_chat_overhead(self.chat_overhead)
+            });
+            for (index, mut doc) in sentence_splitter
+                .split(&text, limit)?
+                .into_iter()
+                .enumerate()
+            {
+                doc.metadata.id = id.as_ref().map(|id| format!("{id}-{index}"));
+                doc.metadata.fields.clone_from(&fields);
+                docs.push(doc);
+            }
+        }
+
+        number_chunks(&mut docs);
+        Ok(docs)
+    }
+
+    /// Separate leading YAML front-matter from the body.
+    ///
+    /// Returns the parsed front-matter (an empty object if the block is
+    /// empty) and the remaining text. Text without a closed front-matter
+    /// block, or whose block is not valid YAML, is returned unchanged.
+    fn split_frontmatter(text: &str) -> (Option<serde_json::Value>, &str) {
+        let Some(rest) = text
+            .strip_prefix("---\r\n")
+            .or_else(|| text.strip_prefix("---\n"))
+        else {
+            return (None, text);
+        };
+
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if matches!(line.trim_end(), "---" | "...") {
+                let yaml = &rest[..offset];
+                let body = &rest[offset + line.len()..];
+                return match serde_yaml::from_str::<serde_json::Value>(yaml) {
+                    Ok(serde_json::Value::Null) => (
+                        Some(serde_json::Value::Object(serde_json::Map::new())),
+                        body,
+                    ),
+                    Ok(value) => (Some(value), body),
+                    Err(e) => {
+                        debug!("Ignoring invalid front-matter: {}", e);
+                        (None, text)
+                    }
+                };
+            }
+            offset += line.len();
+        }
+
+        (None, text)
+    }
+
+    fn get_chunk(
+        &self,
+        text: &str,
+        start: usize,
+        limit: usize,
+        blocks: &[(usize, usize)],
+    ) -> Result<(String, usize)> {
         debug!("Getting chunk starting at {} with limit {}", start, limit);
-        let overhead = self.count_tokens(&self.format_for_tokenization("")) - self.count_tokens("");
 
-        let mut end = (start + ((text.len() - start) * limit / self.count_tokens(&text[start..])))
-            .min(text.len());
+        // A chunk holds at least one character, even if it alone exceeds the limit
+        let min_end = start + text[start..].chars().next().map_or(0, char::len_utf8);
+
+        // Guard against a zero count so the estimate never divides by zero
+        let remaining_tokens = self.count_tokens(&text[start..]).max(1);
+        let mut end =
+            (start + ((text.len() - start) * limit / remaining_tokens)).clamp(min_end, text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
 
         let mut chunk_text = text[start..end].to_string();
         let mut tokens = self.count_tokens(&chunk_text);
 
-        while tokens + overhead > limit && end > start {
+        while tokens > limit && end > min_end {
             debug!(
                 "Chunk exceeds limit with {} tokens. Adjusting end position...",
-                tokens + overhead
+                tokens
             );
             end = self.find_new_chunk_end(text, start, end);
             chunk_text = text[start..end].to_string();
             tokens = self.count_tokens(&chunk_text);
         }
 
-        end = self.adjust_chunk_end(text, start, end, tokens + overhead, limit);
+        end = self.adjust_chunk_end(text, start, end, tokens, limit);
+        end = self.avoid_block_split(text, start, end, limit, blocks);
         chunk_text = text[start..end].to_string();
         debug!("Final chunk end: {}", end);
         Ok((chunk_text, end))
@@ -169,21 +1473,171 @@ impl TextSplitter {
         end
     }
 
-    fn find_new_chunk_end(&self, _text: &str, start: usize, end: usize) -> usize {
-        // Reduce end position to try to fit within token limit
-        let new_end = end - ((end - start) / 10);
-        if new_end <= start {
-            start + 1
+    /// Fenced code blocks of `text`. An unclosed fence runs to the end.
+    fn find_code_fences(text: &str) -> Vec<CodeFence> {
+        let mut fences = Vec::new();
+        let mut open: Option<(usize, char, usize, &str)> = None;
+        let mut offset = 0;
+
+        for line in text.split_inclusive('\n') {
+            let line_start = offset;
+            offset += line.len();
+
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            let trimmed = line[indent..].trim_end();
+            let Some(marker) = trimmed.chars().next().filter(|c| matches!(c, '`' | '~')) else {
+                continue;
+            };
+            let run = trimmed.chars().take_while(|&c| c == marker).count();
+            if indent > 3 || run < 3 {
+                continue;
+            }
+
+            match open {
+                None => open = Some((line_start, marker, run, line.trim_end())),
+                Some((fence_start, open_marker, open_run, opening))
+                    if marker == open_marker && run >= open_run && trimmed.len() == run =>
+                {
+                    fences.push(CodeFence {
+                        start: fence_start,
+                        end: offset,
+                        opening: opening.to_string(),
+                        closing: line.trim_end().to_string(),
+                    });
+                    open = None;
+                }
+                Some(_) => {}
+            }
+        }
+
+        if let Some((fence_start, marker, run, opening)) = open {
+            fences.push(CodeFence {
+                start: fence_start,
+                end: text.len(),
+                opening: opening.to_string(),
+                closing: marker.to_string().repeat(run),
+            });
+        }
+        fences
+    }
+
+    /// Byte ranges of the blocks no chunk boundary should fall inside:
+    /// fenced code blocks and tables, i.e. runs of two or more lines starting
+    /// with `|` outside code blocks
+    fn atomic_blocks(text: &str, fences: &[CodeFence]) -> Vec<(usize, usize)> {
+        let mut blocks: Vec<(usize, usize)> = fences
+            .iter()
+            .map(|fence| (fence.start, fence.end))
+            .collect();
+
+        let mut table: Option<(usize, usize, usize)> = None;
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let line_start = offset;
+            offset += line.len();
+            let in_fence = fences
+                .iter()
+                .any(|fence| (fence.start..fence.end).contains(&line_start));
+
+            if !in_fence && line.trim_start().starts_with('|') {
+                table = Some(match table {
+                    Some((start, _, rows)) => (start, offset, rows + 1),
+                    None => (line_start, offset, 1),
+                });
+            } else if let Some((start, end, rows)) = table.take() {
+                if rows >= 2 {
+                    blocks.push((start, end));
+                }
+            }
+        }
+        if let Some((start, end, rows)) = table {
+            if rows >= 2 {
+                blocks.push((start, end));
+            }
+        }
+
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Move a chunk end that falls inside a code block or table to the end
+    /// of the block if it still fits in `limit`, otherwise out of the block
+    /// with [`TextSplitter::snap_to_block_boundary`]
+    fn avoid_block_split(
+        &self,
+        text: &str,
+        start: usize,
+        end: usize,
+        limit: usize,
+        blocks: &[(usize, usize)],
+    ) -> usize {
+        let Some(&(_, block_end)) = blocks
+            .iter()
+            .find(|&&(block_start, block_end)| block_start < end && end < block_end)
+        else {
+            return end;
+        };
+
+        if self.count_tokens(&text[start..block_end]) <= limit {
+            debug!("Extending chunk to end of block at {}", block_end);
+            block_end
+        } else {
+            Self::snap_to_block_boundary(text, start, end, blocks)
+        }
+    }
+
+    /// Move a chunk end that falls inside a block to the start of the block,
+    /// or, for a block that begins the chunk and so is too large for one, back
+    /// to the last line break. A first line with no break is cut as-is.
+    fn snap_to_block_boundary(
+        text: &str,
+        start: usize,
+        end: usize,
+        blocks: &[(usize, usize)],
+    ) -> usize {
+        let Some(&(block_start, _)) = blocks
+            .iter()
+            .find(|&&(block_start, block_end)| block_start < end && end < block_end)
+        else {
+            return end;
+        };
+
+        if block_start > start {
+            debug!("Reducing chunk to start of block at {}", block_start);
+            block_start
         } else {
-            new_end
+            text[start..end]
+                .rfind('\n')
+                .map_or(end, |index| start + index + 1)
+        }
+    }
+
+    fn find_new_chunk_end(&self, text: &str, start: usize, end: usize) -> usize {
+        // Reduce end position to try to fit within token limit, always by at
+        // least one character so the caller's loop terminates
+        let mut new_end = end - ((end - start) / 10).max(1);
+        while new_end > start && !text.is_char_boundary(new_end) {
+            new_end -= 1;
         }
+        if new_end <= start {
+            return start + text[start..].chars().next().map_or(1, char::len_utf8);
+        }
+
+        // Prefer cutting after whitespace in the second half of the chunk
+        let half = start + (new_end - start) / 2;
+        text[start..new_end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(index, c)| start + index + c.len_utf8())
+            .filter(|&cut| cut > half)
+            .unwrap_or(new_end)
     }
 
     fn extract_headers(&self, text: &str) -> Headers {
         let mut headers = Headers::new();
-        let header_regex = Regex::new(r"(?m)^(#{1,6})\s+(.*)$").unwrap();
 
-        for cap in header_regex.captures_iter(text) {
+        for cap in HEADING_REGEX.captures_iter(text) {
             let level = cap[1].len();
             let content = cap[2].trim().to_string();
             headers.insert(format!("h{}", level), content);
@@ -192,6 +1646,54 @@ impl TextSplitter {
         headers
     }
 
+    /// Breadcrumb line opening a chunk under `heading_path` and its token
+    /// count; empty when breadcrumbs are off or, in strict mode, when it
+    /// would take more than half of `limit`
+    fn breadcrumb(&self, heading_path: &[String], limit: usize) -> (String, usize) {
+        if !self.heading_breadcrumbs || heading_path.is_empty() {
+            return (String::new(), 0);
+        }
+        let breadcrumb = format!("{}\n\n", heading_path.join(" > "));
+        let reserved = self.count_tokens(&breadcrumb) - self.count_tokens("");
+        if self.strict && reserved > limit / 2 {
+            debug!(
+                "Breadcrumb of {} tokens leaves too little room, omitting it",
+                reserved
+            );
+            return (String::new(), 0);
+        }
+        (breadcrumb, reserved)
+    }
+
+    /// Append the next line of `reader` to `buffer`, normalized; true at the
+    /// end of the input
+    fn read_normalized_line(
+        reader: &mut impl BufRead,
+        normalizer: &mut LineNormalizer,
+        buffer: &mut String,
+    ) -> Result<bool> {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .context("Failed to read input stream")?;
+        normalizer.push_line(&line, buffer);
+        Ok(read == 0)
+    }
+
+    /// Heading path of a chunk starting at `start`: the headings in effect
+    /// there, updated with any headings that open the chunk
+    fn heading_path_at(&self, text: &str, start: usize, current: &Headers) -> Vec<String> {
+        let leading = text[start..]
+            .lines()
+            .take_while(|line| line.trim().is_empty() || HEADING_START_REGEX.is_match(line))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut headers = current.clone();
+        self.update_current_headers(&mut headers, &self.extract_headers(&leading));
+        headers.path()
+    }
+
     fn update_current_headers(&self, current: &mut Headers, extracted: &Headers) {
         for level in 1..=6 {
             let key = format!("h{}", level);
@@ -207,16 +1709,19 @@ impl TextSplitter {
     fn extract_urls_and_images(&self, text: &str) -> (String, Vec<String>, Vec<String>) {
         let mut urls = Vec::new();
         let mut images = Vec::new();
-        let url_index = 0;
-        let image_index = 0;
+        let keep_inline = self.keep_links_inline;
 
         let image_regex = Regex::new(r"!\[([^\]]*)\]\(([^)]+)\)").unwrap();
-        let url_regex = Regex::new(r"\[([^\]]+)\]\(([^)]+)\)").unwrap();
+        // Also matches images, which are left alone
+        let url_regex = Regex::new(r"(!?)\[([^\]]+)\]\(([^)]+)\)").unwrap();
 
         let content = image_regex
             .replace_all(text, |caps: &regex::Captures| {
-                let url = caps[2].to_string();
-                images.push(url);
+                let image_index = images.len();
+                images.push(caps[2].to_string());
+                if keep_inline {
+                    return caps[0].to_string();
+                }
                 let alt_text = &caps[1];
                 format!("![{}]({{$img{}}})", alt_text, image_index)
             })
@@ -224,9 +1729,15 @@ impl TextSplitter {
 
         let content = url_regex
             .replace_all(&content, |caps: &regex::Captures| {
-                let url = caps[2].to_string();
-                urls.push(url);
-                let link_text = &caps[1];
+                if !caps[1].is_empty() {
+                    return caps[0].to_string();
+                }
+                let url_index = urls.len();
+                urls.push(caps[3].to_string());
+                if keep_inline {
+                    return caps[0].to_string();
+                }
+                let link_text = &caps[2];
                 format!("[{}]({{$url{}}})", link_text, url_index)
             })
             .to_string();
@@ -234,3 +1745,30 @@ impl TextSplitter {
         (content, urls, images)
     }
 }
+
+/// The chunk's id is kept if set, otherwise a UUID is generated, and its
+/// source becomes the document's; every other metadata field becomes a
+/// metadata entry under its serialized name
+impl From<Doc> for Document {
+    fn from(doc: Doc) -> Self {
+        let id = doc.metadata.id.clone();
+        let mut document = Self::from_text(doc.text);
+        document.source.clone_from(&doc.metadata.source);
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(doc.metadata) {
+            document.metadata = fields
+                .into_iter()
+                .filter(|(key, _)| key != "id" && key != "source")
+                .collect();
+        }
+        if let Some(id) = id {
+            document.id = id;
+        }
+        document
+    }
+}
+
+impl Splitter for TextSplitter {
+    fn split(&self, text: &str, limit: usize) -> Result<Vec<Doc>> {
+        Self::split(self, text, limit)
+    }
+}